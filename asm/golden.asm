@@ -0,0 +1,43 @@
+; Frame golden test ROM, draws a fixed tile pattern and spins forever
+; Written for gbr and released into the public domain, assembled to tests/roms/golden.gb
+
+INCLUDE "hardware.inc"
+
+SECTION "Header", ROM0[$100]
+
+  nop
+  jp EntryPoint
+
+  ds $150 - @, 0 ; Make room for the header
+
+EntryPoint:
+  ; Turn the LCD off
+  ld a, 0
+  ldh [rLCDC], a
+
+  ; Every byte of tile data is the low byte of its own address
+  ld hl, $8000
+FillTiles:
+  ld a, l
+  ld [hl], a
+  inc hl
+  ld a, h
+  cp $90
+  jp nz, FillTiles
+
+  ; Every tilemap entry is the low byte of its own address
+  ld hl, $9800
+FillTilemap:
+  ld a, l
+  ld [hl], a
+  inc hl
+  ld a, h
+  cp $9c
+  jp nz, FillTilemap
+
+  ; Turn the LCD on with the background enabled, tile data at $8000
+  ld a, LCDCF_ON | LCDCF_BG8000 | LCDCF_BGON
+  ldh [rLCDC], a
+
+Done:
+  jp Done
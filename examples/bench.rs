@@ -0,0 +1,90 @@
+//! Throughput benchmarks, run with `cargo run --release --example bench`. Each case reports
+//! the best of 5 runs.
+use std::time::Instant;
+
+use gbr::{cartridge::Cartridge, cpu::Cpu, memory::Memory, system::System};
+
+const RUNS: usize = 5;
+const FRAMES: usize = 600;
+const INSTRUCTIONS: usize = 20_000_000;
+
+/// A loop of loads, arithmetic, stack and $CB prefixed instructions at $0150
+fn mixed_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // JP $0150
+    rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+    let body = [
+        0x3e, 0x12, // LD A, $12
+        0x47, // LD B, A
+        0x80, // ADD A, B
+        0x0c, // INC C
+        0x15, // DEC D
+        0xab, // XOR E
+        0xcb, 0x37, // SWAP A
+        0xcb, 0x7f, // BIT 7, A
+        0x65, // LD H, L
+        0xb7, // OR A
+        0x17, // RLA
+        0xd6, 0x03, // SUB $03
+        0xe6, 0x0f, // AND $0F
+        0xc5, // PUSH BC
+        0xc1, // POP BC
+        0x21, 0x00, 0xc0, // LD HL, $C000
+        0x77, // LD [HL], A
+        0x7e, // LD A, [HL]
+        0x23, // INC HL
+        0xcb, 0x11, // RL C
+    ];
+    let end = 0x150 + body.len();
+    rom[0x150..end].copy_from_slice(&body);
+    // JR back to the start of the loop
+    rom[end..end + 2].copy_from_slice(&[0x18, (-(body.len() as i8) - 2) as u8]);
+    rom
+}
+
+fn best_of(mut run: impl FnMut() -> f64) -> f64 {
+    (0..RUNS).map(|_| run()).fold(f64::MAX, f64::min)
+}
+
+/// Instruction decoding and execution alone, without the ppu, timers or interrupts
+fn cpu() {
+    let secs = best_of(|| {
+        let mut mem = Memory::new(Cartridge::new(mixed_rom()).unwrap());
+        let mut cpu = Cpu::default();
+        cpu.registers.pc = 0x100;
+        let start = Instant::now();
+        for _ in 0..INSTRUCTIONS {
+            std::hint::black_box(cpu.execute_decoded(&mut mem).unwrap());
+        }
+        start.elapsed().as_secs_f64()
+    });
+    let mips = INSTRUCTIONS as f64 / secs / 1e6;
+    println!("cpu: {INSTRUCTIONS} instructions in {:.0} ms, {mips:.1} M/s", secs * 1000.0);
+}
+
+/// Whole headless frames
+fn frames(name: &str, rom: &[u8]) {
+    let mut instructions = 0;
+    let secs = best_of(|| {
+        let mut system = System::headless(rom.to_vec()).unwrap();
+        for _ in 0..10 {
+            system.step_frame();
+        }
+        let before = system.stats().instructions;
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            system.step_frame();
+        }
+        instructions = system.stats().instructions - before;
+        start.elapsed().as_secs_f64()
+    });
+    let mips = instructions as f64 / secs / 1e6;
+    println!("{name}: {FRAMES} frames in {:.0} ms, {mips:.1} M instructions/s", secs * 1000.0);
+}
+
+fn main() {
+    cpu();
+    let golden = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+    frames("golden.gb", &std::fs::read(golden).unwrap());
+    frames("mixed", &mixed_rom());
+}
@@ -0,0 +1,25 @@
+/// How much work goes into what games rarely depend on, set with `System::set_accuracy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "lowercase"))]
+pub enum Accuracy {
+    /// Skip bookkeeping only debugging needs, such as the cpu's pc history, and hardware bugs
+    /// few games depend on, such as spurious STAT interrupts or wave ram corrupted on trigger
+    Fast,
+    /// Emulate and record everything
+    #[default]
+    Accurate,
+}
+
+impl std::str::FromStr for Accuracy {
+    type Err = String;
+
+    /// `fast` or `accurate`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "accurate" => Ok(Self::Accurate),
+            _ => Err(format!("Unknown accuracy {s}, expected fast or accurate")),
+        }
+    }
+}
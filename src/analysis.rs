@@ -0,0 +1,410 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    banked::{BankedAddr, ROM_BANK_SIZE},
+    instructions::{ILLEGAL_OPCODES, PREFIX},
+};
+
+/// Where the boot rom hands over to the game
+pub const ENTRY_POINT: u16 = 0x0100;
+
+/// Targets of the RST instructions
+pub const RST_VECTORS: [u16; 8] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38];
+
+/// VBlank, STAT, Timer, Serial and Joypad interrupt handlers
+pub const INTERRUPT_VECTORS: [(u16, &str); 5] = [
+    (0x40, "VBlank"),
+    (0x48, "STAT"),
+    (0x50, "Timer"),
+    (0x58, "Serial"),
+    (0x60, "Joypad"),
+];
+
+/// Length in bytes of the instruction starting with `opcode`
+pub fn instruction_length(opcode: u8) -> u8 {
+    match opcode {
+        0x01 | 0x08 | 0x11 | 0x21 | 0x31 | 0xea | 0xfa => 3,
+        0xc2 | 0xc3 | 0xc4 | 0xca | 0xcc | 0xcd | 0xd2 | 0xd4 | 0xda | 0xdc => 3,
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => 2,
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => 2,
+        0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => 2,
+        PREFIX => 2,
+        _ => 1,
+    }
+}
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEMORY: [&str; 4] = ["(BC)", "(DE)", "(HL+)", "(HL-)"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB", "SBC A,", "AND", "XOR", "OR", "CP"];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+/// The instruction at the start of `bytes` in assembly, `addr` is where it lives so relative
+/// jumps can show their target. Missing operand bytes read as 0.
+/// Read more: https://gbdev.io/pandocs/CPU_Instruction_Set.html
+pub fn disassemble(bytes: &[u8], addr: u16) -> String {
+    let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let opcode = byte(0);
+    let n8 = format!("${:02X}", byte(1));
+    let a16 = format!("${:04X}", u16::from_le_bytes([byte(1), byte(2)]));
+    let e8 = format!("${:04X}", addr.wrapping_add(2).wrapping_add_signed(byte(1) as i8 as i16));
+    let r8 = |i: u8| R8[i as usize & 7];
+    let (y, z, p) = ((opcode >> 3) & 7, opcode & 7, opcode as usize >> 4 & 3);
+    match opcode {
+        0x00 => "NOP".into(),
+        0x10 => "STOP".into(),
+        0x76 => "HALT".into(),
+        0x08 => format!("LD ({a16}), SP"),
+        0x18 => format!("JR {e8}"),
+        0x20 | 0x28 | 0x30 | 0x38 => format!("JR {}, {e8}", CONDITIONS[y as usize - 4]),
+        0x07 | 0x0f | 0x17 | 0x1f | 0x27 | 0x2f | 0x37 | 0x3f => {
+            ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y as usize].into()
+        }
+        0x00..0x40 => match (opcode & 0x0f, z) {
+            (0x01, _) => format!("LD {}, {a16}", R16[p]),
+            (0x02, _) => format!("LD {}, A", R16_MEMORY[p]),
+            (0x0a, _) => format!("LD A, {}", R16_MEMORY[p]),
+            (0x03, _) => format!("INC {}", R16[p]),
+            (0x0b, _) => format!("DEC {}", R16[p]),
+            (0x09, _) => format!("ADD HL, {}", R16[p]),
+            (_, 4) => format!("INC {}", r8(y)),
+            (_, 5) => format!("DEC {}", r8(y)),
+            _ => format!("LD {}, {n8}", r8(y)),
+        },
+        0x40..0x80 => format!("LD {}, {}", r8(y), r8(z)),
+        0x80..0xc0 => format!("{} {}", ALU[y as usize], r8(z)),
+        0xc9 => "RET".into(),
+        0xd9 => "RETI".into(),
+        0xe9 => "JP HL".into(),
+        0xf9 => "LD SP, HL".into(),
+        0xf3 => "DI".into(),
+        0xfb => "EI".into(),
+        0xc3 => format!("JP {a16}"),
+        0xcd => format!("CALL {a16}"),
+        0xe0 => format!("LDH ({n8}), A"),
+        0xf0 => format!("LDH A, ({n8})"),
+        0xe2 => "LD (C), A".into(),
+        0xf2 => "LD A, (C)".into(),
+        0xea => format!("LD ({a16}), A"),
+        0xfa => format!("LD A, ({a16})"),
+        0xe8 => format!("ADD SP, {}", byte(1) as i8),
+        0xf8 => format!("LD HL, SP{:+}", byte(1) as i8),
+        PREFIX => {
+            let cb = byte(1);
+            match cb >> 6 {
+                0 => format!("{} {}", ROTATES[(cb >> 3) as usize & 7], r8(cb)),
+                1 => format!("BIT {}, {}", (cb >> 3) & 7, r8(cb)),
+                2 => format!("RES {}, {}", (cb >> 3) & 7, r8(cb)),
+                _ => format!("SET {}, {}", (cb >> 3) & 7, r8(cb)),
+            }
+        }
+        opcode if ILLEGAL_OPCODES.contains(&opcode) => format!("DB ${opcode:02X}"),
+        _ => match z {
+            0 => format!("RET {}", CONDITIONS[y as usize]),
+            1 => format!("POP {}", R16_STACK[p]),
+            2 => format!("JP {}, {a16}", CONDITIONS[y as usize]),
+            4 => format!("CALL {}, {a16}", CONDITIONS[y as usize]),
+            5 => format!("PUSH {}", R16_STACK[p]),
+            6 => format!("{} {n8}", ALU[y as usize]),
+            _ => format!("RST ${:02X}", opcode & 0x38),
+        },
+    }
+}
+
+/// Where execution can go after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Next,
+    Jump(u16),
+    /// Conditional jump, execution may also fall through
+    Branch(u16),
+    /// CALL or RST, execution carries on after the callee returns
+    Call(u16),
+    /// RET, RETI, JP HL and the opcodes that lock up the cpu
+    End,
+}
+
+fn flow(bytes: &[u8], addr: u16) -> Flow {
+    let a16 = || u16::from_le_bytes([bytes[1], bytes[2]]);
+    let e8 = || addr.wrapping_add(2).wrapping_add_signed(bytes[1] as i8 as i16);
+    match bytes[0] {
+        0xc3 => Flow::Jump(a16()),
+        0xc2 | 0xca | 0xd2 | 0xda => Flow::Branch(a16()),
+        0x18 => Flow::Jump(e8()),
+        0x20 | 0x28 | 0x30 | 0x38 => Flow::Branch(e8()),
+        0xcd | 0xc4 | 0xcc | 0xd4 | 0xdc => Flow::Call(a16()),
+        opcode @ (0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff) => {
+            Flow::Call((opcode & 0x38) as u16)
+        }
+        0xc9 | 0xd9 | 0xe9 => Flow::End,
+        opcode if ILLEGAL_OPCODES.contains(&opcode) => Flow::End,
+        _ => Flow::Next,
+    }
+}
+
+/// A run of instructions only entered at the top and only left at the bottom, calls aside
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Bytes covered by the block
+    pub len: u16,
+    /// Blocks execution may continue in
+    pub successors: Vec<BankedAddr>,
+    /// Functions called from inside the block, in order
+    pub calls: Vec<BankedAddr>,
+}
+
+#[derive(Debug, Clone)]
+struct Decoded {
+    len: u8,
+    flow: Flow,
+    bank: u16,
+}
+
+/// The result of recursively disassembling a ROM from its entry point and vectors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// The entry point, RST and interrupt vectors and every CALL or RST target
+    pub functions: BTreeSet<BankedAddr>,
+    /// JP and JR targets
+    pub labels: BTreeSet<BankedAddr>,
+    /// The control flow graph, keyed by where each block starts
+    pub blocks: BTreeMap<BankedAddr, BasicBlock>,
+}
+
+/// Follow every reachable instruction of `rom`. Only code in ROM is followed, jumps into RAM are
+/// dropped. Which bank a jump into 0x4000-0x7fff lands in is guessed from the last `ld a, n`
+/// written to 0x2000-0x3fff (directly or through hl) on the way there, or the current bank.
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis::default();
+    let mut decoded: BTreeMap<BankedAddr, Decoded> = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    // each item carries the bank the code believes is mapped at 0x4000
+    let mut queue: Vec<(BankedAddr, u16)> = vec![];
+    let vectors = RST_VECTORS.iter().chain(INTERRUPT_VECTORS.iter().map(|(addr, _)| addr));
+    for &addr in std::iter::once(&ENTRY_POINT).chain(vectors) {
+        analysis.functions.insert(BankedAddr::new(0, addr));
+        leaders.insert(BankedAddr::new(0, addr));
+        queue.push((BankedAddr::new(0, addr), 1));
+    }
+    while let Some((start, mut mapped)) = queue.pop() {
+        let mut at = start;
+        // constants a bank number could be written from
+        let (mut a, mut hl): (Option<u8>, Option<u16>) = (None, None);
+        while !decoded.contains_key(&at) {
+            let Some(offset) = at.rom_offset() else {
+                break;
+            };
+            let Some(&opcode) = rom.get(offset) else {
+                break;
+            };
+            let len = instruction_length(opcode);
+            let Some(bytes) = rom.get(offset..offset + len as usize) else {
+                break;
+            };
+            let flow = flow(bytes, at.addr);
+            decoded.insert(at, Decoded { len, flow, bank: mapped });
+            let bank_write = match (opcode, a) {
+                (0xea, Some(a)) => Some((u16::from_le_bytes([bytes[1], bytes[2]]), a)),
+                (0x77, Some(a)) => hl.map(|hl| (hl, a)),
+                _ => None,
+            };
+            if let Some((0x2000..0x4000, bank)) = bank_write {
+                mapped = bank.max(1) as u16;
+            }
+            (a, hl) = match opcode {
+                0x3e => (Some(bytes[1]), hl),
+                0x21 => (a, Some(u16::from_le_bytes([bytes[1], bytes[2]]))),
+                0xea | 0x77 | 0x00 => (a, hl),
+                _ => (None, None),
+            };
+            let next = at.addr.checked_add(len as u16);
+            let next = next.map(|addr| BankedAddr::new(mapped, addr));
+            let target = |addr: u16| (addr < 0x8000).then(|| BankedAddr::new(mapped, addr));
+            match flow {
+                Flow::Jump(addr) | Flow::Branch(addr) => {
+                    if let Some(target) = target(addr) {
+                        analysis.labels.insert(target);
+                        leaders.insert(target);
+                        queue.push((target, mapped));
+                    }
+                }
+                Flow::Call(addr) => {
+                    if let Some(target) = target(addr) {
+                        analysis.functions.insert(target);
+                        leaders.insert(target);
+                        queue.push((target, mapped));
+                    }
+                }
+                _ => {}
+            }
+            match (flow, next) {
+                (Flow::Next | Flow::Call(_), Some(next)) => at = next,
+                (Flow::Branch(_), Some(next)) => {
+                    leaders.insert(next);
+                    queue.push((next, mapped));
+                    break;
+                }
+                _ => break,
+            }
+        }
+    }
+    // a leader reached by falling through from the instruction before it also splits a block
+    for &leader in leaders.iter().filter(|leader| decoded.contains_key(leader)) {
+        let mut block = BasicBlock {
+            len: 0,
+            successors: vec![],
+            calls: vec![],
+        };
+        let mut at = leader;
+        loop {
+            let instruction = &decoded[&at];
+            block.len += instruction.len as u16;
+            let next = at
+                .addr
+                .checked_add(instruction.len as u16)
+                .map(|addr| BankedAddr::new(instruction.bank, addr))
+                .filter(|next| decoded.contains_key(next));
+            let bank = instruction.bank;
+            let target = |addr: u16| (addr < 0x8000).then(|| BankedAddr::new(bank, addr));
+            match instruction.flow {
+                Flow::Jump(addr) => block.successors.extend(target(addr)),
+                Flow::Branch(addr) => {
+                    block.successors.extend(target(addr));
+                    block.successors.extend(next);
+                }
+                Flow::Call(addr) => block.calls.extend(target(addr)),
+                _ => {}
+            }
+            match (instruction.flow, next) {
+                (Flow::Next | Flow::Call(_), Some(next)) if leaders.contains(&next) => {
+                    block.successors.push(next);
+                }
+                (Flow::Next | Flow::Call(_), Some(next)) => {
+                    at = next;
+                    continue;
+                }
+                _ => {}
+            }
+            break;
+        }
+        analysis.blocks.insert(leader, block);
+    }
+    analysis
+}
+
+impl Analysis {
+    /// Names for every function and label, vectors get their usual names and everything else
+    /// follows mgbdis: `Call_BBB_AAAA` and `Jump_BBB_AAAA`
+    pub fn symbols(&self) -> BTreeMap<BankedAddr, String> {
+        let mut symbols = BTreeMap::new();
+        for &label in &self.labels {
+            symbols.insert(label, format!("Jump_{:03x}_{:04x}", label.bank, label.addr));
+        }
+        for &function in &self.functions {
+            symbols.insert(function, format!("Call_{:03x}_{:04x}", function.bank, function.addr));
+        }
+        for addr in RST_VECTORS {
+            symbols.insert(BankedAddr::new(0, addr), format!("RST_{addr:02x}"));
+        }
+        for (addr, name) in INTERRUPT_VECTORS {
+            symbols.insert(BankedAddr::new(0, addr), format!("{name}Interrupt"));
+        }
+        symbols.insert(BankedAddr::new(0, ENTRY_POINT), "Entry".into());
+        symbols
+    }
+
+    /// The symbols as a .sym file, which BGB, Emulicious and mgbdis all read
+    pub fn to_sym(&self) -> String {
+        let mut sym = String::from("; generated by gbr\n");
+        for (addr, name) in self.symbols() {
+            sym.push_str(&format!("{addr} {name}\n"));
+        }
+        sym
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 4 * ROM_BANK_SIZE];
+        // jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x150..0x15f].copy_from_slice(&[
+            0xcd, 0x60, 0x01, // call $0160
+            0x3e, 0x02, // ld a, 2
+            0xea, 0x00, 0x20, // ld [$2000], a
+            0xcd, 0x00, 0x40, // call $4000, in bank 2
+            0x20, 0xf3, // jr nz, $0150
+            0x18, 0xfe, // jr $015d
+        ]);
+        // ret
+        rom[0x160] = 0xc9;
+        rom[2 * ROM_BANK_SIZE] = 0xc9;
+        rom
+    }
+
+    #[test]
+    fn test_instruction_length() {
+        assert_eq!(instruction_length(0x00), 1);
+        assert_eq!(instruction_length(0x3e), 2);
+        assert_eq!(instruction_length(0xcb), 2);
+        assert_eq!(instruction_length(0xcd), 3);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let cases: [(&[u8], &str); 16] = [
+            (&[0x00], "NOP"),
+            (&[0x01, 0x34, 0x12], "LD BC, $1234"),
+            (&[0x22], "LD (HL+), A"),
+            (&[0x35], "DEC (HL)"),
+            (&[0x3e, 0x42], "LD A, $42"),
+            (&[0x20, 0xfe], "JR NZ, $0150"),
+            (&[0x41], "LD B, C"),
+            (&[0x76], "HALT"),
+            (&[0x8e], "ADC A, (HL)"),
+            (&[0xc8], "RET Z"),
+            (&[0xf1], "POP AF"),
+            (&[0xdc, 0x00, 0x40], "CALL C, $4000"),
+            (&[0xfe, 0x90], "CP $90"),
+            (&[0xff], "RST $38"),
+            (&[0xcb, 0x7c], "BIT 7, H"),
+            (&[0xd3], "DB $D3"),
+        ];
+        for (bytes, assembly) in cases {
+            assert_eq!(disassemble(bytes, 0x0150), assembly);
+        }
+        assert_eq!(disassemble(&[0xf8, 0xfe], 0), "LD HL, SP-2");
+        assert_eq!(disassemble(&[0xcb, 0x37], 0), "SWAP A");
+    }
+
+    #[test]
+    fn test_analyze() {
+        let analysis = analyze(&test_rom());
+        assert!(analysis.functions.contains(&BankedAddr::new(0, 0x160)));
+        assert!(analysis.functions.contains(&BankedAddr { bank: 2, addr: 0x4000 }));
+        assert!(!analysis.functions.contains(&BankedAddr { bank: 1, addr: 0x4000 }));
+        assert!(analysis.labels.contains(&BankedAddr::new(0, 0x15d)));
+        assert_eq!(
+            analysis.blocks[&BankedAddr::new(0, 0x150)],
+            BasicBlock {
+                len: 13,
+                successors: vec![BankedAddr::new(0, 0x150), BankedAddr::new(0, 0x15d)],
+                calls: vec![BankedAddr::new(0, 0x160), BankedAddr { bank: 2, addr: 0x4000 }],
+            }
+        );
+        // the vectors are all nops that run into each other
+        assert_eq!(
+            analysis.blocks[&BankedAddr::new(0, 0x00)].successors,
+            vec![BankedAddr::new(0, 0x08)]
+        );
+        let sym = analysis.to_sym();
+        assert!(sym.contains("00:0100 Entry\n"));
+        assert!(sym.contains("00:0040 VBlankInterrupt\n"));
+        assert!(sym.contains("02:4000 Call_002_4000\n"));
+        assert!(sym.contains("00:015d Jump_000_015d\n"));
+    }
+}
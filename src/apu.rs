@@ -1,14 +1,940 @@
-// this is a big TODO For Now
-// The audio processing unit of the GB
-#[derive(Debug)]
-pub struct Apu {}
+use crate::memory::{
+    Memory,
+    registers::{NR10, NR13, NR14, NR30, NR44, NR50, NR51, NR52, WAVE_RAM_START},
+};
+
+/// The APU mixes a stereo sample every M-cycle
+pub const OUTPUT_RATE: u32 = 1 << 20;
+
+/// Each channel owns five registers starting at NR10, NRx0-NRx4
+const CHANNEL_REGISTERS: usize = 5;
+
+/// Channel 3 plays wave ram and has no envelope
+const WAVE: usize = 2;
+
+/// T-cycles between triggering channel 3 and its first wave ram read on top of the period
+const WAVE_TRIGGER_DELAY: u16 = 6;
+
+/// Frequencies above this overflow the sweep and silence channel 1
+const MAX_FREQUENCY: u16 = 2047;
+
+/// The four pulse waveforms NRx1 picks from, bit n is step n
+/// Read more: https://gbdev.io/pandocs/Audio_Registers.html
+const DUTY: [u8; 4] = [0b1000_0000, 0b1000_0001, 0b1110_0001, 0b0111_1110];
+
+/// Channel 4 is clocked every divisor << shift T-cycles, NR43 bits 0-2 pick the divisor
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Channel 4 plays noise from an LFSR instead of a waveform
+const NOISE: usize = 3;
+
+/// How far NR32 shifts channel 3's samples down, muted, 100%, 50% and 25%
+const WAVE_SHIFT: [u8; 4] = [4, 0, 1, 2];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Channel {
+    /// Shown in NR52, cleared when the length runs out, the DAC is turned off or the sweep
+    /// overflows, only a trigger sets it again
+    pub enabled: bool,
+    /// With the DAC off the channel can't be enabled, NRx2 bits 3-7 (NR30 bit 7 on channel 3)
+    pub dac: bool,
+    /// Length steps left until the channel is silenced, only counting down while
+    /// `length_enabled`
+    pub length: u16,
+    pub length_enabled: bool,
+    /// Envelope volume, 0-15
+    pub volume: u8,
+    envelope_timer: u8,
+    /// NRx0-NRx4 as last written
+    registers: [u8; CHANNEL_REGISTERS],
+}
+
+impl Channel {
+    /// The 11-bit period from NRx3 and the low bits of NRx4
+    fn frequency(&self) -> u16 {
+        u16::from_be_bytes([self.registers[4] & 0x07, self.registers[3]])
+    }
+
+    fn clock_length(&mut self) {
+        if self.length_enabled && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let period = self.registers[2] & 0x07;
+        if period == 0 {
+            return;
+        }
+        self.envelope_timer = self.envelope_timer.saturating_sub(1);
+        if self.envelope_timer == 0 {
+            self.envelope_timer = period;
+            match self.registers[2] & 0x08 != 0 {
+                true if self.volume < 15 => self.volume += 1,
+                false if self.volume > 0 => self.volume -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Channel 1's frequency sweep, it works on a copy of the frequency taken on trigger
+#[derive(Debug, Clone, Copy, Default)]
+struct Sweep {
+    shadow: u16,
+    timer: u8,
+    enabled: bool,
+    /// A frequency has been calculated in negate mode since the last trigger, turning negate
+    /// off in NR10 after that disables channel 1
+    negated: bool,
+}
+
+/// Where a pulse channel is in its duty cycle
+#[derive(Debug, Clone, Copy, Default)]
+struct Pulse {
+    /// Step of the waveform, 0-7
+    position: u8,
+    /// T-cycles until the next step
+    timer: u16,
+}
+
+/// Channel 4's linear feedback shift register, bit 0 is the output
+/// Read more: https://gbdev.io/pandocs/Audio_details.html#noise-channel-ch4
+#[derive(Debug, Clone, Copy, Default)]
+struct Noise {
+    lfsr: u16,
+    /// T-cycles until the LFSR is next clocked
+    timer: u32,
+}
+
+impl Noise {
+    /// Bits 0 and 1 XNOR'd go into bit 15, and bit 7 too in 7-bit mode, then everything shifts
+    /// right so the sequence repeats every 32767 steps, or every 127 in 7-bit mode
+    fn clock_lfsr(&mut self, short: bool) {
+        let bit = !(self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr & 0x7fff) | bit << 15;
+        if short {
+            self.lfsr = (self.lfsr & !0x80) | bit << 7;
+        }
+        self.lfsr >>= 1;
+    }
+}
+
+/// Where channel 3 is in wave ram
+#[derive(Debug, Clone, Copy, Default)]
+struct Wave {
+    /// Sample being played, 0-31, two per byte of wave ram
+    position: u8,
+    /// T-cycles until the next sample is read
+    timer: u16,
+}
+
+/// What a channel is doing right now, for debugging and tests
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelState {
+    pub enabled: bool,
+    pub dac: bool,
+    /// Tone frequency in Hz, for the noise channel how often the LFSR is clocked
+    pub frequency: f32,
+    /// 0-15, the envelope volume or channel 3's output level scaled to it
+    pub volume: u8,
+    /// NRx1 bits 6-7 on the pulse channels
+    pub duty: Option<u8>,
+    /// Length steps left
+    pub length: u16,
+    pub length_enabled: bool,
+    /// The channel's digital output, 0-15
+    pub sample: u8,
+}
+
+/// A snapshot of the APU from `Apu::state`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ApuState {
+    pub power: bool,
+    pub channels: [ChannelState; 4],
+}
+
+/// The audio processing unit of the GB
+/// Read more: https://gbdev.io/pandocs/Audio_Registers.html
+#[derive(Debug, Clone, Default)]
+pub struct Apu {
+    /// Interleaved left and right samples at `OUTPUT_RATE`, waiting to be resampled for the
+    /// audio device
+    pub output: Vec<f32>,
+    /// NR52 bit 7, turning it off clears every audio register
+    pub power: bool,
+    pub channels: [Channel; 4],
+    sweep: Sweep,
+    pulses: [Pulse; 2],
+    wave: Wave,
+    noise: Noise,
+    /// Triggering channel 3 on the DMG while it reads wave ram overwrites the start of wave ram
+    pub wave_trigger_bug: bool,
+    /// The next frame sequencer step, 0-7
+    step: u8,
+}
 
 impl Apu {
-    pub fn process(&mut self) {}
+    /// Pick up the state the boot rom left in the audio registers
+    pub fn reset(&mut self, mem: &Memory) {
+        let nr52 = mem.io.get(NR52);
+        *self = Self {
+            power: nr52 & 0x80 != 0,
+            output: std::mem::take(&mut self.output),
+            wave_trigger_bug: self.wave_trigger_bug,
+            ..Self::default()
+        };
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            for (register, value) in channel.registers.iter_mut().enumerate() {
+                *value = mem.io.get(NR10 + index * CHANNEL_REGISTERS + register);
+            }
+            channel.dac = match index {
+                WAVE => channel.registers[0] & 0x80 != 0,
+                _ => channel.registers[2] & 0xf8 != 0,
+            };
+            channel.enabled = channel.dac && nr52 & (1 << index) != 0;
+        }
+    }
+
+    /// Advance the channels by `cycles` M-cycles, mixing a sample for each of them, and show
+    /// which channels are on in NR52
+    pub fn process(&mut self, cycles: usize, mem: &mut Memory) {
+        if self.power {
+            self.clock_pulses(cycles * 4);
+            self.clock_wave(cycles * 4);
+            self.clock_noise(cycles * 4);
+        }
+        let status = self.channels.iter().enumerate().fold(0, |status, (index, channel)| {
+            status | (channel.enabled as u8) << index
+        });
+        mem.io.set(NR52, (self.power as u8) << 7 | status);
+        let [left, right] = self.mix(mem);
+        for _ in 0..cycles {
+            self.output.extend([left, right]);
+        }
+    }
+
+    /// The channels sent to each side by NR51, scaled by NR50's master volumes. The VIN bits
+    /// are ignored since no cartridge drives VIN.
+    /// Read more: https://gbdev.io/pandocs/Audio_details.html#mixer
+    pub fn mix(&self, mem: &Memory) -> [f32; 2] {
+        let (nr50, nr51) = (mem.io.get(NR50), mem.io.get(NR51));
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (index, channel) in self.channels.iter().enumerate() {
+            if !channel.enabled {
+                continue;
+            }
+            // the DACs map 0-15 to 1 down to -1
+            let analog = 1.0 - self.sample(index, mem) as f32 / 7.5;
+            if nr51 & (0x10 << index) != 0 {
+                left += analog;
+            }
+            if nr51 & (0x01 << index) != 0 {
+                right += analog;
+            }
+        }
+        let volume = |level: u8| (level & 0x07) as f32 + 1.0;
+        [left * volume(nr50 >> 4) / 32.0, right * volume(nr50) / 32.0]
+    }
+
+    /// Called whenever the cpu writes an audio register or wave ram
+    pub fn io_event(&mut self, addr: usize, value: u8, mem: &mut Memory) {
+        if addr == NR52 {
+            self.set_power(value & 0x80 != 0, mem);
+            return;
+        }
+        if addr >= WAVE_RAM_START {
+            return;
+        }
+        // with the power off every register but NR52 and wave ram is read-only
+        if !self.power {
+            mem.io.set(addr, 0);
+            return;
+        }
+        // NR50 and NR51 are read when mixing
+        if addr > NR44 {
+            return;
+        }
+        let index = (addr - NR10) / CHANNEL_REGISTERS;
+        let register = (addr - NR10) % CHANNEL_REGISTERS;
+        let channel = &mut self.channels[index];
+        channel.registers[register] = value;
+        match (index, register) {
+            (0, 0) if value & 0x08 == 0 && self.sweep.negated => channel.enabled = false,
+            (WAVE, 0) => channel.dac = value & 0x80 != 0,
+            (WAVE, 1) => channel.length = 256 - value as u16,
+            (_, 1) => channel.length = 64 - (value & 0x3f) as u16,
+            (WAVE, 2) => {}
+            (_, 2) => channel.dac = value & 0xf8 != 0,
+            (_, 4) => self.write_control(index, value, mem),
+            _ => {}
+        }
+        let channel = &mut self.channels[index];
+        channel.enabled &= channel.dac;
+    }
+
+    fn set_power(&mut self, power: bool, mem: &mut Memory) {
+        if !power && self.power {
+            for addr in NR10..=NR51 {
+                mem.io.set(addr, 0);
+            }
+            self.channels = Default::default();
+            self.sweep = Sweep::default();
+            self.pulses = Default::default();
+            self.wave = Wave::default();
+            self.noise = Noise::default();
+        }
+        if power && !self.power {
+            self.step = 0;
+        }
+        self.power = power;
+    }
+
+    /// NRx4, enabling the length timer or triggering the channel
+    /// Read more: https://gbdev.io/pandocs/Audio_details.html#obscure-behavior
+    fn write_control(&mut self, index: usize, value: u8, mem: &mut Memory) {
+        // odd steps don't clock the length timers, enabling one on an even step clocks it once
+        let length_step_next = self.step % 2 == 0;
+        let channel = &mut self.channels[index];
+        let enabling = !channel.length_enabled && value & 0x40 != 0;
+        channel.length_enabled = value & 0x40 != 0;
+        if enabling && !length_step_next && channel.length > 0 {
+            channel.length -= 1;
+            if channel.length == 0 && value & 0x80 == 0 {
+                channel.enabled = false;
+            }
+        }
+        if value & 0x80 != 0 {
+            self.trigger(index, length_step_next, mem);
+        }
+    }
+
+    /// Restart a channel, it only plays if its DAC is on but the timers are reloaded either way
+    /// Read more: https://gbdev.io/pandocs/Audio_Registers.html#triggering
+    fn trigger(&mut self, index: usize, length_step_next: bool, mem: &mut Memory) {
+        if index == WAVE {
+            self.trigger_wave(mem);
+        }
+        // the duty step carries on from where it was
+        if index < 2 {
+            self.pulses[index].timer = self.pulse_period(index);
+        }
+        if index == NOISE {
+            self.noise = Noise {
+                lfsr: 0,
+                timer: self.noise_period(),
+            };
+        }
+        let channel = &mut self.channels[index];
+        channel.enabled = channel.dac;
+        if channel.length == 0 {
+            channel.length = if index == WAVE { 256 } else { 64 };
+            if channel.length_enabled && !length_step_next {
+                channel.length -= 1;
+            }
+        }
+        channel.volume = channel.registers[2] >> 4;
+        channel.envelope_timer = channel.registers[2] & 0x07;
+        if index == 0 {
+            let (period, shift) = self.sweep_settings();
+            self.sweep.shadow = self.channels[0].frequency();
+            self.sweep.timer = if period == 0 { 8 } else { period };
+            self.sweep.enabled = period != 0 || shift != 0;
+            self.sweep.negated = false;
+            // the overflow check runs straight away unless the shift is 0
+            if shift != 0 && self.sweep_frequency() > MAX_FREQUENCY {
+                self.channels[0].enabled = false;
+            }
+        }
+    }
+
+    /// Restart wave ram from the first sample, on the DMG a trigger landing on a read corrupts
+    /// the first bytes of wave ram: reading one of the first four bytes copies it over byte 0,
+    /// reading any later byte copies the four aligned bytes it's in over bytes 0-3
+    /// Read more: https://gbdev.io/pandocs/Audio_details.html#obscure-behavior
+    fn trigger_wave(&mut self, mem: &mut Memory) {
+        if self.wave_trigger_bug && self.channels[WAVE].enabled && self.wave.timer <= 4 {
+            let byte = ((self.wave.position + 1) % 32 / 2) as usize;
+            let (from, len) = match byte {
+                0..=3 => (byte, 1),
+                _ => (byte & !0x03, 4),
+            };
+            for offset in 0..len {
+                let value = mem.io.get(WAVE_RAM_START + from + offset);
+                mem.io.set(WAVE_RAM_START + offset, value);
+            }
+        }
+        self.wave.position = 0;
+        self.wave.timer = self.wave_period() + WAVE_TRIGGER_DELAY;
+    }
+
+    /// T-cycles a pulse channel stays on each step of its waveform
+    fn pulse_period(&self, index: usize) -> u16 {
+        (2048 - self.channels[index].frequency()) * 4
+    }
+
+    /// Step the pulse channels through their waveforms while they're playing
+    fn clock_pulses(&mut self, t_cycles: usize) {
+        for index in 0..2 {
+            if !self.channels[index].enabled {
+                continue;
+            }
+            let mut t_cycles = t_cycles;
+            while t_cycles >= self.pulses[index].timer as usize {
+                t_cycles -= self.pulses[index].timer as usize;
+                self.pulses[index].position = (self.pulses[index].position + 1) % 8;
+                self.pulses[index].timer = self.pulse_period(index);
+            }
+            self.pulses[index].timer -= t_cycles as u16;
+        }
+    }
+
+    /// T-cycles channel 3 plays each sample for
+    fn wave_period(&self) -> u16 {
+        (2048 - self.channels[WAVE].frequency()) * 2
+    }
+
+    /// Step channel 3 through wave ram while it's playing
+    fn clock_wave(&mut self, t_cycles: usize) {
+        if !self.channels[WAVE].enabled {
+            return;
+        }
+        let mut t_cycles = t_cycles;
+        while t_cycles >= self.wave.timer as usize {
+            t_cycles -= self.wave.timer as usize;
+            self.wave.position = (self.wave.position + 1) % 32;
+            self.wave.timer = self.wave_period();
+        }
+        self.wave.timer -= t_cycles as u16;
+    }
+
+    /// A channel's digital output, 0-15, wave ram is read from `mem` for channel 3
+    fn sample(&self, index: usize, mem: &Memory) -> u8 {
+        let channel = &self.channels[index];
+        if !channel.enabled {
+            return 0;
+        }
+        match index {
+            0 | 1 => {
+                let duty = DUTY[(channel.registers[1] >> 6) as usize];
+                (duty >> self.pulses[index].position & 1) * channel.volume
+            }
+            WAVE => {
+                let shift = WAVE_SHIFT[(channel.registers[2] >> 5 & 0x03) as usize];
+                let byte = mem.io.get(WAVE_RAM_START + self.wave.position as usize / 2);
+                let nibble = match self.wave.position % 2 {
+                    0 => byte >> 4,
+                    _ => byte & 0x0f,
+                };
+                nibble >> shift
+            }
+            _ => (self.noise.lfsr & 1) as u8 * channel.volume,
+        }
+    }
+
+    /// What every channel is doing
+    pub fn state(&self, mem: &Memory) -> ApuState {
+        let mut state = ApuState {
+            power: self.power,
+            ..ApuState::default()
+        };
+        for (index, channel) in self.channels.iter().enumerate() {
+            let period = (2048 - channel.frequency()) as f32;
+            let (frequency, volume, duty) = match index {
+                0 | 1 => (131072.0 / period, channel.volume, Some(channel.registers[1] >> 6)),
+                WAVE => {
+                    let shift = WAVE_SHIFT[(channel.registers[2] >> 5 & 0x03) as usize];
+                    (65536.0 / period, 15 >> shift, None)
+                }
+                _ => (4194304.0 / self.noise_period() as f32, channel.volume, None),
+            };
+            state.channels[index] = ChannelState {
+                enabled: channel.enabled,
+                dac: channel.dac,
+                frequency,
+                volume,
+                duty,
+                length: channel.length,
+                length_enabled: channel.length_enabled,
+                sample: self.sample(index, mem),
+            };
+        }
+        state
+    }
+
+    /// T-cycles between clocks of channel 4's LFSR, from NR43
+    fn noise_period(&self) -> u32 {
+        let nr43 = self.channels[NOISE].registers[3];
+        NOISE_DIVISORS[(nr43 & 0x07) as usize] << (nr43 >> 4)
+    }
+
+    /// Clock the LFSR while channel 4 is playing, with a shift of 14 or 15 it never is
+    fn clock_noise(&mut self, t_cycles: usize) {
+        let nr43 = self.channels[NOISE].registers[3];
+        if !self.channels[NOISE].enabled || nr43 >> 4 >= 14 {
+            return;
+        }
+        let short = nr43 & 0x08 != 0;
+        let mut t_cycles = t_cycles as u32;
+        while t_cycles >= self.noise.timer {
+            t_cycles -= self.noise.timer;
+            self.noise.clock_lfsr(short);
+            self.noise.timer = self.noise_period();
+        }
+        self.noise.timer -= t_cycles;
+    }
+
+    /// NR10's period and shift
+    fn sweep_settings(&self) -> (u8, u8) {
+        let nr10 = self.channels[0].registers[0];
+        ((nr10 >> 4) & 0x07, nr10 & 0x07)
+    }
+
+    /// The next frequency the sweep would produce from the shadow frequency
+    /// Read more: https://gbdev.io/pandocs/Audio_details.html#pulse-channel-with-sweep-ch1
+    fn sweep_frequency(&mut self) -> u16 {
+        let (_, shift) = self.sweep_settings();
+        let delta = self.sweep.shadow >> shift;
+        let negate = self.channels[0].registers[0] & 0x08 != 0;
+        self.sweep.negated |= negate;
+        match negate {
+            true => self.sweep.shadow.wrapping_sub(delta),
+            false => self.sweep.shadow + delta,
+        }
+    }
+
+    /// DIV-APU fell, 512 times a second unless DIV is written. Steps the frame sequencer:
+    /// length on even steps, the sweep on 2 and 6 and the envelopes on 7.
+    /// Read more: https://gbdev.io/pandocs/Audio_details.html#div-apu
+    pub fn div_apu(&mut self, mem: &mut Memory) {
+        if !self.power {
+            return;
+        }
+        if self.step % 2 == 0 {
+            self.channels.iter_mut().for_each(Channel::clock_length);
+        }
+        if self.step == 2 || self.step == 6 {
+            self.clock_sweep(mem);
+        }
+        if self.step == 7 {
+            for index in [0, 1, 3] {
+                self.channels[index].clock_envelope();
+            }
+        }
+        self.step = (self.step + 1) % 8;
+    }
+
+    fn clock_sweep(&mut self, mem: &mut Memory) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer > 0 {
+            return;
+        }
+        let (period, shift) = self.sweep_settings();
+        self.sweep.timer = if period == 0 { 8 } else { period };
+        if !self.sweep.enabled || period == 0 {
+            return;
+        }
+        let frequency = self.sweep_frequency();
+        if frequency > MAX_FREQUENCY {
+            self.channels[0].enabled = false;
+        } else if shift != 0 {
+            self.sweep.shadow = frequency;
+            let [high, low] = frequency.to_be_bytes();
+            let channel = &mut self.channels[0];
+            channel.registers[3] = low;
+            channel.registers[4] = (channel.registers[4] & !0x07) | high;
+            mem.io.set(NR13, low);
+            mem.io.set(NR14, (mem.io.get(NR14) & !0x07) | high);
+            // and once more with the new frequency, without writing it back
+            if self.sweep_frequency() > MAX_FREQUENCY {
+                self.channels[0].enabled = false;
+            }
+        }
+    }
 }
 
-impl Default for Apu {
-    fn default() -> Self {
-        Self {}
+mod tests {
+    use super::*;
+    use crate::{
+        cartridge::Cartridge,
+        memory::registers::{
+            NR11, NR12, NR21, NR22, NR23, NR24, NR31, NR32, NR33, NR34, NR42, NR43, NR44,
+        },
+    };
+
+    fn setup() -> (Apu, Memory) {
+        let mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut apu = Apu::default();
+        apu.reset(&mem);
+        (apu, mem)
+    }
+
+    /// Write like the cpu does and let the apu see it
+    fn write(apu: &mut Apu, mem: &mut Memory, addr: usize, value: u8) {
+        mem.write(addr, value);
+        for event in mem.io.drain_events().collect::<Vec<_>>() {
+            if let crate::io::IoEvent::Apu(addr, value) = event {
+                apu.io_event(addr, value, mem);
+            }
+        }
+        apu.process(0, mem);
+    }
+
+    /// Run the frame sequencer up to and including `steps` more steps
+    fn run_steps(apu: &mut Apu, mem: &mut Memory, steps: usize) {
+        for _ in 0..steps {
+            apu.div_apu(mem);
+        }
+        apu.process(0, mem);
+    }
+
+    #[test]
+    fn test_post_boot() {
+        let (apu, mem) = setup();
+        assert!(apu.power);
+        assert!(apu.channels[0].enabled && apu.channels[0].dac);
+        assert!(!apu.channels[1].enabled);
+        assert_eq!(mem.io.read(NR52), 0xf1);
+    }
+
+    #[test]
+    fn test_trigger() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR22, 0xf0);
+        write(&mut apu, &mut mem, NR24, 0x80);
+        assert_eq!(mem.read(NR52) & 0x02, 0x02);
+        assert_eq!(apu.channels[1].volume, 0x0f);
+        // the length ran out before, triggering reloads it
+        assert_eq!(apu.channels[1].length, 64);
+        // turning the DAC off disables the channel, triggering doesn't bring it back
+        write(&mut apu, &mut mem, NR22, 0x07);
+        assert_eq!(mem.read(NR52) & 0x02, 0x00);
+        write(&mut apu, &mut mem, NR24, 0x80);
+        assert_eq!(mem.read(NR52) & 0x02, 0x00);
+        // turning the DAC back on doesn't either
+        write(&mut apu, &mut mem, NR22, 0x10);
+        assert_eq!(mem.read(NR52) & 0x02, 0x00);
+        write(&mut apu, &mut mem, NR24, 0x80);
+        assert_eq!(mem.read(NR52) & 0x02, 0x02);
+    }
+
+    #[test]
+    fn test_trigger_with_dac_off_reloads_length() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR30, 0x00);
+        write(&mut apu, &mut mem, NR31, 0xff);
+        assert_eq!(apu.channels[WAVE].length, 1);
+        run_steps(&mut apu, &mut mem, 1);
+        write(&mut apu, &mut mem, NR34, 0xc0);
+        assert!(!apu.channels[WAVE].enabled);
+        // the length was 0 so the trigger reloaded it, then enabling it on an odd step
+        // clocked it once
+        assert_eq!(apu.channels[WAVE].length, 255);
+    }
+
+    #[test]
+    fn test_length() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR22, 0xf0);
+        write(&mut apu, &mut mem, NR21, 0x3e);
+        write(&mut apu, &mut mem, NR24, 0xc0);
+        assert_eq!(apu.channels[1].length, 2);
+        run_steps(&mut apu, &mut mem, 1);
+        assert_eq!(mem.read(NR52) & 0x02, 0x02);
+        // step 1 doesn't clock the length, step 2 does
+        run_steps(&mut apu, &mut mem, 2);
+        assert_eq!(mem.read(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn test_length_extra_clock() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR22, 0xf0);
+        write(&mut apu, &mut mem, NR21, 0x3f);
+        write(&mut apu, &mut mem, NR24, 0x80);
+        run_steps(&mut apu, &mut mem, 1);
+        // the next step won't clock the length, enabling it does instead
+        write(&mut apu, &mut mem, NR24, 0x40);
+        assert_eq!(apu.channels[1].length, 0);
+        assert_eq!(mem.read(NR52) & 0x02, 0x00);
+        // unless it's also triggered, then it's reloaded and clocked
+        write(&mut apu, &mut mem, NR24, 0x00);
+        write(&mut apu, &mut mem, NR24, 0xc0);
+        assert_eq!(apu.channels[1].length, 63);
+        assert_eq!(mem.read(NR52) & 0x02, 0x02);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let (mut apu, mut mem) = setup();
+        // start at 2, decrease every step 7
+        write(&mut apu, &mut mem, NR22, 0x21);
+        write(&mut apu, &mut mem, NR24, 0x80);
+        run_steps(&mut apu, &mut mem, 8);
+        assert_eq!(apu.channels[1].volume, 1);
+        run_steps(&mut apu, &mut mem, 16);
+        assert_eq!(apu.channels[1].volume, 0);
+        // the DAC is still on, so the channel is too
+        assert!(apu.channels[1].enabled);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR12, 0xf0);
+        // period 1, add shift 1
+        write(&mut apu, &mut mem, NR10, 0x11);
+        write(&mut apu, &mut mem, NR13, 0x00);
+        write(&mut apu, &mut mem, NR14, 0x82);
+        assert!(apu.channels[0].enabled);
+        // step 2 sweeps 0x200 to 0x300
+        run_steps(&mut apu, &mut mem, 3);
+        assert_eq!((mem.io.get(NR13), mem.io.get(NR14) & 0x07), (0x00, 0x03));
+        // 0x480 then the check against 0x6c0 passes, 0x6c0 overflows on the next check
+        run_steps(&mut apu, &mut mem, 4);
+        assert_eq!((mem.io.get(NR13), mem.io.get(NR14) & 0x07), (0x80, 0x04));
+        assert!(apu.channels[0].enabled);
+        run_steps(&mut apu, &mut mem, 4);
+        assert!(!apu.channels[0].enabled);
+        // a trigger that would overflow straight away never enables it
+        write(&mut apu, &mut mem, NR13, 0xff);
+        write(&mut apu, &mut mem, NR14, 0x87);
+        assert!(!apu.channels[0].enabled);
+    }
+
+    #[test]
+    fn test_power() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR52, 0x00);
+        assert_eq!(mem.read(NR52), 0x70);
+        assert_eq!(mem.io.get(NR12), 0x00);
+        assert_eq!(mem.io.get(NR51), 0x00);
+        // registers are read-only while off, wave ram isn't
+        write(&mut apu, &mut mem, NR11, 0x80);
+        write(&mut apu, &mut mem, WAVE_RAM_START, 0x12);
+        assert_eq!(mem.io.get(NR11), 0x00);
+        assert_eq!(mem.read(WAVE_RAM_START), 0x12);
+        write(&mut apu, &mut mem, NR52, 0x80);
+        write(&mut apu, &mut mem, NR11, 0x80);
+        assert_eq!(mem.io.get(NR11), 0x80);
+        // the channel bits can't be written
+        write(&mut apu, &mut mem, NR52, 0x8f);
+        assert_eq!(mem.read(NR52), 0xf0);
+    }
+
+    /// Fill wave ram with $00, $11, .. $ff and play it at 8 M-cycles a sample, then run until the
+    /// channel is 2 T-cycles away from reading the sample after `position`
+    fn play_wave(apu: &mut Apu, mem: &mut Memory, position: usize) {
+        for byte in 0..16 {
+            write(apu, mem, WAVE_RAM_START + byte, byte as u8 * 0x11);
+        }
+        write(apu, mem, NR30, 0x80);
+        write(apu, mem, NR33, 0xf0);
+        write(apu, mem, NR34, 0x87);
+        // the first read lands 38 T-cycles after the trigger, then every 32
+        apu.process(10 + 8 * (position - 1) + 7, mem);
+        assert_eq!((apu.wave.position as usize, apu.wave.timer), (position, 2));
+    }
+
+    fn wave_ram(mem: &Memory) -> Vec<u8> {
+        (0..16).map(|byte| mem.io.get(WAVE_RAM_START + byte)).collect()
+    }
+
+    #[test]
+    fn test_wave_trigger_corruption() {
+        let expected = |start: &[u8]| {
+            let mut ram: Vec<u8> = (0..16).map(|byte| byte * 0x11).collect();
+            ram[..start.len()].copy_from_slice(start);
+            ram
+        };
+        // reading one of the first four bytes only overwrites byte 0
+        let (mut apu, mut mem) = setup();
+        apu.wave_trigger_bug = true;
+        play_wave(&mut apu, &mut mem, 3);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), expected(&[0x22]));
+        assert_eq!(apu.wave.position, 0);
+        // later bytes overwrite the first four with the aligned four they're in
+        let (mut apu, mut mem) = setup();
+        apu.wave_trigger_bug = true;
+        play_wave(&mut apu, &mut mem, 9);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), expected(&[0x44, 0x55, 0x66, 0x77]));
+        let (mut apu, mut mem) = setup();
+        apu.wave_trigger_bug = true;
+        play_wave(&mut apu, &mut mem, 30);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), expected(&[0xcc, 0xdd, 0xee, 0xff]));
+    }
+
+    #[test]
+    fn test_wave_trigger_no_corruption() {
+        // away from a read
+        let (mut apu, mut mem) = setup();
+        apu.wave_trigger_bug = true;
+        play_wave(&mut apu, &mut mem, 9);
+        apu.process(1, &mut mem);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), (0..16).map(|byte| byte * 0x11).collect::<Vec<u8>>());
+        // while the channel is off
+        let (mut apu, mut mem) = setup();
+        apu.wave_trigger_bug = true;
+        play_wave(&mut apu, &mut mem, 9);
+        write(&mut apu, &mut mem, NR30, 0x00);
+        write(&mut apu, &mut mem, NR30, 0x80);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), (0..16).map(|byte| byte * 0x11).collect::<Vec<u8>>());
+        // without the quirk, as on the CGB
+        let (mut apu, mut mem) = setup();
+        play_wave(&mut apu, &mut mem, 9);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        assert_eq!(wave_ram(&mem), (0..16).map(|byte| byte * 0x11).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_state() {
+        let (mut apu, mut mem) = setup();
+        // channel 2 at 512Hz with a 50% duty, high for the first step of its waveform
+        write(&mut apu, &mut mem, NR21, 0x80);
+        write(&mut apu, &mut mem, NR22, 0xf0);
+        write(&mut apu, &mut mem, NR23, 0x00);
+        write(&mut apu, &mut mem, NR24, 0x87);
+        let pulse = apu.state(&mem).channels[1];
+        assert!(pulse.enabled && !pulse.length_enabled);
+        assert_eq!((pulse.frequency, pulse.volume, pulse.duty), (512.0, 15, Some(2)));
+        assert_eq!((pulse.length, pulse.sample), (64, 15));
+        // each step lasts 256 M-cycles
+        apu.process(256, &mut mem);
+        assert_eq!(apu.state(&mem).channels[1].sample, 0);
+        // channel 3 at 256Hz playing the high nibble of $a5 at 25%
+        write(&mut apu, &mut mem, WAVE_RAM_START, 0xa5);
+        write(&mut apu, &mut mem, NR30, 0x80);
+        write(&mut apu, &mut mem, NR32, 0x60);
+        write(&mut apu, &mut mem, NR33, 0x00);
+        write(&mut apu, &mut mem, NR34, 0x87);
+        let wave = apu.state(&mem).channels[WAVE];
+        assert_eq!((wave.frequency, wave.volume, wave.duty), (256.0, 3, None));
+        assert_eq!(wave.sample, 0x0a >> 2);
+        // channel 4 clocked every 64 T-cycles, shift 2 and divisor 1
+        write(&mut apu, &mut mem, NR42, 0xf0);
+        write(&mut apu, &mut mem, NR43, 0x21);
+        let noise = apu.state(&mem).channels[NOISE];
+        assert_eq!((noise.frequency, noise.volume), (65536.0, 0));
+        // turning the power off silences everything
+        write(&mut apu, &mut mem, NR52, 0x00);
+        let state = apu.state(&mem);
+        assert!(!state.power);
+        assert!(state.channels.iter().all(|channel| channel.sample == 0));
+    }
+
+    #[test]
+    fn test_lfsr() {
+        let mut noise = Noise::default();
+        let outputs: Vec<u16> = (0..8)
+            .map(|_| {
+                noise.clock_lfsr(false);
+                noise.lfsr & 1
+            })
+            .collect();
+        // starting from 0 ones are shifted in from the top until they reach bit 0
+        assert_eq!(noise.lfsr, 0x7f80);
+        assert_eq!(outputs, [0; 8]);
+        for (short, period) in [(false, 32767), (true, 127)] {
+            let mut noise = Noise::default();
+            // in 7-bit mode the bits above bit 6 don't feed back so only the low 7 repeat
+            let start = noise.lfsr;
+            let steps = (1..=32767)
+                .find(|_| {
+                    noise.clock_lfsr(short);
+                    noise.lfsr & 0x7f == start & 0x7f && (short || noise.lfsr == start)
+                })
+                .unwrap();
+            assert_eq!(steps, period);
+        }
+    }
+
+    #[test]
+    fn test_noise() {
+        let (mut apu, mut mem) = setup();
+        // divisor code 0 with shift 1 clocks the LFSR every 16 T-cycles
+        write(&mut apu, &mut mem, NR42, 0xf0);
+        write(&mut apu, &mut mem, NR43, 0x10);
+        write(&mut apu, &mut mem, NR44, 0x80);
+        assert_eq!(apu.state(&mem).channels[NOISE].frequency, 262144.0);
+        assert_eq!(apu.noise.lfsr, 0);
+        apu.process(3, &mut mem);
+        assert_eq!(apu.noise.lfsr, 0);
+        apu.process(1, &mut mem);
+        assert_eq!(apu.noise.lfsr, 0x4000);
+        // 15 clocks bring the first one down to bit 0
+        apu.process(14 * 4, &mut mem);
+        assert_eq!(apu.state(&mem).channels[NOISE].sample, 15);
+        // a shift of 14 stops the LFSR, triggering resets it
+        write(&mut apu, &mut mem, NR43, 0xe0);
+        write(&mut apu, &mut mem, NR44, 0x80);
+        apu.process(1 << 16, &mut mem);
+        assert_eq!(apu.noise.lfsr, 0);
+        // 7-bit mode copies the new bit into bit 7 as well
+        write(&mut apu, &mut mem, NR43, 0x08);
+        write(&mut apu, &mut mem, NR44, 0x80);
+        apu.process(2, &mut mem);
+        assert_eq!(apu.noise.lfsr, 0x4040);
+    }
+
+    #[test]
+    fn test_sweep_negate_disable() {
+        let (mut apu, mut mem) = setup();
+        write(&mut apu, &mut mem, NR12, 0xf0);
+        // period 1, negate shift 0, nothing is calculated on trigger
+        write(&mut apu, &mut mem, NR10, 0x18);
+        write(&mut apu, &mut mem, NR13, 0x00);
+        write(&mut apu, &mut mem, NR14, 0x82);
+        write(&mut apu, &mut mem, NR10, 0x10);
+        assert!(apu.channels[0].enabled);
+        // with a shift the trigger calculates in negate mode, leaving it disables the channel
+        write(&mut apu, &mut mem, NR10, 0x19);
+        write(&mut apu, &mut mem, NR14, 0x82);
+        write(&mut apu, &mut mem, NR10, 0x1a);
+        assert!(apu.channels[0].enabled);
+        write(&mut apu, &mut mem, NR10, 0x11);
+        assert!(!apu.channels[0].enabled);
+        // a trigger forgets the calculation
+        write(&mut apu, &mut mem, NR10, 0x10);
+        write(&mut apu, &mut mem, NR14, 0x82);
+        write(&mut apu, &mut mem, NR10, 0x18);
+        write(&mut apu, &mut mem, NR10, 0x10);
+        assert!(apu.channels[0].enabled);
+    }
+
+    #[test]
+    fn test_mix() {
+        let (mut apu, mut mem) = setup();
+        // channel 2 at full volume on the high step of its waveform, channel 1 off
+        write(&mut apu, &mut mem, NR12, 0x00);
+        write(&mut apu, &mut mem, NR21, 0x80);
+        write(&mut apu, &mut mem, NR22, 0xf0);
+        write(&mut apu, &mut mem, NR23, 0x00);
+        write(&mut apu, &mut mem, NR24, 0x87);
+        write(&mut apu, &mut mem, NR50, 0x77);
+        write(&mut apu, &mut mem, NR51, 0x22);
+        assert_eq!(apu.mix(&mem), [-0.25, -0.25]);
+        // each NR51 bit only feeds its own side
+        write(&mut apu, &mut mem, NR51, 0x20);
+        assert_eq!(apu.mix(&mem), [-0.25, 0.0]);
+        write(&mut apu, &mut mem, NR51, 0x02);
+        assert_eq!(apu.mix(&mem), [0.0, -0.25]);
+        // the master volumes go from 1/8 to 8/8, VIN makes no difference
+        write(&mut apu, &mut mem, NR51, 0x22);
+        write(&mut apu, &mut mem, NR50, 0x88);
+        assert_eq!(apu.mix(&mem), [-0.25 / 8.0, -0.25 / 8.0]);
+        // a sample per M-cycle
+        apu.output.clear();
+        apu.process(3, &mut mem);
+        assert_eq!(apu.output.len(), 6);
+        assert_eq!(apu.output[4..], [-0.25 / 8.0, -0.25 / 8.0]);
     }
 }
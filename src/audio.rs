@@ -0,0 +1,310 @@
+use std::{
+    collections::VecDeque,
+    f64::consts::PI,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Zero crossings of the sinc kernel on each side of an output frame
+const SINC_ZEROS: f64 = 16.0;
+
+/// Entries in the sinc kernel table per frame of distance
+const KERNEL_RESOLUTION: f64 = 64.0;
+
+/// Output settings, a smaller buffer lowers latency but underruns more easily
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    /// Frames the audio device asks for at once
+    pub buffer_size: usize,
+    pub resampling: Resampling,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            buffer_size: 512,
+            resampling: Resampling::default(),
+        }
+    }
+}
+
+impl AudioConfig {
+    /// How long a full buffer takes to play
+    pub fn latency(&self) -> Duration {
+        Duration::from_secs_f64(self.buffer_size as f64 / self.sample_rate as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    samples: VecDeque<f32>,
+    underruns: u64,
+}
+
+/// Interleaved stereo samples waiting for the audio device, shared between the emulator
+/// (which pushes) and the device callback (which drains). At most two buffers are kept,
+/// anything older is dropped so latency can't build up.
+#[derive(Debug, Clone, Default)]
+pub struct AudioQueue {
+    queue: Arc<Mutex<Queue>>,
+    capacity: usize,
+}
+
+impl AudioQueue {
+    pub fn new(config: &AudioConfig) -> Self {
+        Self {
+            queue: Arc::default(),
+            capacity: config.buffer_size * 2 * 2,
+        }
+    }
+
+    pub fn push(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.samples.extend(samples);
+        let excess = queue.samples.len().saturating_sub(self.capacity);
+        queue.samples.drain(..excess);
+    }
+
+    /// Fill `out` from the queue, padding with silence and counting an underrun if it runs dry
+    pub fn fill(&self, out: &mut [f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        let available = queue.samples.len().min(out.len());
+        for (sample, queued) in out.iter_mut().zip(queue.samples.drain(..available)) {
+            *sample = queued;
+        }
+        if available < out.len() {
+            out[available..].fill(0.0);
+            queue.underruns += 1;
+        }
+    }
+
+    /// Times the device asked for more samples than were queued
+    pub fn underruns(&self) -> u64 {
+        self.queue.lock().unwrap().underruns
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().samples.len()
+    }
+}
+
+/// How the APU's output is brought down to the device's sample rate. Either way blocks of the
+/// APU's frames are averaged first, which leaves little above the device's range to alias.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Resampling {
+    /// Straight lines between the averaged frames, cheap but lets some aliasing through
+    Linear,
+    /// A Blackman windowed sinc low-pass just under the device's Nyquist frequency
+    #[default]
+    Sinc,
+}
+
+impl std::str::FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "sinc" => Ok(Self::Sinc),
+            _ => Err(format!("Unknown resampling {s}, expected linear or sinc")),
+        }
+    }
+}
+
+/// Converts the APU's interleaved stereo output to the device's sample rate without the square
+/// waves' harmonics folding back down as aliasing
+/// Read more: https://ccrma.stanford.edu/~jos/resample/
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    /// Input frames averaged into each intermediate frame
+    factor: usize,
+    /// The block being averaged and how many frames are in it so far
+    sum: [f32; 2],
+    count: usize,
+    /// Averaged frames still in reach of the next output frame, oldest first
+    history: VecDeque<[f32; 2]>,
+    /// Where the next output frame lands in `history`
+    position: f64,
+    /// Intermediate frames per output frame
+    step: f64,
+    /// How many intermediate frames either side of an output frame contribute to it
+    reach: f64,
+    /// The windowed sinc from 0 out to `reach`, empty when interpolating linearly
+    kernel: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, resampling: Resampling) -> Self {
+        // averaging down to at least twice the output rate keeps the kernel short
+        let factor = (from / (2 * to)).max(1) as usize;
+        let step = from as f64 / factor as f64 / to as f64;
+        let (reach, kernel) = match resampling {
+            Resampling::Linear => (1.0, vec![]),
+            Resampling::Sinc => {
+                // in cycles per intermediate frame
+                let cutoff = 0.45 / step;
+                let reach = SINC_ZEROS / (2.0 * cutoff);
+                let kernel = (0..=(reach * KERNEL_RESOLUTION) as usize)
+                    .map(|i| {
+                        let distance = i as f64 / KERNEL_RESOLUTION;
+                        let x = PI * 2.0 * cutoff * distance;
+                        let sinc = if i == 0 { 1.0 } else { x.sin() / x };
+                        let w = PI * distance / reach;
+                        let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                        (2.0 * cutoff * sinc * window) as f32
+                    })
+                    .collect();
+                (reach, kernel)
+            }
+        };
+        // start with silence behind the first frame
+        let pad = reach.ceil() as usize;
+        let mut history = VecDeque::with_capacity(2 * pad + 2);
+        history.extend(std::iter::repeat_n([0.0; 2], pad));
+        Self {
+            factor,
+            sum: [0.0; 2],
+            count: 0,
+            history,
+            position: pad as f64,
+            step,
+            reach,
+            kernel,
+        }
+    }
+
+    /// Resample `input` onto the end of `out`. Output frames are produced once every input frame
+    /// in their reach has arrived, so the output lags by `reach` intermediate frames.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        for frame in input.chunks_exact(2) {
+            self.sum[0] += frame[0];
+            self.sum[1] += frame[1];
+            self.count += 1;
+            if self.count < self.factor {
+                continue;
+            }
+            let n = self.factor as f32;
+            self.history.push_back([self.sum[0] / n, self.sum[1] / n]);
+            self.sum = [0.0; 2];
+            self.count = 0;
+            while self.position + self.reach <= (self.history.len() - 1) as f64 {
+                out.extend(self.interpolate());
+                self.position += self.step;
+            }
+            while self.position - self.reach > 1.0 {
+                self.history.pop_front();
+                self.position -= 1.0;
+            }
+        }
+    }
+
+    /// The output frame at `position`
+    fn interpolate(&self) -> [f32; 2] {
+        if self.kernel.is_empty() {
+            let i = self.position as usize;
+            let t = (self.position - i as f64) as f32;
+            let (a, b) = (self.history[i], self.history[i + 1]);
+            return [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+        }
+        let first = (self.position - self.reach).ceil() as usize;
+        let last = (self.position + self.reach) as usize;
+        let mut out = [0.0; 2];
+        for i in first..=last {
+            let distance = (i as f64 - self.position).abs();
+            let tap = (distance * KERNEL_RESOLUTION) as usize;
+            let weight = self.kernel[tap.min(self.kernel.len() - 1)];
+            out[0] += self.history[i][0] * weight;
+            out[1] += self.history[i][1] * weight;
+        }
+        out
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl sdl3::audio::AudioCallback<f32> for AudioQueue {
+    fn callback(&mut self, out: &mut [f32]) {
+        self.fill(out);
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency() {
+        let config = AudioConfig {
+            sample_rate: 48000,
+            buffer_size: 480,
+            ..AudioConfig::default()
+        };
+        assert_eq!(config.latency(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_underruns() {
+        let queue = AudioQueue::new(&AudioConfig::default());
+        queue.push(&[0.5, -0.5]);
+        let mut out = [1.0; 4];
+        queue.fill(&mut out);
+        assert_eq!(out, [0.5, -0.5, 0.0, 0.0]);
+        assert_eq!(queue.underruns(), 1);
+        queue.push(&[0.25; 4]);
+        queue.fill(&mut out);
+        assert_eq!(queue.underruns(), 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let config = AudioConfig {
+            sample_rate: 48000,
+            buffer_size: 2,
+            ..AudioConfig::default()
+        };
+        let queue = AudioQueue::new(&config);
+        queue.push(&[0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9]);
+        assert_eq!(queue.len(), 8);
+        let mut out = [0.0; 2];
+        queue.fill(&mut out);
+        // the oldest samples were dropped
+        assert_eq!(out, [0.2, 0.3]);
+    }
+
+    /// A second of a `frequency` Hz sine on both sides at the APU's rate through `resampling`,
+    /// returns the loudest output once the resampler has settled
+    fn resample_sine(frequency: f64, resampling: Resampling) -> f32 {
+        let from = 1 << 20;
+        let mut resampler = Resampler::new(from, 48000, resampling);
+        let input: Vec<f32> = (0..from)
+            .flat_map(|i| {
+                let sample = (2.0 * PI * frequency * i as f64 / from as f64).sin() as f32;
+                [sample, sample]
+            })
+            .collect();
+        let mut out = vec![];
+        resampler.process(&input, &mut out);
+        // everything but the lag comes out
+        assert!((47950..=48000).contains(&(out.len() / 2)));
+        out[2000..].iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn test_resampler() {
+        for resampling in [Resampling::Linear, Resampling::Sinc] {
+            let peak = resample_sine(1000.0, resampling);
+            assert!((0.98..1.02).contains(&peak), "{resampling:?} {peak}");
+        }
+        // 35kHz is above what 48kHz can hold, the sinc filters it out instead of aliasing it
+        // down to 13kHz
+        assert!(resample_sine(35000.0, Resampling::Sinc) < 0.01);
+        assert!(resample_sine(35000.0, Resampling::Linear) > 0.1);
+    }
+
+    #[test]
+    fn test_parse_resampling() {
+        assert_eq!("linear".parse(), Ok(Resampling::Linear));
+        assert_eq!("sinc".parse(), Ok(Resampling::Sinc));
+        assert!("cubic".parse::<Resampling>().is_err());
+    }
+}
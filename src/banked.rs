@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use crate::memory::regions::*;
+
+/// Size of a ROM bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// An address together with the bank mapped where it points, so code and data in switchable
+/// ROM, VRAM, external RAM and WRAM banks can't be mistaken for each other.
+/// Addresses outside of a switchable region always have bank 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BankedAddr {
+    pub bank: u16,
+    pub addr: u16,
+}
+
+impl BankedAddr {
+    /// `addr` as seen while `bank` is mapped into its region. MBCs map ROM bank 0 to 1 and
+    /// WRAM bank 0 reads as bank 1, so those are bumped.
+    pub fn new(bank: u16, addr: u16) -> Self {
+        let bank = match addr as usize {
+            ROM_BANK_1_START..=ROM_BANK_1_END | WRAM_2_START..=WRAM_2_END => bank.max(1),
+            VRAM_START..=EXTERNAL_RAM_END => bank,
+            _ => 0,
+        };
+        Self { bank, addr }
+    }
+
+    /// Offset into the ROM file, `None` for anything that isn't ROM
+    pub fn rom_offset(self) -> Option<usize> {
+        match self.addr as usize {
+            addr @ ROM_BANK_0_START..=ROM_BANK_0_END => Some(addr),
+            addr @ ROM_BANK_1_START..=ROM_BANK_1_END => {
+                Some(self.bank as usize * ROM_BANK_SIZE + addr - ROM_BANK_1_START)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `BB:AAAA` in hex, the way .sym files and most debuggers write banked addresses
+impl std::fmt::Display for BankedAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02x}:{:04x}", self.bank, self.addr)
+    }
+}
+
+impl FromStr for BankedAddr {
+    type Err = String;
+
+    /// `BB:AAAA`, or a bare `AAAA` in the bank a fixed region always has
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |value: &str| {
+            u16::from_str_radix(value.trim_start_matches("0x").trim_start_matches('$'), 16)
+                .map_err(|_| format!("invalid address {s}, expected BB:AAAA"))
+        };
+        match s.split_once(':') {
+            Some((bank, addr)) => Ok(Self::new(hex(bank)?, hex(addr)?)),
+            None => Ok(Self::new(0, hex(s)?)),
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banked_addr() {
+        assert_eq!(BankedAddr::new(5, 0x0150), BankedAddr { bank: 0, addr: 0x0150 });
+        assert_eq!(BankedAddr::new(0, 0x4000), BankedAddr { bank: 1, addr: 0x4000 });
+        assert_eq!(BankedAddr::new(0, 0xa000), BankedAddr { bank: 0, addr: 0xa000 });
+        assert_eq!(BankedAddr::new(3, 0xff80), BankedAddr { bank: 0, addr: 0xff80 });
+        assert_eq!(BankedAddr::new(3, 0x4001).rom_offset(), Some(0xc001));
+        assert_eq!(BankedAddr::new(0, 0x3fff).rom_offset(), Some(0x3fff));
+        assert_eq!(BankedAddr::new(1, 0xc000).rom_offset(), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let addr = BankedAddr::new(2, 0x4abc);
+        assert_eq!(addr.to_string(), "02:4abc");
+        assert_eq!("02:4ABC".parse(), Ok(addr));
+        assert_eq!("$0150".parse(), Ok(BankedAddr::new(0, 0x0150)));
+        assert!("zz:0000".parse::<BankedAddr>().is_err());
+    }
+}
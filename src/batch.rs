@@ -0,0 +1,99 @@
+//! Many headless systems stepped at once across the host's cores, for training agents and
+//! fuzzing where aggregate frames per second matter more than any one instance
+use std::{num::NonZeroUsize, thread};
+
+use crate::{errors::BatchError, state::SaveState, system::System};
+
+/// What an instance starts from
+pub enum Start {
+    /// Power on with this ROM
+    Rom(Vec<u8>),
+    /// Resume a ROM from a state saved with it
+    State(Vec<u8>, SaveState),
+}
+
+impl Start {
+    fn system(self, index: usize) -> Result<System, BatchError> {
+        match self {
+            Start::Rom(rom) => System::headless(rom).map_err(|e| BatchError::System(index, e)),
+            Start::State(rom, state) => {
+                let mut system = System::headless(rom).map_err(|e| BatchError::System(index, e))?;
+                system.load_state(&state).map_err(|e| BatchError::State(index, e))?;
+                Ok(system)
+            }
+        }
+    }
+}
+
+/// Run an instance for each of `starts` for `steps` frames, split evenly over one thread per
+/// core. `per_instance` is called with the instance's index after every frame, which is where
+/// to `observe` it and `set_buttons` for the next one. Returns the instances in the order of
+/// `starts` once all of them are done.
+pub fn run_parallel<F>(
+    starts: Vec<Start>,
+    steps: usize,
+    per_instance: F,
+) -> Result<Vec<System>, BatchError>
+where
+    F: Fn(usize, &mut System) + Sync,
+{
+    let mut systems = starts
+        .into_iter()
+        .enumerate()
+        .map(|(index, start)| start.system(index))
+        .collect::<Result<Vec<_>, _>>()?;
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = systems.len().div_ceil(threads).max(1);
+    let per_instance = &per_instance;
+    thread::scope(|scope| {
+        for (i, chunk) in systems.chunks_mut(chunk_size).enumerate() {
+            scope.spawn(move || {
+                for (j, system) in chunk.iter_mut().enumerate() {
+                    let index = i * chunk_size + j;
+                    for _ in 0..steps {
+                        system.step_frame();
+                        per_instance(index, system);
+                    }
+                }
+            });
+        }
+    });
+    Ok(systems)
+}
+
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A rom that spins over the header forever
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // JP $0150, JP $0150
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        rom[0x150..0x153].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        rom
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        let mut system = System::headless(rom()).unwrap();
+        for _ in 0..5 {
+            system.step_frame();
+        }
+        let mut starts: Vec<_> = (0..9).map(|_| Start::Rom(rom())).collect();
+        starts.push(Start::State(rom(), system.save_state()));
+        let calls: Vec<_> = (0..10).map(|_| AtomicUsize::new(0)).collect();
+        let systems = run_parallel(starts, 3, |index, system| {
+            calls[index].fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        assert!(calls.iter().all(|calls| calls.load(Ordering::Relaxed) == 3));
+        assert_eq!(systems[0].frame, systems[8].frame);
+        assert_eq!(systems[9].frame, systems[0].frame + 5);
+        // a state saved with another rom is rejected before anything runs
+        let mut other = rom();
+        other[0x14e] = 0x12;
+        let starts = vec![Start::Rom(rom()), Start::State(other, system.save_state())];
+        assert!(matches!(run_parallel(starts, 1, |_, _| {}), Err(BatchError::State(1, _))));
+    }
+}
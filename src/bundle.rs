@@ -0,0 +1,83 @@
+use crate::hash::crc32;
+
+/// Instructions kept for the trace in a bug bundle
+pub const BUNDLE_TRACE_LENGTH: usize = 10_000;
+
+// 1980-01-01 00:00, the earliest date a zip can hold, so bundles don't depend on host time
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = 0x0021;
+
+/// Pack files into a zip archive without compressing them
+/// Read more: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+pub fn zip(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, data) in files {
+        let offset = archive.len() as u32;
+        let crc = crc32(data);
+        // local file header, then the data
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        file_header(&mut archive, name, crc, data.len() as u32);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(data);
+        // central directory entry, written after all of the files
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // version made by
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        file_header(&mut directory, name, crc, data.len() as u32);
+        // comment length, disk number, internal and external attributes
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    // end of central directory
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    archive
+}
+
+/// The fields local headers and central directory entries share:
+/// version needed | flags | method | time | date | crc | sizes | name length | extra length
+fn file_header(out: &mut Vec<u8>, name: &str, crc: u32, size: u32) {
+    out.extend_from_slice(&20u16.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    // stored
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&DOS_TIME.to_le_bytes());
+    out.extend_from_slice(&DOS_DATE.to_le_bytes());
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zip() {
+        let archive = zip(&[("a.txt", b"hello".to_vec()), ("b.bin", vec![1, 2, 3])]);
+        assert_eq!(&archive[..4], &[0x50, 0x4b, 0x03, 0x04]);
+        assert_eq!(&archive[30..35], b"a.txt");
+        assert_eq!(&archive[35..40], b"hello");
+        assert_eq!(&archive[40..44], &[0x50, 0x4b, 0x03, 0x04]);
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(&end[..4], &[0x50, 0x4b, 0x05, 0x06]);
+        // two entries, the central directory starts after both local files
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let offset = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(offset, 40 + 30 + 5 + 3);
+        assert_eq!(&archive[offset..offset + 4], &[0x50, 0x4b, 0x01, 0x02]);
+        // the second entry points back at its local header
+        let second = offset + 46 + 5;
+        assert_eq!(u32::from_le_bytes(archive[second + 42..second + 46].try_into().unwrap()), 40);
+    }
+}
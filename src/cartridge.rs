@@ -1,16 +1,23 @@
 use std::alloc::System;
 
-use crate::{errors::CartridgeError, memory::Memory};
+use crate::{errors::CartridgeError, hash::RomHashes, memory::Memory};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cartridge {
+    #[cfg_attr(feature = "serde", serde(with = "crate::rle"))]
     pub rom: Vec<u8>,
     pub cartridge_type: CartridgeType,
     pub logo: Vec<u8>,
     pub title: String,
     pub cgb_flag: bool,
+    /// The game uses Super Game Boy features
+    pub sgb_flag: bool,
     pub rom_size: usize,
     pub ram_size: RamSize,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub hashes: RomHashes,
 }
 
 pub const ENTRY_POINT_START: usize = 0x0100;
@@ -20,9 +27,14 @@ pub const LOGO_END: usize = 0x0133;
 pub const TITLE_START: usize = 0x0134;
 pub const TITLE_END: usize = 0x0143;
 pub const CGB_FLAG: usize = 0x0143; // huh?
+pub const SGB_FLAG: usize = 0x0146;
+pub const OLD_LICENSEE: usize = 0x014b;
 pub const CARTRIDGE_TYPE: usize = 0x0147;
 pub const ROM_SIZE: usize = 0x0148;
 pub const RAM_SIZE: usize = 0x0149;
+pub const HEADER_CHECKSUM: usize = 0x014d;
+pub const GLOBAL_CHECKSUM_START: usize = 0x014e;
+pub const GLOBAL_CHECKSUM_END: usize = 0x014f;
 
 impl Cartridge {
     pub fn new(rom: Vec<u8>) -> Result<Self, CartridgeError> {
@@ -33,21 +45,67 @@ impl Cartridge {
             0x80 | 0xc0 => true,
             _ => false,
         };
+        // the SGB ignores the flag unless the old licensee code is $33
+        let sgb_flag = rom[SGB_FLAG] == 0x03 && rom[OLD_LICENSEE] == 0x33;
         let rom_size = get_rom_size(rom[ROM_SIZE])?;
         let ram_size = RamSize::try_from(rom[RAM_SIZE])?;
+        let header_checksum = rom[HEADER_CHECKSUM];
+        // the global checksum is the only big-endian value in the header
+        let global_checksum =
+            u16::from_be_bytes([rom[GLOBAL_CHECKSUM_START], rom[GLOBAL_CHECKSUM_END]]);
+        let hashes = RomHashes::new(&rom);
         Ok(Cartridge {
             rom,
             cartridge_type,
             title,
             logo: logo.to_vec(),
             cgb_flag,
+            sgb_flag,
             ram_size,
             rom_size,
+            header_checksum,
+            global_checksum,
+            hashes,
         })
     }
+
+    /// No ROM at all, only what `Memory::flat` needs: no mapper and 8KiB of ram
+    pub fn blank() -> Self {
+        Cartridge {
+            rom: vec![],
+            cartridge_type: CartridgeType::RomOnly,
+            title: String::new(),
+            logo: vec![],
+            cgb_flag: false,
+            sgb_flag: false,
+            ram_size: RamSize::Ram8KiB(1),
+            rom_size: 2,
+            header_checksum: 0,
+            global_checksum: 0,
+            hashes: RomHashes::new(&[]),
+        }
+    }
+
+    /// How the size of the file compares to the banks declared in the header, `None` when
+    /// they agree
+    pub fn size_mismatch(&self) -> Option<RomSizeMismatch> {
+        let mismatch = RomSizeMismatch { banks: self.rom_size, found: self.rom.len() };
+        (mismatch.expected() != mismatch.found).then_some(mismatch)
+    }
+
+    /// CRC-32 of the whole ROM
+    pub fn crc32(&self) -> u32 {
+        self.hashes.crc32
+    }
+
+    /// SHA-1 of the whole ROM
+    pub fn sha1(&self) -> [u8; 20] {
+        self.hashes.sha1
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RamSize {
     Zero,
     Ram8KiB(u8),
@@ -70,6 +128,51 @@ impl TryFrom<u8> for RamSize {
     }
 }
 
+impl RamSize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            Self::Zero => 0,
+            Self::Ram8KiB(_) => 0x2000,
+            Self::Ram32KiB(_) => 0x8000,
+            Self::Ram128KiB(_) => 0x20000,
+            Self::Ram64KiB(_) => 0x10000,
+        }
+    }
+}
+
+/// Bytes in a ROM bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// A ROM file that doesn't hold the banks its header declares, dumps with extra data at the
+/// end or with the last banks missing are common
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomSizeMismatch {
+    /// Banks declared in the header
+    pub banks: usize,
+    /// Size of the file in bytes
+    pub found: usize,
+}
+
+impl RomSizeMismatch {
+    pub fn expected(&self) -> usize {
+        self.banks * ROM_BANK_SIZE
+    }
+}
+
+impl std::fmt::Display for RomSizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (expected, found) = (self.expected(), self.found);
+        let kind = if found > expected { "overdump" } else { "underdump" };
+        write!(
+            f,
+            "ROM is {found} bytes but the header declares {} banks ({expected} bytes), likely an {kind}",
+            self.banks
+        )
+    }
+}
+
+/// Banks declared by the ROM size byte, $52-$54 are the odd 1.1, 1.2 and 1.5MiB sizes
+/// Read more: https://gbdev.io/pandocs/The_Cartridge_Header.html#0148--rom-size
 fn get_rom_size(value: u8) -> Result<usize, CartridgeError> {
     match value {
         0x00 => Ok(2),
@@ -89,13 +192,17 @@ fn get_rom_size(value: u8) -> Result<usize, CartridgeError> {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CartridgeType {
     RomOnly,
     MBC1 {
         ram: bool,
         battery: bool,
     },
+    /// MBC1 wired for multicarts, the header can't tell it apart from MBC1 so it's only
+    /// ever set by a per-game config
+    MBC1M,
     MBC2 {
         battery: bool,
     },
@@ -123,6 +230,21 @@ pub enum CartridgeType {
     HuC1,
 }
 
+impl CartridgeType {
+    /// Whether the cartridge ram keeps its contents while the console is off
+    pub fn battery(&self) -> bool {
+        match *self {
+            Self::MBC1 { battery, .. }
+            | Self::MBC2 { battery }
+            | Self::MMM01 { battery, .. }
+            | Self::MBC3 { battery, .. }
+            | Self::MBC5 { battery, .. } => battery,
+            Self::RomRamBattery | Self::MBC7 | Self::PocketCamera | Self::HuC3 | Self::HuC1 => true,
+            _ => false,
+        }
+    }
+}
+
 impl TryFrom<u8> for CartridgeType {
     type Error = CartridgeError;
 
@@ -0,0 +1,99 @@
+use std::ops::RangeInclusive;
+
+use crate::memory::{Memory, regions::*};
+
+/// Where cheat searches look, game state almost always lives in work ram
+pub const SEARCH_RANGE: RangeInclusive<usize> = WRAM_1_START..=WRAM_2_END;
+
+/// How a byte has to compare against the previous snapshot to stay a candidate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    /// Holds exactly this value now, whatever it was before
+    Value(u8),
+}
+
+impl Comparison {
+    pub fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Self::Equal => current == previous,
+            Self::Changed => current != previous,
+            Self::Increased => current > previous,
+            Self::Decreased => current < previous,
+            Self::Value(value) => current == value,
+        }
+    }
+}
+
+/// A RAM scanner: start from every byte of work ram and narrow the candidates down by
+/// comparing each new snapshot against the last one, e.g. "lives decreased" after dying
+#[derive(Debug, Clone)]
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    pub fn new(mem: &Memory) -> Self {
+        Self {
+            snapshot: Self::snapshot(mem),
+            candidates: SEARCH_RANGE.map(|addr| addr as u16).collect(),
+        }
+    }
+
+    fn snapshot(mem: &Memory) -> Vec<u8> {
+        SEARCH_RANGE.map(|addr| mem.peek(addr)).collect()
+    }
+
+    /// The value `addr` had when the last snapshot was taken
+    pub fn previous(&self, addr: u16) -> u8 {
+        self.snapshot[addr as usize - SEARCH_RANGE.start()]
+    }
+
+    /// Drop the candidates that don't match and take a new snapshot, returns how many are left
+    pub fn filter(&mut self, mem: &Memory, comparison: Comparison) -> usize {
+        let previous = std::mem::replace(&mut self.snapshot, Self::snapshot(mem));
+        let start = *SEARCH_RANGE.start();
+        self.candidates.retain(|&addr| {
+            let index = addr as usize - start;
+            comparison.matches(previous[index], self.snapshot[index])
+        });
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// The candidates and their current values, ready to be frozen
+    pub fn cheats<'a>(&'a self, mem: &'a Memory) -> impl Iterator<Item = (u16, u8)> + 'a {
+        self.candidates.iter().map(|&addr| (addr, mem.peek(addr as usize)))
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_search() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.poke(0xc100, 3);
+        mem.poke(0xd200, 3);
+        let mut search = CheatSearch::new(&mem);
+        assert_eq!(search.candidates().len(), 0x2000);
+        assert_eq!(search.filter(&mem, Comparison::Value(3)), 2);
+        // lose a life in one of them
+        mem.poke(0xc100, 2);
+        assert_eq!(search.filter(&mem, Comparison::Decreased), 1);
+        assert_eq!(search.previous(0xc100), 2);
+        assert_eq!(search.filter(&mem, Comparison::Equal), 1);
+        mem.poke(0xc100, 5);
+        assert_eq!(search.filter(&mem, Comparison::Changed), 1);
+        assert_eq!(search.cheats(&mem).collect::<Vec<_>>(), vec![(0xc100, 5)]);
+        assert_eq!(search.filter(&mem, Comparison::Increased), 0);
+    }
+}
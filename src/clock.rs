@@ -1,40 +1,166 @@
-use crate::memory::Memory;
+use crate::{io::IoEvent, memory::registers::TAC};
 
-#[derive(Debug)]
+/// T-cycles in an M-cycle, the cpu's instructions take a whole number of M-cycles
+pub const T_CYCLES_PER_M_CYCLE: u64 = 4;
+
+/// The APU's frame sequencer steps when this bit of the divider falls, bit 4 of DIV
+/// Read more: https://gbdev.io/pandocs/Audio_details.html#div-apu
+const DIV_APU_BIT: u32 = 12;
+
+/// An internally clocked serial transfer shifts a bit when this bit of the divider falls,
+/// 8192 times a second
+/// Read more: https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html#internal-clock
+const SERIAL_BIT: u32 = 8;
+
+/// The bit of the divider TIMA counts the falls of for each of TAC's frequencies: 4096, 262144,
+/// 65536 and 16384 Hz
+/// Read more: https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+const TIMER_BITS: [u32; 4] = [9, 3, 5, 7];
+
+/// How many times the divider bits other components are clocked by fell
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DividerEdges {
+    /// Steps of the APU's frame sequencer
+    pub div_apu: usize,
+    /// Bits shifted by an internally clocked serial transfer
+    pub serial: usize,
+    /// Increments of TIMA
+    pub timer: usize,
+}
+
+impl DividerEdges {
+    fn between(before: u64, after: u64, tac: u8) -> Self {
+        let falls = |bit: u32| ((after >> (bit + 1)) - (before >> (bit + 1))) as usize;
+        Self {
+            div_apu: falls(DIV_APU_BIT),
+            serial: falls(SERIAL_BIT),
+            timer: match tac & 0x04 {
+                0 => 0,
+                _ => falls(TIMER_BITS[(tac & 0x03) as usize]),
+            },
+        }
+    }
+}
+
+/// Time since power on, where the PPU is within a scanline is kept by the PPU itself
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Clock {
-    pub master_clock: usize,
-    pub m_cycles: usize,
-    pub dots: usize,
+    /// T-cycles (dots) since power on, 4194304 a second
+    pub t_cycles: u64,
+    /// The timer's internal counter, it counts T-cycles and DIV is its upper byte
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub divider: u16,
+    /// TAC as of its last write, kept to tell whether a write made the timer's input fall
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tac: u8,
 }
 
 impl Clock {
     pub fn new() -> Self {
         Self {
-            master_clock: 0,
-            m_cycles: 0,
-            dots: 0,
+            t_cycles: 0,
+            divider: 0,
+            tac: 0,
         }
     }
-    pub fn tick(&mut self, mem: &mut Memory) {
-        self.master_clock += 1;
-        // self.dots = self.m_cycles * 4;
-        // a scanline has been completed, 456 dots per scanline
-        if self.dots % 456 == 0 {
-            mem.inc_scanline();
-        }
-        // a second should have elapsed
-        // 70224 dots * 59.7 fps = ~4190000 (the clock speed of the system)
-        if self.dots % 70224 == 0 {
-            // println!("1 second");
-        }
-        // request vblank int
-        if self.m_cycles > 143 {
-            let vblank = mem.get_interrupt_registers() | 1;
-            mem.set_interrupt_registers(vblank);
-        }
-        // reset scan lines,
-        if self.m_cycles > 153 {
-            self.m_cycles = 0;
+    /// Called whenever the cpu writes DIV, TIMA, TMA or TAC. Writing DIV clears the whole
+    /// divider, which is a falling edge for any of its bits that were set. The timer counts
+    /// the falls of its enable bit and its divider bit together, so clearing the divider or
+    /// changing TAC can increment TIMA too.
+    pub fn io_event(&mut self, event: IoEvent) -> DividerEdges {
+        match event {
+            IoEvent::DivReset => {
+                let set = |bit: u32| (self.divider >> bit & 1) as usize;
+                let edges = DividerEdges {
+                    div_apu: set(DIV_APU_BIT),
+                    serial: set(SERIAL_BIT),
+                    timer: self.timer_input() as usize,
+                };
+                self.divider = 0;
+                edges
+            }
+            IoEvent::Timer(TAC, value) => {
+                let before = self.timer_input();
+                self.tac = value;
+                DividerEdges {
+                    timer: (before && !self.timer_input()) as usize,
+                    ..DividerEdges::default()
+                }
+            }
+            _ => DividerEdges::default(),
         }
     }
+    /// Advance by `m_cycles` M-cycles, returns how many times each bit fell on the way
+    pub fn tick(&mut self, m_cycles: usize) -> DividerEdges {
+        let t_cycles = m_cycles as u64 * T_CYCLES_PER_M_CYCLE;
+        self.t_cycles += t_cycles;
+        let (before, after) = (self.divider as u64, self.divider as u64 + t_cycles);
+        self.divider = after as u16;
+        DividerEdges::between(before, after, self.tac)
+    }
+    /// Whether the timer is enabled and the divider bit TAC selects is set
+    fn timer_input(&self) -> bool {
+        let bit = TIMER_BITS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && self.divider >> bit & 1 == 1
+    }
+    /// M-cycles since power on
+    pub fn m_cycles(&self) -> u64 {
+        self.t_cycles / T_CYCLES_PER_M_CYCLE
+    }
+    /// The upper byte of the divider
+    pub fn div(&self) -> u8 {
+        (self.divider >> 8) as u8
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_apu() {
+        let mut clock = Clock::new();
+        clock.divider = 0x1ffc;
+        assert_eq!(clock.tick(1).div_apu, 1);
+        assert_eq!((clock.divider, clock.div()), (0x2000, 0x20));
+        // bit 12 rises here, it only counts when it falls
+        clock.divider = 0x0ffc;
+        assert_eq!(clock.tick(1).div_apu, 0);
+        assert_eq!(clock.io_event(IoEvent::DivReset).div_apu, 1);
+        assert_eq!(clock.io_event(IoEvent::DivReset).div_apu, 0);
+        assert_eq!(clock.io_event(IoEvent::Timer(0xff05, 0x00)), DividerEdges::default());
+    }
+
+    #[test]
+    fn test_timer_edges() {
+        let mut clock = Clock::new();
+        // disabled, nothing counts
+        assert_eq!(clock.tick(1024).timer, 0);
+        // enabled at 262144 Hz, every 4 M-cycles
+        assert_eq!(clock.io_event(IoEvent::Timer(TAC, 0x05)).timer, 0);
+        assert_eq!(clock.tick(16).timer, 4);
+        assert_eq!(clock.tick(4).timer, 1);
+        // 4096 Hz, every 256 M-cycles
+        clock.io_event(IoEvent::Timer(TAC, 0x04));
+        clock.divider = 0;
+        assert_eq!(clock.tick(255).timer, 0);
+        assert_eq!(clock.tick(1).timer, 1);
+        // clearing the divider or disabling the timer while the bit is set counts once
+        clock.divider = 0x0200;
+        assert_eq!(clock.io_event(IoEvent::DivReset).timer, 1);
+        clock.divider = 0x0200;
+        assert_eq!(clock.io_event(IoEvent::Timer(TAC, 0x00)).timer, 1);
+        assert_eq!(clock.io_event(IoEvent::Timer(TAC, 0x00)).timer, 0);
+    }
+
+    #[test]
+    fn test_serial_clock() {
+        let mut clock = Clock::new();
+        // a bit every 128 M-cycles, 8 of them make the 1024 a byte takes
+        assert_eq!(clock.tick(1024).serial, 8);
+        assert_eq!(clock.tick(127).serial, 0);
+        assert_eq!(clock.tick(1).serial, 1);
+        clock.divider = 0x0100;
+        assert_eq!(clock.io_event(IoEvent::DivReset).serial, 1);
+    }
 }
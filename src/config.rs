@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use crate::{
+    accuracy::Accuracy,
+    cartridge::{Cartridge, CartridgeType},
+    errors::ConfigError,
+    model::Model,
+};
+
+/// Mapper behaviour the header can't express
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapperQuirk {
+    /// MBC1 multicart, the header of these says plain MBC1
+    #[cfg_attr(feature = "json", serde(rename = "MBC1M"))]
+    Mbc1M,
+}
+
+/// Settings for a single game, matched on its header title and/or global checksum.
+/// An entry without either never matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct GameConfig {
+    pub title: Option<String>,
+    pub global_checksum: Option<u16>,
+    /// RGB colors for the four shades, lightest first
+    pub palette: Option<[[u8; 3]; 4]>,
+    pub mapper: Option<MapperQuirk>,
+    pub accuracy: Option<Accuracy>,
+    pub model: Option<Model>,
+}
+
+impl GameConfig {
+    pub fn matches(&self, cartridge: &Cartridge) -> bool {
+        let title = cartridge.title.trim_end_matches('\0');
+        (self.title.is_some() || self.global_checksum.is_some())
+            && self.title.as_deref().is_none_or(|t| t == title)
+            && self.global_checksum.is_none_or(|c| c == cartridge.global_checksum)
+    }
+
+    /// Apply the mapper override to a freshly loaded cartridge
+    pub fn apply(&self, cartridge: &mut Cartridge) {
+        if let Some(MapperQuirk::Mbc1M) = self.mapper {
+            cartridge.cartridge_type = CartridgeType::MBC1M;
+        }
+    }
+}
+
+/// The config file, stored as json:
+/// ```json
+/// { "rom_dir": "roms", "games": [{
+///     "title": "TETRIS",
+///     "palette": [[224, 248, 208], [136, 192, 112], [52, 104, 86], [8, 24, 32]]
+/// }] }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(default))]
+pub struct Config {
+    /// Folder the launcher lists ROMs from when gbr is started without one
+    pub rom_dir: Option<String>,
+    pub games: Vec<GameConfig>,
+}
+
+impl Config {
+    #[cfg(feature = "json")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// The overrides for a game, later entries win when several match
+    pub fn game(&self, cartridge: &Cartridge) -> GameConfig {
+        let mut game = GameConfig::default();
+        for entry in self.games.iter().filter(|entry| entry.matches(cartridge)) {
+            game.palette = entry.palette.or(game.palette);
+            game.mapper = entry.mapper.or(game.mapper);
+            game.accuracy = entry.accuracy.or(game.accuracy);
+            game.model = entry.model.or(game.model);
+        }
+        game
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn cartridge(title: &[u8], checksum: u16) -> Cartridge {
+        let mut rom = vec![0; 0xffff];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+        Cartridge::new(rom).unwrap()
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_matching() {
+        let config: Config = serde_json::from_str(
+            r#"{ "games": [
+                { "title": "TETRIS", "palette": [[1, 1, 1], [2, 2, 2], [3, 3, 3], [4, 4, 4]] },
+                { "global_checksum": 4660, "mapper": "MBC1M", "accuracy": "fast", "model": "mgb" },
+                { "palette": [[0, 0, 0], [0, 0, 0], [0, 0, 0], [0, 0, 0]] }
+            ] }"#,
+        )
+        .unwrap();
+        let tetris = config.game(&cartridge(b"TETRIS", 0x1234));
+        assert_eq!(tetris.palette, Some([[1; 3], [2; 3], [3; 3], [4; 3]]));
+        assert_eq!(tetris.mapper, Some(MapperQuirk::Mbc1M));
+        assert_eq!(tetris.accuracy, Some(Accuracy::Fast));
+        assert_eq!(tetris.model, Some(Model::Mgb));
+        // the last entry has nothing to match on
+        assert_eq!(config.game(&cartridge(b"ZELDA", 0)), GameConfig::default());
+        let mut multicart = cartridge(b"BOMCOL", 0x1234);
+        config.game(&multicart).apply(&mut multicart);
+        assert_eq!(multicart.cartridge_type, CartridgeType::MBC1M);
+    }
+}
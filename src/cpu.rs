@@ -1,5 +1,8 @@
 use crate::{
-    DecodeContext, Mnemonic, errors::CpuError, extract_bytes, instructions::INSTRUCTION_SET,
+    DecodeContext, Mnemonic,
+    errors::CpuError,
+    extract_bytes,
+    instructions::{DecodedInstruction, INSTRUCTION_SET},
     memory::Memory,
 };
 
@@ -12,6 +15,7 @@ pub enum Condition {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers {
     pub a: u8,
     pub b: u8,
@@ -92,7 +96,7 @@ impl Registers {
             }
             R8::L => {
                 self.l = value;
-                self.hl = self.hl & 0x0ff0 | value as u16;
+                self.hl = self.hl & 0xff00 | value as u16;
             }
         }
     }
@@ -161,11 +165,77 @@ pub enum R8 {
     L,
 }
 
-#[derive(Debug)]
+/// Instructions the pc history keeps unless told otherwise
+pub const PC_HISTORY_LENGTH: usize = 64;
+
+/// A ring buffer of the pc and opcode of the last executed instructions, for post-mortem
+/// debugging. Recording costs nothing but a branch once the capacity is 0.
+#[derive(Debug, Clone, Default)]
+pub struct PcHistory {
+    entries: Vec<(u16, u8)>,
+    capacity: usize,
+    // where the next entry goes once full, which is also the oldest one
+    next: usize,
+}
+
+impl PcHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keep the last `capacity` instructions from now on, 0 turns recording off
+    pub fn set_capacity(&mut self, capacity: usize) {
+        *self = Self::new(capacity);
+    }
+
+    pub fn push(&mut self, pc: u16, opcode: u8) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() < self.capacity {
+            self.entries.push((pc, opcode));
+        } else {
+            self.entries[self.next] = (pc, opcode);
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+
+    /// `(pc, opcode)` of the recorded instructions, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (u16, u8)> + '_ {
+        let (newest, oldest) = self.entries.split_at(self.next % self.entries.len().max(1));
+        oldest.iter().chain(newest).copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     pub registers: Registers,
     // Interrupt master enable flag
     pub ime: bool,
+    // EI was just executed, IME is set once the next instruction starts
+    pub ime_scheduled: bool,
+    /// HALT was executed, nothing runs until an interrupt is pending
+    pub halted: bool,
+    /// HALT was executed with IME clear and an interrupt pending, the next opcode is read
+    /// twice because pc doesn't increment after it
+    pub halt_bug: bool,
+    /// The last instructions executed, not part of save states
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub pc_history: PcHistory,
 }
 
 impl Default for Cpu {
@@ -173,6 +243,10 @@ impl Default for Cpu {
         Self {
             registers: Registers::default(),
             ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            pc_history: PcHistory::new(PC_HISTORY_LENGTH),
         }
     }
 }
@@ -188,35 +262,64 @@ impl Cpu {
         }
     }
     pub fn execute(&mut self, memory: &mut Memory) -> Result<u8, CpuError> {
+        Ok(self.execute_decoded(memory)?.1)
+    }
+
+    /// Execute the instruction at pc, returning it alongside the cycles it took.
+    /// While halted a cycle passes without executing anything until an interrupt is pending.
+    pub fn execute_decoded(
+        &mut self,
+        memory: &mut Memory,
+    ) -> Result<(DecodedInstruction, u8), CpuError> {
+        if self.halted {
+            // waking up takes a cycle, a pending interrupt is serviced right after it
+            self.halted = memory.pending_interrupts() == 0;
+            return Ok((DecodedInstruction::new(Mnemonic::HALT, &[0x76]), 1));
+        }
         let pc = self.registers.pc as usize;
-        let mut cloned_memory = memory.clone();
-        let rom = &cloned_memory.rom()[pc..];
+        // copy the instruction out of memory so the decoder can borrow memory mutably
+        let mut window = [0; 3];
+        let available = window.len().min(memory.block.len() - pc);
+        for (i, byte) in window[..available].iter_mut().enumerate() {
+            // peek so code running out of cartridge ram or io fetches what a read would see
+            *byte = memory.peek(pc + i);
+        }
+        let halt_bug = std::mem::take(&mut self.halt_bug);
+        if halt_bug {
+            // the opcode byte is also read as whatever comes after it
+            window.copy_within(0..2, 1);
+        }
+        let rom = &window[..available];
         let mut iter = rom.iter();
         let opcode_byte = *iter.next().ok_or(CpuError::MissingOpcodeByte)?;
+        self.pc_history.push(pc as u16, opcode_byte);
+        // the instruction after EI runs before any interrupt can be serviced, unless it's a DI
+        if self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.ime = true;
+        }
+        let logged = memory.bus_log.as_ref().map_or(0, Vec::len);
         let mut ctx = DecodeContext {
             iter,
             cpu: self,
             memory,
         };
-        if let Ok(instruction) = INSTRUCTION_SET[opcode_byte as usize](&mut ctx) {
-            println!("0x{opcode_byte:0x}");
-            match instruction.mnemonic {
-                Mnemonic::NOP | Mnemonic::RST => (),
-                Mnemonic::RETI | Mnemonic::EI => self.ime = true,
-                // Mnemonic::JR => {
-                //     println!("{}", self.registers.pc);
-                // },
-                // _ => (),
-                _ => println!("{instruction:?}"),
-            };
-            return Ok(instruction.cycles);
+        let instruction = INSTRUCTION_SET[opcode_byte as usize](&mut ctx)
+            .map_err(|e| CpuError::Decode(opcode_byte, e))?;
+        let len = (instruction.bytes as usize).clamp(1, rom.len());
+        // with the halt bug pc didn't move past the opcode, so it's fetched twice
+        let addrs = (0..len).map(|i| pc + i - usize::from(halt_bug && i > 0));
+        memory.log_fetch(logged, addrs, &rom[..len]);
+        let decoded = DecodedInstruction::new(instruction.mnemonic, &rom[..len]);
+        if halt_bug && self.registers.pc as usize == pc + instruction.bytes as usize {
+            self.registers.pc -= 1;
         }
-        // perhaps panicking here makes more sense?
-        Err(CpuError::NoCycles)
+        Ok((decoded, instruction.cycles))
     }
 }
 /// Z = Zero, N = Subtraction, H = Half Carry, C = Carry
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Flags {
     pub zero: bool,
     pub subtraction: bool,
@@ -227,10 +330,7 @@ pub struct Flags {
 // i've opted for a higher-level representation of flags instead of bit-fiddling the lower 8 bits of the AF register constantly
 impl Flags {
     pub fn set(&mut self, value: u8) {
-        self.zero = (value >> 7) & 1 == 1;
-        self.subtraction = (value >> 6) & 1 == 1;
-        self.half_carry = (value >> 5) & 1 == 1;
-        self.carry = (value >> 4) & 1 == 1;
+        *self = Self::from(value);
     }
     pub fn clear(&mut self) {
         self.zero = false;
@@ -239,35 +339,87 @@ impl Flags {
         self.carry = false;
     }
 }
+/// The flags left by the DMG boot rom for a cartridge with a non-zero header checksum, see
+/// `Flags::post_boot`
 impl Default for Flags {
     fn default() -> Self {
+        Self::post_boot(1)
+    }
+}
+
+impl Flags {
+    /// The boot rom leaves Z set and N clear, H and C are only set when the header checksum isn't 0
+    /// Read more: https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn post_boot(header_checksum: u8) -> Self {
         Self {
             zero: true,
             subtraction: false,
-            half_carry: true,
-            carry: true,
+            half_carry: header_checksum != 0,
+            carry: header_checksum != 0,
         }
     }
 }
+
 // make sure we can still cast flags back into an u8 when certain operations (PUSH AF) expect a byte
-impl Into<u8> for Flags {
-    fn into(self) -> u8 {
-        let mut flags: u8 = 0;
-        flags |= (self.zero as u8) << 7;
-        flags |= (self.subtraction as u8) << 6;
-        flags |= (self.half_carry as u8) << 5;
-        flags |= (self.carry as u8) << 7;
-        flags
+impl From<Flags> for u8 {
+    fn from(flags: Flags) -> Self {
+        let mut value: u8 = 0;
+        value |= (flags.zero as u8) << 7;
+        value |= (flags.subtraction as u8) << 6;
+        value |= (flags.half_carry as u8) << 5;
+        value |= (flags.carry as u8) << 4;
+        value
     }
 }
 
+/// The low nibble of F doesn't exist and is dropped
 impl From<u8> for Flags {
     fn from(value: u8) -> Self {
         Self {
-            zero: value & 0x80 == 1,
-            subtraction: value & 0x40 == 1,
-            half_carry: value & 0x20 == 1,
-            carry: value & 0x10 == 1,
+            zero: value & 0x80 != 0,
+            subtraction: value & 0x40 != 0,
+            half_carry: value & 0x20 != 0,
+            carry: value & 0x10 != 0,
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pc_history() {
+        let mut history = PcHistory::new(3);
+        history.push(0x100, 0x00);
+        history.push(0x101, 0xc3);
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec![(0x100, 0x00), (0x101, 0xc3)]);
+        history.push(0x150, 0x3e);
+        history.push(0x152, 0xe0);
+        assert_eq!(
+            history.iter().collect::<Vec<_>>(),
+            vec![(0x101, 0xc3), (0x150, 0x3e), (0x152, 0xe0)]
+        );
+        history.set_capacity(0);
+        history.push(0x154, 0x00);
+        assert_eq!(history.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_flags_conversion() {
+        for value in (0x00..=0xf0).step_by(0x10) {
+            assert_eq!(u8::from(Flags::from(value)), value);
         }
+        assert_eq!(u8::from(Flags::from(0xff)), 0xf0);
+        let flags = Flags::from(0x10);
+        assert!(flags.carry && !flags.zero && !flags.subtraction && !flags.half_carry);
+        let flags = Flags::from(0x80);
+        assert!(flags.zero && !flags.carry);
+    }
+
+    #[test]
+    fn test_post_boot_flags() {
+        assert_eq!(u8::from(Flags::default()), 0xb0);
+        assert_eq!(u8::from(Flags::post_boot(0x33)), 0xb0);
+        assert_eq!(u8::from(Flags::post_boot(0x00)), 0x80);
     }
 }
@@ -0,0 +1,72 @@
+use crate::{
+    cpu::Cpu,
+    errors::CpuError,
+    memory::Memory,
+    trace::TraceEntry,
+};
+
+/// Instructions listed in a crash report, the one that failed last
+pub const CRASH_HISTORY: usize = 32;
+/// Bytes of the stack listed in a crash report, starting at sp
+pub const CRASH_STACK_BYTES: usize = 16;
+
+/// Everything needed to triage an instruction the cpu couldn't execute, printed in place of a
+/// bare unwrap so a report from a user is enough to start looking
+#[derive(Debug)]
+pub struct CrashReport {
+    pub error: CpuError,
+    /// Frames completed before the crash
+    pub frame: u64,
+    /// Registers at the time of the crash, with the bank pc points into
+    pub registers: TraceEntry,
+    pub ime: bool,
+    /// `(pc, opcode)` of the instructions leading up to the crash from the cpu's pc history,
+    /// oldest first
+    pub history: Vec<(u16, u8)>,
+    /// The bytes at sp and above
+    pub stack: Vec<u8>,
+    pub rom_bank: u16,
+    pub ram_bank: u16,
+}
+
+impl CrashReport {
+    pub fn new(error: CpuError, frame: u64, cpu: &Cpu, mem: &Memory) -> Self {
+        let sp = cpu.registers.sp as usize;
+        let mut history: Vec<_> = cpu.pc_history.iter().rev().take(CRASH_HISTORY).collect();
+        history.reverse();
+        Self {
+            error,
+            frame,
+            registers: TraceEntry::from_cpu(cpu).with_bank(mem),
+            ime: cpu.ime,
+            history,
+            stack: (sp..sp + CRASH_STACK_BYTES)
+                .map(|addr| mem.peek(addr & 0xffff))
+                .collect(),
+            rom_bank: mem.rom_bank(),
+            ram_bank: mem.ram_bank(),
+        }
+    }
+}
+
+impl std::error::Error for CrashReport {}
+
+impl std::fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "The cpu crashed in frame {}: {}", self.frame, self.error)?;
+        writeln!(f, "Registers: {} IME:{}", self.registers, self.ime as u8)?;
+        writeln!(f, "Banks: ROM {} RAM {}", self.rom_bank, self.ram_bank)?;
+        let stack: Vec<String> = self.stack.iter().map(|byte| format!("{byte:02X}")).collect();
+        let sp = self.registers.sp.unwrap_or_default();
+        writeln!(f, "Stack at {sp:04X}: {}", stack.join(" "))?;
+        if self.history.is_empty() {
+            writeln!(f, "No pc history, it isn't recorded at accuracy level fast")?;
+        } else {
+            writeln!(f, "Last {} instructions, oldest first:", self.history.len())?;
+        }
+        for (pc, opcode) in &self.history {
+            writeln!(f, "  {pc:04X}: {opcode:02X}")?;
+        }
+        Ok(())
+    }
+}
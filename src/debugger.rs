@@ -0,0 +1,108 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::memory::Memory;
+
+/// Commands for poking at memory while a game runs, typed at the TUI's `:` prompt or passed
+/// with `gbr --command`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// `memdump START END FILE`, write START through END inclusive to FILE
+    MemDump { start: u16, end: u16, path: PathBuf },
+    /// `memload ADDR FILE`, copy FILE into memory from ADDR on
+    MemLoad { addr: u16, path: PathBuf },
+}
+
+impl FromStr for DebugCommand {
+    type Err = String;
+
+    /// Addresses are hex, with or without a `0x` or `$` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |value: &str| {
+            u16::from_str_radix(value.trim_start_matches("0x").trim_start_matches('$'), 16)
+                .map_err(|_| format!("invalid address {value}"))
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            ["memdump", start, end, path] => {
+                let (start, end) = (hex(start)?, hex(end)?);
+                if start > end {
+                    return Err(format!("memdump ends at {end:04x} before it starts"));
+                }
+                Ok(Self::MemDump { start, end, path: path.into() })
+            }
+            ["memload", addr, path] => Ok(Self::MemLoad { addr: hex(addr)?, path: path.into() }),
+            ["memdump", ..] => Err("usage: memdump START END FILE".to_string()),
+            ["memload", ..] => Err("usage: memload ADDR FILE".to_string()),
+            _ => Err(format!("Unknown command {s}, expected memdump or memload")),
+        }
+    }
+}
+
+impl DebugCommand {
+    /// Run the command and describe what it did. Memory is read and written the way the
+    /// memory editor does it: the PPU doesn't block anything, the mapped banks are the ones
+    /// dumped and loading into ROM patches it.
+    pub fn run(&self, mem: &mut Memory) -> std::io::Result<String> {
+        match self {
+            Self::MemDump { start, end, path } => {
+                let bytes: Vec<u8> =
+                    (*start..=*end).map(|addr| mem.peek(addr as usize)).collect();
+                std::fs::write(path, &bytes)?;
+                Ok(format!("Dumped {start:04x}-{end:04x} to {}", path.display()))
+            }
+            Self::MemLoad { addr, path } => {
+                let bytes = std::fs::read(path)?;
+                let start = *addr as usize;
+                if start + bytes.len() > 0x10000 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} doesn't fit after {addr:04x}", path.display()),
+                    ));
+                }
+                for (offset, value) in bytes.iter().enumerate() {
+                    mem.poke(start + offset, *value);
+                }
+                Ok(format!("Loaded {} bytes at {addr:04x}", bytes.len()))
+            }
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "memdump 0x8000 0x9FFF vram.bin".parse(),
+            Ok(DebugCommand::MemDump { start: 0x8000, end: 0x9fff, path: "vram.bin".into() })
+        );
+        assert_eq!(
+            "memload $c000 setup.bin".parse(),
+            Ok(DebugCommand::MemLoad { addr: 0xc000, path: "setup.bin".into() })
+        );
+        assert!("memdump 0x9fff 0x8000 vram.bin".parse::<DebugCommand>().is_err());
+        assert!("memload c000".parse::<DebugCommand>().is_err());
+        assert!("step".parse::<DebugCommand>().is_err());
+    }
+
+    #[test]
+    fn test_dump_and_load() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0x8000]).unwrap());
+        let path = std::env::temp_dir().join(format!("gbr-memdump-{}.bin", std::process::id()));
+        for (offset, value) in [0x12, 0x34, 0x56].into_iter().enumerate() {
+            mem.poke(0xc000 + offset, value);
+        }
+        let dump = DebugCommand::MemDump { start: 0xc000, end: 0xc002, path: path.clone() };
+        dump.run(&mut mem).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [0x12, 0x34, 0x56]);
+        let load = DebugCommand::MemLoad { addr: 0xff80, path: path.clone() };
+        assert_eq!(load.run(&mut mem).unwrap(), "Loaded 3 bytes at ff80");
+        assert_eq!(mem.peek(0xff82), 0x56);
+        // a file running past the end of the address space is refused
+        let load = DebugCommand::MemLoad { addr: 0xfffe, path: path.clone() };
+        assert!(load.run(&mut mem).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
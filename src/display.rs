@@ -1,22 +1,32 @@
-extern crate sdl3;
-
 use std::io::{Read, Write};
 
-use sdl3::pixels::{Color, PixelFormat};
-use sdl3::rect::Rect;
-use sdl3::render::{Canvas, FRect, Texture, TextureAccess, TextureCreator};
-use sdl3::sys::pixels::{
-    SDL_PIXELFORMAT_ABGR32, SDL_PIXELFORMAT_RGB24, SDL_PixelFormat, SDL_PixelFormatDetails,
-};
-use sdl3::sys::rect::SDL_GetRectAndLineIntersectionFloat;
-use sdl3::sys::stdinc::SDL_sinf;
-use sdl3::video::{SystemTheme, Window, WindowContext};
-use sdl3::{Error, EventPump};
-
-use crate::clock::Clock;
-use crate::io::LcdControl;
+#[cfg(feature = "sdl")]
+use sdl3::{EventPump, render::Canvas, video::Window};
+
+#[cfg(feature = "sdl")]
+use crate::errors::VideoError;
+
+use crate::PALETTE;
+use crate::clock::{Clock, T_CYCLES_PER_M_CYCLE};
+use crate::io::{IoEvent, LcdControl};
 use crate::memory::Memory;
-use crate::memory::registers::{LCDC, LY};
+use crate::oam::{OamAttributes, ScanlineObjects};
+use crate::tile::{TileAddressing, TileId, TileMap};
+use crate::interrupts::Interrupt;
+use crate::memory::registers::{LCDC, LY, LYC, OGBP0, OGBP1, STAT};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// The background map is 32x32 tiles, the screen shows a 160x144 viewport into it
+const BG_SIZE: usize = 256;
+/// M-cycles a scanline takes, 456 dots
+pub const SCANLINE_CYCLES: u64 = 114;
+/// Scanlines in a frame, vblank included
+pub const FRAME_SCANLINES: u64 = 154;
+const SCANLINE_DOTS: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+// TODO: drawing takes 172-289 dots depending on SCX, the window and objects
+const DRAWING_DOTS: u16 = 172;
 
 /// ```ignore
 /// These modes represent the modes the PPU cycles between during a frame
@@ -34,38 +44,321 @@ use crate::memory::registers::{LCDC, LY};
 ///  153 |-------------- Vertical Blank ------------------|
 /// ```
 /// Read more: https://gbdev.io/pandocs/Rendering.html
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PpuMode {
     HorizontalBlank, // waiting until the end of the scanline
     VerticalBlank,   // waiting until the next frame, all vram sectitons become accessible to cpu
     OAMScan,         // searching for OBJS which overlap the current scanline
     Drawing,         // sending pixels to the LCD
 }
+/// Everything about the ppu worth saving, the SDL handles can't be serialized
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PpuState {
+    pub obj_penalty: usize,
+    pub scanline: u16,
+    pub dot: u16,
+    pub mode: PpuMode,
+    #[cfg_attr(feature = "serde", serde(with = "crate::rle"))]
+    pub frame_buffer: Vec<u8>,
+    pub window: WindowState,
+    pub stat_line: bool,
+}
+/// Where the window is at, it has a line counter of its own instead of using LY - WY
+/// Read more: https://gbdev.io/pandocs/Tile_Maps.html#window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowState {
+    /// The scanline the rest of the state describes
+    pub scanline: Option<u8>,
+    /// The window row drawn on `scanline`, only advances on lines where the window was drawn
+    pub line: u8,
+    /// LY has matched WY at some point this frame
+    pub triggered: bool,
+    /// First pixel of the window on `scanline`, `None` when it isn't drawn
+    pub start: Option<isize>,
+    /// WX was 166 on the previous line, which makes the window cover all of this one
+    pub wrap: bool,
+}
+impl WindowState {
+    /// Latch the window position for a new scanline
+    fn begin_line(&mut self, mem: &Memory, lcdc: &LcdControl, scanline: u8) {
+        if scanline == 0 || self.scanline.is_none_or(|last| scanline < last) {
+            *self = Self::default();
+        } else if self.start.is_some() {
+            self.line = self.line.wrapping_add(1);
+        }
+        self.scanline = Some(scanline);
+        self.triggered |= mem.wy() == scanline;
+        let wx = mem.wx() as isize;
+        let wrap = std::mem::take(&mut self.wrap);
+        // on DMG LCDC.0 turns the window off along with the background
+        if !(lcdc.window_enable && lcdc.bg_window_enable && self.triggered) {
+            self.start = None;
+            return;
+        }
+        self.start = match wx {
+            _ if wrap => Some(0),
+            // the window stutters with the fine scroll position
+            0 => Some(-7 - (mem.scx() & 0x07) as isize),
+            166 => {
+                self.wrap = true;
+                Some(159)
+            }
+            0..166 => Some(wx - 7),
+            _ => None,
+        };
+    }
+}
 pub struct Ppu {
-    pub canvas: Canvas<Window>,
-    pub event_pump: EventPump,
     pub obj_penalty: usize,
     pub scanline: u16,
+    /// Dots into the current scanline, 0-455
+    pub dot: u16,
     pub mode: PpuMode,
     pub frame_buffer: Vec<u8>,
+    /// RGB colors for the four shades, lightest first
+    pub palette: [[u8; 3]; 4],
+    pub window: WindowState,
+    /// The STAT interrupt sources or-ed together, the interrupt is requested when it goes high
+    pub stat_line: bool,
+    /// DMG quirk, writing STAT selects every interrupt source for a cycle
+    /// Read more: https://gbdev.io/pandocs/STAT.html#spurious-stat-interrupts
+    pub stat_write_bug: bool,
+    // background color ids of every line of the map, reused until the vram they came from
+    // changes
+    bg_lines: Vec<Option<BgLine>>,
+}
+/// The background color ids of a whole line of the map and what they were drawn from
+#[derive(Debug, Clone, Copy)]
+struct BgLine {
+    map: TileMap,
+    addressing: TileAddressing,
+    /// Address of each of the 32 tiles on the line
+    tiles: [usize; 32],
+    ids: [u8; BG_SIZE],
 }
 impl Ppu {
-    pub fn new() -> Self {
-        let (canvas, event_pump) = setup_ctx().unwrap();
+    /// A ppu that only renders into `frame_buffer`, without opening a window
+    pub fn headless() -> Self {
         Self {
-            canvas,
-            event_pump,
             obj_penalty: 0,
             scanline: 0,
+            dot: 0,
             mode: PpuMode::OAMScan,
-            frame_buffer: vec![],
+            // RGB24, 3 bytes per pixel
+            frame_buffer: vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            palette: PALETTE.map(|shade| [shade; 3]),
+            window: WindowState::default(),
+            stat_line: false,
+            stat_write_bug: false,
+            bg_lines: vec![None; BG_SIZE],
+        }
+    }
+    pub fn state(&self) -> PpuState {
+        PpuState {
+            obj_penalty: self.obj_penalty,
+            scanline: self.scanline,
+            dot: self.dot,
+            mode: self.mode,
+            frame_buffer: self.frame_buffer.clone(),
+            window: self.window,
+            stat_line: self.stat_line,
+        }
+    }
+    pub fn restore(&mut self, state: &PpuState) {
+        self.obj_penalty = state.obj_penalty;
+        self.scanline = state.scanline;
+        self.dot = state.dot;
+        self.mode = state.mode;
+        self.frame_buffer.copy_from_slice(&state.frame_buffer);
+        self.window = state.window;
+        self.stat_line = state.stat_line;
+        self.bg_lines.fill(None);
+    }
+    /// React to the cpu writing an LCD register
+    pub fn io_event(&mut self, event: IoEvent, mem: &mut Memory) {
+        // turning the LCD off resets LY and leaves the PPU in mode 0 until it's turned back on
+        if let IoEvent::Lcd(LCDC, value) = event {
+            if !LcdControl::from(value).lcd_ppu_enable {
+                mem.io.set(LY, 0);
+                self.scanline = 0;
+                self.dot = 0;
+                self.window = WindowState::default();
+                self.mode = PpuMode::HorizontalBlank;
+            }
+        }
+        if let IoEvent::Lcd(STAT, _) = event {
+            if self.stat_write_bug && mem.lcd_control().lcd_ppu_enable {
+                // LY=LYC and modes 0 and 1 raise the line, mode 2 doesn't
+                let line = mem.io.get(LY) == mem.io.get(LYC)
+                    || matches!(self.mode, PpuMode::HorizontalBlank | PpuMode::VerticalBlank);
+                if line && !self.stat_line {
+                    mem.request_interrupt(Interrupt::STAT);
+                }
+                self.stat_line |= line;
+            }
+        }
+    }
+    /// Advance by `m_cycles` M-cycles, moving on to the next scanline every 456 dots and
+    /// requesting the VBlank interrupt once LY reaches 144. The LCD being off stops the PPU at the
+    /// start of line 0.
+    /// Returns whether the frame was completed, which is when VBlank starts.
+    /// Read more: https://gbdev.io/pandocs/Rendering.html#ppu-modes
+    pub fn tick(&mut self, mem: &mut Memory, m_cycles: usize, lcd_on: bool) -> bool {
+        if !lcd_on {
+            return false;
+        }
+        let mut frame_done = false;
+        self.dot += (m_cycles as u64 * T_CYCLES_PER_M_CYCLE) as u16;
+        while self.dot >= SCANLINE_DOTS {
+            self.dot -= SCANLINE_DOTS;
+            mem.inc_scanline();
+            if mem.io.get(LY) == SCREEN_HEIGHT as u8 {
+                mem.request_interrupt(Interrupt::VBLANK);
+                frame_done = true;
+            }
         }
+        self.scanline = mem.io.get(LY) as u16;
+        self.mode = match (self.scanline, self.dot) {
+            (144.., _) => PpuMode::VerticalBlank,
+            (_, dot) if dot < OAM_SCAN_DOTS => PpuMode::OAMScan,
+            (_, dot) if dot < OAM_SCAN_DOTS + DRAWING_DOTS => PpuMode::Drawing,
+            _ => PpuMode::HorizontalBlank,
+        };
+        frame_done
     }
-    pub fn oam_scan(&mut self, mem: &mut Memory, scanline: u8) {
-        let oam = mem.get_oam();
-        for chunk in oam.chunks_exact(4) {
-            if chunk[0] == scanline {}
+    /// Mirror the mode and LY=LYC into STAT and request the STAT interrupt when one of the
+    /// selected sources raises the line, sources that are already high don't request it again
+    /// Read more: https://gbdev.io/pandocs/Interrupt_Sources.html#int-48--stat-interrupt
+    pub fn update_stat(&mut self, mem: &mut Memory, lcd_on: bool) {
+        let mut stat = mem.lcd_status();
+        stat.lyu_lc = mem.io.get(LY) == mem.io.get(LYC);
+        // with the LCD off STAT reads mode 0 and nothing is requested
+        stat.ppu_mode = match (lcd_on, self.mode) {
+            (false, _) | (_, PpuMode::HorizontalBlank) => 0,
+            (_, PpuMode::VerticalBlank) => 1,
+            (_, PpuMode::OAMScan) => 2,
+            (_, PpuMode::Drawing) => 3,
+        };
+        mem.set_lcd_status(stat);
+        let line = lcd_on && stat.interrupt_line();
+        if line && !self.stat_line {
+            mem.request_interrupt(Interrupt::STAT);
         }
+        self.stat_line = line;
+    }
+    /// The objects drawn on `scanline`, the hardware picks at most 10 in OAM order
+    /// Read more: https://gbdev.io/pandocs/OAM.html#selection-priority
+    pub fn oam_scan(&mut self, mem: &Memory, lcdc: &LcdControl, scanline: u8) -> ScanlineObjects {
+        let height = lcdc.obj_size.height();
+        let mut objects = ScanlineObjects::default();
+        for entry in mem.oam_entries() {
+            if entry.on_scanline(scanline, height) && !objects.push(entry) {
+                break;
+            }
+        }
+        objects
+    }
+    /// Draw the objects on `scanline` over the background color ids in `bg`.
+    /// On DMG the object with the smaller X wins an overlap, then the one earlier in OAM,
+    /// and an object with the priority bit set is hidden behind background colors 1-3.
+    /// Read more: https://gbdev.io/pandocs/OAM.html#drawing-priority
+    // TODO: CGB BG map attributes can force the background on top as well
+    pub fn draw_objects(
+        &mut self,
+        mem: &Memory,
+        lcdc: &LcdControl,
+        scanline: u8,
+        bg: &[u8; SCREEN_WIDTH],
+        shades: &mut [u8; SCREEN_WIDTH],
+    ) {
+        let height = lcdc.obj_size.height() as usize;
+        let mut objects = self.oam_scan(mem, lcdc, scanline);
+        // stable, so objects at the same X stay in OAM order
+        objects.sort_by_key(|object| object.x);
+        let mut drawn = [false; SCREEN_WIDTH];
+        for object in objects.iter() {
+            let mut row = scanline as usize + 16 - object.y as usize;
+            if object.attrs.contains(OamAttributes::Y_FLIP) {
+                row = height - 1 - row;
+            }
+            let id = lcdc.obj_size.tile(object.tile, row);
+            let tile = mem.tile(id, TileAddressing::Unsigned).rows[row % 8];
+            let palette = match object.attrs.contains(OamAttributes::DMG_PALETTE) {
+                true => mem.io.get(OGBP1),
+                false => mem.io.get(OGBP0),
+            };
+            for column in 0..8 {
+                let Some(x) = (object.x as usize + column).checked_sub(8) else {
+                    continue;
+                };
+                if x >= SCREEN_WIDTH || drawn[x] {
+                    continue;
+                }
+                let column = match object.attrs.contains(OamAttributes::X_FLIP) {
+                    true => 7 - column,
+                    false => column,
+                };
+                let color = PALETTE
+                    .iter()
+                    .position(|shade| *shade == tile[7 - column])
+                    .unwrap_or(0);
+                // color 0 is transparent and lets the next object through
+                if color == 0 {
+                    continue;
+                }
+                drawn[x] = true;
+                if object.attrs.contains(OamAttributes::PRIORITY) && bg[x] != 0 {
+                    continue;
+                }
+                shades[x] = palette >> (color * 2) & 0x03;
+            }
+        }
+    }
+    /// The background color ids of `scanline`, the 160 pixels starting at SCX on map line
+    /// LY + SCY, both wrapping around the edges of the map
+    /// Read more: https://gbdev.io/pandocs/Scrolling.html
+    fn bg_line(&mut self, mem: &mut Memory, lcdc: &LcdControl, scanline: u8) -> [u8; SCREEN_WIDTH] {
+        let y = scanline.wrapping_add(mem.scy()) as usize;
+        let ids = self.bg_map_line(mem, lcdc, y);
+        let scx = mem.scx() as usize;
+        std::array::from_fn(|x| ids[(scx + x) % BG_SIZE])
+    }
+    /// The color ids of line `y` of the background map, drawn again only if the tile map row or
+    /// one of the tiles it was drawn from has been written since
+    fn bg_map_line(&mut self, mem: &mut Memory, lcdc: &LcdControl, y: usize) -> [u8; BG_SIZE] {
+        let writes = mem.take_vram_writes();
+        if !writes.is_empty() {
+            for (y, slot) in self.bg_lines.iter_mut().enumerate() {
+                let stale = slot.as_ref().is_some_and(|line| {
+                    writes.map_row(line.map, y / 8) || line.tiles.iter().any(|&t| writes.tile(t))
+                });
+                if stale {
+                    *slot = None;
+                }
+            }
+        }
+        let (map, addressing) = (lcdc.bg_tile_map, lcdc.tile_addressing);
+        if let Some(line) = &self.bg_lines[y] {
+            if line.map == map && line.addressing == addressing {
+                return line.ids;
+            }
+        }
+        let mut line = BgLine { map, addressing, tiles: [0; 32], ids: [0; BG_SIZE] };
+        for x in 0..32 {
+            let id = mem.tile_id(map, x, y / 8);
+            line.tiles[x] = addressing.address(id);
+            let tile = mem.tile(id, addressing).rows[y % 8];
+            for i in 0..8 {
+                // tiles come out of `decode_tile` already shaded, with the rightmost pixel first
+                let color = PALETTE.iter().position(|shade| *shade == tile[i]).unwrap_or(0);
+                line.ids[x * 8 + 7 - i] = color as u8;
+            }
+        }
+        self.bg_lines[y] = Some(line);
+        line.ids
     }
     pub fn update_scanline(
         &mut self,
@@ -74,82 +367,433 @@ impl Ppu {
         lcdc: &LcdControl,
         scanline: u8,
     ) -> [u8; 480] {
-        // scrolling positions
-        let scx = *mem.scx() as usize;
-        let scy = *mem.scy() as usize;
+        mem.sync_tiles();
         // 160 visible vertical pixels, 3 bytes per pixel
         let mut pixels: [u8; 480] = [0u8; 480];
-        let mut buffer_index = 480;
-        // let window_tilemap = mem.get_tile_map(lcdc.window_tile_map_area);
-        let bg_tilemap = mem.get_tile_map(lcdc.bg_tile_map_area);
-        let (tile_block_0, tile_block_1) = mem.get_tile_data(lcdc.tile_data_area);
-        // index into tilemap: there are 32x32 (1024) indices which represents all 256x256 pixels
-        // but only 160x144 pixels are visible at any given time, each tile is 8x8 pixels; when iterating
-        // over a scanline we only want to display the  pixels in the correct row (i think?)
         let y = scanline as usize;
-        for x in (0..20).rev() {
-            let tilemap = bg_tilemap[y / 8][x];
-            let tile = if tilemap < 127 {
-                tile_block_1[tilemap as usize][y % 8]
-            } else {
-                tile_block_0[tilemap as usize][y % 8]
-            };
-            for i in 0..8 {
-                let pixel = tile[i];
-                pixels[buffer_index-1] = pixel;
-                pixels[buffer_index-2] = pixel;
-                pixels[buffer_index-3] = pixel;
-                buffer_index -= 3;
+        // color ids, 0 is what objects with the priority bit can be seen through
+        let mut line = self.bg_line(mem, lcdc, scanline);
+        if self.window.scanline != Some(scanline) {
+            self.window.begin_line(mem, lcdc, scanline);
+        }
+        if let Some(start) = self.window.start {
+            let y = self.window.line as usize;
+            for x in start.max(0) as usize..SCREEN_WIDTH {
+                let column = (x as isize - start) as usize;
+                let map = lcdc.window_tile_map;
+                let tile = mem.tile_at(map, column / 8, y / 8, lcdc.tile_addressing);
+                let shade = tile.rows[y % 8][7 - column % 8];
+                line[x] = PALETTE.iter().position(|s| *s == shade).unwrap_or(0) as u8;
             }
         }
+        let mut shades = line;
+        if lcdc.obj_enable {
+            self.draw_objects(mem, lcdc, scanline, &line, &mut shades);
+        }
+        for (pixel, shade) in pixels.chunks_exact_mut(3).zip(shades) {
+            pixel.copy_from_slice(&self.palette[shade as usize]);
+        }
         pixels
     }
 }
 
-pub fn setup_ctx() -> Result<(Canvas<Window>, EventPump), Error> {
-    let sdl_context = sdl3::init()?;
-    let video_subsystem = sdl_context.video()?;
+/// Open a window of `width` x `height` pixels, failing rather than panicking when there's no
+/// display to open it on, e.g: on CI without Wayland or X11
+#[cfg(feature = "sdl")]
+pub fn setup_ctx(width: u32, height: u32) -> Result<(Canvas<Window>, EventPump), VideoError> {
+    fn window_error(e: impl std::fmt::Display) -> VideoError {
+        VideoError::Window(e.to_string())
+    }
+    let sdl_context = sdl3::init().map_err(window_error)?;
+    let video_subsystem = sdl_context.video().map_err(window_error)?;
     let window = video_subsystem
-        .window("test", 160, 144)
+        .window("test", width, height)
         .position_centered()
         .build()
-        .unwrap();
+        .map_err(window_error)?;
+    let event_pump = sdl_context.event_pump().map_err(window_error)?;
+    Ok((window.into_canvas(), event_pump))
+}
 
-    Ok((window.into_canvas(), sdl_context.event_pump()?))
+/// A window and its events, kept by the frontend rather than the ppu since neither can leave the
+/// thread that opened them
+#[cfg(feature = "sdl")]
+pub struct Screen {
+    pub canvas: Canvas<Window>,
+    pub event_pump: EventPump,
+}
+
+#[cfg(feature = "sdl")]
+impl Screen {
+    /// Open a window the size of the Game Boy's screen
+    pub fn new() -> Result<Self, VideoError> {
+        let (canvas, event_pump) = setup_ctx(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)?;
+        Ok(Self { canvas, event_pump })
+    }
 }
 
 mod tests {
 
-    use crate::{cartridge::{self, Cartridge}, decode_tile, dump_tiles, memory::Memory};
+    use crate::{cartridge::{self, Cartridge}, decode_tile, memory::Memory};
+    use crate::{oam::{ObjSize, OamEntry}, tile::{TileAddressing, TileMap}, vram_export};
     use super::{TILES, TILEMAP};
 
     #[test]
     fn test_decode() {
-        let mut image_buffer = vec![];
         let path = format!("{}/roms/rom.gb", env!("CARGO_MANIFEST_DIR"));
         let binary = std::fs::read(&path).unwrap();
         let cartridge = Cartridge::new(binary).unwrap();
         let mut memory = Memory::new(cartridge);
-        let mut lcdc = memory.lcd_control();
-        lcdc.tile_data_area = [[0x8800, 0x8fff], [0x9000, 0x97ff]];
         memory.block[0x9800..=0x9bff].copy_from_slice(&TILEMAP);
         memory.block[0x9000..0x9000+1120].copy_from_slice(&TILES);
-        let bg_tilemap = memory.get_tile_map(lcdc.bg_tile_map_area);
-        let (tile_block_0, tile_block_1) = memory.get_tile_data(lcdc.tile_data_area);
-        for y in 0..256 {
-            for x in (0..32).rev() {
-                let tilemap = bg_tilemap[y / 8][x];
-                for i in 0..8 {
-                    let pixel = if tilemap < 127 {
-                        tile_block_1[tilemap as usize][y % 8][i]
-                    } else {
-                        tile_block_0[tilemap as usize][y % 8][i]
-                    };
-                    image_buffer.push(pixel);
-                }
+        let shades = [[255; 3], [170; 3], [85; 3], [0; 3]];
+        let map = vram_export::tile_map(&memory, TileMap::Low, TileAddressing::Signed, &shades);
+        assert_eq!(map.pixels.len(), 256 * 256 * 3);
+    }
+
+    /// Draw objects on line 0 over a background that's color 0 on the left half and color 3 on
+    /// the right, the same situations the mealybug-tearoom priority tests check
+    fn priority_setup(objects: &[OamEntry]) -> [u8; super::SCREEN_WIDTH] {
+        use crate::memory::registers::OGBP0;
+
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        let lcdc = memory.lcd_control();
+        // object tile 1: left 4 pixels color 1, right 4 color 2
+        memory.block[0x8010] = 0xf0;
+        memory.block[0x8011] = 0x0f;
+        // identity palette so shades equal color ids
+        memory.io.set(OGBP0, 0xe4);
+        for (index, object) in objects.iter().enumerate() {
+            memory.set_oam_entry(index, *object);
+        }
+        let mut bg = [0u8; super::SCREEN_WIDTH];
+        bg[80..].fill(3);
+        let mut shades = bg;
+        ppu.draw_objects(&memory, &lcdc, 0, &bg, &mut shades);
+        shades
+    }
+
+    #[test]
+    fn test_bg_priority() {
+        use crate::oam::OamAttributes;
+
+        let object = |x, attrs| OamEntry { y: 16, x, tile: 1, attrs };
+        // drawn over both colors without the priority bit
+        let shades = priority_setup(&[
+            object(8, OamAttributes::empty()),
+            object(88, OamAttributes::empty()),
+        ]);
+        assert_eq!(shades[0..8], [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(shades[80..88], [1, 1, 1, 1, 2, 2, 2, 2]);
+        // with it, only visible over color 0
+        let shades = priority_setup(&[
+            object(8, OamAttributes::PRIORITY),
+            object(88, OamAttributes::PRIORITY),
+        ]);
+        assert_eq!(shades[0..8], [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(shades[80..88], [3; 8]);
+    }
+
+    #[test]
+    fn test_object_priority() {
+        use crate::oam::OamAttributes;
+
+        let object = |x, attrs| OamEntry { y: 16, x, tile: 1, attrs };
+        // the smaller X wins even when it's later in OAM
+        let shades = priority_setup(&[
+            object(12, OamAttributes::empty()),
+            object(8, OamAttributes::X_FLIP),
+        ]);
+        assert_eq!(shades[0..12], [2, 2, 2, 2, 1, 1, 1, 1, 2, 2, 2, 2]);
+        // a hidden object still hides the objects behind it
+        let shades = priority_setup(&[
+            object(84, OamAttributes::PRIORITY),
+            object(88, OamAttributes::empty()),
+        ]);
+        assert_eq!(shades[76..80], [1, 1, 1, 1]);
+        assert_eq!(shades[80..84], [3, 3, 3, 3]);
+        assert_eq!(shades[84..88], [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_tall_objects() {
+        use crate::{memory::registers::OGBP0, oam::OamAttributes};
+
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        let mut lcdc = memory.lcd_control();
+        lcdc.obj_size = ObjSize::Tall;
+        memory.set_lcd_control(lcdc);
+        // tile 2 is color 1 throughout and tile 3 color 2
+        memory.block[0x8020..0x8030].copy_from_slice(&[0xff, 0x00].repeat(8));
+        memory.block[0x8030..0x8040].copy_from_slice(&[0x00, 0xff].repeat(8));
+        memory.io.set(OGBP0, 0xe4);
+        let bg = [0u8; super::SCREEN_WIDTH];
+        let draw = |memory: &mut Memory, ppu: &mut super::Ppu, attrs, scanline| {
+            // bit 0 of the tile is ignored, the object still starts with tile 2
+            memory.set_oam_entry(0, OamEntry { y: 16, x: 8, tile: 3, attrs });
+            let mut shades = bg;
+            ppu.draw_objects(memory, &memory.lcd_control(), scanline, &bg, &mut shades);
+            shades[0]
+        };
+        assert_eq!(draw(&mut memory, &mut ppu, OamAttributes::empty(), 0), 1);
+        assert_eq!(draw(&mut memory, &mut ppu, OamAttributes::empty(), 15), 2);
+        // flipping swaps the halves too
+        assert_eq!(draw(&mut memory, &mut ppu, OamAttributes::Y_FLIP, 0), 2);
+        assert_eq!(draw(&mut memory, &mut ppu, OamAttributes::Y_FLIP, 15), 1);
+        assert_eq!(draw(&mut memory, &mut ppu, OamAttributes::empty(), 16), 0);
+    }
+
+    /// Render 20 scanlines with the window enabled on the lines where `enabled` says so.
+    /// The window's first tile is color 3, the rest of the window is color 1 apart from its
+    /// first tile row which is color 3 as well.
+    fn render_window(wx: u8, scx: u8, enabled: impl Fn(u8) -> bool) -> Vec<[u8; 480]> {
+        use crate::memory::registers::{LCDC, SCX, WX, WY};
+
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        let clock = crate::clock::Clock::new();
+        memory.block[0x8010..0x8020].copy_from_slice(&[0xff; 16]);
+        memory.block[0x8020..0x8030].copy_from_slice(&[0xff, 0x00].repeat(8));
+        memory.block[TileMap::High.address(0, 0)..][..64].fill(2);
+        memory.block[TileMap::High.address(0, 0)..][..32].fill(1);
+        memory.block[TileMap::High.address(1, 0)] = 2;
+        memory.io.set(WY, 0);
+        memory.io.set(WX, wx);
+        memory.io.set(SCX, scx);
+        (0..20)
+            .map(|scanline| {
+                // unsigned addressing, high window map, window on or off
+                let lcdc = 0xd1 | (enabled(scanline) as u8) << 5;
+                memory.io.set(LCDC, lcdc);
+                let lcdc = memory.lcd_control();
+                // the ppu renders a line several times, that mustn't advance the window twice
+                ppu.update_scanline(&mut memory, &clock, &lcdc, scanline);
+                ppu.update_scanline(&mut memory, &clock, &lcdc, scanline)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bg_line_cache() {
+        use crate::PALETTE;
+        use crate::memory::registers::LCDC;
+
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        let clock = crate::clock::Clock::new();
+        // background only, unsigned addressing and the low map
+        memory.io.set(LCDC, 0x91);
+        let lcdc = memory.lcd_control();
+        let render = |ppu: &mut super::Ppu, memory: &mut Memory| {
+            ppu.update_scanline(memory, &clock, &lcdc, 9)[0]
+        };
+        assert_eq!(render(&mut ppu, &mut memory), PALETTE[0]);
+        // tile 0, row 1 to color 3
+        memory.write(0x8002, 0xff);
+        memory.write(0x8003, 0xff);
+        assert_eq!(render(&mut ppu, &mut memory), PALETTE[3]);
+        // pointing the map row at another tile draws the line again
+        memory.write(TileMap::Low.address(0, 1), 1);
+        assert_eq!(render(&mut ppu, &mut memory), PALETTE[0]);
+        // writes behind `write`'s back aren't seen until the tiles are invalidated
+        memory.block[TileMap::Low.address(0, 1)] = 0;
+        assert_eq!(render(&mut ppu, &mut memory), PALETTE[0]);
+        memory.invalidate_tiles();
+        assert_eq!(render(&mut ppu, &mut memory), PALETTE[3]);
+    }
+
+    #[test]
+    fn test_bg_scroll_wrap() {
+        use crate::PALETTE;
+        use crate::memory::registers::{LCDC, SCX, SCY};
+
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        let clock = crate::clock::Clock::new();
+        // tile 1 is color 3, tile 2 color 1
+        memory.block[0x8010..0x8020].copy_from_slice(&[0xff; 16]);
+        memory.block[0x8020..0x8030].copy_from_slice(&[0xff, 0x00].repeat(8));
+        memory.block[TileMap::Low.address(31, 31)] = 1;
+        memory.block[TileMap::Low.address(0, 0)] = 2;
+        memory.io.set(LCDC, 0x91);
+        memory.io.set(SCX, 252);
+        memory.io.set(SCY, 252);
+        let lcdc = memory.lcd_control();
+        let lines: Vec<_> =
+            (0..8).map(|y| ppu.update_scanline(&mut memory, &clock, &lcdc, y)).collect();
+        // the bottom right corner of the map shows up in the top left corner of the screen
+        assert_eq!(shade(&lines, 3, 3), PALETTE[3]);
+        assert_eq!(shade(&lines, 4, 3), PALETTE[0]);
+        // with the top left corner of the map right after it in both directions
+        assert_eq!(shade(&lines, 4, 4), PALETTE[1]);
+        assert_eq!(shade(&lines, 3, 4), PALETTE[0]);
+        assert_eq!(shade(&lines, 12, 4), PALETTE[0]);
+        // and nothing from past the edges of the viewport
+        assert_eq!(shade(&lines, 159, 3), PALETTE[0]);
+    }
+
+    fn shade(lines: &[[u8; 480]], x: usize, y: usize) -> u8 {
+        lines[y][x * 3]
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        use crate::PALETTE;
+
+        let lines = render_window(7, 0, |scanline| !(4..10).contains(&scanline));
+        assert_eq!(shade(&lines, 0, 3), PALETTE[3]);
+        assert_eq!(shade(&lines, 0, 5), PALETTE[0]);
+        // the window picks up at its 5th row instead of skipping ahead to LY - WY
+        assert_eq!(shade(&lines, 0, 13), PALETTE[3]);
+        assert_eq!(shade(&lines, 0, 14), PALETTE[1]);
+    }
+
+    #[test]
+    fn test_window_wx_edge_cases() {
+        use crate::PALETTE;
+
+        let lines = render_window(20, 0, |_| true);
+        assert_eq!(shade(&lines, 12, 0), PALETTE[0]);
+        assert_eq!(shade(&lines, 13, 0), PALETTE[3]);
+        // WX=166 draws a single pixel and then covers the whole next line
+        let lines = render_window(166, 0, |_| true);
+        assert_eq!(shade(&lines, 158, 0), PALETTE[0]);
+        assert_eq!(shade(&lines, 159, 0), PALETTE[3]);
+        assert_eq!(shade(&lines, 0, 1), PALETTE[3]);
+        // WX=0 cuts off the first 7 pixels, and another SCX % 8 on top of that
+        let lines = render_window(0, 0, |_| true);
+        assert_eq!(shade(&lines, 0, 0), PALETTE[3]);
+        assert_eq!(shade(&lines, 1, 0), PALETTE[1]);
+        let lines = render_window(0, 3, |_| true);
+        assert_eq!(shade(&lines, 0, 0), PALETTE[1]);
+    }
+
+    #[test]
+    fn test_oam_scan() {
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        for index in 0..12 {
+            memory.set_oam_entry(index, OamEntry { y: 16, x: index as u8, ..Default::default() });
+        }
+        memory.set_oam_entry(12, OamEntry { y: 24, x: 12, ..Default::default() });
+        let mut lcdc = memory.lcd_control();
+        let mut ppu = super::Ppu::headless();
+        let objects = ppu.oam_scan(&memory, &lcdc, 0);
+        // only the first 10 are picked
+        assert_eq!(objects.len(), 10);
+        assert_eq!(objects[9].x, 9);
+        assert_eq!(*ppu.oam_scan(&memory, &lcdc, 8), [memory.oam_entry(12)]);
+        // 8x16 objects reach further down, pushing the last one out again
+        lcdc.obj_size = ObjSize::Tall;
+        assert_eq!(ppu.oam_scan(&memory, &lcdc, 8).last().unwrap().x, 9);
+    }
+
+    fn stat_requested(memory: &mut Memory) -> bool {
+        use crate::memory::registers::IF;
+        let requested = memory.io.get(IF) & 0x02 != 0;
+        memory.io.set(IF, 0xe0);
+        requested
+    }
+
+    #[test]
+    fn test_ppu_timing() {
+        use super::PpuMode;
+        use crate::memory::registers::IF;
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        memory.io.set(super::LY, 0);
+        memory.io.set(IF, 0xe0);
+        let modes: Vec<_> = [19, 1, 42, 1, 50]
+            .into_iter()
+            .map(|m_cycles| {
+                ppu.tick(&mut memory, m_cycles, true);
+                (ppu.mode, ppu.dot)
+            })
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                (PpuMode::OAMScan, 76),
+                (PpuMode::Drawing, 80),
+                (PpuMode::Drawing, 248),
+                (PpuMode::HorizontalBlank, 252),
+                (PpuMode::HorizontalBlank, 452)
+            ]
+        );
+        assert_eq!(memory.io.get(super::LY), 0);
+        // the dots left over carry into the next line
+        ppu.tick(&mut memory, 2, true);
+        assert_eq!((memory.io.get(super::LY), ppu.dot, ppu.mode), (1, 4, PpuMode::OAMScan));
+        assert!(!ppu.tick(&mut memory, 114 * 142, true));
+        assert_eq!(memory.io.get(IF), 0xe0);
+        // entering VBlank completes the frame
+        assert!(ppu.tick(&mut memory, 114, true));
+        assert_eq!((memory.io.get(super::LY), ppu.mode), (144, PpuMode::VerticalBlank));
+        assert_eq!(memory.io.get(IF), 0xe1);
+        ppu.tick(&mut memory, 114 * 10, true);
+        assert_eq!((memory.io.get(super::LY), ppu.mode), (0, PpuMode::OAMScan));
+        // nothing moves with the LCD off
+        ppu.tick(&mut memory, 114, false);
+        assert_eq!((memory.io.get(super::LY), ppu.dot), (0, 4));
+    }
+
+    #[test]
+    fn test_stat_interrupt() {
+        use super::{PpuMode, STAT};
+        let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = super::Ppu::headless();
+        memory.io.set(super::LY, 1);
+        // mode 0 selected
+        memory.write(STAT, 0x08);
+        ppu.mode = PpuMode::Drawing;
+        ppu.update_stat(&mut memory, true);
+        assert_eq!(memory.read(STAT), 0x8b);
+        assert!(!stat_requested(&mut memory));
+        ppu.mode = PpuMode::HorizontalBlank;
+        ppu.update_stat(&mut memory, true);
+        assert_eq!(memory.read(STAT), 0x88);
+        assert!(stat_requested(&mut memory));
+        // only the rising edge requests it
+        ppu.update_stat(&mut memory, true);
+        assert!(!stat_requested(&mut memory));
+        // LY=LYC selected as well keeps the line high through the mode change
+        memory.write(STAT, 0x48);
+        memory.io.set(super::LYC, 1);
+        ppu.mode = PpuMode::OAMScan;
+        ppu.update_stat(&mut memory, true);
+        assert_eq!(memory.read(STAT), 0xce);
+        assert!(!stat_requested(&mut memory));
+        ppu.update_stat(&mut memory, false);
+        assert_eq!(memory.read(STAT) & 0x03, 0);
+        assert!(!ppu.stat_line);
+    }
+
+    #[test]
+    fn test_stat_write_bug() {
+        use super::{PpuMode, STAT};
+        for (quirk, mode, requested) in [
+            (true, PpuMode::HorizontalBlank, true),
+            (true, PpuMode::VerticalBlank, true),
+            (true, PpuMode::OAMScan, false),
+            (false, PpuMode::HorizontalBlank, false),
+        ] {
+            let mut memory = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+            let mut ppu = super::Ppu::headless();
+            ppu.stat_write_bug = quirk;
+            ppu.mode = mode;
+            memory.io.set(super::LY, 1);
+            ppu.update_stat(&mut memory, true);
+            stat_requested(&mut memory);
+            // no source selected, only the bug can request it
+            memory.write(STAT, 0x00);
+            for event in memory.io.drain_events().collect::<Vec<_>>() {
+                ppu.io_event(event, &mut memory);
             }
+            assert_eq!(stat_requested(&mut memory), requested, "{mode:?}");
+            ppu.update_stat(&mut memory, true);
+            assert!(!ppu.stat_line);
         }
-        dump_tiles(image_buffer, 256, 256);
     }
 }
 
@@ -60,11 +60,19 @@ impl std::fmt::Display for SystemError {
 pub enum CpuError {
     MissingOpcodeByte,
     NoCycles,
+    /// The instruction starting with this opcode couldn't be decoded
+    Decode(u8, DecodeError),
 }
 
+impl std::error::Error for CpuError {}
+
 impl std::fmt::Display for CpuError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        match self {
+            Self::MissingOpcodeByte => write!(f, "Missing opcode byte"),
+            Self::NoCycles => write!(f, "Instruction took no cycles"),
+            Self::Decode(opcode, e) => write!(f, "Couldn't decode opcode 0x{opcode:02x}: {e}"),
+        }
     }
 }
 
@@ -92,3 +100,218 @@ impl std::fmt::Display for CartridgeError {
         }
     }
 }
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(std::io::Error),
+    #[cfg(feature = "json")]
+    Index(serde_json::Error),
+    NotAState,
+    Truncated,
+    UnsupportedFormat(u8),
+    RomMismatch { expected: u16, found: u16 },
+    InvalidSlot(u8),
+}
+
+impl std::error::Error for StateError {}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "json")]
+            Self::Index(e) => write!(f, "Invalid slot index: {e}"),
+            Self::NotAState => write!(f, "Not a save state"),
+            Self::Truncated => write!(f, "Save state is truncated"),
+            Self::UnsupportedFormat(version) => {
+                write!(f, "Unsupported save state format: {version}")
+            }
+            Self::RomMismatch { expected, found } => {
+                write!(f, "State is for ROM 0x{found:04x}, not 0x{expected:04x}")
+            }
+            Self::InvalidSlot(slot) => write!(f, "No slot {slot}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for StateError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for StateError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Index(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(std::io::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    RomMismatch { expected: u16, found: u16 },
+}
+
+impl std::error::Error for MovieError {}
+
+impl std::fmt::Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "json")]
+            Self::Json(e) => write!(f, "Invalid movie: {e}"),
+            Self::RomMismatch { expected, found } => {
+                write!(f, "Movie is for ROM 0x{found:04x}, not 0x{expected:04x}")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for MovieError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for ConfigError {}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "json")]
+            Self::Json(e) => write!(f, "Invalid config: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum GameDbError {
+    Io(std::io::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for GameDbError {}
+
+impl std::fmt::Display for GameDbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            #[cfg(feature = "json")]
+            Self::Json(e) => write!(f, "Invalid game database: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for GameDbError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for GameDbError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(std::io::Error),
+    NotGbr,
+    RomMismatch { expected: u16, found: u16 },
+    SeedMismatch { expected: u64, found: u64 },
+}
+
+impl std::error::Error for NetplayError {}
+
+impl std::fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::NotGbr => write!(f, "The other side isn't a gbr instance"),
+            Self::RomMismatch { expected, found } => {
+                write!(f, "The other side runs ROM 0x{found:04x}, not 0x{expected:04x}")
+            }
+            Self::SeedMismatch { expected, found } => {
+                write!(f, "The other side uses seed {found}, not {expected}")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for NetplayError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    /// The instance at this index couldn't be created from its ROM
+    System(usize, SystemError),
+    /// The instance at this index couldn't be restored from its state
+    State(usize, StateError),
+}
+
+impl std::error::Error for BatchError {}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::System(index, e) => write!(f, "Instance {index}: {e}"),
+            Self::State(index, e) => write!(f, "Instance {index}: {e}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VideoError {
+    /// The window or the surface drawn into it couldn't be created
+    Window(String),
+    /// A frame couldn't be drawn into the window
+    Present(String),
+}
+
+impl std::error::Error for VideoError {}
+
+impl std::fmt::Display for VideoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Window(e) => write!(f, "Couldn't open a window: {e}"),
+            Self::Present(e) => write!(f, "Couldn't draw a frame: {e}"),
+        }
+    }
+}
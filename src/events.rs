@@ -0,0 +1,68 @@
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use crate::{cartridge::RomSizeMismatch, watchdog::Stall};
+
+/// Something the core did that frontends, scripts and tests may want to react to without
+/// polling memory, see `System::subscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreEvent {
+    /// The frame with this number was completed, after movie and netplay input were applied
+    FrameDone(u64),
+    /// The cpu jumped to the vblank interrupt handler
+    VBlank,
+    /// A serial transfer completed, in either direction
+    SerialByte { sent: u8, received: u8 },
+    /// The cpu is about to execute the instruction at a breakpoint
+    Breakpoint(u16),
+    /// The watchdog caught the cpu stuck in a loop
+    Stalled(Stall),
+    /// The ROM file isn't the size its header declares, emitted before the first instruction
+    RomSizeMismatch(RomSizeMismatch),
+}
+
+/// Hands every event to each subscriber, subscribers that went away are forgotten
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Vec<Sender<CoreEvent>>,
+}
+
+impl EventBus {
+    /// A receiver that gets every event emitted from now on, drain it with `try_iter`
+    pub fn subscribe(&mut self) -> Receiver<CoreEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn emit(&mut self, event: CoreEvent) {
+        self.subscribers.retain(|subscriber| subscriber.send(event).is_ok());
+    }
+
+    /// Whether anything is listening, so events that take work to produce can be skipped
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribers() {
+        let mut bus = EventBus::default();
+        bus.emit(CoreEvent::VBlank);
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.emit(CoreEvent::FrameDone(1));
+        drop(second);
+        bus.emit(CoreEvent::Breakpoint(0x150));
+        assert_eq!(
+            first.try_iter().collect::<Vec<_>>(),
+            vec![CoreEvent::FrameDone(1), CoreEvent::Breakpoint(0x150)]
+        );
+        assert!(!bus.is_empty());
+        drop(first);
+        bus.emit(CoreEvent::VBlank);
+        assert!(bus.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use crate::{
+    cartridge::{CARTRIDGE_TYPE, Cartridge, CartridgeType, RAM_SIZE, RamSize},
+    errors::{CartridgeError, GameDbError},
+};
+
+/// A known good dump and the hardware it really runs on
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameEntry {
+    /// CRC-32 of the whole ROM as 8 hex digits, like in No-Intro DAT files
+    pub crc32: String,
+    pub title: String,
+    /// The correct cartridge type and RAM size header bytes
+    pub cartridge_type: u8,
+    pub ram_size: u8,
+}
+
+/// A header byte that disagrees with the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderMismatch {
+    CartridgeType { header: u8, known: u8 },
+    RamSize { header: u8, known: u8 },
+}
+
+impl std::fmt::Display for HeaderMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CartridgeType { header, known } => write!(
+                f,
+                "Header says cartridge type 0x{header:02x}, the game uses 0x{known:02x}"
+            ),
+            Self::RamSize { header, known } => {
+                write!(f, "Header says RAM size 0x{header:02x}, the game uses 0x{known:02x}")
+            }
+        }
+    }
+}
+
+impl GameEntry {
+    /// Header bytes of `rom` that don't match this entry, bootlegs often lie about their mapper
+    pub fn mismatches(&self, rom: &[u8]) -> Vec<HeaderMismatch> {
+        let mut mismatches = vec![];
+        if rom[CARTRIDGE_TYPE] != self.cartridge_type {
+            mismatches.push(HeaderMismatch::CartridgeType {
+                header: rom[CARTRIDGE_TYPE],
+                known: self.cartridge_type,
+            });
+        }
+        if rom[RAM_SIZE] != self.ram_size {
+            mismatches.push(HeaderMismatch::RamSize {
+                header: rom[RAM_SIZE],
+                known: self.ram_size,
+            });
+        }
+        mismatches
+    }
+
+    pub fn cartridge_type(&self) -> Result<CartridgeType, CartridgeError> {
+        CartridgeType::try_from(self.cartridge_type)
+    }
+
+    pub fn ram_size(&self) -> Result<RamSize, CartridgeError> {
+        RamSize::try_from(self.ram_size)
+    }
+}
+
+/// ROMs identified by their CRC-32, loaded from a json file:
+/// ```json
+/// [{ "crc32": "0123abcd", "title": "Some Game", "cartridge_type": 3, "ram_size": 2 }]
+/// ```
+/// Nothing is embedded, an empty database knows no games.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(transparent))]
+pub struct GameDb {
+    pub entries: Vec<GameEntry>,
+}
+
+impl GameDb {
+    #[cfg(feature = "json")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GameDbError> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn lookup(&self, cartridge: &Cartridge) -> Option<&GameEntry> {
+        let crc = format!("{:08x}", cartridge.crc32());
+        self.entries.iter().find(|entry| entry.crc32.eq_ignore_ascii_case(&crc))
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::hash::crc32;
+
+    #[test]
+    fn test_lookup() {
+        let mut rom = vec![0; 0xffff];
+        // the header claims ROM only without RAM
+        let db = GameDb {
+            entries: vec![GameEntry {
+                crc32: format!("{:08X}", crc32(&rom)),
+                title: "Bootleg".into(),
+                cartridge_type: 0x03,
+                ram_size: 0x02,
+            }],
+        };
+        let entry = db.lookup(&Cartridge::new(rom.clone()).unwrap()).unwrap();
+        assert_eq!(
+            entry.mismatches(&rom),
+            vec![
+                HeaderMismatch::CartridgeType { header: 0x00, known: 0x03 },
+                HeaderMismatch::RamSize { header: 0x00, known: 0x02 },
+            ]
+        );
+        assert!(matches!(
+            entry.cartridge_type(),
+            Ok(CartridgeType::MBC1 { ram: true, battery: true })
+        ));
+        rom[0] = 1;
+        assert!(db.lookup(&Cartridge::new(rom).unwrap()).is_none());
+    }
+}
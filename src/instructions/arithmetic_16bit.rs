@@ -5,46 +5,22 @@ use crate::{
 
 use super::{Instruction, InstructionResult};
 
-pub fn add_16bit(a: u16, b: u16, carry_flag: Option<bool>) -> (u16, u8) {
-    let carry = match carry_flag {
-        Some(num) => num as u16,
-        None => 0,
-    };
-    let half_carry = ((a & 0x00ff) + (b & 0x00ff) & 0x0100) == 0x0100;
-    let (sum, carry) = a.overflowing_add(b + carry);
-    let mut flags: u8 = 0;
-    flags |= ((sum == 0) as u8) << 7;
-    flags |= 0 << 6;
-    flags |= (half_carry as u8) << 5;
-    flags |= (carry as u8) << 4;
-    (sum, flags)
-}
-
-pub fn sub_16bit(a: u16, b: u16, carry_flag: Option<bool>) -> (u16, u8) {
-    let carry = match carry_flag {
-        Some(num) => num as u16,
-        None => 0,
-    };
-    let a_mask = a as i32 & 0x00ff;
-    let b_mask = b as i32 & 0x00ff;
-    let half_carry = a_mask - b_mask > 0;
-    let (sum, carry) = a.overflowing_sub(b - carry);
-    let mut flags: u8 = 0;
-    flags |= ((sum == 0) as u8) << 7;
-    flags |= 0 << 6;
-    flags |= (half_carry as u8) << 5;
-    flags |= (carry as u8) << 4;
-    (sum, flags)
+/// HL + n16 as used by ADD HL,r16 and ADD HL,SP
+/// Z is left alone, N is reset, H and C are set from carries out of bits 11 and 15
+pub fn add_hl(n16: u16, cpu: &mut Cpu) {
+    let hl = cpu.registers.hl;
+    let (sum, carry) = hl.overflowing_add(n16);
+    cpu.registers.flags.subtraction = false;
+    cpu.registers.flags.half_carry = (hl & 0x0fff) + (n16 & 0x0fff) > 0x0fff;
+    cpu.registers.flags.carry = carry;
+    cpu.registers.set_r16(R16::HL, sum);
 }
 
 /// ADD HL, r16
 /// Add the value in r16 to HL
+/// Flags: Z unaffected, N reset, H set on carry out of bit 11, C set on carry out of bit 15
 pub fn add_r16_hl(r16: R16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    let r16 = cpu.registers.get_r16(r16);
-    let hl = cpu.registers.hl;
-    let (sum, flags) = add_16bit(r16, hl, None);
-    cpu.registers.set_r16(R16::HL, sum);
-    cpu.registers.flags.set(flags);
+    add_hl(cpu.registers.get_r16(r16), cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::ADD,
@@ -55,10 +31,10 @@ pub fn add_r16_hl(r16: R16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 
 /// DEC r16
 /// Decrement the value in register r16 by 1.
+/// Flags are not affected.
 pub fn dec_r16(r16: R16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let reg = cpu.registers.get_r16(r16);
-    let (sum, _) = sub_16bit(reg, 1, None);
-    cpu.registers.set_r16(r16, sum);
+    cpu.registers.set_r16(r16, reg.wrapping_sub(1));
     if r16 != R16::PC {
         cpu.registers.pc += 1;
     }
@@ -71,10 +47,10 @@ pub fn dec_r16(r16: R16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 
 /// INC r16
 /// Increment the value in register r16 by 1.
+/// Flags are not affected.
 pub fn inc_r16(r16: R16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let reg = cpu.registers.get_r16(r16);
-    let (sum, _) = add_16bit(reg, 1, None);
-    cpu.registers.set_r16(r16, sum);
+    cpu.registers.set_r16(r16, reg.wrapping_add(1));
     if r16 != R16::PC {
         cpu.registers.pc += 1;
     }
@@ -93,8 +69,66 @@ mod tests {
         let mut cpu = Cpu::default();
         cpu.registers.set_r16(R16::BC, 0xfffe);
         cpu.registers.set_r16(R16::HL, 0x0002);
+        cpu.registers.flags.zero = false;
         let _ = add_r16_hl(R16::BC, &mut cpu);
         assert_eq!(cpu.registers.hl, 0);
-        // assert_eq!(cpu.get_r8(Register8::Flags), 0xb0);
+        // Z is untouched even though the result is zero
+        assert!(!cpu.registers.flags.zero);
+        assert!(!cpu.registers.flags.subtraction);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(cpu.registers.flags.carry);
+        cpu.registers.set_r16(R16::HL, 0x0fff);
+        cpu.registers.flags.subtraction = true;
+        add_r16_hl(R16::HL, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.hl, 0x1ffe);
+        assert!(!cpu.registers.flags.subtraction);
+        assert!(cpu.registers.flags.half_carry);
+        assert!(!cpu.registers.flags.carry);
+    }
+
+    #[test]
+    fn test_inc_dec_r16_flags() {
+        for flags in [0x00, 0xf0] {
+            let mut cpu = Cpu::default();
+            cpu.registers.flags.set(flags);
+            let flags = cpu.registers.flags;
+            cpu.registers.set_r16(R16::BC, 0xffff);
+            inc_r16(R16::BC, &mut cpu).unwrap();
+            assert_eq!(cpu.registers.bc, 0x0000);
+            assert_eq!(cpu.registers.flags, flags);
+            dec_r16(R16::BC, &mut cpu).unwrap();
+            assert_eq!(cpu.registers.bc, 0xffff);
+            assert_eq!(cpu.registers.flags, flags);
+            cpu.registers.set_r16(R16::HL, 0x0fff);
+            inc_r16(R16::HL, &mut cpu).unwrap();
+            assert_eq!(cpu.registers.hl, 0x1000);
+            assert_eq!(cpu.registers.flags, flags);
+            dec_r16(R16::HL, &mut cpu).unwrap();
+            assert_eq!(cpu.registers.hl, 0x0fff);
+            assert_eq!(cpu.registers.flags, flags);
+            assert_eq!(cpu.registers.pc, 0x0104);
+        }
     }
+
+    #[test]
+    fn test_inc_dec_r16_decoding() {
+        use crate::{cartridge::Cartridge, memory::Memory};
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        // INC BC ; DEC BC ; INC DE ; DEC DE ; INC HL ; DEC HL ; INC SP ; DEC SP
+        mem.rom()[0x0100..0x0108]
+            .copy_from_slice(&[0x03, 0x0b, 0x13, 0x1b, 0x23, 0x2b, 0x33, 0x3b]);
+        cpu.registers.flags.set(0xf0);
+        let flags = cpu.registers.flags;
+        for r16 in [R16::BC, R16::DE, R16::HL, R16::SP] {
+            let before = cpu.registers.get_r16(r16);
+            assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+            assert_eq!(cpu.registers.get_r16(r16), before.wrapping_add(1), "{r16:?}");
+            assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+            assert_eq!(cpu.registers.get_r16(r16), before, "{r16:?}");
+        }
+        assert_eq!(cpu.registers.flags, flags);
+    }
+
+    crate::sm83_tests!(x03, x09, x0b, x13, x19, x1b, x23, x29, x2b, x33, x39, x3b);
 }
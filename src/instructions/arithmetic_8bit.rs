@@ -11,40 +11,35 @@ pub fn add_8bit(a: u8, b: u8, carry_flag: Option<bool>) -> (u8, u8) {
         Some(num) => num as u8,
         None => 0,
     };
-    // https://stackoverflow.com/a/57822729 thanks
-    let b = b + carry;
-    let half_carry = ((a & 0x0f) + (b & 0x0f) & 0x10) == 0x10;
-    let (sum, carry) = a.overflowing_add(b);
+    // widen so that adding the carry to 0xff can't overflow before the flags are computed
+    let sum = a as u16 + b as u16 + carry as u16;
+    let half_carry = (a & 0x0f) + (b & 0x0f) + carry > 0x0f;
     let mut flags: u8 = 0;
     // set the zero flag if sum == 0
-    flags |= ((sum == 0) as u8) << 7;
+    flags |= ((sum as u8 == 0) as u8) << 7;
     // set the subtraction flag to false
     flags |= 0 << 6;
     // set the half carry flag
     flags |= (half_carry as u8) << 5;
     // set the carry flag
-    flags |= (carry as u8) << 4;
-    (sum, flags)
+    flags |= ((sum > 0xff) as u8) << 4;
+    (sum as u8, flags)
 }
 
-// TODO: this is kind of a mess
 pub fn sub_8bit(a: u8, b: u8, carry_flag: Option<bool>) -> (u8, u8) {
     let carry = match carry_flag {
-        Some(num) => num as u8,
+        Some(num) => num as i16,
         None => 0,
     };
-    let a_mask = a as i16 & 0x0f;
-    let b_mask = b as i16 & 0x0f;
-    let half_carry = a_mask - b_mask < 0;
-    let (sum, overflows) = a.overflowing_sub(b - carry);
-    let carry = b >= sum;
+    // a borrow out of bit 4 (half carry) or bit 8 (carry) leaves a negative difference
+    let difference = a as i16 - b as i16 - carry;
+    let half_carry = (a & 0x0f) as i16 - (b & 0x0f) as i16 - carry < 0;
     let mut flags: u8 = 0;
-    let is_zero = overflows || sum == 0;
-    flags |= (is_zero as u8) << 7;
+    flags |= ((difference as u8 == 0) as u8) << 7;
     flags |= 1 << 6;
     flags |= (half_carry as u8) << 5;
-    flags |= (carry as u8) << 4;
-    (sum, flags)
+    flags |= ((difference < 0) as u8) << 4;
+    (difference as u8, flags)
 }
 
 /// ADC A,r8
@@ -53,7 +48,7 @@ pub fn adc_a_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let r8 = cpu.registers.get_r8(r8);
     let (sum, flags) = add_8bit(a, r8, Some(cpu.registers.flags.carry));
-    cpu.registers.a = sum;
+    cpu.registers.set_r8(R8::A, sum);
     cpu.registers.flags.set(flags);
     cpu.registers.pc += 1;
     Ok(Instruction {
@@ -136,10 +131,10 @@ pub fn add_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let (sum, flags) = add_8bit(a, n8, None);
     cpu.registers.set_r8(R8::A, sum);
     cpu.registers.flags.set(flags);
-    cpu.registers.pc += 1;
+    cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::ADD,
-        bytes: 1,
+        bytes: 2,
         cycles: 2,
     })
 }
@@ -175,7 +170,7 @@ pub fn cp_a_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
     Ok(Instruction {
         mnemonic: Mnemonic::CP,
         bytes: 1,
-        cycles: 1,
+        cycles: 2,
     })
 }
 
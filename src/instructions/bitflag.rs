@@ -11,8 +11,8 @@ use super::Instruction;
 /// Test bit u3 in register r8, set the zero flag if bit not set.
 pub fn bit_u3_r8(u3: u8, r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let r8 = cpu.registers.get_r8(r8);
-    let bit = (r8 >> u3) & u3;
-    cpu.registers.flags.zero = bit == 1;
+    let bit = (r8 >> u3) & 1;
+    cpu.registers.flags.zero = bit == 0;
     cpu.registers.flags.subtraction = false;
     cpu.registers.flags.half_carry = true;
     cpu.registers.pc += 2;
@@ -28,8 +28,8 @@ pub fn bit_u3_r8(u3: u8, r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction
 pub fn bit_u3_hl(u3: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
-    let bit = (byte >> u3) & u3;
-    cpu.registers.flags.zero = bit == 1;
+    let bit = (byte >> u3) & 1;
+    cpu.registers.flags.zero = bit == 0;
     cpu.registers.flags.subtraction = false;
     cpu.registers.flags.half_carry = true;
     cpu.registers.pc += 2;
@@ -44,7 +44,7 @@ pub fn bit_u3_hl(u3: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<I
 /// Set bit u3 in register r8 to 0. Bit 0 is the rightmost one, bit 7 the leftmost one.
 pub fn res_u3_r8(u3: u8, r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let mut reg = cpu.registers.get_r8(r8);
-    reg |= 0 << u3;
+    reg &= !(1 << u3);
     cpu.registers.set_r8(r8, reg);
     cpu.registers.pc += 2;
     Ok(Instruction {
@@ -59,7 +59,7 @@ pub fn res_u3_r8(u3: u8, r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction
 pub fn res_u3_hl(u3: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let mut byte = mem.read(hl as usize);
-    byte |= 0 << u3;
+    byte &= !(1 << u3);
     mem.write(hl as usize, byte);
     cpu.registers.pc += 2;
     Ok(Instruction {
@@ -97,3 +97,117 @@ pub fn set_u3_hl(u3: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<I
         cycles: 4,
     })
 }
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    const REGISTERS: [R8; 7] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::A];
+
+    fn setup() -> (Cpu, Memory) {
+        let mut cpu = Cpu::default();
+        cpu.registers.set_r16(R16::HL, 0xc000);
+        (cpu, Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap()))
+    }
+
+    #[test]
+    fn test_bit() {
+        let (mut cpu, mut mem) = setup();
+        for u3 in 0..8 {
+            for r8 in REGISTERS {
+                cpu.registers.set_r8(r8, 1 << u3);
+                bit_u3_r8(u3, r8, &mut cpu).unwrap();
+                assert!(!cpu.registers.flags.zero, "BIT {u3},{r8:?}");
+                cpu.registers.set_r8(r8, !(1 << u3));
+                bit_u3_r8(u3, r8, &mut cpu).unwrap();
+                assert!(cpu.registers.flags.zero, "BIT {u3},{r8:?}");
+                assert!(!cpu.registers.flags.subtraction);
+                assert!(cpu.registers.flags.half_carry);
+            }
+            cpu.registers.set_r16(R16::HL, 0xc000);
+            mem.write(0xc000, 1 << u3);
+            bit_u3_hl(u3, &mut cpu, &mut mem).unwrap();
+            assert!(!cpu.registers.flags.zero, "BIT {u3},[HL]");
+            mem.write(0xc000, !(1 << u3));
+            bit_u3_hl(u3, &mut cpu, &mut mem).unwrap();
+            assert!(cpu.registers.flags.zero, "BIT {u3},[HL]");
+        }
+    }
+
+    #[test]
+    fn test_res_set() {
+        let (mut cpu, mut mem) = setup();
+        for u3 in 0..8 {
+            for r8 in REGISTERS {
+                cpu.registers.set_r8(r8, 0xff);
+                res_u3_r8(u3, r8, &mut cpu).unwrap();
+                assert_eq!(cpu.registers.get_r8(r8), !(1 << u3), "RES {u3},{r8:?}");
+                cpu.registers.set_r8(r8, 0x00);
+                set_u3_r8(u3, r8, &mut cpu).unwrap();
+                assert_eq!(cpu.registers.get_r8(r8), 1 << u3, "SET {u3},{r8:?}");
+            }
+            cpu.registers.set_r16(R16::HL, 0xc000);
+            mem.write(0xc000, 0xff);
+            res_u3_hl(u3, &mut cpu, &mut mem).unwrap();
+            assert_eq!(mem.read(0xc000), !(1 << u3), "RES {u3},[HL]");
+            mem.write(0xc000, 0x00);
+            set_u3_hl(u3, &mut cpu, &mut mem).unwrap();
+            assert_eq!(mem.read(0xc000), 1 << u3, "SET {u3},[HL]");
+        }
+    }
+
+    #[test]
+    fn test_prefix_table() {
+        // $CB $40-$FF: BIT, RES and SET with the bit in bits 3-5 and the operand in bits 0-2
+        for opcode in 0x40..=0xffu8 {
+            let (mut cpu, mut mem) = setup();
+            let u3 = (opcode >> 3) & 0x07;
+            let operand = opcode & 0x07;
+            let initial = match opcode >> 6 {
+                // BIT and RES start with every bit set, SET with none
+                0b11 => 0x00,
+                _ => 0xff,
+            };
+            match operand {
+                6 => mem.write(0xc000, initial),
+                7 => cpu.registers.set_r8(R8::A, initial),
+                _ => cpu.registers.set_r8(REGISTERS[operand as usize], initial),
+            }
+            mem.rom()[0x0100..0x0102].copy_from_slice(&[0xcb, opcode]);
+            cpu.execute(&mut mem).unwrap();
+            assert_eq!(cpu.registers.pc, 0x0102, "$CB ${opcode:02x}");
+            let value = match operand {
+                6 => mem.read(0xc000),
+                7 => cpu.registers.a,
+                _ => cpu.registers.get_r8(REGISTERS[operand as usize]),
+            };
+            match opcode >> 6 {
+                0b01 => {
+                    assert_eq!(value, 0xff, "$CB ${opcode:02x}");
+                    assert!(!cpu.registers.flags.zero, "$CB ${opcode:02x}");
+                }
+                0b10 => assert_eq!(value, !(1 << u3), "$CB ${opcode:02x}"),
+                _ => assert_eq!(value, 1 << u3, "$CB ${opcode:02x}"),
+            }
+        }
+    }
+
+    crate::sm83_tests!(
+        cb_40, cb_41, cb_42, cb_43, cb_44, cb_45, cb_46, cb_47, cb_48, cb_49, cb_4a, cb_4b,
+        cb_4c, cb_4d, cb_4e, cb_4f, cb_50, cb_51, cb_52, cb_53, cb_54, cb_55, cb_56, cb_57,
+        cb_58, cb_59, cb_5a, cb_5b, cb_5c, cb_5d, cb_5e, cb_5f, cb_60, cb_61, cb_62, cb_63,
+        cb_64, cb_65, cb_66, cb_67, cb_68, cb_69, cb_6a, cb_6b, cb_6c, cb_6d, cb_6e, cb_6f,
+        cb_70, cb_71, cb_72, cb_73, cb_74, cb_75, cb_76, cb_77, cb_78, cb_79, cb_7a, cb_7b,
+        cb_7c, cb_7d, cb_7e, cb_7f, cb_80, cb_81, cb_82, cb_83, cb_84, cb_85, cb_86, cb_87,
+        cb_88, cb_89, cb_8a, cb_8b, cb_8c, cb_8d, cb_8e, cb_8f, cb_90, cb_91, cb_92, cb_93,
+        cb_94, cb_95, cb_96, cb_97, cb_98, cb_99, cb_9a, cb_9b, cb_9c, cb_9d, cb_9e, cb_9f,
+        cb_a0, cb_a1, cb_a2, cb_a3, cb_a4, cb_a5, cb_a6, cb_a7, cb_a8, cb_a9, cb_aa, cb_ab,
+        cb_ac, cb_ad, cb_ae, cb_af, cb_b0, cb_b1, cb_b2, cb_b3, cb_b4, cb_b5, cb_b6, cb_b7,
+        cb_b8, cb_b9, cb_ba, cb_bb, cb_bc, cb_bd, cb_be, cb_bf, cb_c0, cb_c1, cb_c2, cb_c3,
+        cb_c4, cb_c5, cb_c6, cb_c7, cb_c8, cb_c9, cb_ca, cb_cb, cb_cc, cb_cd, cb_ce, cb_cf,
+        cb_d0, cb_d1, cb_d2, cb_d3, cb_d4, cb_d5, cb_d6, cb_d7, cb_d8, cb_d9, cb_da, cb_db,
+        cb_dc, cb_dd, cb_de, cb_df, cb_e0, cb_e1, cb_e2, cb_e3, cb_e4, cb_e5, cb_e6, cb_e7,
+        cb_e8, cb_e9, cb_ea, cb_eb, cb_ec, cb_ed, cb_ee, cb_ef, cb_f0, cb_f1, cb_f2, cb_f3,
+        cb_f4, cb_f5, cb_f6, cb_f7, cb_f8, cb_f9, cb_fa, cb_fb, cb_fc, cb_fd, cb_fe, cb_ff,
+    );
+}
@@ -290,8 +290,8 @@ pub fn rrca(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// Shift Left Arithmetically register r8.
 pub fn sla_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let mut reg = cpu.registers.get_r8(r8);
-    reg <<= 1;
     let msb = (reg & 0x80) >> 7;
+    reg <<= 1;
     cpu.registers.flags.zero = reg == 0;
     cpu.registers.flags.subtraction = false;
     cpu.registers.flags.half_carry = false;
@@ -310,8 +310,8 @@ pub fn sla_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 pub fn sla_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let mut byte = mem.read(hl as usize);
-    byte <<= 1;
     let msb = (byte & 0x80) >> 7;
+    byte <<= 1;
     cpu.registers.flags.zero = byte == 0;
     cpu.registers.flags.subtraction = false;
     cpu.registers.flags.half_carry = false;
@@ -393,8 +393,8 @@ pub fn srl_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 pub fn srl_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let mut byte = mem.read(hl as usize);
-    byte >>= 1;
     let lsb = byte & 1;
+    byte >>= 1;
     cpu.registers.flags.zero = byte == 0;
     cpu.registers.flags.subtraction = false;
     cpu.registers.flags.half_carry = false;
@@ -441,7 +441,7 @@ pub fn swap_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
     Ok(Instruction {
         mnemonic: Mnemonic::SWAP,
         bytes: 2,
-        cycles: 2,
+        cycles: 4,
     })
 }
 
@@ -717,4 +717,13 @@ mod tests {
             carry: false
         });
     }
+
+    crate::sm83_tests!(
+        x07, x0f, x17, x1f, cb_00, cb_01, cb_02, cb_03, cb_04, cb_05, cb_06, cb_07, cb_08,
+        cb_09, cb_0a, cb_0b, cb_0c, cb_0d, cb_0e, cb_0f, cb_10, cb_11, cb_12, cb_13, cb_14,
+        cb_15, cb_16, cb_17, cb_18, cb_19, cb_1a, cb_1b, cb_1c, cb_1d, cb_1e, cb_1f, cb_20,
+        cb_21, cb_22, cb_23, cb_24, cb_25, cb_26, cb_27, cb_28, cb_29, cb_2a, cb_2b, cb_2c,
+        cb_2d, cb_2e, cb_2f, cb_30, cb_31, cb_32, cb_33, cb_34, cb_35, cb_36, cb_37, cb_38,
+        cb_39, cb_3a, cb_3b, cb_3c, cb_3d, cb_3e, cb_3f,
+    );
 }
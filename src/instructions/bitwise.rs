@@ -6,13 +6,12 @@ use crate::{
 
 use super::{Instruction, InstructionResult};
 
-fn and_flags(result: u8) -> u8 {
-    let mut flags: u8 = 0;
-    flags |= ((result == 0) as u8) << 7;
-    flags |= 0 << 6;
-    flags |= 1 << 5;
-    flags |= 0 << 4;
-    flags
+/// Store the result of a logic operation in A and set the flags, AND is the only one setting the half carry flag
+fn store_result(result: u8, half_carry: bool, cpu: &mut Cpu) {
+    cpu.registers.set_r8(R8::A, result);
+    cpu.registers.flags.clear();
+    cpu.registers.flags.zero = result == 0;
+    cpu.registers.flags.half_carry = half_carry;
 }
 
 /// AND A,r8
@@ -21,8 +20,7 @@ pub fn and_a_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let r8 = cpu.registers.get_r8(r8);
     let b = a & r8;
-    cpu.registers.a = b;
-    cpu.registers.flags.set(and_flags(b));
+    store_result(b, true, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::AND,
@@ -38,8 +36,7 @@ pub fn and_a_immed_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Inst
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
     let b = byte & a;
-    cpu.registers.a = b;
-    cpu.registers.flags.set(and_flags(b));
+    store_result(b, true, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::AND,
@@ -53,8 +50,7 @@ pub fn and_a_immed_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Inst
 pub fn and_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let b = n8 & a;
-    cpu.registers.a = b;
-    cpu.registers.flags.set(and_flags(b));
+    store_result(b, true, cpu);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::AND,
@@ -66,11 +62,10 @@ pub fn and_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// CPL
 /// ComPLement accumulator (A = ~A); also called bitwise NOT.
 pub fn cpl(cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    let a = cpu.registers.a;
-    let a = a != a;
+    let a = !cpu.registers.a;
     cpu.registers.flags.subtraction = true;
     cpu.registers.flags.half_carry = true;
-    cpu.registers.a = a as u8;
+    cpu.registers.set_r8(R8::A, a);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::CPL,
@@ -85,9 +80,7 @@ pub fn or_a_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let r8 = cpu.registers.get_r8(r8);
     let b = a | r8;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = b;
+    store_result(b, false, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::OR,
@@ -102,9 +95,7 @@ pub fn or_a_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
     let b = a | byte;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = b;
+    store_result(b, false, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::OR,
@@ -117,9 +108,7 @@ pub fn or_a_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
 pub fn or_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let b = a | n8;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = a as u8;
+    store_result(b, false, cpu);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::OR,
@@ -134,9 +123,7 @@ pub fn xor_a_r8(r8: R8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let r8 = cpu.registers.get_r8(r8);
     let b = a ^ r8;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = b;
+    store_result(b, false, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::XOR,
@@ -152,9 +139,7 @@ pub fn xor_a_immed_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Inst
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
     let b = a ^ byte;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = b;
+    store_result(b, false, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::XOR,
@@ -168,9 +153,7 @@ pub fn xor_a_immed_hl(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Inst
 pub fn xor_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     let a = cpu.registers.a;
     let b = a ^ n8;
-    cpu.registers.flags.clear();
-    cpu.registers.flags.zero = b == 0;
-    cpu.registers.a = a as u8;
+    store_result(b, false, cpu);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::XOR,
@@ -178,3 +161,97 @@ pub fn xor_a_n8(n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
         cycles: 2,
     })
 }
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    const VALUES: [u8; 8] = [0x00, 0x01, 0x0f, 0x5a, 0x80, 0xa5, 0xf0, 0xff];
+
+    /// Every form (r8, [HL], n8) of a logic instruction
+    type Forms = [fn(u8, &mut Cpu, &mut Memory) -> InstructionResult<Instruction>; 3];
+
+    fn check(forms: Forms, op: fn(u8, u8) -> u8, half_carry: bool) {
+        for a in VALUES {
+            for operand in VALUES {
+                for (i, form) in forms.iter().enumerate() {
+                    let mut cpu = Cpu::default();
+                    let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+                    cpu.registers.set_r8(R8::A, a);
+                    cpu.registers.set_r16(R16::HL, 0xc000);
+                    mem.write(0xc000, operand);
+                    form(operand, &mut cpu, &mut mem).unwrap();
+                    let result = op(a, operand);
+                    assert_eq!(cpu.registers.a, result, "form {i}: a=0x{a:02x} operand=0x{operand:02x}");
+                    assert_eq!(cpu.registers.af >> 8, result as u16);
+                    assert_eq!(cpu.registers.flags.zero, result == 0);
+                    assert!(!cpu.registers.flags.subtraction);
+                    assert_eq!(cpu.registers.flags.half_carry, half_carry);
+                    assert!(!cpu.registers.flags.carry);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_and() {
+        check(
+            [
+                |n, cpu, _| {
+                    cpu.registers.set_r8(R8::B, n);
+                    and_a_r8(R8::B, cpu)
+                },
+                |_, cpu, mem| and_a_immed_hl(cpu, mem),
+                |n, cpu, _| and_a_n8(n, cpu),
+            ],
+            |a, b| a & b,
+            true,
+        );
+    }
+
+    #[test]
+    fn test_or() {
+        check(
+            [
+                |n, cpu, _| {
+                    cpu.registers.set_r8(R8::B, n);
+                    or_a_r8(R8::B, cpu)
+                },
+                |_, cpu, mem| or_a_hl(cpu, mem),
+                |n, cpu, _| or_a_n8(n, cpu),
+            ],
+            |a, b| a | b,
+            false,
+        );
+    }
+
+    #[test]
+    fn test_xor() {
+        check(
+            [
+                |n, cpu, _| {
+                    cpu.registers.set_r8(R8::B, n);
+                    xor_a_r8(R8::B, cpu)
+                },
+                |_, cpu, mem| xor_a_immed_hl(cpu, mem),
+                |n, cpu, _| xor_a_n8(n, cpu),
+            ],
+            |a, b| a ^ b,
+            false,
+        );
+    }
+
+    #[test]
+    fn test_cpl() {
+        for a in VALUES {
+            let mut cpu = Cpu::default();
+            cpu.registers.set_r8(R8::A, a);
+            let zero = cpu.registers.flags.zero;
+            cpl(&mut cpu).unwrap();
+            assert_eq!(cpu.registers.a, !a);
+            assert!(cpu.registers.flags.subtraction);
+            assert!(cpu.registers.flags.half_carry);
+            assert_eq!(cpu.registers.flags.zero, zero);
+        }
+    }
+}
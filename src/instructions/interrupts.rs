@@ -1,4 +1,4 @@
-use crate::{Mnemonic, cpu::Cpu};
+use crate::{Mnemonic, cpu::Cpu, memory::Memory};
 
 use super::{Instruction, InstructionResult};
 
@@ -6,6 +6,7 @@ use super::{Instruction, InstructionResult};
 /// Disable Interrupts by clearing the IME flag.
 pub fn di(cpu: &mut Cpu) -> InstructionResult<Instruction> {
     cpu.ime = false;
+    cpu.ime_scheduled = false;
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::DI,
@@ -18,6 +19,7 @@ pub fn di(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// Enable Interrupts by setting the IME flag.
 /// The flag is only set after the instruction following EI.
 pub fn ei(cpu: &mut Cpu) -> InstructionResult<Instruction> {
+    cpu.ime_scheduled = true;
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::EI,
@@ -34,11 +36,16 @@ pub fn ei(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// As soon as an interrupt becomes pending, the CPU resumes execution. This is like the above, except that the handler is not called.
 /// If the IME flag is not set, and some interrupt is pending:
 /// The CPU continues execution after the HALT, but the byte after it is read twice in a row (PC is not incremented, due to a hardware bug).
-pub fn halt(cpu: &mut Cpu) -> InstructionResult<Instruction> {
+pub fn halt(cpu: &mut Cpu, memory: &Memory) -> InstructionResult<Instruction> {
+    if !cpu.ime && memory.pending_interrupts() != 0 {
+        cpu.halt_bug = true;
+    } else {
+        cpu.halted = true;
+    }
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::HALT,
         bytes: 1,
-        cycles: 0,
+        cycles: 1,
     })
 }
@@ -10,12 +10,12 @@ use super::{Condition, Instruction, InstructionResult, pop_stack, push_stack};
 /// Call address n16.
 /// This pushes the address of the instruction after the CALL on the stack, such that RET can pop it later; then, it executes an implicit JP n16.
 pub fn call_n16(n16: u16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    push_stack(cpu.registers.pc + 3, cpu, mem);
+    let cycles = 4 + push_stack(cpu.registers.pc.wrapping_add(3), cpu, mem);
     cpu.registers.pc = n16;
     Ok(Instruction {
         mnemonic: Mnemonic::CALL,
         bytes: 3,
-        cycles: 6,
+        cycles,
     })
 }
 
@@ -28,12 +28,12 @@ pub fn call_cc_n16(
     mem: &mut Memory,
 ) -> InstructionResult<Instruction> {
     if cpu.cc(condition) {
-        push_stack(cpu.registers.pc + 3, cpu, mem);
+        let cycles = 4 + push_stack(cpu.registers.pc.wrapping_add(3), cpu, mem);
         cpu.registers.pc = n16;
         return Ok(Instruction {
             mnemonic: Mnemonic::CALL,
             bytes: 3,
-            cycles: 6,
+            cycles,
         });
     }
     cpu.registers.pc += 3;
@@ -87,11 +87,9 @@ pub fn jp_cc_n16(n16: u16, condition: Condition, cpu: &mut Cpu) -> InstructionRe
 
 /// JR n16
 /// Relative Jump to address n16.
-/// The address is encoded as a signed 8-bit offset from the address immediately following the JR instruction, so the target address n16 must be between -128 and 127 bytes away. For example:
+/// The address is encoded as a signed 8-bit offset from the address immediately following the JR instruction, so the target address n16 must be between -128 and 127 bytes away.
 pub fn jr_n16(e8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    let offset = e8 as i8;
-    cpu.registers
-        .set_r16(R16::PC, cpu.registers.pc.wrapping_add(offset as u16));
+    cpu.registers.set_r16(R16::PC, jr_target(cpu.registers.pc, e8));
     Ok(Instruction {
         mnemonic: Mnemonic::JR,
         bytes: 2,
@@ -99,21 +97,23 @@ pub fn jr_n16(e8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     })
 }
 
+/// The offset is relative to the byte after the 2-byte JR, wrapping around the address space
+fn jr_target(pc: u16, e8: u8) -> u16 {
+    pc.wrapping_add(2).wrapping_add_signed(e8 as i8 as i16)
+}
+
 /// JR cc,n16
 /// Relative Jump to address n16 if condition cc is met.
 pub fn jr_cc_n16(e8: u8, condition: Condition, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     if cpu.cc(condition) {
-        let offset = e8 as i8;
-        // println!("{offset} {e8} {cpu:?}");
-        cpu.registers
-            .set_r16(R16::PC, cpu.registers.pc.wrapping_add(offset as u16));
+        cpu.registers.set_r16(R16::PC, jr_target(cpu.registers.pc, e8));
         return Ok(Instruction {
             mnemonic: Mnemonic::JR,
             bytes: 2,
             cycles: 3,
         });
     }
-    cpu.registers.pc += 2;
+    cpu.registers.pc = cpu.registers.pc.wrapping_add(2);
     Ok(Instruction {
         mnemonic: Mnemonic::JR,
         bytes: 2,
@@ -129,12 +129,11 @@ pub fn ret_cc(
     mem: &mut Memory,
 ) -> InstructionResult<Instruction> {
     if cpu.cc(condition) {
-        pop_stack(R16::PC, cpu, mem);
-        cpu.registers.pc += 1;
+        let cycles = 3 + pop_stack(R16::PC, cpu, mem);
         return Ok(Instruction {
             mnemonic: Mnemonic::RET,
             bytes: 1,
-            cycles: 5,
+            cycles,
         });
     }
     cpu.registers.pc += 1;
@@ -148,36 +147,37 @@ pub fn ret_cc(
 /// RET
 /// Return from subroutine. This is basically a POP PC (if such an instruction existed). See POP r16 for an explanation of how POP works
 pub fn ret(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    pop_stack(R16::PC, cpu, mem);
-    cpu.registers.pc += 1;
+    let cycles = 2 + pop_stack(R16::PC, cpu, mem);
     Ok(Instruction {
         mnemonic: Mnemonic::RET,
         bytes: 1,
-        cycles: 4,
+        cycles,
     })
 }
 
 /// RETI
 /// Return from subroutine and enable interrupts. This is basically equivalent to executing EI then RET, meaning that IME is set right after this instruction.
+/// Unlike EI there's no delay, IME is set straight away.
 pub fn reti(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    pop_stack(R16::PC, cpu, mem);
-    cpu.registers.pc += 1;
+    let cycles = 2 + pop_stack(R16::PC, cpu, mem);
+    cpu.ime = true;
     Ok(Instruction {
         mnemonic: Mnemonic::RETI,
         bytes: 1,
-        cycles: 4,
+        cycles,
     })
 }
 
 /// RST vec
 /// Call address vec. This is a shorter and faster equivalent to CALL for suitable values of vec.
+/// vec is one of: $00, $08, $10, $18, $20, $28, $30, $38
 pub fn rst(vec: u16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    push_stack(cpu.registers.pc + 2, cpu, mem);
+    let cycles = 2 + push_stack(cpu.registers.pc.wrapping_add(1), cpu, mem);
     cpu.registers.set_r16(R16::PC, vec);
     Ok(Instruction {
         mnemonic: Mnemonic::RST,
         bytes: 1,
-        cycles: 4,
+        cycles,
     })
 }
 
@@ -235,7 +235,45 @@ mod tests {
         let mut cpu = Cpu::default();
         assert_eq!(cpu.registers.pc, 0x0100);
         jr_n16(0xfc, &mut cpu).unwrap();
-        assert_eq!(cpu.registers.pc, 0x00fc);
+        assert_eq!(cpu.registers.pc, 0x00fe);
+        jr_n16(0x10, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0110);
+        // JR -2 loops on itself
+        jr_n16(0xfe, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0110);
+        jr_n16(0x7f, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0191);
+        jr_n16(0x80, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0113);
+    }
+
+    #[test]
+    fn test_jr_wraps() {
+        let mut cpu = Cpu::default();
+        // backwards across 0x0000
+        cpu.registers.set_r16(R16::PC, 0x0001);
+        jr_n16(0xfc, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0xffff);
+        cpu.registers.set_r16(R16::PC, 0x0000);
+        jr_cc_n16(0x80, Condition::Carry, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0xff82);
+        // forwards across 0xffff
+        cpu.registers.set_r16(R16::PC, 0xfffe);
+        jr_n16(0x01, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0001);
+        cpu.registers.set_r16(R16::PC, 0xffff);
+        jr_cc_n16(0x00, Condition::NotCarry, &mut cpu).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0001);
+    }
+
+    #[test]
+    fn test_jr_execute() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        // JR -2
+        mem.rom()[0x0100..0x0102].copy_from_slice(&[0x18, 0xfe]);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 3);
+        assert_eq!(cpu.registers.pc, 0x0100);
     }
 
     #[test]
@@ -243,7 +281,7 @@ mod tests {
         let mut cpu = Cpu::default();
         assert_eq!(cpu.registers.pc, 0x0100);
         jr_cc_n16(0xfc, Condition::Carry, &mut cpu).unwrap();
-        assert_eq!(cpu.registers.pc, 0x00fc);
+        assert_eq!(cpu.registers.pc, 0x00fe);
         cpu.registers.set_r16(R16::PC, 0x0100);
         jr_cc_n16(0xfc, Condition::NotCarry, &mut cpu).unwrap();
         assert_eq!(cpu.registers.pc, 0x0102);
@@ -255,19 +293,44 @@ mod tests {
         let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
         push_stack(cpu.registers.pc + 3, &mut cpu, &mut mem);
         ret_cc(Condition::Carry, &mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+        // not taken, only skip over the RET itself
+        cpu.registers.flags.carry = false;
+        ret_cc(Condition::Carry, &mut cpu, &mut mem).unwrap();
         assert_eq!(cpu.registers.pc, 0x104);
     }
 
     #[test]
     fn test_ret() {
-        // let (cpu, mem) = test.setup();
-        // cpu.registers.sp = 0;
-        // mem[0] =
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        call_n16(0x420, &mut cpu, &mut mem).unwrap();
+        ret(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert!(!cpu.ime);
     }
 
     #[test]
-    fn test_reti() {}
+    fn test_reti() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        call_n16(0x420, &mut cpu, &mut mem).unwrap();
+        reti(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert!(cpu.ime);
+    }
 
     #[test]
-    fn test_rst() {}
+    fn test_rst() {
+        for (i, opcode) in (0xc7..=0xffu8).step_by(8).enumerate() {
+            let mut cpu = Cpu::default();
+            let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+            mem.rom()[0x0100] = opcode;
+            assert_eq!(cpu.execute(&mut mem).unwrap(), 4);
+            assert_eq!(cpu.registers.pc, i as u16 * 8, "0x{opcode:02x}");
+            assert_eq!(cpu.registers.sp, 0xfffc);
+            assert_eq!(mem.read_u16(0xfffc), 0x0101);
+        }
+    }
 }
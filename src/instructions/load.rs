@@ -2,7 +2,7 @@ use crate::{
     Mnemonic,
     cpu::{Cpu, R8},
     instructions::R16,
-    memory::Memory,
+    memory::{HighAddr, Memory},
 };
 
 use super::{Instruction, InstructionResult};
@@ -17,7 +17,6 @@ pub fn ld_r8_r8(
     dest: R8,
     cpu: &mut Cpu,
 ) -> InstructionResult<Instruction> {
-    println!("load source r8: {source:?} into dest r8: {dest:?}");
     let src = cpu.registers.get_r8(source);
     cpu.registers.set_r8(dest, src);
     cpu.registers.pc += 1;
@@ -31,7 +30,6 @@ pub fn ld_r8_r8(
 /// LD r8, n8
 /// Copy the value n8 into register r8.
 pub fn ld_r8_n8(r8: R8, n8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    println!("load n8: {n8} into r8: {r8:?}");
     cpu.registers.set_r8(r8, n8);
     cpu.registers.pc += 2;
     Ok(Instruction {
@@ -60,11 +58,11 @@ pub fn ld_r8_hl(r8: R8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<In
     let hl = cpu.registers.hl;
     let r8 = cpu.registers.get_r8(r8);
     mem.write(hl as usize, r8);
-    cpu.registers.pc += 2;
+    cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
-        bytes: 2,
-        cycles: 3,
+        bytes: 1,
+        cycles: 2,
     })
 }
 
@@ -87,11 +85,11 @@ pub fn ld_hl_r8(r8: R8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<In
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
     cpu.registers.set_r8(r8, byte);
-    cpu.registers.pc += 2;
+    cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
-        bytes: 2,
-        cycles: 3,
+        bytes: 1,
+        cycles: 2,
     })
 }
 
@@ -135,16 +133,11 @@ pub fn ld_a_immed_n16(n16: u16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionR
     })
 }
 
-/// LDH A, [n16]
-/// Copy the byte at address n16 into register A, provided the address is between $FF00 and $FFFF.
-pub fn ldh_a_immed_n16(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let b: usize = 0xff00 + a8 as usize;
-    let byte = mem.read(b as usize);
-    // if (0xff00..=0xffff).contains(&n16) {
-    //     println!("ldh_a_immed_n16: {n16}");
+/// LDH A, [a8]
+/// Copy the byte at address $FF00+a8 into register A.
+pub fn ldh_a_a8(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
+    let byte = mem.read_high(HighAddr(a8));
     cpu.registers.set_r8(R8::A, byte);
-    // cpu.registers.a = byte;
-    // }
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::LDH,
@@ -153,16 +146,10 @@ pub fn ldh_a_immed_n16(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionRe
     })
 }
 
-/// LDH [n16], A
-/// Copy the value in register A into the byte at address n16, provided the address is between $FF00 and $FFFF.
-pub fn ldh_immed_n16_a(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    // println!("ldh [n16], a: {a8}");
-    let a = cpu.registers.a;
-    let b: usize = 0xff00 + a8 as usize;
-    mem.write(b, a);
-    // if (0xff00..0xffff).contains(&n16) {
-    //     mem.write(n16 as usize, a);
-    // }
+/// LDH [a8], A
+/// Copy the value in register A into the byte at address $FF00+a8.
+pub fn ldh_a8_a(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
+    mem.write_high(HighAddr(a8), cpu.registers.a);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::LDH,
@@ -174,10 +161,8 @@ pub fn ldh_immed_n16_a(a8: u8, cpu: &mut Cpu, mem: &mut Memory) -> InstructionRe
 /// LDH A, [C]
 /// Copy the byte at address $FF00+C into register A.
 pub fn ldh_a_c(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let c = cpu.registers.c;
-    let byte = mem.read(0xff00 + c as usize);
+    let byte = mem.read_high(HighAddr(cpu.registers.c));
     cpu.registers.set_r8(R8::A, byte);
-    // println!("ldh a, [c]: {byte}");
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LDH,
@@ -189,10 +174,7 @@ pub fn ldh_a_c(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
 /// LDH [C],A
 /// Copy the value in register A into the byte at address $FF00+C.
 pub fn ldh_c_a(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let a = cpu.registers.a;
-    let c = cpu.registers.c;
-    mem.write(0xff00 + c as usize, a);
-    // println!("ldh [c], a: {c}");
+    mem.write_high(HighAddr(cpu.registers.c), cpu.registers.a);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LDH,
@@ -220,9 +202,8 @@ pub fn ld_immed_r16_a(r16: R16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionR
 pub fn ld_a_hli(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let a = cpu.registers.a;
-    let lcdc = mem.lcd_control();
     mem.write(hl as usize, a);
-    cpu.registers.set_r16(R16::HL, hl + 1);
+    cpu.registers.set_r16(R16::HL, hl.wrapping_add(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -237,7 +218,7 @@ pub fn ld_a_hld(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instructio
     let hl = cpu.registers.hl;
     let a = cpu.registers.a;
     mem.write(hl as usize, a);
-    cpu.registers.set_r16(R16::HL, hl - 1);
+    cpu.registers.set_r16(R16::HL, hl.wrapping_sub(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -245,16 +226,14 @@ pub fn ld_a_hld(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instructio
         cycles: 2,
     })
 }
+
 /// LD A,[HLD]
 /// Copy the byte pointed to by HL into register A, and decrement HL afterwards.
 pub fn ld_hld_a(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
-    let a = cpu.registers.a;
     cpu.registers.set_r8(R8::A, byte);
-    cpu.registers.set_r16(R16::HL, hl - 1);
-    println!("loading a: 0x{a:0x} into byte at hl(0x{hl:0x}): 0x{byte:0x}");
-    // println!("{} {byte} {}", cpu.registers.hl, cpu.registers.a);
+    cpu.registers.set_r16(R16::HL, hl.wrapping_sub(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -268,9 +247,8 @@ pub fn ld_hld_a(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instructio
 pub fn ld_hli_a(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
     let hl = cpu.registers.hl;
     let byte = mem.read(hl as usize);
-    // println!("loading 0x{byte:0x} into 0x{hl:0x}");
     cpu.registers.set_r8(R8::A, byte);
-    cpu.registers.set_r16(R16::HL, hl + 1);
+    cpu.registers.set_r16(R16::HL, hl.wrapping_add(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -309,4 +287,124 @@ mod tests {
         assert_eq!(cpu.registers.b, 0x04);
         assert_eq!(cpu.registers.c, 0x20);
     }
+
+    #[test]
+    fn test_ldh() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        cpu.registers.set_r8(R8::A, 0x42);
+        ldh_a8_a(0x80, &mut cpu, &mut mem).unwrap();
+        assert_eq!(mem.read(0xff80), 0x42);
+        assert_eq!(cpu.registers.pc, 0x0102);
+        cpu.registers.set_r8(R8::A, 0x00);
+        ldh_a_a8(0x80, &mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.a, 0x42);
+        cpu.registers.set_r8(R8::C, 0xfe);
+        ldh_c_a(&mut cpu, &mut mem).unwrap();
+        assert_eq!(mem.read(0xfffe), 0x42);
+        cpu.registers.set_r8(R8::A, 0x00);
+        ldh_a_c(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn test_ldh_decoding() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        cpu.registers.set_r8(R8::A, 0x42);
+        // LDH [$90], A ; LDH A, [$91]
+        mem.rom()[0x0100..0x0104].copy_from_slice(&[0xe0, 0x90, 0xf0, 0x91]);
+        mem.write(0xff91, 0x24);
+        cpu.execute(&mut mem).unwrap();
+        assert_eq!(mem.read(0xff90), 0x42);
+        cpu.execute(&mut mem).unwrap();
+        assert_eq!(cpu.registers.a, 0x24);
+        assert_eq!(cpu.registers.pc, 0x0104);
+    }
+
+    #[test]
+    fn test_ld_n16_decoding() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        cpu.registers.set_r8(R8::A, 0x42);
+        // LD [$C123], A ; LD A, [$C456]
+        mem.rom()[0x0100..0x0106].copy_from_slice(&[0xea, 0x23, 0xc1, 0xfa, 0x56, 0xc4]);
+        mem.write(0xc456, 0x24);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 4);
+        assert_eq!(mem.read(0xc123), 0x42);
+        assert_eq!(cpu.registers.pc, 0x0103);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 4);
+        assert_eq!(cpu.registers.a, 0x24);
+        assert_eq!(cpu.registers.pc, 0x0106);
+    }
+
+    #[test]
+    fn test_ldh_c_decoding() {
+        use crate::memory::registers::{DIV, IF, SCY};
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        // LDH [C], A ; LDH A, [C] three times
+        mem.rom()[0x0100..0x0106].copy_from_slice(&[0xe2, 0xf2, 0xe2, 0xf2, 0xe2, 0xf2]);
+        cpu.registers.set_r8(R8::A, 0x17);
+        cpu.registers.set_r8(R8::C, (SCY & 0xff) as u8);
+        mem.io_accessed = false;
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert!(mem.io_accessed);
+        assert_eq!(mem.read(SCY), 0x17);
+        cpu.registers.set_r8(R8::A, 0x00);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.registers.a, 0x17);
+        // any write resets the divider
+        mem.io.set(DIV, 0x18);
+        cpu.registers.set_r8(R8::C, (DIV & 0xff) as u8);
+        cpu.execute(&mut mem).unwrap();
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.registers.a, 0x00);
+        // only the low five bits of IF are writable, the rest read back as 1
+        cpu.registers.set_r8(R8::A, 0x00);
+        cpu.registers.set_r8(R8::C, (IF & 0xff) as u8);
+        cpu.execute(&mut mem).unwrap();
+        assert_eq!(mem.io.get(IF) & 0x1f, 0x00);
+        cpu.execute(&mut mem).unwrap();
+        assert_eq!(cpu.registers.a, 0xe0);
+        assert_eq!(cpu.registers.pc, 0x0106);
+    }
+
+    #[test]
+    fn test_hl_increment_decoding() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        // LD [HLI], A ; LD [HLD], A ; LD A, [HLI] ; LD A, [HLD]
+        mem.rom()[0x0100..0x0104].copy_from_slice(&[0x22, 0x32, 0x2a, 0x3a]);
+        cpu.registers.set_r16(R16::HL, 0xc000);
+        cpu.registers.set_r8(R8::A, 0x42);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(mem.read(0xc000), 0x42);
+        assert_eq!(cpu.registers.hl, 0xc001);
+        cpu.registers.set_r8(R8::A, 0x24);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(mem.read(0xc001), 0x24);
+        assert_eq!(cpu.registers.hl, 0xc000);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(cpu.registers.hl, 0xc001);
+        assert_eq!(cpu.execute(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.registers.a, 0x24);
+        assert_eq!(cpu.registers.hl, 0xc000);
+        assert_eq!(cpu.registers.pc, 0x0104);
+    }
+
+    #[test]
+    fn test_hl_increment_wraps() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        cpu.registers.set_r16(R16::HL, 0xffff);
+        ld_hli_a(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.hl, 0x0000);
+        ld_hld_a(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.hl, 0xffff);
+        cpu.registers.set_r16(R16::HL, 0x0000);
+        ld_a_hld(&mut cpu, &mut mem).unwrap();
+        assert_eq!(cpu.registers.hl, 0xffff);
+    }
 }
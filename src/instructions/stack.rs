@@ -4,36 +4,34 @@ use crate::{
     memory::Memory,
 };
 
-use super::{Instruction, InstructionResult, arithmetic_16bit::add_16bit};
-
-/// Push onto the stack
-pub fn push_stack(n16: u16, cpu: &mut Cpu, mem: &mut Memory) {
-    let high = (n16 & 0xff00) >> 8;
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp - 1);
-    mem.write(cpu.registers.sp as usize, high as u8);
-    let low = (n16 & 0xff) as u8;
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp - 1);
-    mem.write(cpu.registers.sp as usize, low);
-}
-
-/// Pop from the stack
-pub fn pop_stack(r16: R16, cpu: &mut Cpu, mem: &mut Memory) {
-    let mut n16: u16 = 0;
-    let low = mem.read(cpu.registers.sp as usize) as u16;
-    n16 |= low;
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp + 1);
-    let high = mem.read(cpu.registers.sp as usize) as u16;
-    n16 |= high << 8;
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp + 1);
+use super::{Instruction, InstructionResult, arithmetic_16bit::add_hl};
+
+/// Push onto the stack, SP wraps around at 0x0000
+/// Returns the m-cycles taken by the two memory writes
+pub fn push_stack(n16: u16, cpu: &mut Cpu, mem: &mut Memory) -> u8 {
+    let sp = cpu.registers.sp.wrapping_sub(2);
+    cpu.registers.set_r16(R16::SP, sp);
+    // the high byte goes out first as SP counts down
+    let [low, high] = n16.to_le_bytes();
+    mem.write(sp.wrapping_add(1) as usize, high);
+    mem.write(sp as usize, low);
+    2
+}
+
+/// Pop from the stack, SP wraps around at 0xffff
+/// Returns the m-cycles taken by the two memory reads
+pub fn pop_stack(r16: R16, cpu: &mut Cpu, mem: &mut Memory) -> u8 {
+    let n16 = mem.read_u16(cpu.registers.sp as usize);
+    cpu.registers.set_r16(R16::SP, cpu.registers.sp.wrapping_add(2));
     cpu.registers.set_r16(r16, n16);
+    2
 }
 
 /// ADD HL, SP
 /// Add the value in SP to HL
+/// Flags: Z unaffected, N reset, H set on carry out of bit 11, C set on carry out of bit 15
 pub fn add_hl_sp(cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    let (sum, flags) = add_16bit(cpu.registers.sp, cpu.registers.hl, None);
-    cpu.registers.flags.set(flags);
-    cpu.registers.set_r16(R16::HL, sum);
+    add_hl(cpu.registers.sp, cpu);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::ADD,
@@ -45,9 +43,8 @@ pub fn add_hl_sp(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// ADD SP,e8
 /// Add the signed value e8 to SP.
 pub fn add_sp_e8(e8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    let offset = e8 as i8;
-    let _ = cpu.registers.pc.wrapping_add(offset as u16);
-    // TODO
+    let sp = sp_plus_e8(e8, cpu);
+    cpu.registers.set_r16(R16::SP, sp);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::ADD,
@@ -56,10 +53,21 @@ pub fn add_sp_e8(e8: u8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
     })
 }
 
+/// SP + e8 as used by ADD SP,e8 and LD HL,SP+e8
+/// Z and N are reset, H and C are set from an unsigned add of e8 to the low byte of SP
+fn sp_plus_e8(e8: u8, cpu: &mut Cpu) -> u16 {
+    let sp = cpu.registers.sp;
+    cpu.registers.flags.clear();
+    cpu.registers.flags.half_carry = (sp & 0x0f) + (e8 as u16 & 0x0f) > 0x0f;
+    cpu.registers.flags.carry = (sp & 0xff) + e8 as u16 > 0xff;
+    sp.wrapping_add(e8 as i8 as u16)
+}
+
 /// DEC SP
 /// Decrement the value in register SP by 1.
+/// Flags are not affected.
 pub fn dec_sp(cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp - 1);
+    cpu.registers.set_r16(R16::SP, cpu.registers.sp.wrapping_sub(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::DEC,
@@ -70,8 +78,9 @@ pub fn dec_sp(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 
 /// INC SP
 /// Increment the value in register SP by 1
+/// Flags are not affected.
 pub fn inc_sp(cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp + 1);
+    cpu.registers.set_r16(R16::SP, cpu.registers.sp.wrapping_add(1));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::INC,
@@ -95,10 +104,7 @@ pub fn load_sp_n16(n16: u16, cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// LD [n16],SP
 /// Copy SP & $FF at address n16 and SP >> 8 at address n16 + 1.
 pub fn load_a16_sp(n16: u16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let sp = cpu.registers.sp;
-    let n16 = n16 as usize;
-    mem.write(n16, (sp & 0xff) as u8);
-    mem.write(n16 + 1, (sp >> 8) as u8);
+    mem.write_u16(n16 as usize, cpu.registers.sp);
     cpu.registers.pc += 3;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -110,7 +116,8 @@ pub fn load_a16_sp(n16: u16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResu
 /// LD HL,SP+e8
 /// Add the signed value e8 to SP and copy the result in HL.
 pub fn load_hl_sp_e8(e8: i8, cpu: &mut Cpu) -> InstructionResult<Instruction> {
-    //TODO
+    let hl = sp_plus_e8(e8 as u8, cpu);
+    cpu.registers.set_r16(R16::HL, hl);
     cpu.registers.pc += 2;
     Ok(Instruction {
         mnemonic: Mnemonic::LD,
@@ -139,15 +146,11 @@ pub fn load_sp_hl(cpu: &mut Cpu) -> InstructionResult<Instruction> {
 /// LD A, [SP]
 /// INC SP
 pub fn pop_af(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let low = mem.read(cpu.registers.sp as usize);
-    cpu.registers.flags.zero = low >> 7 == 1;
-    cpu.registers.flags.subtraction = low >> 6 == 1;
-    cpu.registers.flags.half_carry = low >> 5 == 1;
-    cpu.registers.flags.carry = low >> 4 == 1;
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp + 1);
-    let high = mem.read(cpu.registers.sp as usize);
-    cpu.registers.set_r8(R8::A, high);
-    cpu.registers.set_r16(R16::SP, cpu.registers.sp + 1);
+    let [low, high] = mem.read_u16(cpu.registers.sp as usize).to_le_bytes();
+    // the lower nibble of F always reads back as 0
+    cpu.registers
+        .set_r16(R16::AF, u16::from_be_bytes([high, low & 0xf0]));
+    cpu.registers.set_r16(R16::SP, cpu.registers.sp.wrapping_add(2));
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::POP,
@@ -163,12 +166,12 @@ pub fn pop_af(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction>
 /// LD HIGH(r16), [SP]  ; B, D or H
 /// INC SP
 pub fn pop_r16(r16: R16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    pop_stack(r16, cpu, mem);
+    let cycles = 1 + pop_stack(r16, cpu, mem);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::POP,
         bytes: 1,
-        cycles: 3,
+        cycles,
     })
 }
 
@@ -179,17 +182,14 @@ pub fn pop_r16(r16: R16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<I
 /// DEC SP
 /// LD [SP], F.Z << 7 | F.N << 6 | F.H << 5 | F.C << 4
 pub fn push_af(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    let mut af = cpu.registers.get_r16(R16::AF);
-    af |= (cpu.registers.flags.zero as u16) << 7;
-    af |= (cpu.registers.flags.subtraction as u16) << 6;
-    af |= (cpu.registers.flags.half_carry as u16) << 5;
-    af |= (cpu.registers.flags.carry as u16) << 4;
-    push_stack(af, cpu, mem);
+    // the low byte of `af` isn't kept in sync with `flags`, so build F from the flags
+    let af = u16::from_be_bytes([cpu.registers.a, cpu.registers.flags.into()]);
+    let cycles = 2 + push_stack(af, cpu, mem);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::PUSH,
         bytes: 1,
-        cycles: 4,
+        cycles,
     })
 }
 
@@ -200,17 +200,17 @@ pub fn push_af(cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction
 /// DEC SP
 /// LD [SP], LOW(r16)   ; C, E or L
 pub fn push_r16(r16: R16, cpu: &mut Cpu, mem: &mut Memory) -> InstructionResult<Instruction> {
-    push_stack(cpu.registers.get_r16(r16), cpu, mem);
+    let cycles = 2 + push_stack(cpu.registers.get_r16(r16), cpu, mem);
     cpu.registers.pc += 1;
     Ok(Instruction {
         mnemonic: Mnemonic::PUSH,
         bytes: 1,
-        cycles: 4,
+        cycles,
     })
 }
 
 mod tests {
-    use crate::{cartridge::Cartridge, instructions::add_a_n8};
+    use crate::instructions::add_a_n8;
 
     use super::*;
 
@@ -228,7 +228,64 @@ mod tests {
     #[test]
     fn test_push_af() {
         let mut cpu = Cpu::default();
-        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut mem = Memory::flat();
         push_af(&mut cpu, &mut mem).unwrap();
     }
+
+    #[test]
+    fn test_push_pop_stack() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::flat();
+        push_stack(0xbeef, &mut cpu, &mut mem);
+        assert_eq!(cpu.registers.sp, 0xfffc);
+        assert_eq!(mem.read(0xfffc), 0xef);
+        assert_eq!(mem.read(0xfffd), 0xbe);
+        pop_stack(R16::BC, &mut cpu, &mut mem);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+        assert_eq!(cpu.registers.bc, 0xbeef);
+    }
+
+    #[test]
+    fn test_load_a16_sp() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::flat();
+        load_a16_sp(0xc000, &mut cpu, &mut mem).unwrap();
+        assert_eq!(mem.read_u16(0xc000), 0xfffe);
+        assert_eq!(mem.read(0xc000), 0xfe);
+    }
+
+    #[test]
+    fn test_push_pop_wraparound() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::flat();
+        // the high byte ends up at 0x0000 and the low byte at 0xffff
+        cpu.registers.sp = 0x0001;
+        assert_eq!(push_stack(0xbeef, &mut cpu, &mut mem), 2);
+        assert_eq!(cpu.registers.sp, 0xffff);
+        assert_eq!(mem.read(0xffff), 0xef);
+        assert_eq!(mem.read(0x0000), 0xbe);
+        assert_eq!(pop_stack(R16::DE, &mut cpu, &mut mem), 2);
+        assert_eq!(cpu.registers.sp, 0x0001);
+        assert_eq!(cpu.registers.de, 0xbeef);
+
+        cpu.registers.sp = 0x0000;
+        push_stack(0x1234, &mut cpu, &mut mem);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+        assert_eq!(mem.read_u16(0xfffe), 0x1234);
+        pop_stack(R16::HL, &mut cpu, &mut mem);
+        assert_eq!(cpu.registers.sp, 0x0000);
+        assert_eq!(cpu.registers.hl, 0x1234);
+    }
+
+    #[test]
+    fn test_push_pop_cycles() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::flat();
+        cpu.registers.sp = 0x0000;
+        assert_eq!(push_r16(R16::BC, &mut cpu, &mut mem).unwrap().cycles, 4);
+        assert_eq!(push_af(&mut cpu, &mut mem).unwrap().cycles, 4);
+        assert_eq!(pop_r16(R16::DE, &mut cpu, &mut mem).unwrap().cycles, 3);
+        assert_eq!(pop_af(&mut cpu, &mut mem).unwrap().cycles, 3);
+        assert_eq!(cpu.registers.sp, 0x0000);
+    }
 }
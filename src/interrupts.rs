@@ -1,27 +1,68 @@
-#[derive(Debug)]
-pub enum Interrupt {
-    Joypad,
-    Serial,
-    Timer,
-    Stat,
-    VBlank,
+use bitflags::bitflags;
+
+bitflags! {
+    /// IE and IF share the same layout, the lower the bit the higher the priority
+    /// Read more: https://gbdev.io/pandocs/Interrupts.html
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Interrupt: u8 {
+        const VBLANK = 0x01;
+        const STAT = 0x02;
+        const TIMER = 0x04;
+        const SERIAL = 0x08;
+        const JOYPAD = 0x10;
+    }
 }
 
 impl Interrupt {
-    pub fn get_interrupt(value: &u8) -> Option<Self> {
-        match value {
-            0x04 => Some(Interrupt::Joypad),
-            0x03 => Some(Interrupt::Serial),
-            0x02 => Some(Interrupt::Timer),
-            0x01 => Some(Interrupt::Stat),
-            0x00 => Some(Interrupt::VBlank),
-            _ => None,
+    /// The interrupt that gets serviced first out of `pending` (usually `IE & IF`)
+    pub fn highest_priority(pending: u8) -> Option<Self> {
+        let pending = Self::from_bits_truncate(pending);
+        if pending.is_empty() {
+            return None;
         }
+        Some(Self::from_bits_retain(1 << pending.bits().trailing_zeros()))
+    }
+
+    /// Address of the handler, one of: $40, $48, $50, $58, $60
+    /// Read more: https://gbdev.io/pandocs/Interrupt_Sources.html
+    pub fn handler(self) -> u16 {
+        0x40 + 8 * self.bits().trailing_zeros() as u16
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, memory::{Memory, registers::IE}};
+
+    #[test]
+    fn test_highest_priority() {
+        assert_eq!(Interrupt::highest_priority(0x00), None);
+        assert_eq!(Interrupt::highest_priority(0xe0), None);
+        assert_eq!(Interrupt::highest_priority(0x1f), Some(Interrupt::VBLANK));
+        assert_eq!(Interrupt::highest_priority(0x14), Some(Interrupt::TIMER));
+        assert_eq!(Interrupt::highest_priority(0xf0), Some(Interrupt::JOYPAD));
+    }
+
+    #[test]
+    fn test_handler() {
+        assert_eq!(Interrupt::VBLANK.handler(), 0x40);
+        assert_eq!(Interrupt::STAT.handler(), 0x48);
+        assert_eq!(Interrupt::TIMER.handler(), 0x50);
+        assert_eq!(Interrupt::SERIAL.handler(), 0x58);
+        assert_eq!(Interrupt::JOYPAD.handler(), 0x60);
+    }
+
+    #[test]
+    fn test_request_acknowledge() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(IE, 0x05);
+        mem.acknowledge_interrupt(Interrupt::VBLANK);
+        assert_eq!(mem.pending_interrupts(), 0);
+        mem.request_interrupt(Interrupt::TIMER);
+        mem.request_interrupt(Interrupt::SERIAL);
+        assert_eq!(mem.pending_interrupts(), 0x04);
+        assert_eq!(Interrupt::highest_priority(mem.pending_interrupts()), Some(Interrupt::TIMER));
+        mem.acknowledge_interrupt(Interrupt::TIMER);
+        assert_eq!(mem.pending_interrupts(), 0);
     }
 }
-pub const TIMER: u8 = 0x02;
-// pub const VBLANK: u8 = 0x00;
-// pub const LCD: u8 = 0x02;
-// pub const TIMER: u8 = 0x04;
-// pub const SERIAL: u8 = 0x08;
-// pub const JOYPAD: u8 = 0x10;
@@ -1,4 +1,13 @@
+use crate::oam::ObjSize;
+use crate::tile::{TileAddressing, TileMap};
+use crate::memory::{
+    regions::{IO_REGISTER_END, IO_REGISTER_START},
+    registers::*,
+};
+
+pub mod infrared;
 pub mod joypad;
+pub mod serial;
 
 // I/O ranges for peripherals;
 pub const JOYPAD_INPUT: u16 = 0xff00;
@@ -21,36 +30,163 @@ pub const BG_OBJ_PALETTE_START: u16 = 0xff68;
 pub const BG_OBJ_PALETTE_END: u16 = 0xff6b;
 pub const WRAM_BANK_SELECT: u16 = 0xff70;
 
-#[derive(Debug)]
+/// A cpu write to an IO register that another component has to react to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoEvent {
+    /// DIV was written, the whole divider is reset to 0
+    DivReset,
+    /// TIMA, TMA or TAC changed
+    Timer(usize, u8),
+    /// LCDC, STAT, scrolling, LYC, palettes or window position changed
+    Lcd(usize, u8),
+    /// An audio register or wave ram changed
+    Apu(usize, u8),
+    /// RP was written, bit 0 turns the infrared LED on
+    Infrared(u8),
+    /// SB or SC was written, bit 7 of SC starts a transfer
+    Serial,
+}
+
+/// The IO registers (0xff00-0xff7f), every cpu access goes through here so hardware semantics
+/// (bits that read as 1, read-only bits, write side effects) live in one place.
+/// Components that need to know about a write drain the queued `IoEvent`s.
+/// Read more: https://gbdev.io/pandocs/Hardware_Reg_List.html
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoRegisters {
+    #[cfg_attr(feature = "serde", serde(with = "crate::rle"))]
+    registers: [u8; 0x80],
+    #[cfg_attr(feature = "serde", serde(skip))]
+    events: Vec<IoEvent>,
+}
+
+impl Default for IoRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoRegisters {
+    pub fn new() -> Self {
+        Self {
+            registers: [0u8; 0x80],
+            events: vec![],
+        }
+    }
+
+    /// Raw value of a register without masks or side effects, for use by the hardware itself
+    pub fn get(&self, addr: usize) -> u8 {
+        self.registers[addr - IO_REGISTER_START]
+    }
+
+    /// Set a register without masks or side effects, for use by the hardware itself (e.g: the PPU updating LY)
+    pub fn set(&mut self, addr: usize, value: u8) {
+        self.registers[addr - IO_REGISTER_START] = value;
+    }
+
+    /// Read from the cpu, unused bits read as 1
+    pub fn read(&self, addr: usize) -> u8 {
+        self.get(addr) | read_mask(addr)
+    }
+
+    /// Write from the cpu, read-only bits are preserved and side effects are applied
+    pub fn write(&mut self, addr: usize, value: u8) {
+        if addr == DIV {
+            self.set(DIV, 0);
+            self.events.push(IoEvent::DivReset);
+            return;
+        }
+        let writable = write_mask(addr);
+        let value = (self.get(addr) & !writable) | (value & writable);
+        self.set(addr, value);
+        let event = match addr {
+            TIMA | TMA | TAC => IoEvent::Timer(addr, value),
+            NR10..=WAVE_RAM_END => IoEvent::Apu(addr, value),
+            LCDC..=WX if addr != DMA => IoEvent::Lcd(addr, value),
+            RP => IoEvent::Infrared(value),
+            SB | SC => IoEvent::Serial,
+            _ => return,
+        };
+        self.events.push(event);
+    }
+
+    /// Take every event queued since the last call, oldest first
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, IoEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Overwrite every register at once without side effects (e.g: loading a save state)
+    pub fn copy_from_slice(&mut self, registers: &[u8]) {
+        self.registers.copy_from_slice(registers);
+        self.events.clear();
+    }
+}
+
+/// Bits which always read back as 1
+/// Read more: https://github.com/Gekkio/mooneye-test-suite/blob/main/acceptance/bits/unused_hwio-GS.s
+pub fn read_mask(addr: usize) -> u8 {
+    match addr {
+        JOYP => 0xc0,
+        SC => 0x7e,
+        TAC => 0xf8,
+        IF => 0xe0,
+        NR10 => 0x80,
+        NR30 => 0x7f,
+        NR32 => 0x9f,
+        NR52 => 0x70,
+        STAT => 0x80,
+        RP => 0x3c,
+        _ => 0x00,
+    }
+}
+
+/// Registers only the CGB has: KEY1, VBK, the HDMA registers, RP, the palette registers, OPRI
+/// and SVBK
+/// Read more: https://gbdev.io/pandocs/CGB_Registers.html
+pub fn cgb_only(addr: usize) -> bool {
+    matches!(addr, 0xff4d | 0xff4f | 0xff51..=0xff56 | 0xff68..=0xff6c | 0xff70)
+}
+
+/// Bits the cpu is allowed to change, the rest are owned by the hardware
+pub fn write_mask(addr: usize) -> u8 {
+    match addr {
+        // only the select bits, the button states are set by the joypad
+        JOYP => 0x30,
+        IF => 0x1f,
+        // the mode and LY=LYC bits are set by the PPU
+        STAT => 0x78,
+        LY => 0x00,
+        // the channel status bits are set by the APU
+        NR52 => 0x80,
+        // the receive bit is set by the infrared device
+        RP => 0xc1,
+        _ => 0xff,
+    }
+}
+
+/// LCDC, read with `Memory::lcd_control` and written back with `Memory::set_lcd_control`
+/// Read more: https://gbdev.io/pandocs/LCDC.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LcdControl {
     pub lcd_ppu_enable: bool,
-    pub window_tile_map_area: [usize; 2],
-    pub bg_tile_map_area: [usize; 2],
+    pub window_tile_map: TileMap,
+    pub bg_tile_map: TileMap,
     pub window_enable: bool,
-    pub tile_data_area: [[usize; 2]; 2],
-    // pub bg_tile_map_area: [usize; 2],
-    obj_size: u8,
-    obj_enable: bool,
+    pub tile_addressing: TileAddressing,
+    pub obj_size: ObjSize,
+    pub obj_enable: bool,
     pub bg_window_enable: bool,
 }
 
 impl std::fmt::Display for LcdControl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let window_tile_map_area = format!(
-            "window tile map area: 0x{:0x} - 0x{:0x}",
-            self.window_tile_map_area[0], self.window_tile_map_area[1]
-        );
-        let bg_tile_map_area = format!(
-            "bg tile map area: 0x{:0x} - 0x{:0x}",
-            self.bg_tile_map_area[0], self.bg_tile_map_area[1]
-        );
-        let tile_data_area = format!(
-            "tile data area block_0: 0x{:0x}-{:0x} - block_1: 0x{:0x}-{:0x}",
-            self.tile_data_area[0][0],
-            self.tile_data_area[0][1],
-            self.tile_data_area[1][0],
-            self.tile_data_area[1][1]
-        );
+        let window_tile_map_area = format!("window tile map: 0x{:0x}", self.window_tile_map.base());
+        let bg_tile_map_area = format!("bg tile map: 0x{:0x}", self.bg_tile_map.base());
+        let tile_data_area = format!("tile addressing: {:?}", self.tile_addressing);
         write!(
             f,
             "\n{window_tile_map_area}\n{bg_tile_map_area}\n{tile_data_area}\nlcd & ppu enabled: {}\nbg window enabled: {}",
@@ -61,39 +197,70 @@ impl std::fmt::Display for LcdControl {
 
 impl From<u8> for LcdControl {
     fn from(value: u8) -> Self {
-        let window_tile_map_area = match (value & 0x40) >> 6 {
-            0 => [0x9800, 0x9bff],
-            _ => [0x9c00, 0x9fff],
+        let window_tile_map = match (value & 0x40) >> 6 {
+            0 => TileMap::Low,
+            _ => TileMap::High,
         };
-        let tile_data_area = match (value & 0x10) >> 4 {
-            0 => [[0x8800, 0x8fff], [0x9000, 0x97ff]],
-            _ => [[0x8000, 0x87ff], [0x8800, 0x8fff]],
+        let tile_addressing = match (value & 0x10) >> 4 {
+            0 => TileAddressing::Signed,
+            _ => TileAddressing::Unsigned,
         };
-        let bg_tile_map_area = match (value & 0x08) >> 3 {
-            0 => [0x9800, 0x9bff],
-            _ => [0x9c00, 0x9fff],
+        let bg_tile_map = match (value & 0x08) >> 3 {
+            0 => TileMap::Low,
+            _ => TileMap::High,
         };
         Self {
             lcd_ppu_enable: (value & 0x80) >> 7 == 1,
-            window_tile_map_area,
+            window_tile_map,
             window_enable: (value & 0x20) >> 5 == 1,
-            tile_data_area,
-            bg_tile_map_area,
-            obj_size: (value & 0x04) >> 2,
+            tile_addressing,
+            bg_tile_map,
+            obj_size: match (value & 0x04) >> 2 {
+                0 => ObjSize::Normal,
+                _ => ObjSize::Tall,
+            },
             obj_enable: (value & 0x02) >> 1 == 1,
             bg_window_enable: (value & 0x01) == 1,
         }
     }
 }
 
-#[derive(Debug)]
+impl From<LcdControl> for u8 {
+    fn from(lcdc: LcdControl) -> Self {
+        (lcdc.lcd_ppu_enable as u8) << 7
+            | ((lcdc.window_tile_map == TileMap::High) as u8) << 6
+            | (lcdc.window_enable as u8) << 5
+            | ((lcdc.tile_addressing == TileAddressing::Unsigned) as u8) << 4
+            | ((lcdc.bg_tile_map == TileMap::High) as u8) << 3
+            | ((lcdc.obj_size == ObjSize::Tall) as u8) << 2
+            | (lcdc.obj_enable as u8) << 1
+            | lcdc.bg_window_enable as u8
+    }
+}
+
+/// STAT, read with `Memory::lcd_status` and written back with `Memory::set_lcd_status`. Bit 7
+/// isn't kept, it always reads as 1 through `read_mask`.
+/// Read more: https://gbdev.io/pandocs/STAT.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LcdStatus {
     pub lyc_int_select: bool,
     pub mode_2_int_select: bool,
     pub mode_1_int_select: bool,
     pub mode_0_int_select: bool,
+    /// LY == LYC
     pub lyu_lc: bool,
-    pub ppu_mode: bool,
+    /// 0-3, always 0 with the LCD off
+    pub ppu_mode: u8,
+}
+
+impl LcdStatus {
+    /// Whether one of the selected interrupt sources holds the STAT line high
+    pub fn interrupt_line(&self) -> bool {
+        (self.lyc_int_select && self.lyu_lc)
+            || (self.mode_2_int_select && self.ppu_mode == 2)
+            || (self.mode_1_int_select && self.ppu_mode == 1)
+            || (self.mode_0_int_select && self.ppu_mode == 0)
+    }
 }
 
 impl From<u8> for LcdStatus {
@@ -104,11 +271,22 @@ impl From<u8> for LcdStatus {
             mode_1_int_select: value & 0x10 != 0,
             mode_0_int_select: value & 0x08 != 0,
             lyu_lc: value & 0x04 != 0,
-            ppu_mode: value & 0x03 != 0,
+            ppu_mode: value & 0x03,
         }
     }
 }
 
+impl From<LcdStatus> for u8 {
+    fn from(stat: LcdStatus) -> Self {
+        (stat.lyc_int_select as u8) << 6
+            | (stat.mode_2_int_select as u8) << 5
+            | (stat.mode_1_int_select as u8) << 4
+            | (stat.mode_0_int_select as u8) << 3
+            | (stat.lyu_lc as u8) << 2
+            | stat.ppu_mode & 0x03
+    }
+}
+
 #[derive(Debug)]
 pub struct TimerControl {
     pub enable: bool,
@@ -156,3 +334,162 @@ impl TryFrom<u8> for TimerControl {
         }
     }
 }
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, memory::Memory};
+
+    #[test]
+    fn test_div_reset() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        assert_eq!(mem.read(DIV), 0xab);
+        mem.write(DIV, 0x42);
+        assert_eq!(mem.read(DIV), 0x00);
+        assert_eq!(mem.io.drain_events().collect::<Vec<_>>(), vec![IoEvent::DivReset]);
+    }
+
+    #[test]
+    fn test_stat_read_only_bits() {
+        let mut io = IoRegisters::new();
+        io.set(STAT, 0x02);
+        io.write(STAT, 0xff);
+        assert_eq!(io.get(STAT), 0x7a);
+        assert_eq!(io.read(STAT), 0xfa);
+        io.write(LY, 0x10);
+        assert_eq!(io.read(LY), 0x00);
+    }
+
+    #[test]
+    fn test_lcd_registers_round_trip() {
+        for value in 0..=0xff {
+            assert_eq!(u8::from(LcdControl::from(value)), value);
+            assert_eq!(u8::from(LcdStatus::from(value)), value & 0x7f);
+        }
+        let mut stat = LcdStatus::from(0x20);
+        assert!(!stat.interrupt_line());
+        stat.ppu_mode = 2;
+        assert!(stat.interrupt_line());
+    }
+
+    #[test]
+    fn test_joypad_read_only_bits() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        assert_eq!(mem.read(JOYP), 0xcf);
+        // selecting the d-pad doesn't touch the button states
+        mem.write(JOYP, 0x20);
+        assert_eq!(mem.read(JOYP), 0xef);
+        mem.io.set(JOYP, 0x2e);
+        mem.write(JOYP, 0x0f);
+        assert_eq!(mem.read(JOYP), 0xce);
+    }
+
+    #[test]
+    fn test_interrupt_flag_upper_bits() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(IF, 0x00);
+        assert_eq!(mem.read(IF), 0xe0);
+        mem.write(IF, 0xff);
+        assert_eq!(mem.read(IF), 0xff);
+        let mut io = IoRegisters::new();
+        io.write(IF, 0xff);
+        assert_eq!(io.get(IF), 0x1f);
+    }
+
+    #[test]
+    fn test_unused_bits() {
+        // writing 0 to every register should still read back the unused bits as 1
+        let mut io = IoRegisters::new();
+        for (addr, mask) in [
+            (JOYP, 0xc0),
+            (SC, 0x7e),
+            (TAC, 0xf8),
+            (IF, 0xe0),
+            (NR10, 0x80),
+            (NR30, 0x7f),
+            (NR32, 0x9f),
+            (NR52, 0x70),
+            (STAT, 0x80),
+            (RP, 0x3c),
+        ] {
+            io.write(addr, 0x00);
+            assert_eq!(io.read(addr), mask, "0x{addr:04x}");
+        }
+    }
+
+    #[test]
+    fn test_cgb_only() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(RP, 0x01);
+        assert_eq!(mem.read(RP), 0xff);
+        assert!(mem.io.drain_events().next().is_none());
+        assert_eq!(mem.read(0xff4f), 0xff);
+        mem.model = crate::model::Model::Cgb;
+        assert_eq!(mem.read(RP), 0x3e);
+        mem.write(RP, 0x01);
+        assert_eq!(mem.read(RP), 0x3f);
+        assert_eq!(mem.io.drain_events().collect::<Vec<_>>(), vec![IoEvent::Infrared(0x03)]);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(0xc000, 0x12);
+        mem.write(0xc09f, 0x34);
+        mem.write(DMA, 0xc0);
+        assert_eq!(mem.read(DMA), 0xc0);
+        // nothing happens until the instruction writing DMA is done, then a cycle to start up
+        mem.tick_oam_dma(3);
+        assert_eq!(mem.read(0xfe00), 0x00);
+        mem.tick_oam_dma(1);
+        // OAM is the DMA's until it has copied all 160 bytes
+        mem.tick_oam_dma(159);
+        assert_eq!(mem.read(0xfe00), 0xff);
+        mem.tick_oam_dma(1);
+        assert_eq!(mem.read(0xfe00), 0x12);
+        assert_eq!(mem.read(0xfe9f), 0x34);
+    }
+
+    /// What mooneye's oam_dma_restart checks: restarting keeps OAM blocked and copies from the
+    /// new source, from the first byte
+    #[test]
+    fn test_oam_dma_restart() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        for offset in 0..0xa0 {
+            mem.write(0xc000 + offset, 0x11);
+            mem.write(0xc100 + offset, 0x22);
+        }
+        mem.write(DMA, 0xc0);
+        mem.tick_oam_dma(1);
+        mem.tick_oam_dma(1 + 10);
+        mem.write(DMA, 0xc1);
+        assert_eq!(mem.read(DMA), 0xc1);
+        // the old transfer carries on while the new one starts up
+        mem.tick_oam_dma(1);
+        assert!(!mem.oam_accessible());
+        mem.tick_oam_dma(1);
+        assert!(!mem.oam_accessible());
+        assert_eq!(mem.peek(0xfe0b), 0x11);
+        mem.tick_oam_dma(0xa0);
+        assert!(mem.oam_accessible());
+        assert_eq!(mem.read(0xfe00), 0x22);
+        assert_eq!(mem.read(0xfe9f), 0x22);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut io = IoRegisters::new();
+        io.write(LCDC, 0x91);
+        io.write(NR52, 0x80);
+        io.write(TAC, 0x05);
+        io.write(0xff7f, 0x01);
+        assert_eq!(
+            io.drain_events().collect::<Vec<_>>(),
+            vec![
+                IoEvent::Lcd(LCDC, 0x91),
+                IoEvent::Apu(NR52, 0x80),
+                IoEvent::Timer(TAC, 0x05)
+            ]
+        );
+        assert_eq!(io.drain_events().count(), 0);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use crate::memory::{Memory, registers::RP};
+
+/// Whatever is on the other side of the CGB infrared port
+/// Read more: https://gbdev.io/pandocs/CGB_Registers.html#ff56--rp-cgb-mode-only-infrared-communications-port
+/// RP only exists on the CGB, other models read $FF and ignore writes
+pub trait IrDevice: Send {
+    /// The game turned its LED on or off
+    fn set_led(&mut self, on: bool);
+    /// Whether light is reaching the receiver
+    fn light(&mut self) -> bool;
+}
+
+/// The LED shines straight into the receiver, games see their own signal
+#[derive(Debug, Default)]
+pub struct Loopback {
+    led: bool,
+}
+
+impl IrDevice for Loopback {
+    fn set_led(&mut self, on: bool) {
+        self.led = on;
+    }
+
+    fn light(&mut self) -> bool {
+        self.led
+    }
+}
+
+/// Another instance over TCP, every LED change is sent as a single byte.
+/// IR protocols are timed in cycles so this only works as long as the connection keeps up,
+/// running both instances on the same machine is the most reliable.
+#[derive(Debug)]
+pub struct TcpIr {
+    stream: TcpStream,
+    led: bool,
+    light: bool,
+}
+
+impl TcpIr {
+    pub fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            led: false,
+            light: false,
+        })
+    }
+}
+
+impl IrDevice for TcpIr {
+    fn set_led(&mut self, on: bool) {
+        if on != self.led {
+            self.led = on;
+            // a dropped connection just looks like the other side went dark
+            let _ = self.stream.write_all(&[on as u8]);
+        }
+    }
+
+    fn light(&mut self) -> bool {
+        let mut buffer = [0u8; 64];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.light = false;
+                    break;
+                }
+                Ok(read) => self.light = buffer[read - 1] == 1,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.light = false;
+                    break;
+                }
+            }
+        }
+        self.light
+    }
+}
+
+/// Forward a write to RP to the device, bit 0 drives the LED
+pub fn write_rp(device: &mut dyn IrDevice, value: u8) {
+    device.set_led(value & 0x01 != 0);
+}
+
+/// Update the receive bit of RP, it reads 0 while light is received and reading is enabled
+/// (bits 6-7 set)
+pub fn update_rp(device: &mut dyn IrDevice, mem: &mut Memory) {
+    let rp = mem.io.get(RP);
+    let receiving = rp & 0xc0 == 0xc0 && device.light();
+    mem.io.set(RP, (rp & !0x02) | (!receiving as u8) << 1);
+}
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, model::Model};
+
+    #[test]
+    fn test_loopback() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.model = Model::Cgb;
+        let mut device = Loopback::default();
+        assert_eq!(mem.read(RP), 0x3e);
+        mem.write(RP, 0x01);
+        write_rp(&mut device, mem.io.get(RP));
+        update_rp(&mut device, &mut mem);
+        // reading isn't enabled
+        assert_eq!(mem.read(RP), 0x3f);
+        mem.write(RP, 0xc1);
+        update_rp(&mut device, &mut mem);
+        assert_eq!(mem.read(RP), 0xfd);
+        mem.write(RP, 0xc0);
+        write_rp(&mut device, mem.io.get(RP));
+        update_rp(&mut device, &mut mem);
+        assert_eq!(mem.read(RP), 0xfe);
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let a = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (b, _) = listener.accept().unwrap();
+        let (mut a, mut b) = (TcpIr::new(a).unwrap(), TcpIr::new(b).unwrap());
+        a.set_led(true);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !b.light() {
+            assert!(std::time::Instant::now() < deadline, "light never arrived");
+        }
+        assert!(!a.light());
+        a.set_led(false);
+        while b.light() {
+            assert!(std::time::Instant::now() < deadline, "light never went out");
+        }
+    }
+}
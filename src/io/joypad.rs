@@ -1,6 +1,12 @@
-use crate::errors::JoypadError;
+use bitflags::bitflags;
 
-#[derive(Debug)]
+use crate::{
+    errors::JoypadError,
+    interrupts::Interrupt,
+    memory::{Memory, registers::JOYP},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Action {
     Start,
     Select,
@@ -8,7 +14,7 @@ pub enum Action {
     B,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     Down,
     Up,
@@ -23,11 +29,165 @@ pub enum JoypadBank {
     AllReleased,
 }
 
+/// A single button on the console, frontends translate their own input into these
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Action(Action),
+    Direction(Direction),
+}
+
+impl Button {
+    /// The action buttons are the low nibble and the d-pad the high nibble, each nibble is laid out
+    /// the same way as the lower bits of JOYP
+    /// Read more: https://gbdev.io/pandocs/Joypad_Input.html
+    pub fn bit(self) -> u8 {
+        match self {
+            Self::Action(Action::A) => 0x01,
+            Self::Action(Action::B) => 0x02,
+            Self::Action(Action::Select) => 0x04,
+            Self::Action(Action::Start) => 0x08,
+            Self::Direction(Direction::Right) => 0x10,
+            Self::Direction(Direction::Left) => 0x20,
+            Self::Direction(Direction::Up) => 0x40,
+            Self::Direction(Direction::Down) => 0x80,
+        }
+    }
+}
+
+bitflags! {
+    /// Every button at once, laid out like `Button::bit`, for anything driving input
+    /// programmatically (bots, replays, netplay) rather than one key at a time
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Buttons: u8 {
+        const A = 0x01;
+        const B = 0x02;
+        const SELECT = 0x04;
+        const START = 0x08;
+        const RIGHT = 0x10;
+        const LEFT = 0x20;
+        const UP = 0x40;
+        const DOWN = 0x80;
+    }
+}
+
+impl From<Button> for Buttons {
+    fn from(button: Button) -> Self {
+        Self::from_bits_retain(button.bit())
+    }
+}
+
+impl std::fmt::Display for Button {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Action(action) => write!(f, "{action:?}"),
+            Self::Direction(direction) => write!(f, "{direction:?}"),
+        }
+    }
+}
+
+/// A button going down or up, applied once the system reaches `cycle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEdge {
+    pub button: Button,
+    pub pressed: bool,
+    /// M-cycles since power on, see `System::cycles`
+    pub cycle: u64,
+}
+
+/// Buttons from a frontend other than SDL's, such as a gamepad library or a script. The system
+/// polls it at each frame boundary, so input latency doesn't depend on how fast it's running.
+pub trait InputProvider: Send {
+    /// The buttons to hold for the next frame
+    fn poll(&mut self) -> Buttons;
+}
+
+/// The input layer, every frontend presses and releases buttons here and the system mirrors the
+/// result into JOYP. Turbo buttons autofire at half the frame rate (30Hz) while they're held.
+#[derive(Debug, Clone, Default)]
 pub struct Joypad {
     register: u8,
+    /// Buttons being held
+    held: u8,
+    /// Buttons with autofire enabled
+    turbo: u8,
+    /// Flips every frame, held turbo buttons only count as pressed while it's unset
+    turbo_phase: bool,
+    /// Buttons fixed for the whole frame (e.g: by netplay), live input is ignored while set
+    latched: Option<u8>,
 }
 
 impl Joypad {
+    pub fn press(&mut self, button: Button) {
+        self.held |= button.bit();
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.held &= !button.bit();
+    }
+
+    /// Buttons being held, laid out like `Button::bit`
+    pub fn held(&self) -> u8 {
+        self.held
+    }
+
+    /// Replace every held button at once (e.g: playing back a movie)
+    pub fn set_held(&mut self, held: u8) {
+        self.held = held;
+    }
+
+    /// Enable or disable autofire on a button, returns whether it's now enabled
+    pub fn toggle_turbo(&mut self, button: Button) -> bool {
+        self.turbo ^= button.bit();
+        self.turbo(button)
+    }
+
+    pub fn turbo(&self, button: Button) -> bool {
+        self.turbo & button.bit() != 0
+    }
+
+    /// Buttons the game currently sees as pressed
+    pub fn pressed(&self) -> u8 {
+        self.latched.unwrap_or_else(|| self.local())
+    }
+
+    /// Buttons held on this instance with autofire applied, ignoring the latch
+    pub fn local(&self) -> u8 {
+        match self.turbo_phase {
+            true => self.held & !self.turbo,
+            false => self.held,
+        }
+    }
+
+    /// Fix the pressed buttons until the next call, `None` goes back to live input
+    pub fn latch(&mut self, buttons: Option<u8>) {
+        self.latched = buttons;
+    }
+
+    /// Advance the autofire phase, called once per frame
+    pub fn frame(&mut self) {
+        self.turbo_phase = !self.turbo_phase;
+    }
+
+    /// Mirror the pressed buttons into the lower bits of JOYP for the groups the game selected,
+    /// a selected button going from released to pressed requests the joypad interrupt
+    pub fn update(&mut self, mem: &mut Memory) {
+        let joyp = mem.io.get(JOYP);
+        let pressed = self.pressed();
+        // 0 means selected and pressed
+        let mut buttons = 0x0f;
+        if joyp & 0x10 == 0 {
+            buttons &= !(pressed >> 4);
+        }
+        if joyp & 0x20 == 0 {
+            buttons &= !(pressed & 0x0f);
+        }
+        if joyp & 0x0f & !buttons != 0 {
+            mem.request_interrupt(Interrupt::JOYPAD);
+        }
+        self.register = (joyp & 0xf0) | buttons;
+        mem.io.set(JOYP, self.register);
+    }
+
     fn active(&mut self) -> std::result::Result<JoypadBank, JoypadError> {
         match self.register >> 4 {
             1 => {
@@ -64,3 +224,86 @@ impl Joypad {
         }
     }
 }
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    const A: Button = Button::Action(Action::A);
+    const DOWN: Button = Button::Direction(Direction::Down);
+
+    #[test]
+    fn test_update_selected_group() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut joypad = Joypad::default();
+        joypad.press(A);
+        joypad.press(DOWN);
+        // d-pad selected
+        mem.write(JOYP, 0x20);
+        joypad.update(&mut mem);
+        assert_eq!(mem.read(JOYP), 0xe7);
+        // buttons selected
+        mem.write(JOYP, 0x10);
+        joypad.update(&mut mem);
+        assert_eq!(mem.read(JOYP), 0xde);
+        joypad.release(A);
+        joypad.update(&mut mem);
+        assert_eq!(mem.read(JOYP), 0xdf);
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut joypad = Joypad::default();
+        mem.write(JOYP, 0x10);
+        joypad.update(&mut mem);
+        mem.acknowledge_interrupt(Interrupt::JOYPAD);
+        // the d-pad isn't selected
+        joypad.press(DOWN);
+        joypad.update(&mut mem);
+        assert_eq!(mem.get_interrupt_flag() & Interrupt::JOYPAD.bits(), 0);
+        joypad.press(A);
+        joypad.update(&mut mem);
+        assert_ne!(mem.get_interrupt_flag() & Interrupt::JOYPAD.bits(), 0);
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut joypad = Joypad::default();
+        joypad.press(A);
+        assert!(joypad.toggle_turbo(A));
+        let frames: Vec<u8> = (0..4)
+            .map(|_| {
+                joypad.frame();
+                joypad.pressed()
+            })
+            .collect();
+        assert_eq!(frames, vec![0x00, 0x01, 0x00, 0x01]);
+        assert!(!joypad.toggle_turbo(A));
+        joypad.frame();
+        assert_eq!(joypad.pressed(), 0x01);
+    }
+
+    #[test]
+    fn test_buttons() {
+        let actions = [Action::A, Action::B, Action::Select, Action::Start].map(Button::Action);
+        let directions = [Direction::Right, Direction::Left, Direction::Up, Direction::Down];
+        let buttons = actions
+            .into_iter()
+            .chain(directions.map(Button::Direction))
+            .fold(Buttons::empty(), |buttons, button| buttons | button.into());
+        assert_eq!(buttons, Buttons::all());
+        assert_eq!(Buttons::from(Button::Direction(Direction::Down)), Buttons::DOWN);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut joypad = Joypad::default();
+        joypad.press(A);
+        joypad.latch(Some(DOWN.bit()));
+        assert_eq!(joypad.pressed(), 0x80);
+        assert_eq!(joypad.local(), 0x01);
+        joypad.latch(None);
+        assert_eq!(joypad.pressed(), 0x01);
+    }
+}
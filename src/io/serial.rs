@@ -0,0 +1,196 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    interrupts::Interrupt,
+    memory::{
+        Memory,
+        registers::{SB, SC},
+    },
+};
+
+/// Bits in a transfer, an internally clocked one shifts them at 8192 Hz off the divider
+pub const TRANSFER_BITS: u8 = 8;
+
+/// Whatever is plugged into the link port, the side that supplies the clock drives transfers
+/// and the other side only sees them complete.
+/// Read more: https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+pub trait SerialDevice: Send {
+    /// This side is waiting on an external clock with `byte` in SB, `None` once it stops
+    fn set_waiting(&mut self, byte: Option<u8>);
+    /// This side clocked a transfer sending `byte`, returns the byte shifted in from the other
+    /// side which is 0xff when nothing is listening
+    fn clock(&mut self, byte: u8) -> u8;
+    /// A byte the other side clocked into this one since the last call
+    fn received(&mut self) -> Option<u8>;
+}
+
+#[derive(Debug, Default)]
+struct Side {
+    waiting: Option<u8>,
+    incoming: Option<u8>,
+}
+
+/// One end of a cable between two instances in the same process, see `LinkPort::pair`
+#[derive(Debug)]
+pub struct LinkPort {
+    sides: Arc<Mutex<[Side; 2]>>,
+    side: usize,
+}
+
+impl LinkPort {
+    /// Both ends of a cable
+    pub fn pair() -> (Self, Self) {
+        let sides = Arc::new(Mutex::new(Default::default()));
+        (Self { sides: sides.clone(), side: 0 }, Self { sides, side: 1 })
+    }
+}
+
+impl SerialDevice for LinkPort {
+    fn set_waiting(&mut self, byte: Option<u8>) {
+        self.sides.lock().unwrap()[self.side].waiting = byte;
+    }
+
+    fn clock(&mut self, byte: u8) -> u8 {
+        let other = &mut self.sides.lock().unwrap()[1 - self.side];
+        match other.waiting.take() {
+            Some(theirs) => {
+                other.incoming = Some(byte);
+                theirs
+            }
+            None => 0xff,
+        }
+    }
+
+    fn received(&mut self) -> Option<u8> {
+        self.sides.lock().unwrap()[self.side].incoming.take()
+    }
+}
+
+/// The transfer in progress, if any
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Serial {
+    #[default]
+    Idle,
+    /// Clocking `outgoing` out with `bits` of it left to shift
+    Internal { bits: u8, outgoing: u8 },
+    /// Waiting for the other side to clock a byte in
+    External,
+}
+
+impl Serial {
+    /// React to a write to SB or SC, bit 7 of SC starts a transfer and bit 0 selects the clock
+    pub fn write(&mut self, device: Option<&mut (dyn SerialDevice + 'static)>, mem: &Memory) {
+        let sc = mem.io.get(SC);
+        *self = match (sc & 0x80 != 0, sc & 0x01 != 0) {
+            (false, _) => Serial::Idle,
+            // writing SB mid transfer doesn't restart it
+            (true, true) => match *self {
+                Serial::Internal { .. } => *self,
+                _ => Serial::Internal {
+                    bits: TRANSFER_BITS,
+                    outgoing: mem.io.get(SB),
+                },
+            },
+            (true, false) => Serial::External,
+        };
+        if let Some(device) = device {
+            let waiting = (*self == Serial::External).then(|| mem.io.get(SB));
+            device.set_waiting(waiting);
+        }
+    }
+
+    /// Shift `bits` bits of an internally clocked transfer, each moves SB left by one with a 1
+    /// coming in behind it, what the line reads with nothing driving it. After the 8th the
+    /// bytes are traded with the other side, which is 0xff with nothing connected. Once that
+    /// happens or the other side clocks an externally clocked transfer, SB holds the byte
+    /// received, bit 7 of SC is cleared and the serial interrupt is requested.
+    /// Returns the bytes sent and received when a transfer completes.
+    pub fn tick(
+        &mut self,
+        bits: usize,
+        device: Option<&mut (dyn SerialDevice + 'static)>,
+        mem: &mut Memory,
+    ) -> Option<(u8, u8)> {
+        let (outgoing, incoming) = match (*self, device) {
+            (Serial::Idle, _) | (Serial::External, None) => return None,
+            (Serial::Internal { bits: left, outgoing }, device) => {
+                let shifted = bits.min(left as usize) as u8;
+                for _ in 0..shifted {
+                    mem.io.set(SB, mem.io.get(SB) << 1 | 1);
+                }
+                if shifted < left {
+                    *self = Serial::Internal { bits: left - shifted, outgoing };
+                    return None;
+                }
+                // nothing on the other end of the cable
+                let incoming = device.map_or(0xff, |device| device.clock(outgoing));
+                (outgoing, incoming)
+            }
+            (Serial::External, Some(device)) => match device.received() {
+                Some(byte) => (mem.io.get(SB), byte),
+                None => return None,
+            },
+        };
+        *self = Serial::Idle;
+        mem.io.set(SB, incoming);
+        mem.io.set(SC, mem.io.get(SC) & 0x7f);
+        mem.request_interrupt(Interrupt::SERIAL);
+        Some((outgoing, incoming))
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, memory::registers::IF};
+
+    fn memory() -> Memory {
+        Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap())
+    }
+
+    #[test]
+    fn test_unplugged() {
+        let mut mem = memory();
+        let mut serial = Serial::default();
+        mem.write(SB, 0x42);
+        mem.write(SC, 0x81);
+        serial.write(None, &mem);
+        serial.tick(4, None, &mut mem);
+        // half shifted out with 1s coming in behind it
+        assert_eq!(mem.read(SB), 0x2f);
+        serial.tick(3, None, &mut mem);
+        assert_eq!(mem.read(SC), 0xff);
+        assert_eq!(serial.tick(1, None, &mut mem), Some((0x42, 0xff)));
+        assert_eq!(mem.read(SB), 0xff);
+        assert_eq!(mem.read(SC), 0x7f);
+        assert_eq!(mem.io.get(IF) & Interrupt::SERIAL.bits(), Interrupt::SERIAL.bits());
+        // an external clock never comes
+        mem.write(SC, 0x80);
+        serial.write(None, &mem);
+        serial.tick(TRANSFER_BITS as usize * 2, None, &mut mem);
+        assert_eq!(serial, Serial::External);
+    }
+
+    #[test]
+    fn test_link() {
+        let (mut a, mut b) = LinkPort::pair();
+        let (mut master, mut slave) = (memory(), memory());
+        let (mut master_serial, mut slave_serial) = (Serial::default(), Serial::default());
+        slave.write(SB, 0x22);
+        slave.write(SC, 0x80);
+        slave_serial.write(Some(&mut b), &slave);
+        master.write(SB, 0x11);
+        master.write(SC, 0x81);
+        master_serial.write(Some(&mut a), &master);
+        slave_serial.tick(4, Some(&mut b), &mut slave);
+        assert_eq!(slave.read(SC), 0xfe);
+        master_serial.tick(TRANSFER_BITS as usize, Some(&mut a), &mut master);
+        slave_serial.tick(4, Some(&mut b), &mut slave);
+        assert_eq!((master.read(SB), slave.read(SB)), (0x22, 0x11));
+        assert_eq!((master.read(SC), slave.read(SC)), (0x7f, 0x7e));
+        // the slave stopped waiting so the next byte goes nowhere
+        master.write(SC, 0x81);
+        master_serial.write(Some(&mut a), &master);
+        master_serial.tick(TRANSFER_BITS as usize, Some(&mut a), &mut master);
+        assert_eq!(master.read(SB), 0xff);
+    }
+}
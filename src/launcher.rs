@@ -0,0 +1,249 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use sdl3::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormat,
+    render::FRect,
+    sys::pixels::SDL_PIXELFORMAT_RGB24,
+};
+
+use crate::{
+    PALETTE,
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH, Screen},
+    osd::{LINE_HEIGHT, draw_text},
+};
+
+/// Where the recent list is kept, next to gbr.json
+pub const RECENT_FILE: &str = "recent.json";
+/// How many ROMs the recent list remembers
+pub const RECENT_LIMIT: usize = 10;
+/// Extensions listed from the ROM folder
+pub const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+/// Entries shown under the title
+pub const ROWS: usize = SCREEN_HEIGHT / (LINE_HEIGHT + 1) - 1;
+// characters that fit on a line after the markers
+const NAME_CHARS: usize = SCREEN_WIDTH / 4 - 3;
+
+/// The ROMs launched last, most recent first, stored as a json list of paths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recent(pub Vec<PathBuf>);
+
+impl Recent {
+    /// A missing file is an empty list
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(recent) => Ok(Self(serde_json::from_slice(&recent)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(&self.0)?)
+    }
+
+    /// Move `rom` to the front, dropping the oldest entry once there are too many
+    pub fn push(&mut self, rom: impl Into<PathBuf>) {
+        let rom = rom.into();
+        self.0.retain(|path| *path != rom);
+        self.0.insert(0, rom);
+        self.0.truncate(RECENT_LIMIT);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Came from the recent list rather than the ROM folder
+    pub recent: bool,
+}
+
+/// The screen shown when gbr is started without a ROM: the recent ROMs that still exist
+/// followed by the ROMs in the configured folder, picked with the arrow keys and Enter
+#[derive(Debug, Clone, Default)]
+pub struct Launcher {
+    pub entries: Vec<Entry>,
+    pub selected: usize,
+    // first entry shown
+    top: usize,
+}
+
+impl Launcher {
+    pub fn new(recent: &Recent, rom_dir: Option<&Path>) -> std::io::Result<Self> {
+        let mut entries: Vec<Entry> = recent
+            .0
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| Entry { path: path.clone(), recent: true })
+            .collect();
+        if let Some(dir) = rom_dir {
+            let mut roms = vec![];
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                if ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()) {
+                    roms.push(path);
+                }
+            }
+            roms.sort();
+            for path in roms {
+                if !entries.iter().any(|entry| entry.path == path) {
+                    entries.push(Entry { path, recent: false });
+                }
+            }
+        }
+        Ok(Self { entries, selected: 0, top: 0 })
+    }
+
+    /// Move the selection by `delta` entries, stopping at either end of the list
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.entries.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + ROWS {
+            self.top = self.selected + 1 - ROWS;
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Path> {
+        self.entries.get(self.selected).map(|entry| entry.path.as_path())
+    }
+
+    /// The title and a page of file names, `>` marks the selection and `*` a recent ROM
+    pub fn lines(&self) -> Vec<String> {
+        if self.entries.is_empty() {
+            return vec!["GBR".into(), "NO ROMS - SET ROM_DIR IN GBR.JSON".into()];
+        }
+        let mut lines = vec![format!("GBR {}/{}", self.selected + 1, self.entries.len())];
+        for (i, entry) in self.entries.iter().enumerate().skip(self.top).take(ROWS) {
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+            lines.push(format!(
+                "{}{}{}",
+                if i == self.selected { '>' } else { ' ' },
+                if entry.recent { '*' } else { ' ' },
+                name.chars().take(NAME_CHARS).collect::<String>()
+            ));
+        }
+        lines
+    }
+
+    /// Draw the launcher onto an RGB24 160x144 framebuffer, replacing whatever was there
+    pub fn draw(&self, frame_buffer: &mut [u8]) {
+        frame_buffer.fill(PALETTE[0]);
+        for (i, line) in self.lines().iter().enumerate() {
+            draw_text(frame_buffer, line, 0, i * (LINE_HEIGHT + 1));
+        }
+    }
+
+    /// Show the launcher on `screen` until a ROM is picked with Enter,
+    /// returns `None` if the window is closed or Escape is pressed
+    pub fn run(&mut self, screen: &mut Screen) -> Option<PathBuf> {
+        let Screen { canvas, event_pump } = screen;
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(
+                PixelFormat::try_from(SDL_PIXELFORMAT_RGB24).unwrap(),
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            )
+            .unwrap();
+        loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => return None,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => match keycode {
+                        Keycode::Up => self.move_selection(-1),
+                        Keycode::Down => self.move_selection(1),
+                        Keycode::PageUp => self.move_selection(-(ROWS as isize)),
+                        Keycode::PageDown => self.move_selection(ROWS as isize),
+                        Keycode::Return => {
+                            if let Some(path) = self.selected() {
+                                return Some(path.to_path_buf());
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            texture
+                .with_lock(None, |buffer: &mut [u8], _: usize| self.draw(buffer))
+                .unwrap();
+            canvas
+                .copy(&texture, None, Some(FRect::new(0.0, 0.0, 160.0, 144.0)))
+                .unwrap();
+            canvas.present();
+            std::thread::sleep(Duration::from_millis(16));
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gbr-launcher-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut recent = Recent::default();
+        for i in 0..RECENT_LIMIT + 2 {
+            recent.push(format!("{i}.gb"));
+        }
+        recent.push("5.gb");
+        assert_eq!(recent.0.len(), RECENT_LIMIT);
+        assert_eq!(recent.0[0], PathBuf::from("5.gb"));
+        assert_eq!(recent.0[1], PathBuf::from("11.gb"));
+        let path = temp_dir("recent").join("recent.json");
+        assert_eq!(Recent::load(&path).unwrap(), Recent::default());
+        recent.save(&path).unwrap();
+        assert_eq!(Recent::load(&path).unwrap(), recent);
+    }
+
+    #[test]
+    fn test_launcher() {
+        let dir = temp_dir("entries");
+        for file in ["b.gb", "a.GBC", "c.gb", "notes.txt"] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+        let recent = Recent(vec![dir.join("c.gb"), dir.join("gone.gb")]);
+        let mut launcher = Launcher::new(&recent, Some(&dir)).unwrap();
+        assert_eq!(launcher.lines(), vec!["GBR 1/3", ">*c.gb", "  a.GBC", "  b.gb"]);
+        launcher.move_selection(-1);
+        assert_eq!(launcher.selected(), Some(dir.join("c.gb").as_path()));
+        launcher.move_selection(5);
+        assert_eq!(launcher.selected(), Some(dir.join("b.gb").as_path()));
+        assert_eq!(launcher.lines()[3], "> b.gb");
+    }
+
+    #[test]
+    fn test_scroll() {
+        let recent = Recent((0..ROWS + 5).map(|i| PathBuf::from(format!("{i}.gb"))).collect());
+        let mut launcher = Launcher {
+            entries: recent.0.into_iter().map(|path| Entry { path, recent: true }).collect(),
+            ..Default::default()
+        };
+        launcher.move_selection(ROWS as isize);
+        assert_eq!(launcher.top, 1);
+        assert_eq!(launcher.lines()[ROWS], format!(">*{ROWS}.gb"));
+        launcher.move_selection(-(ROWS as isize));
+        assert_eq!(launcher.top, 0);
+    }
+}
@@ -1,26 +1,63 @@
 #![allow(warnings)]
-use std::io::Write;
+use std::sync::LazyLock;
 
 use crate::errors::DecodeError;
 use cpu::Cpu;
 use instructions::{Instruction, InstructionResult};
 use memory::Memory;
 
+pub mod accuracy;
+pub mod analysis;
 pub mod apu;
+pub mod audio;
+pub mod banked;
+pub mod batch;
+pub mod bundle;
 pub mod cartridge;
+pub mod cheats;
 pub mod clock;
+pub mod config;
 pub mod cpu;
+pub mod crash;
+pub mod debugger;
+pub mod determinism;
 pub mod display;
 pub mod errors;
+pub mod events;
+pub mod gamedb;
+pub mod hash;
 pub mod instructions;
 pub mod interrupts;
 pub mod io;
+#[cfg(feature = "sdl")]
+pub mod launcher;
 pub mod memory;
+pub mod memory_editor;
+pub mod model;
+pub mod movie;
+pub mod netplay;
+pub mod oam;
+pub mod observation;
+pub mod osd;
+pub mod png;
+pub mod rle;
+pub mod rtc;
+pub mod sm83;
+pub mod state;
+pub mod stats;
 pub mod system;
+pub mod tile;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod video;
+pub mod vram_export;
+pub mod watchdog;
+pub mod y4m;
 
 /// Holds the necessary context for instruction decoding.
 pub struct DecodeContext<'a> {
-    // TODO: remove this and iterate the rom another way, cloning memory in every loop is probably expensive
+    /// The operand bytes following the opcode
     pub iter: std::slice::Iter<'a, u8>,
     pub cpu: &'a mut Cpu,
     pub memory: &'a mut Memory,
@@ -63,7 +100,7 @@ pub struct DecodeContext<'a> {
 // i.e: functions in the dispatch table take different parts of `ctx` as parameters, i think they should always take all of `DecodeContext`
 pub type DecodeFn = fn(&mut DecodeContext) -> InstructionResult<Instruction>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mnemonic {
     PREFIX,
     LD,
@@ -143,43 +180,22 @@ pub fn get_u16(iter: &mut std::slice::Iter<u8>) -> InstructionResult<u16> {
     Ok(n16)
 }
 
-/// Helper that creates .ppm images to debug tile rendering
-pub fn dump_tiles(tiles: Vec<u8>, width: u16, height: u16) {
-    let mut file =
-        std::fs::File::create(format!("{}/test.ppm", env!("CARGO_MANIFEST_DIR"))).unwrap();
-    let header = format!("P3\n{} {}\n255\n", &width, &height);
-    let header = header.as_bytes();
-    file.write(header);
-    for i in 0..height {
-        for j in (0..width).rev() {
-            let pixel = tiles[(j + (i * height)) as usize];
-            let pixel = format!("{} {} {}\n", pixel, pixel, pixel);
-            file.write(pixel.as_bytes());
-        }
-    }
-}
-
 pub const PALETTE: [u8; 4] = [255, 170, 85, 0];
 
+/// Every pair of bitplane bytes decoded into a row of shades, indexed by `high << 8 | low`
+static TILE_ROWS: LazyLock<Vec<[u8; 8]>> = LazyLock::new(|| {
+    (0..=u16::MAX)
+        .map(|planes| {
+            let [low, high] = planes.to_le_bytes();
+            std::array::from_fn(|j| PALETTE[((low >> j) & 1 | ((high >> j) & 1) << 1) as usize])
+        })
+        .collect()
+});
+
 /// Each tile is 16 bytes, after decoding each tile contains 8x8 pixels and has a color depth of 2 bits per pixel
 /// A line is made up of 2 tiles where the even indices specify the LSB of the color and the odd the MSB
 /// e.g: given 00111100 01111110 the first byte would be 0x0 and the second byte would be 0x2
+/// Rows are looked up whole in `TILE_ROWS` instead of being put together a bit at a time.
 pub fn decode_tile(tile: &[u8]) -> [[u8; 8]; 8] {
-    let mut output: [[u8; 8]; 8] = [[0; 8]; 8];
-    let low: [u8; 8] = [
-        tile[0], tile[2], tile[4], tile[6], tile[8], tile[10], tile[12], tile[14],
-    ];
-    let high: [u8; 8] = [
-        tile[1], tile[3], tile[5], tile[7], tile[9], tile[11], tile[13], tile[15],
-    ];
-    for i in 0..8 {
-        for j in 0..8 {
-            let low_px = low[i];
-            let high_px = high[i];
-            let mut pixel = (low_px >> j) & 1;
-            pixel |= ((high_px >> j) & 1) << 1;
-            output[i][j] = PALETTE[pixel as usize];
-        }
-    }
-    return output;
+    std::array::from_fn(|i| TILE_ROWS[(tile[i * 2 + 1] as usize) << 8 | tile[i * 2] as usize])
 }
@@ -1,17 +1,578 @@
-use clap::Parser;
-use gbr::system::System;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use clap::{Parser, Subcommand};
+use gbr::{
+    accuracy::Accuracy,
+    analysis::analyze,
+    audio::{AudioConfig, Resampling},
+    cartridge::Cartridge,
+    config::Config,
+    debugger::DebugCommand,
+    gamedb::GameDb,
+    hash::hex,
+    determinism::DeterminismConfig,
+    display::Screen,
+    io::infrared::{IrDevice, Loopback, TcpIr},
+    launcher::{Launcher, RECENT_FILE, Recent},
+    model::Model,
+    movie::{Movie, MovieMode},
+    netplay::Netplay,
+    rtc::RtcMode,
+    state::{SLOT_COUNT, SaveState, StateSlots, format_timestamp},
+    system::{LinkedSystems, System},
+    tile::TileMap,
+    trace::{Trace, TraceEntry, diff},
+    video::{self, NullVideo, OffscreenVideo, Video},
+    vram_export,
+    watchdog::Watchdog,
+};
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
 struct Args {
-    file: String,
+    file: Option<String>,
+    /// Record a movie from power on to this file
+    #[arg(long, conflicts_with = "play")]
+    record: Option<String>,
+    /// Play back a movie, taking over (T) records over the rest of it
+    #[arg(long)]
+    play: Option<String>,
+    /// Author stored in a recorded movie
+    #[arg(long, default_value = "")]
+    author: String,
+    /// Pause when movie playback runs out of inputs
+    #[arg(long)]
+    pause_on_movie_end: bool,
+    /// How an MBC3 clock advances: host, emulated or frozen[:seconds]
+    #[arg(long)]
+    rtc: Option<RtcMode>,
+    /// fast skips recording the pc history crash reports include, defaults to accurate
+    #[arg(long)]
+    accuracy: Option<Accuracy>,
+    /// Hardware whose boot rom state to start from: dmg0, dmg, mgb, sgb, sgb2 or cgb
+    #[arg(long)]
+    model: Option<Model>,
+    /// Seed for anything random outside of the emulated hardware
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Wait for another instance to join a netplay session on this address, e.g: 0.0.0.0:7654
+    #[arg(long, conflicts_with = "join")]
+    host: Option<String>,
+    /// Join a netplay session hosted at this address
+    #[arg(long)]
+    join: Option<String>,
+    /// Point the infrared port at: loopback, listen:<addr> or connect:<addr>
+    #[arg(long)]
+    ir: Option<String>,
+    /// Per-game overrides, defaults to gbr.json if it exists
+    #[arg(long)]
+    config: Option<String>,
+    /// Folder the launcher lists ROMs from when no file is given, overrides rom_dir in the config
+    #[arg(long)]
+    rom_dir: Option<String>,
+    /// Database of known ROMs to check the header against, defaults to gamedb.json if it exists
+    #[arg(long)]
+    gamedb: Option<String>,
+    /// Blend every frame with the previous one like a DMG LCD, toggled with G
+    #[arg(long)]
+    frame_blend: bool,
+    /// Also write a save state to <rom>.states/emergency.state if the emulator crashes or is
+    /// interrupted, battery ram is always flushed
+    #[arg(long)]
+    emergency_state: bool,
+    /// Write a bug bundle here when the emulator exits, F12 writes one at any time
+    #[arg(long)]
+    bug_bundle: Option<String>,
+    /// Pause with a diagnostic once the cpu spends this many frames in a tiny loop with
+    /// interrupts off and no IO, the usual cause of a black screen
+    #[arg(long)]
+    watchdog: Option<u32>,
+    /// Write `System::stats` to this file as json when the emulator exits
+    #[arg(long)]
+    stats_json: Option<String>,
+    /// Where frames are shown: sdl, softbuffer, offscreen or none. Only sdl has the launcher,
+    /// audio and keyboard, the others are for platforms without SDL3 and for running headless.
+    /// sdl falls back to offscreen when there's no display to open a window on.
+    #[arg(long, default_value = "sdl")]
+    video: Video,
+    /// Stop running offscreen after this many frames
+    #[arg(long)]
+    frames: Option<u64>,
+    /// Encode the frames rendered offscreen to this file as a Y4M video
+    #[arg(long)]
+    encode: Option<String>,
+    /// Run in the terminal instead of opening a window
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    tui: bool,
+    /// Audio output sample rate in Hz
+    #[arg(long, default_value_t = 48000)]
+    sample_rate: u32,
+    /// Audio buffer size in frames, smaller buffers lower latency but can crackle
+    #[arg(long, default_value_t = 512)]
+    audio_buffer: usize,
+    /// How audio is brought down to the sample rate: sinc, or linear which is cheaper but
+    /// aliases more
+    #[arg(long, default_value = "sinc")]
+    resampling: Resampling,
+    /// Debugger command to run before the first frame, like `memload 0xc000 setup.bin`, can be
+    /// given more than once
+    #[arg(long)]
+    exec: Vec<DebugCommand>,
+    /// Debugger command to run once the emulator stops, like `memdump 0x8000 0x97ff tiles.bin`
+    #[arg(long)]
+    exec_on_exit: Vec<DebugCommand>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the header and hashes of a ROM
+    Info { file: String },
+    /// Disassemble a ROM from its entry point and vectors and list the functions found
+    Analyze {
+        file: String,
+        /// Write the functions and labels to a .sym file instead of printing them
+        #[arg(long)]
+        sym: Option<String>,
+    },
+    /// Inspect save states
+    States {
+        #[command(subcommand)]
+        command: StatesCommand,
+    },
+    /// Run two ROMs side by side in one window, connected by a link cable
+    Link { left: String, right: String },
+    /// Run a ROM without a window, checking every instruction against a reference trace and
+    /// stopping at the first mismatch
+    Run {
+        file: String,
+        /// Gameboy Doctor, BGB or SameBoy formatted log
+        #[arg(long)]
+        compare_trace: String,
+        /// Entries to print before the mismatch
+        #[arg(long, default_value_t = 3)]
+        context: usize,
+    },
+    /// Compare two execution traces and print the first divergence
+    TraceDiff {
+        /// Gameboy Doctor, BGB or SameBoy formatted log
+        a: String,
+        b: String,
+        /// Entries to print before the divergence
+        #[arg(long, default_value_t = 3)]
+        context: usize,
+    },
+    /// Render the tile data and both tile maps in VRAM to PNGs in the game's palette
+    ExportTiles {
+        file: String,
+        /// `slotN` or a save state file, without one VRAM is exported as it is after booting
+        #[arg(long)]
+        state: Option<String>,
+        /// Where the tile sheet goes, the maps are written next to it with -9800 and -9c00
+        /// added to the name
+        #[arg(long, default_value = "tiles.png")]
+        out: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StatesCommand {
+    /// List the save state slots of a ROM
+    List { file: String },
+    /// Print the registers and memory that differ between two save state files
+    Diff { a: String, b: String },
+}
+
+fn rom_path(file: &str) -> String {
+    format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
+
+/// `config`, or gbr.json when there is one
+fn load_config(config: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
+    Ok(match config {
+        Some(config) => Config::load(config)?,
+        None if std::path::Path::new(&rom_path("gbr.json")).exists() => {
+            Config::load(rom_path("gbr.json"))?
+        }
+        None => Config::default(),
+    })
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), args.file);
-    let binary = std::fs::read(&path).expect(&format!("Couldn't find {} at {path}", args.file));
-    let mut emulator = System::new(binary)?;
-    emulator.run();
+    let movie = args.record.clone().or(args.play.clone());
+    #[cfg(feature = "tui")]
+    let tui = args.tui;
+    #[cfg(not(feature = "tui"))]
+    let tui = false;
+    match (args.command, args.file) {
+        (Some(Command::States { command }), _) => match command {
+            StatesCommand::List { file } => list_states(&file),
+            StatesCommand::Diff { a, b } => diff_states(&a, &b),
+        },
+        (Some(Command::Info { file }), _) => info(&file),
+        (Some(Command::Analyze { file, sym }), _) => analyze_rom(&file, sym.as_deref()),
+        (Some(Command::Link { left, right }), _) => link(&left, &right),
+        (Some(Command::Run { file, compare_trace: reference, context }), _) => {
+            compare_trace(&file, &reference, context)
+        }
+        (Some(Command::TraceDiff { a, b, context }), _) => trace_diff(&a, &b, context),
+        (Some(Command::ExportTiles { file, state, out }), _) => {
+            export_tiles(&file, state.as_deref(), &out, &load_config(args.config.as_deref())?)
+        }
+        (None, file) => {
+            let config = load_config(args.config.as_deref())?;
+            let recent_path = rom_path(RECENT_FILE);
+            let mut recent = Recent::load(&recent_path)?;
+            let (path, screen) = match file {
+                Some(file) => (rom_path(&file), None),
+                None if tui || args.video != Video::Sdl => return Err("no ROM file given".into()),
+                // without a ROM pick one in the window the emulator then runs in
+                None => {
+                    let rom_dir = args.rom_dir.as_ref().or(config.rom_dir.as_ref());
+                    let rom_dir = rom_dir.map(|dir| rom_path(dir));
+                    let mut launcher = Launcher::new(&recent, rom_dir.as_deref().map(Path::new))?;
+                    let mut screen = Screen::new()?;
+                    match launcher.run(&mut screen) {
+                        Some(path) => (path.display().to_string(), Some(screen)),
+                        None => return Ok(()),
+                    }
+                }
+            };
+            let binary =
+                std::fs::read(&path).map_err(|e| format!("Couldn't find a ROM at {path}: {e}"))?;
+            recent.push(&path);
+            if let Err(e) = recent.save(&recent_path) {
+                eprintln!("warning: couldn't update {recent_path}: {e}");
+            }
+            let mut emulator = System::headless(binary)?;
+            emulator.state_slots = Some(StateSlots::for_rom(&path));
+            let gamedb = match &args.gamedb {
+                Some(gamedb) => GameDb::load(gamedb)?,
+                None if std::path::Path::new(&rom_path("gamedb.json")).exists() => {
+                    GameDb::load(rom_path("gamedb.json"))?
+                }
+                None => GameDb::default(),
+            };
+            for mismatch in emulator.verify_rom(&gamedb)? {
+                eprintln!("warning: {mismatch}");
+            }
+            emulator.configure(&config.game(&emulator.mem.cartridge));
+            if let Some(accuracy) = args.accuracy {
+                emulator.set_accuracy(accuracy);
+            }
+            if let Some(model) = args.model {
+                emulator.set_model(model);
+            }
+            emulator.battery_path = Some(Path::new(&path).with_extension("sav"));
+            emulator.load_battery()?;
+            // SIGINT and SIGTERM stop the run loop so battery ram still gets flushed
+            let quit = emulator.quit.clone();
+            ctrlc::set_handler(move || quit.store(true, Ordering::Relaxed))?;
+            emulator.pause_on_movie_end = args.pause_on_movie_end;
+            emulator.frame_blend = args.frame_blend;
+            if let Some(frames) = args.watchdog {
+                let mut watchdog = Watchdog::new(frames);
+                watchdog.pause = true;
+                emulator.watchdog = Some(watchdog);
+            }
+            // following the host's clock is the only way the core reads host time
+            let host_time = args.rtc == Some(RtcMode::HostSync);
+            if host_time && movie.is_some() {
+                return Err("movies can't be recorded or played back with --rtc host".into());
+            }
+            if host_time && (args.host.is_some() || args.join.is_some()) {
+                return Err("netplay can't be used with --rtc host".into());
+            }
+            emulator.set_determinism(DeterminismConfig {
+                host_time,
+                seed: args.seed,
+            });
+            emulator.set_audio_config(AudioConfig {
+                sample_rate: args.sample_rate,
+                buffer_size: args.audio_buffer,
+                resampling: args.resampling,
+            });
+            if let Some(mode) = args.rtc {
+                emulator.set_rtc_mode(mode);
+            }
+            if let Some(ir) = &args.ir {
+                emulator.ir = Some(ir_device(ir)?);
+            }
+            let checksum = emulator.mem.cartridge.global_checksum;
+            if let Some(addr) = &args.host {
+                println!("Waiting for another instance on {addr}");
+                emulator.start_netplay(Netplay::host(addr, checksum, args.seed)?);
+            } else if let Some(addr) = &args.join {
+                emulator.start_netplay(Netplay::join(addr, checksum, args.seed)?);
+            }
+            if args.record.is_some() {
+                emulator.start_movie(Movie::record(&emulator.mem.cartridge, &args.author));
+            } else if let Some(play) = &args.play {
+                emulator.start_movie(Movie::load(play, &emulator.mem.cartridge)?);
+            }
+            for command in &args.exec {
+                println!("{}", command.run(&mut emulator.mem)?);
+            }
+            // a panic unwinds back here so battery ram can be flushed before the process dies
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| -> std::io::Result<()> {
+                #[cfg(feature = "tui")]
+                if args.tui {
+                    return gbr::tui::run(&mut emulator);
+                }
+                let other = std::io::Error::other;
+                let offscreen = || -> std::io::Result<OffscreenVideo> {
+                    let mut video = OffscreenVideo::new(args.frames);
+                    if let Some(path) = &args.encode {
+                        video.encode_to(path)?;
+                    }
+                    Ok(video)
+                };
+                match args.video {
+                    Video::Sdl => {}
+                    #[cfg(feature = "softbuffer")]
+                    Video::Softbuffer => {
+                        let mut window = video::SoftbufferVideo::new().map_err(other)?;
+                        return video::run(&mut emulator, &mut window).map_err(other);
+                    }
+                    Video::Offscreen => {
+                        return video::run(&mut emulator, &mut offscreen()?).map_err(other);
+                    }
+                    Video::Null => return video::run(&mut emulator, &mut NullVideo).map_err(other),
+                }
+                let screen = match screen {
+                    Some(screen) => Ok(screen),
+                    None => Screen::new(),
+                };
+                match screen {
+                    Ok(mut screen) => emulator.run_on(&mut screen),
+                    // CI and machines without Wayland or X11
+                    Err(e) => {
+                        eprintln!("warning: {e}, running offscreen");
+                        return video::run(&mut emulator, &mut offscreen()?).map_err(other);
+                    }
+                }
+                Ok(())
+            }));
+            match outcome {
+                Ok(_) if !emulator.quit.load(Ordering::Relaxed) => {
+                    emulator.flush_battery()?;
+                }
+                _ => emergency_save(&emulator, args.emergency_state),
+            }
+            outcome.unwrap_or_else(|panic| panic::resume_unwind(panic))?;
+            for command in &args.exec_on_exit {
+                println!("{}", command.run(&mut emulator.mem)?);
+            }
+            if let Some(path) = &args.stats_json {
+                std::fs::write(path, serde_json::to_vec_pretty(&emulator.stats())?)?;
+            }
+            if let Some(path) = &args.bug_bundle {
+                std::fs::write(path, emulator.bug_bundle())?;
+            }
+            // a played back movie is only written again if it was taken over
+            if let (Some(movie), Some(path)) = (&emulator.movie, movie)
+                && movie.mode == MovieMode::Recording
+            {
+                movie.save(&path)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Save whatever can be saved after a crash or an interrupt, failures are only reported since
+/// the process is going down anyway
+fn emergency_save(emulator: &System, write_state: bool) {
+    match emulator.flush_battery() {
+        Ok(true) => eprintln!("Battery ram flushed"),
+        Ok(false) => {}
+        Err(e) => eprintln!("Flushing battery ram failed: {e}"),
+    }
+    if let (true, Some(slots)) = (write_state, &emulator.state_slots) {
+        match slots.save_emergency(&emulator.save_state()) {
+            Ok(path) => eprintln!("Emergency state written to {}", path.display()),
+            Err(e) => eprintln!("Writing the emergency state failed: {e}"),
+        }
+    }
+}
+
+fn ir_device(device: &str) -> Result<Box<dyn IrDevice>, Box<dyn std::error::Error>> {
+    match device.split_once(':') {
+        None if device == "loopback" => Ok(Box::new(Loopback::default())),
+        Some(("listen", addr)) => {
+            println!("Waiting for an infrared connection on {addr}");
+            let (stream, _) = std::net::TcpListener::bind(addr)?.accept()?;
+            Ok(Box::new(TcpIr::new(stream)?))
+        }
+        Some(("connect", addr)) => Ok(Box::new(TcpIr::new(std::net::TcpStream::connect(addr)?)?)),
+        _ => Err(format!("Unknown infrared device {device}").into()),
+    }
+}
+
+fn info(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let cartridge = Cartridge::new(std::fs::read(rom_path(file))?)?;
+    println!("title: {}", cartridge.title.trim_end_matches('\0'));
+    println!("type: {:?}", cartridge.cartridge_type);
+    println!("rom banks: {}", cartridge.rom_size);
+    if let Some(mismatch) = cartridge.size_mismatch() {
+        println!("warning: {mismatch}");
+    }
+    println!("ram: {:?}", cartridge.ram_size);
+    println!("cgb: {}", cartridge.cgb_flag);
+    println!("header checksum: 0x{:02x}", cartridge.header_checksum);
+    println!("global checksum: 0x{:04x}", cartridge.global_checksum);
+    println!("crc32: {:08x}", cartridge.crc32());
+    println!("sha1: {}", hex(&cartridge.sha1()));
+    Ok(())
+}
+
+fn analyze_rom(file: &str, sym: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let analysis = analyze(&std::fs::read(rom_path(file))?);
+    match sym {
+        Some(sym) => {
+            std::fs::write(sym, analysis.to_sym())?;
+            println!(
+                "{} functions, {} labels, {} blocks written to {sym}",
+                analysis.functions.len(),
+                analysis.labels.len(),
+                analysis.blocks.len()
+            );
+        }
+        None => print!("{}", analysis.to_sym()),
+    }
+    Ok(())
+}
+
+fn export_tiles(
+    file: &str,
+    state: Option<&str>,
+    out: &str,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = rom_path(file);
+    let mut system = System::headless(std::fs::read(&path)?)?;
+    system.configure(&config.game(&system.mem.cartridge));
+    let state = match state {
+        Some(state) => match state.strip_prefix("slot").map(str::parse::<u8>) {
+            Some(Ok(slot)) => Some(StateSlots::for_rom(&path).load(slot)?),
+            _ => Some(SaveState::from_bytes(&std::fs::read(state)?)?),
+        },
+        None => None,
+    };
+    if let Some(state) = state {
+        system.load_state(&state)?;
+    }
+    let (mem, shades) = (&system.mem, &system.ppu.palette);
+    let out = Path::new(out);
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    std::fs::write(out, vram_export::tile_sheet(mem, shades).to_png())?;
+    println!("tiles written to {}", out.display());
+    let addressing = mem.lcd_control().tile_addressing;
+    for map in [TileMap::Low, TileMap::High] {
+        let map_path = out.with_file_name(format!("{stem}-{:04x}.png", map.base()));
+        std::fs::write(&map_path, vram_export::tile_map(mem, map, addressing, shades).to_png())?;
+        println!("tile map {:04x} written to {}", map.base(), map_path.display());
+    }
+    Ok(())
+}
+
+fn link(left: &str, right: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (left, right) = (rom_path(left), rom_path(right));
+    let mut linked = LinkedSystems::new(std::fs::read(&left)?, std::fs::read(&right)?)?;
+    linked.left.battery_path = Some(Path::new(&left).with_extension("sav"));
+    // both sides would write the same file when linking a game with itself
+    if left != right {
+        linked.right.battery_path = Some(Path::new(&right).with_extension("sav"));
+    }
+    linked.left.load_battery()?;
+    linked.right.load_battery()?;
+    let quit = linked.left.quit.clone();
+    ctrlc::set_handler(move || quit.store(true, Ordering::Relaxed))?;
+    linked.run();
+    linked.left.flush_battery()?;
+    linked.right.flush_battery()?;
+    Ok(())
+}
+
+fn list_states(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let slots = StateSlots::for_rom(rom_path(file));
+    let index = slots.index()?;
+    for slot in 0..SLOT_COUNT {
+        match index.iter().find(|entry| entry.slot == slot) {
+            Some(entry) => println!(
+                "slot {slot}: {} - {} (checksum 0x{:04x}, thumbnail {})",
+                format_timestamp(entry.timestamp),
+                entry.title,
+                entry.global_checksum,
+                slots.dir.join(&entry.thumbnail).display()
+            ),
+            None => println!("slot {slot}: empty"),
+        }
+    }
+    Ok(())
+}
+
+fn diff_states(a: &str, b: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let state_a = SaveState::from_bytes(&std::fs::read(a)?)?;
+    let state_b = SaveState::from_bytes(&std::fs::read(b)?)?;
+    print!("{}", state_a.diff(&state_b));
+    Ok(())
+}
+
+fn trace_diff(a: &str, b: &str, context: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let trace_a = Trace::parse(&std::fs::read_to_string(a)?);
+    let trace_b = Trace::parse(&std::fs::read_to_string(b)?);
+    let Some(divergence) = diff(&trace_a, &trace_b) else {
+        println!("No divergence in {} entries", trace_a.entries.len());
+        return Ok(());
+    };
+    let start = context.min(divergence.a).min(divergence.b);
+    for offset in (0..=start).rev() {
+        for (file, trace, index) in [(a, &trace_a, divergence.a), (b, &trace_b, divergence.b)] {
+            match trace.entries.get(index - offset) {
+                Some((line, entry)) => println!("{file}:{line}: {entry}"),
+                None => println!("{file}: <end of trace>"),
+            }
+        }
+    }
+    match divergence.fields.is_empty() {
+        true => println!("One of the traces ended early"),
+        false => println!("Diverged on {}", divergence.fields.join(", ")),
+    }
+    Ok(())
+}
+
+fn compare_trace(
+    file: &str,
+    reference: &str,
+    context: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let trace = Trace::parse(&std::fs::read_to_string(reference)?);
+    let mut emulator = System::headless(std::fs::read(rom_path(file))?)?;
+    let Some(divergence) = emulator.compare_trace(&trace)? else {
+        println!("No mismatch in {} entries", trace.entries.len());
+        return Ok(());
+    };
+    // the instructions leading up to the mismatch are the last ones the emulator traced
+    let mut ours: Vec<TraceEntry> = emulator.trace.entries().copied().collect();
+    ours.push(TraceEntry::from_cpu(&emulator.cpu).with_bank(&emulator.mem));
+    let start = context.min(divergence.a).min(divergence.b).min(ours.len() - 1);
+    for offset in (0..=start).rev() {
+        let (line, expected) = &trace.entries[divergence.b - offset];
+        println!("{reference}:{line}: {expected}");
+        println!("{file}#{}: {}", divergence.a - offset, ours[ours.len() - 1 - offset]);
+    }
+    println!(
+        "Diverged on {} after {} instructions",
+        divergence.fields.join(", "),
+        divergence.a
+    );
     Ok(())
 }
@@ -2,10 +2,16 @@ use regions::*;
 use registers::*;
 
 use crate::{
-    cartridge::{Cartridge, CartridgeType},
+    banked::BankedAddr,
+    cartridge::{Cartridge, CartridgeType, ROM_BANK_SIZE},
     decode_tile,
     errors::SystemError,
-    io::{LcdControl, LcdStatus, TimerControl},
+    interrupts::Interrupt,
+    io::{IoRegisters, LcdControl, LcdStatus, TimerControl, cgb_only},
+    model::Model,
+    oam::{OAM_ENTRIES, OamEntry},
+    tile::{TILE_SIZE, Tile, TileAddressing, TileCache, TileId, TileMap, VramWrites},
+    rtc::{RTC_DAY_HIGH, RTC_SECONDS, Rtc, RtcMode},
 };
 
 // Registers
@@ -53,6 +59,7 @@ pub mod registers {
     pub const OGBP1: usize = 0xff49;
     pub const WY: usize = 0xff4a;
     pub const WX: usize = 0xff4b;
+    pub const RP: usize = 0xff56;
     pub const IE: usize = 0xffff;
 }
 
@@ -88,13 +95,137 @@ pub mod regions {
     pub const INTERRUPT_ENABLE_REGISTER: usize = 0xffff;
 }
 
+/// An address in the high page ($FF00-$FFFF) built from a single byte, as used by LDH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HighAddr(pub u8);
+
+impl HighAddr {
+    pub fn addr(self) -> usize {
+        0xff00 | self.0 as usize
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
+    #[cfg_attr(feature = "serde", serde(with = "crate::rle"))]
     pub block: [u8; 65536],
+    /// 0xff00-0xff7f, the matching range of `block` is unused
+    pub io: IoRegisters,
     pub cartridge: Cartridge,
-    pub oam_accessible: bool,
-    pub vram_accessible: bool,
-    pub rom_banks: Vec<[u8; 16383]>,
+    /// External ram on the cartridge, as much as the header declares, reads past it are open bus
+    pub ram: Vec<u8>,
+    // the banks are only read by `setup_mbc`, the mapped ones already live in `block`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub rom_banks: Vec<[u8; ROM_BANK_SIZE]>,
+    /// The real-time clock of MBC3 cartridges with a timer
+    pub rtc: Option<Rtc>,
+    /// The rtc register mapped into 0xa000-0xbfff, if any
+    pub rtc_register: Option<u8>,
+    /// Decoded tiles, anything writing to `block` directly has to call `invalidate_tiles`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub tiles: TileCache,
+    /// VRAM written since the ppu last called `take_vram_writes`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub vram_writes: VramWrites,
+    /// Set whenever the cpu reads or writes an IO register, the watchdog clears it
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub io_accessed: bool,
+    /// Registers only the CGB has aren't mapped on other models, set by `Model::power_up`
+    pub model: Model,
+    /// Every read and write the cpu makes goes here while it's set, in order, for checking
+    /// instruction timing against the SingleStepTests cycle arrays
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub bus_log: Option<Vec<BusAccess>>,
+    /// The OAM DMA transfer copying into OAM, the cpu can't reach OAM until it's done
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub oam_dma: Option<OamDma>,
+    /// A transfer written to DMA and whether the instruction that wrote it has finished, it
+    /// starts up during the cycle after that and then takes over from `oam_dma`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    oam_dma_start: Option<(usize, bool)>,
+}
+
+/// An OAM DMA transfer, copying a byte every M-cycle
+/// Read more: https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamDma {
+    /// Where the 160 bytes are copied from
+    pub source: usize,
+    /// Bytes copied so far
+    pub copied: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusOp {
+    Read,
+    Write,
+}
+
+/// One m-cycle of cpu bus activity, see `Memory::bus_log`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub op: BusOp,
+}
+
+/// Bytes of external ram the cpu sees through one bank
+const RAM_BANK_SIZE: usize = 0x2000;
+/// MBC2 has 512 half bytes built in instead of the ram size in the header
+const MBC2_RAM_SIZE: usize = 0x200;
+
+/// Bytes of external ram `cartridge` comes with
+fn cartridge_ram_size(cartridge: &Cartridge) -> usize {
+    match cartridge.cartridge_type {
+        CartridgeType::MBC2 { .. } => MBC2_RAM_SIZE,
+        _ => cartridge.ram_size.bytes(),
+    }
+}
+
+/// MBC3 cartridges with a timer come with a real-time clock
+fn cartridge_rtc(cartridge_type: CartridgeType) -> Option<Rtc> {
+    match cartridge_type {
+        CartridgeType::MBC3 { timer: true, .. } => Some(Rtc::new(RtcMode::default())),
+        _ => None,
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::flat()
+    }
+}
+
+/// The address space as 64KiB of plain bytes, without the side effects of a cpu access: no
+/// MBC, no ppu blocking, io registers store exactly what's written. Test harnesses use it to
+/// set up and inspect a `Memory::flat` machine.
+pub trait MemoryBus {
+    fn get(&self, addr: u16) -> u8;
+    fn set(&mut self, addr: u16, value: u8);
+}
+
+impl MemoryBus for Memory {
+    fn get(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            IO_REGISTER_START..=IO_REGISTER_END => self.io.get(addr),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.read_ram(addr),
+            _ => self.block[addr],
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        let addr = addr as usize;
+        match addr {
+            IO_REGISTER_START..=IO_REGISTER_END => self.io.set(addr, value),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.write_ram(addr, value),
+            _ => {
+                self.block[addr] = value;
+                self.mark_vram(addr);
+            }
+        }
+    }
 }
 
 impl Memory {
@@ -103,210 +234,645 @@ impl Memory {
     /// Setup memory banks based on cartridge values:
     /// Read more: https://gbdev.io/pandocs/MBCs.html
     pub fn new(cartridge: Cartridge) -> Self {
+        let rtc = cartridge_rtc(cartridge.cartridge_type);
+        let ram = vec![0; cartridge_ram_size(&cartridge)];
         let mut mem = Self {
             block: [0u8; 65536],
+            io: IoRegisters::new(),
             cartridge,
-            oam_accessible: true,
-            vram_accessible: true,
+            ram,
             rom_banks: vec![],
+            rtc,
+            rtc_register: None,
+            tiles: TileCache::default(),
+            vram_writes: VramWrites::default(),
+            io_accessed: false,
+            model: Model::default(),
+            bus_log: None,
+            oam_dma: None,
+            oam_dma_start: None,
         };
         mem.setup_mbc();
-        mem.write(JOYP, 0xcf);
-        mem.write(SB, 0x00);
-        mem.write(SC, 0x7e);
-        mem.write(DIV, 0x18);
-        mem.write(TAC, 0xf8);
-        mem.write(IF, 0xe1);
-        mem.write(NR10, 0x80);
-        mem.write(NR11, 0xbf);
-        mem.write(NR12, 0xf3);
-        mem.write(NR13, 0xff);
-        mem.write(NR14, 0xbf);
-        mem.write(NR21, 0x3f);
-        mem.write(NR22, 0x00);
-        mem.write(NR23, 0xff);
-        mem.write(NR24, 0xbf);
-        mem.write(NR30, 0x7f);
-        mem.write(NR31, 0xff);
-        mem.write(NR32, 0x9f);
-        mem.write(NR33, 0xff);
-        mem.write(NR34, 0xbf);
-        mem.write(NR41, 0xff);
-        mem.write(NR42, 0x00);
-        mem.write(NR43, 0xff);
-        mem.write(NR44, 0xbf);
-        mem.write(NR50, 0x77);
-        mem.write(NR51, 0xf3);
-        mem.write(NR52, 0xf1);
-        mem.write(LCDC, 0x91);
-        mem.write(STAT, 0x81);
-        mem.write(SCY, 0x00);
-        mem.write(SCX, 0x00);
-        mem.write(LY, 0x00);
-        mem.write(LYC, 0x00);
-        mem.write(DMA, 0xff);
-        mem.write(BGP, 0xfc);
-        mem.write(WY, 0x00);
-        mem.write(WX, 0x00);
+        mem.io.set(JOYP, 0xcf);
+        mem.io.set(SB, 0x00);
+        mem.io.set(SC, 0x7e);
+        mem.io.set(DIV, 0xab);
+        mem.io.set(TAC, 0xf8);
+        mem.io.set(IF, 0xe1);
+        mem.io.set(NR10, 0x80);
+        mem.io.set(NR11, 0xbf);
+        mem.io.set(NR12, 0xf3);
+        mem.io.set(NR13, 0xff);
+        mem.io.set(NR14, 0xbf);
+        mem.io.set(NR21, 0x3f);
+        mem.io.set(NR22, 0x00);
+        mem.io.set(NR23, 0xff);
+        mem.io.set(NR24, 0xbf);
+        mem.io.set(NR30, 0x7f);
+        mem.io.set(NR31, 0xff);
+        mem.io.set(NR32, 0x9f);
+        mem.io.set(NR33, 0xff);
+        mem.io.set(NR34, 0xbf);
+        mem.io.set(NR41, 0xff);
+        mem.io.set(NR42, 0x00);
+        mem.io.set(NR43, 0xff);
+        mem.io.set(NR44, 0xbf);
+        mem.io.set(NR50, 0x77);
+        mem.io.set(NR51, 0xf3);
+        mem.io.set(NR52, 0xf1);
+        mem.io.set(LCDC, 0x91);
+        mem.io.set(STAT, 0x85);
+        mem.io.set(SCY, 0x00);
+        mem.io.set(SCX, 0x00);
+        mem.io.set(LY, 0x00);
+        mem.io.set(LYC, 0x00);
+        mem.io.set(DMA, 0xff);
+        mem.io.set(BGP, 0xfc);
+        mem.io.set(WY, 0x00);
+        mem.io.set(WX, 0x00);
+        mem.io.set(RP, 0x02);
         mem.write(IE, 0x00);
         mem
     }
-    pub fn read(&mut self, addr: usize) -> u8 {
-        if addr >= 0x8000 && addr <= 0x97ff {
-            // println!("accessing vram: {addr:?}");
+    /// A machine with nothing but ram for unit tests and the SingleStepTests harness: no
+    /// cartridge header to validate, every register zeroed instead of its post-boot value and
+    /// the ROM area as writable as the rest of the address space
+    pub fn flat() -> Self {
+        Self {
+            block: [0u8; 65536],
+            io: IoRegisters::new(),
+            cartridge: Cartridge::blank(),
+            ram: vec![0; RAM_BANK_SIZE],
+            rom_banks: vec![],
+            rtc: None,
+            rtc_register: None,
+            tiles: TileCache::default(),
+            vram_writes: VramWrites::default(),
+            io_accessed: false,
+            model: Model::default(),
+            bus_log: None,
+            oam_dma: None,
+            oam_dma_start: None,
         }
-        // oam can't be read or written to during ppu mode 2 or mode 3
-        if addr >= 0xfe00 && addr <= 0xfe9f && (!self.oam_accessible || !self.vram_accessible) {
+    }
+
+    /// The cpu is blocked from OAM during modes 2 and 3, going by the mode the PPU shows in
+    /// STAT, and while OAM DMA is copying. With the LCD off STAT reads mode 0, so only DMA
+    /// blocks it.
+    /// Read more: https://gbdev.io/pandocs/Accessing_VRAM_and_OAM.html
+    pub fn oam_accessible(&self) -> bool {
+        self.oam_dma.is_none() && self.lcd_status().ppu_mode < 2
+    }
+
+    /// Like `oam_accessible`, VRAM is only blocked during mode 3
+    pub fn vram_accessible(&self) -> bool {
+        self.lcd_status().ppu_mode != 3
+    }
+
+    pub fn read(&mut self, addr: usize) -> u8 {
+        let value = self.read_bus(addr);
+        self.log_access(addr, value, BusOp::Read);
+        value
+    }
+
+    fn read_bus(&mut self, addr: usize) -> u8 {
+        if (OAM_START..=OAM_END).contains(&addr) && !self.oam_accessible() {
             return 0xff;
         }
-        // vram can't be read or written to during ppu mode 3
-        if addr >= 0x8000 && addr <= 0x9fff && !self.vram_accessible {
+        if (IO_REGISTER_START..=IO_REGISTER_END).contains(&addr) {
+            self.io_accessed = true;
+            if cgb_only(addr) && !self.model.is_cgb() {
+                return 0xff;
+            }
+            return self.io.read(addr);
+        }
+        if let (Some(rtc), Some(register)) = (&self.rtc, self.rtc_register) {
+            if (EXTERNAL_RAM_START..=EXTERNAL_RAM_END).contains(&addr) {
+                return rtc.read(register);
+            }
+        }
+        if (VRAM_START..=VRAM_END).contains(&addr) && !self.vram_accessible() {
             return 0xff;
         }
+        if (EXTERNAL_RAM_START..=EXTERNAL_RAM_END).contains(&addr) {
+            return self.read_ram(addr);
+        }
         self.block[addr]
     }
 
+    /// What the cpu would read at `addr` if the ppu wasn't blocking it, for debuggers
+    pub fn peek(&self, addr: usize) -> u8 {
+        if (IO_REGISTER_START..=IO_REGISTER_END).contains(&addr) {
+            if cgb_only(addr) && !self.model.is_cgb() {
+                return 0xff;
+            }
+            return self.io.read(addr);
+        }
+        if let (Some(rtc), Some(register)) = (&self.rtc, self.rtc_register) {
+            if (EXTERNAL_RAM_START..=EXTERNAL_RAM_END).contains(&addr) {
+                return rtc.read(register);
+            }
+        }
+        if (EXTERNAL_RAM_START..=EXTERNAL_RAM_END).contains(&addr) {
+            return self.read_ram(addr);
+        }
+        self.block[addr]
+    }
+
+    /// Store `value` at `addr` as-is, for debuggers. Unlike `write` this patches ROM instead
+    /// of talking to the MBC and ignores ppu blocking, io registers still see a cpu write.
+    pub fn poke(&mut self, addr: usize, value: u8) {
+        match addr {
+            IO_REGISTER_START..=IO_REGISTER_END => self.io.write(addr, value),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.write_ram(addr, value),
+            _ => {
+                self.block[addr] = value;
+                self.mark_vram(addr);
+            }
+        }
+    }
+
+    pub fn read_high(&mut self, addr: HighAddr) -> u8 {
+        self.read(addr.addr())
+    }
+
+    pub fn write_high(&mut self, addr: HighAddr, value: u8) {
+        self.write(addr.addr(), value);
+    }
+
+    /// Read a little-endian word as two accesses, the low byte at `addr` first and then the high byte at `addr + 1`
+    pub fn read_u16(&mut self, addr: usize) -> u16 {
+        let low = self.read(addr);
+        let high = self.read((addr + 1) & 0xffff);
+        u16::from_le_bytes([low, high])
+    }
+
+    /// Write a little-endian word as two accesses, the low byte at `addr` first and then the high byte at `addr + 1`
+    pub fn write_u16(&mut self, addr: usize, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write(addr, low);
+        self.write((addr + 1) & 0xffff, high);
+    }
+
     // TODO: wire up MBC
     pub fn write(&mut self, addr: usize, value: u8) {
-        if addr >= 0x2000 && addr <= 0x3fff {
-            println!("switching rom banks");
+        self.log_access(addr, value, BusOp::Write);
+        if self.write_rtc(addr, value) {
+            return;
         }
-        if addr >= 0x4000 && addr <= 0x5fff {
-            println!("switching rom banks");
+        // the ppu owns the bus, the write is lost
+        if (OAM_START..=OAM_END).contains(&addr) && !self.oam_accessible() {
+            return;
         }
-        if addr >= 0x6000 && addr <= 0x7fff {
-            println!("banking mode select");
+        if (VRAM_START..=VRAM_END).contains(&addr) && !self.vram_accessible() {
+            return;
         }
-        if addr >= 0xfe00 && addr <= 0xfe9f && (!self.oam_accessible || !self.vram_accessible) {
-            // println!("Attempting to write to hram");
-            // return;
+        if (IO_REGISTER_START..=IO_REGISTER_END).contains(&addr) {
+            self.io_accessed = true;
+            if cgb_only(addr) && !self.model.is_cgb() {
+                return;
+            }
+            self.io.write(addr, value);
+            if addr == DMA {
+                // sources from $e0 on read the echo of work ram
+                let source = (value as usize) << 8;
+                let source = if value >= 0xe0 { source - 0x2000 } else { source };
+                self.oam_dma_start = Some((source, false));
+            }
+            return;
         }
-        if addr >= 0x8000 && addr <= 0x9fff {
-            // println!("Attempting to write to vram");
-            // return;
+        if (EXTERNAL_RAM_START..=EXTERNAL_RAM_END).contains(&addr) {
+            self.write_ram(addr, value);
+            return;
         }
         self.block[addr] = value;
+        self.mark_vram(addr);
     }
 
-    pub fn inc_scanline(&mut self) {
-        let ly = self.read(LY);
-        if self.read(LY) == 153 {
-            self.write(LY, 0);
-        } else {
-            self.write(LY, ly + 1);
+    /// Where in `ram` `addr` lands, `None` past the end of the ram the cartridge has.
+    /// MBC2's 512 bytes repeat across the whole of 0xa000-0xbfff.
+    /// Read more: https://gbdev.io/pandocs/MBC2.html
+    fn ram_index(&self, addr: usize) -> Option<usize> {
+        let offset = addr - EXTERNAL_RAM_START;
+        let index = match self.cartridge.cartridge_type {
+            CartridgeType::MBC2 { .. } => offset % MBC2_RAM_SIZE,
+            _ => self.ram_bank() as usize * RAM_BANK_SIZE + offset,
+        };
+        (index < self.ram.len()).then_some(index)
+    }
+
+    /// External ram, open bus reads 0xff and MBC2 only stores the low half of each byte
+    fn read_ram(&self, addr: usize) -> u8 {
+        match (self.ram_index(addr), self.cartridge.cartridge_type) {
+            (Some(index), CartridgeType::MBC2 { .. }) => self.ram[index] | 0xf0,
+            (Some(index), _) => self.ram[index],
+            (None, _) => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, value: u8) {
+        if let Some(index) = self.ram_index(addr) {
+            self.ram[index] = value;
+        }
+    }
+
+    fn log_access(&mut self, addr: usize, value: u8, op: BusOp) {
+        if let Some(log) = &mut self.bus_log {
+            log.push(BusAccess { addr: addr as u16, value, op });
+        }
+    }
+
+    /// Log the opcode and operand reads of an instruction ahead of the accesses it made from
+    /// `at` on, the cpu only knows how long an instruction is once it has run
+    pub fn log_fetch(&mut self, at: usize, addrs: impl Iterator<Item = usize>, bytes: &[u8]) {
+        if let Some(log) = &mut self.bus_log {
+            let fetches = addrs.zip(bytes).map(|(addr, &value)| BusAccess {
+                addr: addr as u16,
+                value,
+                op: BusOp::Read,
+            });
+            log.splice(at..at, fetches);
+        }
+    }
+
+    fn mark_vram(&mut self, addr: usize) {
+        if (VRAM_START..=VRAM_END).contains(&addr) {
+            self.tiles.mark(addr);
+            self.vram_writes.mark(addr);
+        }
+    }
+
+    /// MBC3 maps the clock registers over external ram, returns whether the write was handled
+    /// Read more: https://gbdev.io/pandocs/MBC3.html
+    fn write_rtc(&mut self, addr: usize, value: u8) -> bool {
+        let Some(rtc) = &mut self.rtc else {
+            return false;
+        };
+        match addr {
+            // 0x08-0x0c select a clock register, anything else a ram bank
+            0x4000..=0x5fff => {
+                self.rtc_register = (RTC_SECONDS..=RTC_DAY_HIGH).contains(&value).then_some(value);
+                true
+            }
+            0x6000..=0x7fff => {
+                rtc.write_latch(value);
+                true
+            }
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => match self.rtc_register {
+                Some(register) => {
+                    rtc.write(register, value);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
         }
     }
 
-    pub fn get_tile_map(&mut self, tile_map_area: [usize; 2]) -> [[u8; 32]; 32] {
-        let mut tile_map = [[0u8; 32]; 32];
-        for (index, chunk) in self.block[tile_map_area[0]..=tile_map_area[1]].chunks_exact(32).enumerate() {
-            let mut tile_chunk: [u8; 32] = [0u8; 32];
-            tile_chunk.copy_from_slice(chunk);
-            tile_map[index] = tile_chunk;
+    /// Advance OAM DMA by `cycles` M-cycles, the ones the last instruction took. A transfer
+    /// starts up during the cycle after the write to DMA and then copies a byte a cycle. Writing
+    /// DMA again restarts it from the first byte, the old transfer keeps copying until the new
+    /// one has started up.
+    pub fn tick_oam_dma(&mut self, cycles: usize) {
+        if self.oam_dma.is_none() && self.oam_dma_start.is_none() {
+            return;
+        }
+        for _ in 0..cycles {
+            if let Some(mut dma) = self.oam_dma {
+                let value = self.get((dma.source + dma.copied) as u16);
+                self.block[OAM_START + dma.copied] = value;
+                dma.copied += 1;
+                let done = dma.copied > OAM_END - OAM_START;
+                self.oam_dma = if done { None } else { Some(dma) };
+            }
+            if let Some((source, true)) = self.oam_dma_start {
+                self.oam_dma = Some(OamDma { source, copied: 0 });
+                self.oam_dma_start = None;
+            }
+        }
+        // the write landed on the instruction's last cycle
+        if let Some((_, written)) = &mut self.oam_dma_start {
+            *written = true;
         }
-        tile_map
     }
 
-    pub fn get_tile_data(
-        &mut self,
-        tile_data_area: [[usize; 2]; 2],
-    ) -> ([[[u8; 8]; 8]; 128], [[[u8; 8]; 8]; 128]) {
-        let mut tile_block_0 = [[[0u8; 8]; 8]; 128];
-        for (tile, chunk) in tile_block_0
-            .iter_mut()
-            .zip(self.block[tile_data_area[0][0]..=tile_data_area[0][1]].chunks_exact(16))
-        {
-            *tile = decode_tile(chunk);
+    /// The whole address space as the cpu would see it without any masking, IO registers and
+    /// the mapped external ram bank included
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut block = self.block.to_vec();
+        block[IO_REGISTER_START..=IO_REGISTER_END].copy_from_slice(self.io.as_slice());
+        for addr in EXTERNAL_RAM_START..=EXTERNAL_RAM_END {
+            block[addr] = self.read_ram(addr);
         }
-        let mut tile_block_1 = [[[0u8; 8]; 8]; 128];
-        for (tile, chunk) in tile_block_1
-            .iter_mut()
-            .zip(self.block[tile_data_area[1][0]..=tile_data_area[1][1]].chunks_exact(16))
-        {
-            *tile = decode_tile(chunk);
+        block
+    }
+
+    /// Restore a `snapshot`, without triggering any IO side effects
+    pub fn restore_snapshot(&mut self, block: &[u8]) {
+        self.block.copy_from_slice(block);
+        self.io
+            .copy_from_slice(&block[IO_REGISTER_START..=IO_REGISTER_END]);
+        for addr in EXTERNAL_RAM_START..=EXTERNAL_RAM_END {
+            self.write_ram(addr, block[addr]);
         }
-        (tile_block_0, tile_block_1)
+        // states don't record a transfer in progress
+        self.oam_dma = None;
+        self.oam_dma_start = None;
+        self.invalidate_tiles();
+    }
+
+    pub fn inc_scanline(&mut self) {
+        let ly = self.io.get(LY);
+        if ly == 153 {
+            self.io.set(LY, 0);
+        } else {
+            self.io.set(LY, ly + 1);
+        }
+    }
+
+    /// The id stored in `map` for the tile at column `x` and row `y`
+    pub fn tile_id(&self, map: TileMap, x: usize, y: usize) -> TileId {
+        TileId(self.block[map.address(x, y)])
+    }
+
+    /// The cached tile if it's up to date, decoded from vram otherwise
+    pub fn tile(&self, id: TileId, addressing: TileAddressing) -> Tile {
+        let start = addressing.address(id);
+        match self.tiles.get(start) {
+            Some(tile) => *tile,
+            None => Tile::decode(&self.block[start..start + TILE_SIZE]),
+        }
+    }
+
+    /// Decode the tiles written since the last sync, the ppu calls this before every scanline
+    pub fn sync_tiles(&mut self) {
+        self.tiles.sync(&self.block);
+    }
+
+    /// Throw the decoded tiles away after writing to `block` behind `write`'s back
+    pub fn invalidate_tiles(&mut self) {
+        self.tiles.mark_all();
+        self.vram_writes = VramWrites::all();
+    }
+
+    /// The VRAM writes since the last call
+    pub fn take_vram_writes(&mut self) -> VramWrites {
+        std::mem::take(&mut self.vram_writes)
+    }
+
+    /// The tile shown at column `x` and row `y` of `map`
+    pub fn tile_at(&self, map: TileMap, x: usize, y: usize, addressing: TileAddressing) -> Tile {
+        self.tile(self.tile_id(map, x, y), addressing)
     }
 
     pub fn lcd_status(&self) -> LcdStatus {
-        LcdStatus::from(self.block[STAT])
+        LcdStatus::from(self.io.get(STAT))
+    }
+
+    /// Write STAT without the cpu's write mask or an `IoEvent`, the way the PPU updates it
+    pub fn set_lcd_status(&mut self, stat: LcdStatus) {
+        self.io.set(STAT, stat.into());
     }
 
     pub fn lcd_control(&self) -> LcdControl {
-        LcdControl::from(self.block[LCDC])
+        LcdControl::from(self.io.get(LCDC))
+    }
+
+    /// Write LCDC without an `IoEvent`, the PPU isn't told about the change
+    pub fn set_lcd_control(&mut self, lcdc: LcdControl) {
+        self.io.set(LCDC, lcdc.into());
     }
 
     pub fn timer_control(&self) -> TimerControl {
-        TimerControl::try_from(self.block[TAC]).unwrap()
+        TimerControl::try_from(self.io.get(TAC)).unwrap()
+    }
+
+    /// Every bank of cartridge ram, MBC2 has 512 half bytes built in
+    pub fn cartridge_ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Restore cartridge ram from a battery save, extra bytes are ignored
+    pub fn load_cartridge_ram(&mut self, ram: &[u8]) {
+        let len = self.ram.len().min(ram.len());
+        self.ram[..len].copy_from_slice(&ram[..len]);
+    }
+
+    /// The ROM bank mapped at 0x4000-0x7fff, always 1 until the MBCs switch banks
+    pub fn rom_bank(&self) -> u16 {
+        1
+    }
+
+    /// The external RAM bank mapped at 0xa000-0xbfff
+    pub fn ram_bank(&self) -> u16 {
+        0
+    }
+
+    /// `addr` with whichever bank is currently mapped there
+    pub fn banked(&self, addr: u16) -> BankedAddr {
+        let bank = match addr as usize {
+            ROM_BANK_1_START..=ROM_BANK_1_END => self.rom_bank(),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.ram_bank(),
+            _ => 0,
+        };
+        BankedAddr::new(bank, addr)
     }
 
     pub fn rom(&mut self) -> &mut [u8] {
         &mut self.block[ROM_BANK_0_START..ROM_BANK_1_END]
     }
 
+    /// Replace the mapper the header asked for, adding or removing the clock and resizing the
+    /// ram to match
+    pub fn set_cartridge_type(&mut self, cartridge_type: CartridgeType) {
+        self.cartridge.cartridge_type = cartridge_type;
+        self.ram.resize(cartridge_ram_size(&self.cartridge), 0);
+        if cartridge_rtc(cartridge_type).is_none() {
+            self.rtc = None;
+        } else if self.rtc.is_none() {
+            self.rtc = cartridge_rtc(cartridge_type);
+        }
+    }
+
+    /// Split the ROM into as many banks as the header declares, missing banks of an underdump
+    /// read as open bus and whatever an overdump has past the last bank is left out
     pub fn setup_mbc(&mut self) {
-        let chunks: Vec<[u8; 16383]> = self
-            .cartridge
-            .rom
-            .chunks_exact(16383)
-            .map(|chunk| <[u8; 16383]>::try_from(chunk).unwrap())
+        let rom = &self.cartridge.rom;
+        self.rom_banks = (0..self.cartridge.rom_size)
+            .map(|bank| {
+                let mut data = [0xff; ROM_BANK_SIZE];
+                let start = (bank * ROM_BANK_SIZE).min(rom.len());
+                let chunk = &rom[start..rom.len().min(start + ROM_BANK_SIZE)];
+                data[..chunk.len()].copy_from_slice(chunk);
+                data
+            })
             .collect();
-        self.rom_banks = chunks;
-        self.block[ROM_BANK_0_START..ROM_BANK_0_END].copy_from_slice(&self.rom_banks[0]);
-        self.block[ROM_BANK_1_START..ROM_BANK_1_END].copy_from_slice(&self.rom_banks[1]);
+        self.block[ROM_BANK_0_START..=ROM_BANK_0_END].copy_from_slice(&self.rom_banks[0]);
+        self.block[ROM_BANK_1_START..=ROM_BANK_1_END].copy_from_slice(&self.rom_banks[1]);
     }
 
+    /// Count TIMA up once, when it overflows it's reloaded from TMA and the timer interrupt is
+    /// requested. The hardware reloads it a cycle later, TIMA reads 0 in between.
+    /// Read more: https://gbdev.io/pandocs/Timer_and_Divider_Registers.html
     pub fn inc_tima(&mut self) {
-        let tima = self.read(TIMA);
-        // This timer is incremented at the clock frequency specified by the TAC register ($FF07).
-        // When the value overflows (exceeds $FF) it is reset to the value specified in TMA (FF06) and an interrupt is requested.
-        if tima == 0xff {
-            self.block[IE] = crate::interrupts::TIMER;
-            self.block[TIMA] = self.read(TMA);
+        match self.io.get(TIMA).checked_add(1) {
+            Some(tima) => self.io.set(TIMA, tima),
+            None => {
+                self.io.set(TIMA, self.io.get(TMA));
+                self.request_interrupt(Interrupt::TIMER);
+            }
         }
-        self.block[TIMA] += 1;
     }
 
     pub fn inc_div(&mut self) {
-        if self.block[DIV] == 0xff {
-            self.block[DIV] = 0;
-        }
-        self.block[DIV] += 1;
+        self.io.set(DIV, self.io.get(DIV).wrapping_add(1));
     }
 
     pub fn get_vram(&self) -> &[u8] {
         &self.block[VRAM_START..VRAM_END]
     }
 
-    pub fn get_oam(&self) -> &[u8] {
-        &self.block[OAM_START..OAM_END]
+    pub fn oam_entry(&self, index: usize) -> OamEntry {
+        let start = OAM_START + index * 4;
+        OamEntry::from_bytes(self.block[start..start + 4].try_into().unwrap())
     }
 
-    pub fn get_interrupt_flag(&self) -> &u8 {
-        &self.block[INTERRUPT_FLAG]
+    pub fn oam_entries(&self) -> [OamEntry; OAM_ENTRIES] {
+        std::array::from_fn(|index| self.oam_entry(index))
     }
 
-    pub fn set_interrupt_registers(&mut self, value: u8) {
-        self.write(INTERRUPT_ENABLE_REGISTER, value);
+    /// Replace an object directly, bypassing the OAM access restrictions of `write`
+    pub fn set_oam_entry(&mut self, index: usize, entry: OamEntry) {
+        let start = OAM_START + index * 4;
+        self.block[start..start + 4].copy_from_slice(&entry.to_bytes());
     }
 
-    pub fn get_interrupt_registers(&self) -> &u8 {
-        &self.block[INTERRUPT_ENABLE_REGISTER]
+    pub fn get_interrupt_flag(&self) -> u8 {
+        self.io.get(INTERRUPT_FLAG)
     }
 
-    pub fn scx(&self) -> &u8 {
-        &self.block[SCX]
+    /// Set the interrupt's bit in IF
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flag = self.io.get(INTERRUPT_FLAG);
+        self.io.set(INTERRUPT_FLAG, flag | interrupt.bits());
     }
 
-    pub fn scy(&self) -> &u8 {
-        &self.block[SCY]
+    /// Clear the interrupt's bit in IF once it's being serviced
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        let flag = self.io.get(INTERRUPT_FLAG);
+        self.io.set(INTERRUPT_FLAG, flag & !interrupt.bits());
     }
 
-    pub fn wx(&self) -> &u8 {
-        &self.block[WX]
+    /// Interrupts which are both enabled and requested (IE & IF)
+    pub fn pending_interrupts(&self) -> u8 {
+        self.block[INTERRUPT_ENABLE_REGISTER] & self.io.get(INTERRUPT_FLAG) & 0x1f
     }
 
-    pub fn wy(&self) -> &u8 {
-        &self.block[WY]
+    pub fn scx(&self) -> u8 {
+        self.io.get(SCX)
+    }
+
+    pub fn scy(&self) -> u8 {
+        self.io.get(SCY)
+    }
+
+    pub fn wx(&self) -> u8 {
+        self.io.get(WX)
+    }
+
+    pub fn wy(&self) -> u8 {
+        self.io.get(WY)
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::display::PpuMode;
+
+    #[test]
+    fn test_ppu_access() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut ppu = crate::display::Ppu::headless();
+        let mut enter = |mode, lcd_on, mem: &mut Memory| {
+            ppu.mode = mode;
+            ppu.update_stat(mem, lcd_on);
+        };
+        mem.write(0x8000, 0x12);
+        mem.write(0xfe00, 0x34);
+        enter(PpuMode::OAMScan, true, &mut mem);
+        assert_eq!(mem.read(0x8000), 0x12);
+        assert_eq!(mem.read(0xfe00), 0xff);
+        mem.write(0xfe00, 0x56);
+        enter(PpuMode::Drawing, true, &mut mem);
+        assert_eq!(mem.read(0x8000), 0xff);
+        mem.write(0x8000, 0x78);
+        // writes during mode 3 are dropped
+        enter(PpuMode::HorizontalBlank, true, &mut mem);
+        assert_eq!(mem.read(0x8000), 0x12);
+        assert_eq!(mem.read(0xfe00), 0x34);
+        // nothing is blocked with the LCD off
+        enter(PpuMode::Drawing, false, &mut mem);
+        mem.write(0x8000, 0x78);
+        assert_eq!(mem.read(0x8000), 0x78);
+    }
+
+    #[test]
+    fn test_external_ram() {
+        let memory = |cartridge_type: u8, ram_size: u8| {
+            let mut rom = vec![0; 0xffff];
+            rom[0x147] = cartridge_type;
+            rom[0x149] = ram_size;
+            Memory::new(Cartridge::new(rom).unwrap())
+        };
+        // no ram, the bus is open
+        let mut mem = memory(0x01, 0x00);
+        mem.write(0xa000, 0x12);
+        assert_eq!(mem.read(0xa000), 0xff);
+        assert!(mem.cartridge_ram().is_empty());
+        // 8KiB fills the whole window
+        let mut mem = memory(0x03, 0x02);
+        mem.write(0xbfff, 0x12);
+        assert_eq!(mem.read(0xbfff), 0x12);
+        assert_eq!(mem.cartridge_ram().len(), 0x2000);
+        assert_eq!(mem.snapshot()[0xbfff], 0x12);
+        // 32KiB is saved in full even though only the first bank is mapped
+        let mut mem = memory(0x03, 0x03);
+        mem.load_cartridge_ram(&[0x34; 0x8000]);
+        assert_eq!(mem.cartridge_ram(), &[0x34; 0x8000]);
+        assert_eq!(mem.read(0xa000), 0x34);
+        // MBC2 repeats its 512 half bytes
+        let mut mem = memory(0x06, 0x00);
+        mem.write(0xa001, 0x05);
+        assert_eq!(mem.read(0xa201), 0xf5);
+        assert_eq!(mem.read(0xbe01), 0xf5);
+        assert_eq!(mem.cartridge_ram().len(), 0x200);
+    }
+
+    #[test]
+    fn test_rom_banks() {
+        use crate::cartridge::RomSizeMismatch;
+        let rom = |banks: usize, header: u8| {
+            let mut rom: Vec<u8> =
+                (0..banks * ROM_BANK_SIZE).map(|i| (i / ROM_BANK_SIZE) as u8).collect();
+            rom[0x148] = header;
+            Cartridge::new(rom).unwrap()
+        };
+        // 1.1MiB, 72 banks
+        let mem = Memory::new(rom(72, 0x52));
+        assert_eq!(mem.rom_banks.len(), 72);
+        assert_eq!(mem.rom_banks[71][0], 71);
+        assert!(mem.cartridge.size_mismatch().is_none());
+        // the last byte of each mapped bank is copied too
+        assert_eq!((mem.block[0x3fff], mem.block[0x7fff]), (0, 1));
+        // an overdump only gets the declared banks
+        let mem = Memory::new(rom(6, 0x01));
+        assert_eq!(mem.rom_banks.len(), 4);
+        let mismatch = RomSizeMismatch { banks: 4, found: 6 * ROM_BANK_SIZE };
+        assert_eq!(mem.cartridge.size_mismatch(), Some(mismatch));
+        // an underdump is padded with open bus
+        let mem = Memory::new(rom(3, 0x02));
+        assert_eq!(mem.rom_banks.len(), 8);
+        assert_eq!((mem.rom_banks[2][0], mem.rom_banks[3][0]), (2, 0xff));
+        assert!(mem.cartridge.size_mismatch().unwrap().to_string().contains("underdump"));
     }
 }
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    cheats::{CheatSearch, Comparison},
+    memory::Memory,
+    osd::{LINE_HEIGHT, draw_text},
+};
+
+/// Bytes shown on each row of the pane
+pub const ROW_BYTES: u16 = 8;
+/// Rows of bytes shown under the header
+pub const ROWS: u16 = 16;
+
+/// Regions Tab cycles through, and the name shown in the header while the cursor is in them
+pub const BOOKMARKS: [(&str, u16); 8] = [
+    ("ROM0", 0x0000),
+    ("ROMX", 0x4000),
+    ("VRAM", 0x8000),
+    ("SRAM", 0xa000),
+    ("WRAM", 0xc000),
+    ("OAM", 0xfe00),
+    ("IO", 0xff00),
+    ("HRAM", 0xff80),
+];
+
+/// A hex editor pane drawn over the screen. Bytes are typed in as two hex digits, frozen
+/// addresses are rewritten with their value every frame, even while the pane is closed.
+/// The pane can switch to a cheat search, where typed bytes filter by value instead.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEditor {
+    pub open: bool,
+    pub cursor: u16,
+    // first address of the top row
+    top: u16,
+    // the high nibble typed so far
+    pending: Option<u8>,
+    /// Addresses and the value they're held at
+    pub frozen: BTreeMap<u16, u8>,
+    pub search: Option<CheatSearch>,
+    /// Show the cheat search instead of the hex view
+    pub search_view: bool,
+}
+
+impl MemoryEditor {
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.pending = None;
+    }
+
+    /// Move the cursor by `delta` bytes, scrolling just enough to keep it visible
+    pub fn move_cursor(&mut self, delta: i32) {
+        self.cursor = self.cursor.wrapping_add_signed(delta as i16);
+        self.pending = None;
+        let row = self.cursor & !(ROW_BYTES - 1);
+        if row.wrapping_sub(self.top) >= ROWS * ROW_BYTES {
+            self.top = match delta < 0 {
+                true => row,
+                false => row.wrapping_sub((ROWS - 1) * ROW_BYTES),
+            };
+        }
+    }
+
+    /// Put the cursor on `addr` and scroll its row to the top
+    pub fn jump(&mut self, addr: u16) {
+        self.cursor = addr;
+        self.top = addr & !(ROW_BYTES - 1);
+        self.pending = None;
+    }
+
+    /// Jump to the start of the bookmark after the cursor, wrapping around to the first
+    pub fn next_bookmark(&mut self) {
+        let next = BOOKMARKS.iter().find(|(_, addr)| *addr > self.cursor);
+        self.jump(next.unwrap_or(&BOOKMARKS[0]).1);
+    }
+
+    /// The bookmark the cursor is in
+    pub fn region(&self) -> &'static str {
+        let bookmark = BOOKMARKS.iter().rev().find(|(_, addr)| *addr <= self.cursor);
+        bookmark.unwrap_or(&BOOKMARKS[0]).0
+    }
+
+    /// Type one hex digit, the second digit of a byte writes it and moves on to the next.
+    /// In the search view the byte filters the candidates by value instead.
+    pub fn input(&mut self, digit: u8, mem: &mut Memory) {
+        let Some(high) = self.pending else {
+            self.pending = Some(digit & 0x0f);
+            return;
+        };
+        let value = high << 4 | digit & 0x0f;
+        if self.search_view {
+            self.pending = None;
+            self.filter(mem, Comparison::Value(value));
+            return;
+        }
+        mem.poke(self.cursor as usize, value);
+        if let Some(frozen) = self.frozen.get_mut(&self.cursor) {
+            *frozen = value;
+        }
+        self.move_cursor(1);
+    }
+
+    /// Freeze the byte under the cursor at its current value, or unfreeze it
+    pub fn toggle_freeze(&mut self, mem: &Memory) {
+        if self.frozen.remove(&self.cursor).is_none() {
+            self.frozen.insert(self.cursor, mem.peek(self.cursor as usize));
+        }
+    }
+
+    /// Switch between the hex and search views, the first switch starts a search
+    pub fn toggle_search(&mut self, mem: &Memory) {
+        self.search_view = !self.search_view;
+        self.pending = None;
+        if self.search.is_none() {
+            self.restart_search(mem);
+        }
+    }
+
+    /// Throw away the candidates and snapshot work ram again
+    pub fn restart_search(&mut self, mem: &Memory) {
+        self.search = Some(CheatSearch::new(mem));
+    }
+
+    pub fn filter(&mut self, mem: &Memory, comparison: Comparison) {
+        if let Some(search) = &mut self.search {
+            search.filter(mem, comparison);
+        }
+    }
+
+    /// Freeze every candidate at its current value once the search is down to a page of them,
+    /// returns how many were frozen
+    pub fn freeze_matches(&mut self, mem: &Memory) -> usize {
+        let Some(search) = &self.search else {
+            return 0;
+        };
+        if search.candidates().len() > ROWS as usize {
+            return 0;
+        }
+        self.frozen.extend(search.cheats(mem));
+        search.candidates().len()
+    }
+
+    /// Rewrite every frozen address, called once per frame
+    pub fn apply_freezes(&self, mem: &mut Memory) {
+        for (&addr, &value) in &self.frozen {
+            mem.poke(addr as usize, value);
+        }
+    }
+
+    /// The header and rows as text, `>` marks the cursor and `*` a frozen byte
+    pub fn lines(&self, mem: &Memory) -> Vec<String> {
+        if let (true, Some(search)) = (self.search_view, &self.search) {
+            return self.search_lines(search, mem);
+        }
+        let value = match self.pending {
+            Some(high) => format!("{high:X}-"),
+            None => format!("{:02X}", mem.peek(self.cursor as usize)),
+        };
+        let mut header = format!("{} {:04X}:{value}", self.region(), self.cursor);
+        if !self.frozen.is_empty() {
+            header.push_str(&format!(" {} FROZEN", self.frozen.len()));
+        }
+        let mut lines = vec![header];
+        for row in 0..ROWS {
+            let start = self.top.wrapping_add(row * ROW_BYTES);
+            let mut line = format!("{start:04X}");
+            for addr in (0..ROW_BYTES).map(|i| start.wrapping_add(i)) {
+                let marker = match (addr == self.cursor, self.frozen.contains_key(&addr)) {
+                    (true, _) => '>',
+                    (false, true) => '*',
+                    _ => ' ',
+                };
+                line.push_str(&format!("{marker}{:02X}", mem.peek(addr as usize)));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// A page of candidates with their current and previous values
+    fn search_lines(&self, search: &CheatSearch, mem: &Memory) -> Vec<String> {
+        let candidates = search.candidates();
+        let mut header = format!("SEARCH {} MATCHES", candidates.len());
+        if let Some(high) = self.pending {
+            header.push_str(&format!(" ={high:X}-"));
+        }
+        let mut lines = vec![header];
+        for &addr in candidates.iter().take(ROWS as usize) {
+            let marker = match self.frozen.contains_key(&addr) {
+                true => '*',
+                false => ' ',
+            };
+            let value = mem.peek(addr as usize);
+            lines.push(format!("{addr:04X}{marker}{value:02X} WAS {:02X}", search.previous(addr)));
+        }
+        lines
+    }
+
+    /// Composite the pane onto an RGB24 160x144 framebuffer
+    pub fn draw(&self, frame_buffer: &mut [u8], mem: &Memory) {
+        for (i, line) in self.lines(mem).iter().enumerate() {
+            draw_text(frame_buffer, line, 0, i * (LINE_HEIGHT + 1));
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_edit() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut editor = MemoryEditor::default();
+        editor.jump(0xc000);
+        editor.input(0x1, &mut mem);
+        assert_eq!(mem.peek(0xc000), 0x00);
+        assert_eq!(editor.lines(&mem)[0], "WRAM C000:1-");
+        editor.input(0xf, &mut mem);
+        assert_eq!(mem.peek(0xc000), 0x1f);
+        assert_eq!(editor.cursor, 0xc001);
+        assert_eq!(editor.lines(&mem)[1], "C000 1F>00 00 00 00 00 00 00");
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut editor = MemoryEditor::default();
+        editor.jump(0xc000);
+        editor.move_cursor((ROWS * ROW_BYTES) as i32);
+        assert_eq!(editor.top, 0xc008);
+        editor.move_cursor(-((ROWS * ROW_BYTES) as i32) - 1);
+        assert_eq!((editor.cursor, editor.top), (0xbfff, 0xbff8));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut editor = MemoryEditor::default();
+        mem.poke(0xff80, 0x42);
+        editor.jump(0xff80);
+        editor.toggle_freeze(&mem);
+        mem.poke(0xff80, 0);
+        editor.apply_freezes(&mut mem);
+        assert_eq!(mem.peek(0xff80), 0x42);
+        assert_eq!(editor.lines(&mem)[0], "HRAM FF80:42 1 FROZEN");
+        editor.toggle_freeze(&mem);
+        assert!(editor.frozen.is_empty());
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let mut editor = MemoryEditor::default();
+        editor.jump(0xfe10);
+        assert_eq!(editor.region(), "OAM");
+        editor.next_bookmark();
+        assert_eq!((editor.cursor, editor.region()), (0xff00, "IO"));
+        editor.next_bookmark();
+        editor.next_bookmark();
+        assert_eq!((editor.cursor, editor.region()), (0x0000, "ROM0"));
+    }
+
+    #[test]
+    fn test_search_view() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut editor = MemoryEditor::default();
+        mem.poke(0xc010, 0x63);
+        editor.toggle_search(&mem);
+        // nothing to freeze while the whole of wram matches
+        assert_eq!(editor.freeze_matches(&mem), 0);
+        editor.input(0x6, &mut mem);
+        assert_eq!(editor.lines(&mem)[0], "SEARCH 8192 MATCHES =6-");
+        editor.input(0x3, &mut mem);
+        // typing in the search view doesn't write to the cursor
+        assert_eq!(mem.peek(0x0000), 0x00);
+        mem.poke(0xc010, 0x64);
+        editor.filter(&mem, Comparison::Increased);
+        // the previous value is from the last filter
+        mem.poke(0xc010, 0x65);
+        assert_eq!(editor.lines(&mem), vec!["SEARCH 1 MATCHES", "C010 65 WAS 64"]);
+        assert_eq!(editor.freeze_matches(&mem), 1);
+        assert_eq!(editor.frozen.get(&0xc010), Some(&0x65));
+        editor.toggle_search(&mem);
+        assert!(editor.lines(&mem)[0].starts_with("ROM0"));
+    }
+}
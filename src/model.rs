@@ -0,0 +1,212 @@
+use crate::{
+    cartridge::{CGB_FLAG, Cartridge, OLD_LICENSEE},
+    cpu::{Cpu, Flags, R16, Registers},
+    memory::{
+        Memory,
+        registers::{DIV, DMA, NR52, SC, STAT},
+    },
+};
+
+/// Used instead of the old licensee code when that is $33
+const NEW_LICENSEE: usize = 0x0144;
+
+/// The hardware being emulated, each boot rom leaves the registers in a different state
+/// Read more: https://gbdev.io/pandocs/Power_Up_Sequence.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    any(feature = "json", feature = "serde"),
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Model {
+    /// The earliest DMG boot rom
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    Sgb2,
+    /// Game Boy Color running a DMG cartridge
+    Cgb,
+}
+
+impl Model {
+    /// The model a cartridge asks for: CGB only games get a CGB, SGB enhanced ones an SGB and
+    /// everything else, including games that merely support the CGB, a DMG
+    pub fn detect(cartridge: &Cartridge) -> Self {
+        if cartridge.rom[CGB_FLAG] == 0xc0 {
+            Self::Cgb
+        } else if cartridge.sgb_flag {
+            Self::Sgb
+        } else {
+            Self::Dmg
+        }
+    }
+
+    pub fn is_cgb(self) -> bool {
+        self == Self::Cgb
+    }
+
+    /// Whether writing STAT can spuriously request a STAT interrupt, only the CGB fixed it
+    pub fn stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// Whether triggering the wave channel while it reads wave ram corrupts it, fixed on the CGB
+    pub fn wave_trigger_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    /// AF, BC, DE and HL as the boot rom hands them over at $0100
+    pub fn registers(self, cartridge: &Cartridge) -> Registers {
+        let dmg_flags = u8::from(Flags::post_boot(cartridge.header_checksum));
+        let (af, bc, de, hl) = match self {
+            Self::Dmg0 => (0x0100, 0xff13, 0x00c1, 0x8403),
+            Self::Dmg => (0x0100 | dmg_flags as u16, 0x0013, 0x00d8, 0x014d),
+            Self::Mgb => (0xff00 | dmg_flags as u16, 0x0013, 0x00d8, 0x014d),
+            Self::Sgb => (0x0100, 0x0014, 0x0000, 0xc060),
+            Self::Sgb2 => (0xff00, 0x0014, 0x0000, 0xc060),
+            Self::Cgb => (0x1180, (licensed_title_sum(cartridge) as u16) << 8, 0x0008, 0x007c),
+        };
+        let mut registers = Registers::default();
+        for (r16, value) in [(R16::AF, af), (R16::BC, bc), (R16::DE, de), (R16::HL, hl)] {
+            registers.set_r16(r16, value);
+        }
+        registers.sp = 0xfffe;
+        registers.pc = 0x0100;
+        registers
+    }
+
+    /// Hardware registers that power up differently from the DMG values `Memory::new` sets
+    pub fn io(self) -> &'static [(usize, u8)] {
+        match self {
+            Self::Dmg0 => &[(DIV, 0x18), (STAT, 0x81)],
+            Self::Dmg | Self::Mgb => &[],
+            Self::Sgb | Self::Sgb2 => &[(NR52, 0xf0)],
+            Self::Cgb => &[(SC, 0x7f), (DMA, 0x00)],
+        }
+    }
+
+    /// Put the cpu and hardware registers in their post-boot state for this model
+    pub fn power_up(self, cpu: &mut Cpu, mem: &mut Memory) {
+        cpu.registers = self.registers(&mem.cartridge);
+        mem.model = self;
+        for &(addr, value) in self.io() {
+            mem.io.set(addr, value);
+        }
+    }
+}
+
+/// The CGB boot rom sums the title of Nintendo published games to pick a palette and leaves the
+/// sum in B, anything else gets 0
+fn licensed_title_sum(cartridge: &Cartridge) -> u8 {
+    let rom = &cartridge.rom;
+    let nintendo = match rom[OLD_LICENSEE] {
+        0x01 => true,
+        0x33 => rom[NEW_LICENSEE..NEW_LICENSEE + 2] == *b"01",
+        _ => false,
+    };
+    match nintendo {
+        true => rom[0x0134..=0x0143].iter().fold(0, |sum, b| sum.wrapping_add(*b)),
+        false => 0,
+    }
+}
+
+impl std::str::FromStr for Model {
+    type Err = String;
+
+    /// `dmg0`, `dmg`, `mgb`, `sgb`, `sgb2` or `cgb`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dmg0" => Ok(Self::Dmg0),
+            "dmg" => Ok(Self::Dmg),
+            "mgb" => Ok(Self::Mgb),
+            "sgb" => Ok(Self::Sgb),
+            "sgb2" => Ok(Self::Sgb2),
+            "cgb" => Ok(Self::Cgb),
+            _ => Err(format!("Unknown model {s}, expected dmg0, dmg, mgb, sgb, sgb2 or cgb")),
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn cartridge(header_checksum: u8, licensee: u8) -> Cartridge {
+        let mut rom = vec![0; 0xffff];
+        rom[0x0134..0x0138].copy_from_slice(b"GAME");
+        rom[OLD_LICENSEE] = licensee;
+        rom[0x014d] = header_checksum;
+        Cartridge::new(rom).unwrap()
+    }
+
+    #[test]
+    fn test_registers() {
+        let registers = Model::Dmg.registers(&cartridge(0x4d, 0));
+        assert_eq!(
+            [registers.af, registers.bc, registers.de, registers.hl],
+            [0x01b0, 0x0013, 0x00d8, 0x014d]
+        );
+        assert_eq!((registers.sp, registers.pc), (0xfffe, 0x0100));
+        // H and C are only set when the header checksum isn't 0
+        let registers = Model::Dmg.registers(&cartridge(0, 0));
+        assert_eq!(registers.af, 0x0180);
+        assert_eq!(registers.flags, Flags::post_boot(0));
+        assert_eq!(Model::Mgb.registers(&cartridge(0x4d, 0)).af, 0xffb0);
+        let registers = Model::Sgb.registers(&cartridge(0x4d, 0));
+        assert_eq!([registers.af, registers.bc, registers.hl], [0x0100, 0x0014, 0xc060]);
+        assert_eq!((registers.a, registers.h, registers.l), (0x01, 0xc0, 0x60));
+        assert_eq!(Model::Sgb2.registers(&cartridge(0x4d, 0)).af, 0xff00);
+        let registers = Model::Dmg0.registers(&cartridge(0x4d, 0));
+        assert_eq!([registers.af, registers.bc, registers.hl], [0x0100, 0xff13, 0x8403]);
+    }
+
+    #[test]
+    fn test_cgb_title_sum() {
+        let sum = b"GAME".iter().map(|b| *b as u16).sum::<u16>() as u8;
+        let registers = Model::Cgb.registers(&cartridge(0x4d, 0x01));
+        assert_eq!([registers.af, registers.de, registers.hl], [0x1180, 0x0008, 0x007c]);
+        assert_eq!(registers.b, sum);
+        assert_eq!(Model::Cgb.registers(&cartridge(0x4d, 0x02)).b, 0);
+        // $33 defers to the new licensee code
+        let mut rom = cartridge(0x4d, 0x33).rom;
+        rom[NEW_LICENSEE..NEW_LICENSEE + 2].copy_from_slice(b"01");
+        assert_eq!(Model::Cgb.registers(&Cartridge::new(rom).unwrap()).b, sum);
+    }
+
+    #[test]
+    fn test_power_up() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::new(cartridge(0x4d, 0));
+        assert_eq!(mem.io.get(DIV), 0xab);
+        Model::Dmg0.power_up(&mut cpu, &mut mem);
+        assert_eq!(mem.io.get(DIV), 0x18);
+        assert_eq!(cpu.registers.b, 0xff);
+        Model::Cgb.power_up(&mut cpu, &mut mem);
+        assert_eq!((mem.io.get(SC), mem.io.get(DMA)), (0x7f, 0x00));
+        assert_eq!(cpu.registers.a, 0x11);
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Model::detect(&cartridge(0x4d, 0x33)), Model::Dmg);
+        let mut rom = cartridge(0x4d, 0x33).rom;
+        rom[0x0146] = 0x03;
+        assert_eq!(Model::detect(&Cartridge::new(rom.clone()).unwrap()), Model::Sgb);
+        // without the $33 licensee code the SGB flag is ignored
+        rom[OLD_LICENSEE] = 0x01;
+        assert_eq!(Model::detect(&Cartridge::new(rom.clone()).unwrap()), Model::Dmg);
+        rom[CGB_FLAG] = 0x80;
+        assert_eq!(Model::detect(&Cartridge::new(rom.clone()).unwrap()), Model::Dmg);
+        rom[CGB_FLAG] = 0xc0;
+        assert_eq!(Model::detect(&Cartridge::new(rom).unwrap()), Model::Cgb);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("sgb2".parse(), Ok(Model::Sgb2));
+        assert!("gba".parse::<Model>().is_err());
+    }
+}
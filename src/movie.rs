@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use crate::{cartridge::Cartridge, errors::MovieError, io::joypad::Joypad};
+
+/// Everything about a movie besides its inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct MovieMetadata {
+    pub title: String,
+    /// Global checksum of the ROM the movie was recorded on
+    pub global_checksum: u16,
+    pub author: String,
+    /// How many times part of the movie was thrown away and recorded again
+    pub rerecords: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MovieMode {
+    /// Live input is appended to the movie
+    Recording,
+    /// The movie drives the joypad and live input is ignored
+    #[default]
+    Playback,
+    /// Playback reached the end of the movie, input is live again but not recorded
+    Finished,
+}
+
+/// A recording of the buttons held at the start of every frame, stored as json.
+/// Movies always start from power on, playing one back relies on the emulator being deterministic.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Movie {
+    pub metadata: MovieMetadata,
+    /// Held buttons for every frame, laid out like `Button::bit`
+    pub inputs: Vec<u8>,
+    /// The next frame to record or play back
+    #[cfg_attr(feature = "json", serde(skip))]
+    pub frame: usize,
+    #[cfg_attr(feature = "json", serde(skip))]
+    pub mode: MovieMode,
+}
+
+impl Movie {
+    /// Start recording a new movie from power on
+    pub fn record(cartridge: &Cartridge, author: impl Into<String>) -> Self {
+        Self {
+            metadata: MovieMetadata {
+                title: cartridge.title.trim_end_matches('\0').to_string(),
+                global_checksum: cartridge.global_checksum,
+                author: author.into(),
+                rerecords: 0,
+            },
+            inputs: vec![],
+            frame: 0,
+            mode: MovieMode::Recording,
+        }
+    }
+
+    /// Load a movie for playback, refusing to do so if it was recorded on a different ROM
+    #[cfg(feature = "json")]
+    pub fn load(path: impl AsRef<Path>, cartridge: &Cartridge) -> Result<Self, MovieError> {
+        let movie: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if movie.metadata.global_checksum != cartridge.global_checksum {
+            return Err(MovieError::RomMismatch {
+                expected: cartridge.global_checksum,
+                found: movie.metadata.global_checksum,
+            });
+        }
+        Ok(movie)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), MovieError> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Called at the start of every frame, records the held buttons or replaces them with the movie's.
+    /// Returns true on the frame playback runs out of inputs.
+    pub fn frame(&mut self, joypad: &mut Joypad) -> bool {
+        match self.mode {
+            MovieMode::Recording => {
+                self.inputs.push(joypad.held());
+                self.frame += 1;
+            }
+            MovieMode::Playback => match self.inputs.get(self.frame) {
+                Some(&held) => {
+                    joypad.set_held(held);
+                    self.frame += 1;
+                }
+                None => {
+                    self.mode = MovieMode::Finished;
+                    return true;
+                }
+            },
+            MovieMode::Finished => {}
+        }
+        false
+    }
+
+    /// Switch from playback to live input, everything after the current frame is recorded over
+    pub fn take_over(&mut self) {
+        if self.mode == MovieMode::Recording {
+            return;
+        }
+        if self.frame < self.inputs.len() {
+            self.inputs.truncate(self.frame);
+            self.metadata.rerecords += 1;
+        }
+        self.mode = MovieMode::Recording;
+    }
+
+    /// A save state created during `frame` was loaded, while recording the inputs after it are
+    /// thrown away and counted as a re-record, during playback the movie just continues from there
+    pub fn seek(&mut self, frame: usize, joypad: &mut Joypad) {
+        if self.mode == MovieMode::Recording {
+            self.inputs.truncate(frame + 1);
+            self.metadata.rerecords += 1;
+        }
+        if let Some(&held) = self.inputs.get(frame) {
+            joypad.set_held(held);
+        }
+        self.frame = frame + 1;
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn cartridge() -> Cartridge {
+        let mut rom = vec![0; 0xffff];
+        rom[0x14e] = 0x12;
+        rom[0x14f] = 0x34;
+        Cartridge::new(rom).unwrap()
+    }
+
+    fn record(inputs: &[u8]) -> Movie {
+        let mut movie = Movie::record(&cartridge(), "gbr");
+        let mut joypad = Joypad::default();
+        for &held in inputs {
+            joypad.set_held(held);
+            movie.frame(&mut joypad);
+        }
+        movie
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut movie = record(&[0x01, 0x00, 0x80]);
+        movie.frame = 0;
+        movie.mode = MovieMode::Playback;
+        let mut joypad = Joypad::default();
+        let mut played = vec![];
+        while !movie.frame(&mut joypad) {
+            played.push(joypad.held());
+        }
+        assert_eq!(played, vec![0x01, 0x00, 0x80]);
+        assert_eq!(movie.mode, MovieMode::Finished);
+        // input is live again
+        joypad.set_held(0x02);
+        assert!(!movie.frame(&mut joypad));
+        assert_eq!(joypad.held(), 0x02);
+    }
+
+    #[test]
+    fn test_take_over() {
+        let mut movie = record(&[0x01, 0x02, 0x04]);
+        movie.frame = 1;
+        movie.mode = MovieMode::Playback;
+        movie.take_over();
+        assert_eq!(movie.mode, MovieMode::Recording);
+        assert_eq!(movie.inputs, vec![0x01]);
+        assert_eq!(movie.metadata.rerecords, 1);
+        let mut joypad = Joypad::default();
+        joypad.set_held(0x08);
+        movie.frame(&mut joypad);
+        assert_eq!(movie.inputs, vec![0x01, 0x08]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut joypad = Joypad::default();
+        let mut movie = record(&[0x01, 0x02, 0x04]);
+        movie.seek(0, &mut joypad);
+        assert_eq!(movie.inputs, vec![0x01]);
+        assert_eq!(movie.metadata.rerecords, 1);
+        assert_eq!(joypad.held(), 0x01);
+        let mut movie = record(&[0x01, 0x02, 0x04]);
+        movie.mode = MovieMode::Playback;
+        movie.seek(1, &mut joypad);
+        assert_eq!(movie.inputs.len(), 3);
+        assert_eq!(movie.metadata.rerecords, 0);
+        assert_eq!(joypad.held(), 0x02);
+        movie.frame(&mut joypad);
+        assert_eq!(joypad.held(), 0x04);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_save_load() {
+        let dir = std::env::temp_dir().join(format!("gbr-movie-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.movie");
+        let mut movie = record(&[0x01, 0x02]);
+        movie.metadata.rerecords = 3;
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path, &cartridge()).unwrap();
+        assert_eq!(loaded.metadata, movie.metadata);
+        assert_eq!(loaded.inputs, movie.inputs);
+        assert_eq!(loaded.mode, MovieMode::Playback);
+        let other = Cartridge::new(vec![0; 0xffff]).unwrap();
+        assert!(matches!(
+            Movie::load(&path, &other),
+            Err(MovieError::RomMismatch { expected: 0, found: 0x1234 })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::errors::NetplayError;
+
+pub const NETPLAY_MAGIC: &[u8; 4] = b"GBRN";
+
+/// Lockstep netplay between two instances running the same ROM with the same seed.
+/// Both sides send the buttons they held at the end of a frame and wait for the other's,
+/// then play the next frame with the union of both, which keeps the two emulators identical.
+/// Link cable transfers aren't sent over it, `LinkedSystems` runs both ends in one process.
+#[derive(Debug)]
+pub struct Netplay {
+    stream: TcpStream,
+}
+
+impl Netplay {
+    /// Wait for the other instance to join
+    pub fn host(
+        addr: impl ToSocketAddrs,
+        checksum: u16,
+        seed: u64,
+    ) -> Result<Self, NetplayError> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::handshake(stream, checksum, seed)
+    }
+
+    pub fn join(
+        addr: impl ToSocketAddrs,
+        checksum: u16,
+        seed: u64,
+    ) -> Result<Self, NetplayError> {
+        Self::handshake(TcpStream::connect(addr)?, checksum, seed)
+    }
+
+    /// Layout: magic | global checksum | seed, both sides send theirs and refuse a mismatch
+    pub fn handshake(
+        mut stream: TcpStream,
+        checksum: u16,
+        seed: u64,
+    ) -> Result<Self, NetplayError> {
+        // a single byte is sent every frame, don't let it sit in a buffer
+        stream.set_nodelay(true)?;
+        let mut hello = NETPLAY_MAGIC.to_vec();
+        hello.extend_from_slice(&checksum.to_le_bytes());
+        hello.extend_from_slice(&seed.to_le_bytes());
+        stream.write_all(&hello)?;
+        let mut other = [0u8; 14];
+        stream.read_exact(&mut other)?;
+        if other[..4] != *NETPLAY_MAGIC {
+            return Err(NetplayError::NotGbr);
+        }
+        let found = u16::from_le_bytes([other[4], other[5]]);
+        if found != checksum {
+            return Err(NetplayError::RomMismatch {
+                expected: checksum,
+                found,
+            });
+        }
+        let found = u64::from_le_bytes(other[6..].try_into().unwrap());
+        if found != seed {
+            return Err(NetplayError::SeedMismatch {
+                expected: seed,
+                found,
+            });
+        }
+        Ok(Self { stream })
+    }
+
+    /// Send this frame's buttons and block until the other side's arrive
+    pub fn exchange(&mut self, local: u8) -> Result<u8, NetplayError> {
+        self.stream.write_all(&[local])?;
+        let mut remote = [0u8; 1];
+        self.stream.read_exact(&mut remote)?;
+        Ok(remote[0])
+    }
+}
+
+mod tests {
+    use super::*;
+
+    type Connection = Result<Netplay, NetplayError>;
+
+    /// Connect two instances over loopback, each side is `(checksum, seed)`
+    fn connect(host: (u16, u64), join: (u16, u64)) -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let joiner = std::thread::spawn(move || Netplay::join(addr, join.0, join.1));
+        let (stream, _) = listener.accept().unwrap();
+        let host = Netplay::handshake(stream, host.0, host.1);
+        (host, joiner.join().unwrap())
+    }
+
+    #[test]
+    fn test_exchange() {
+        let (host, join) = connect((0x1234, 7), (0x1234, 7));
+        let (mut host, mut join) = (host.unwrap(), join.unwrap());
+        let joiner = std::thread::spawn(move || {
+            (0..3).map(|frame| join.exchange(0x10 << frame).unwrap()).collect::<Vec<_>>()
+        });
+        let received: Vec<u8> = (0..3)
+            .map(|frame| host.exchange(0x01 << frame).unwrap())
+            .collect();
+        assert_eq!(received, vec![0x10, 0x20, 0x40]);
+        assert_eq!(joiner.join().unwrap(), vec![0x01, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn test_handshake_mismatch() {
+        let (host, join) = connect((0x1234, 7), (0xbeef, 7));
+        assert!(matches!(
+            host,
+            Err(NetplayError::RomMismatch { expected: 0x1234, found: 0xbeef })
+        ));
+        assert!(matches!(
+            join,
+            Err(NetplayError::RomMismatch { expected: 0xbeef, found: 0x1234 })
+        ));
+        let (host, _) = connect((0x1234, 7), (0x1234, 8));
+        assert!(matches!(host, Err(NetplayError::SeedMismatch { expected: 7, found: 8 })));
+    }
+}
@@ -0,0 +1,159 @@
+use bitflags::bitflags;
+
+use crate::tile::TileId;
+
+/// OAM holds 40 objects of 4 bytes each
+pub const OAM_ENTRIES: usize = 40;
+/// The most objects the ppu picks for a single scanline
+pub const OBJECTS_PER_LINE: usize = 10;
+
+bitflags! {
+    /// Byte 3 of an OAM entry, the lower bits are CGB only
+    /// Read more: https://gbdev.io/pandocs/OAM.html#byte-3--attributesflags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct OamAttributes: u8 {
+        const CGB_PALETTE = 0x07;
+        const CGB_BANK = 0x08;
+        /// Use OBP1 instead of OBP0 (DMG only)
+        const DMG_PALETTE = 0x10;
+        const X_FLIP = 0x20;
+        const Y_FLIP = 0x40;
+        /// BG and window colors 1-3 are drawn over the object
+        const PRIORITY = 0x80;
+    }
+}
+
+/// LCDC.2, whether objects are one tile or two stacked on top of each other
+/// Read more: https://gbdev.io/pandocs/LCDC.html#lcdc2--obj-size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjSize {
+    /// 8x8
+    #[default]
+    Normal,
+    /// 8x16
+    Tall,
+}
+
+impl ObjSize {
+    pub fn height(self) -> u8 {
+        match self {
+            Self::Normal => 8,
+            Self::Tall => 16,
+        }
+    }
+
+    /// The tile holding `row` of an object showing `tile`, rows counting from the top after
+    /// any flip. Tall objects ignore bit 0 of `tile`: the even tile is the top half and the odd
+    /// one the bottom.
+    pub fn tile(self, tile: u8, row: usize) -> TileId {
+        match self {
+            Self::Normal => TileId(tile),
+            Self::Tall => TileId((tile & 0xfe) | (row / 8) as u8),
+        }
+    }
+}
+
+/// A single object, positions are stored the way the hardware does:
+/// `y` is the screen position + 16 and `x` is the screen position + 8
+/// Read more: https://gbdev.io/pandocs/OAM.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OamEntry {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attrs: OamAttributes,
+}
+
+impl OamEntry {
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self {
+            y: bytes[0],
+            x: bytes[1],
+            tile: bytes[2],
+            attrs: OamAttributes::from_bits_retain(bytes[3]),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 4] {
+        [self.y, self.x, self.tile, self.attrs.bits()]
+    }
+
+    /// Whether the object covers `scanline` given the object height from LCDC.2 (8 or 16)
+    pub fn on_scanline(&self, scanline: u8, height: u8) -> bool {
+        let top = self.y as i16 - 16;
+        (top..top + height as i16).contains(&(scanline as i16))
+    }
+}
+
+/// The objects picked for a scanline, kept inline so the scan doesn't allocate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanlineObjects {
+    entries: [OamEntry; OBJECTS_PER_LINE],
+    len: usize,
+}
+
+impl ScanlineObjects {
+    /// Add an object, returns false once the line is full
+    pub fn push(&mut self, entry: OamEntry) -> bool {
+        if self.len == OBJECTS_PER_LINE {
+            return false;
+        }
+        self.entries[self.len] = entry;
+        self.len += 1;
+        true
+    }
+}
+
+impl std::ops::Deref for ScanlineObjects {
+    type Target = [OamEntry];
+
+    fn deref(&self) -> &[OamEntry] {
+        &self.entries[..self.len]
+    }
+}
+
+impl std::ops::DerefMut for ScanlineObjects {
+    fn deref_mut(&mut self) -> &mut [OamEntry] {
+        &mut self.entries[..self.len]
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, memory::Memory};
+
+    #[test]
+    fn test_oam_entries() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(0xfe9c, 0x20);
+        mem.write(0xfe9f, 0xa0);
+        let entry = mem.oam_entries()[39];
+        assert_eq!(entry.y, 0x20);
+        assert_eq!(entry.attrs, OamAttributes::PRIORITY | OamAttributes::X_FLIP);
+        let moved = OamEntry { x: 0x10, ..entry };
+        mem.set_oam_entry(0, moved);
+        assert_eq!(mem.oam_entry(0), moved);
+        assert_eq!(mem.read(0xfe01), 0x10);
+    }
+
+    #[test]
+    fn test_on_scanline() {
+        let entry = OamEntry { y: 16, ..Default::default() };
+        assert!(entry.on_scanline(0, 8));
+        assert!(entry.on_scanline(7, 8));
+        assert!(!entry.on_scanline(8, 8));
+        assert!(entry.on_scanline(15, 16));
+        // partially above the screen
+        let entry = OamEntry { y: 4, ..Default::default() };
+        assert!(entry.on_scanline(0, 16));
+        assert!(!entry.on_scanline(0, 8));
+    }
+
+    #[test]
+    fn test_obj_size_tile() {
+        assert_eq!(ObjSize::Normal.tile(0x03, 7), TileId(0x03));
+        assert_eq!(ObjSize::Tall.tile(0x03, 7), TileId(0x02));
+        assert_eq!(ObjSize::Tall.tile(0x02, 8), TileId(0x03));
+        assert_eq!(ObjSize::Tall.tile(0xff, 15), TileId(0xff));
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    io::joypad::Buttons,
+};
+
+/// Everything a training loop looks at after a frame gathered in one call, see `System::observe`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observation {
+    /// The frame as 160x144 grayscale pixels, row by row
+    pub pixels: Vec<u8>,
+    pub buttons: Buttons,
+    pub frame: u64,
+    /// The byte at every watched address, in the order they were passed to `System::watch`
+    pub ram: Vec<u8>,
+}
+
+impl Default for Observation {
+    fn default() -> Self {
+        Self {
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            buttons: Buttons::empty(),
+            frame: 0,
+            ram: vec![],
+        }
+    }
+}
+
+/// Convert an RGB24 frame into one byte per pixel using the Rec. 601 luma weights
+pub fn grayscale(rgb: &[u8], out: &mut [u8]) {
+    for (pixel, gray) in rgb.chunks_exact(3).zip(out) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(u32::from);
+        *gray = ((r * 299 + g * 587 + b * 114) / 1000) as u8;
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grayscale() {
+        let mut out = [0; 4];
+        grayscale(&[0xff, 0xff, 0xff, 0x55, 0x55, 0x55, 0xff, 0, 0, 0, 0, 0xff], &mut out);
+        assert_eq!(out, [0xff, 0x55, 76, 29]);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    PALETTE,
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+/// How long a message stays on screen before it's dropped
+pub const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+/// Glyphs are 3x5 pixels, each row is stored as 3 bits where bit 2 is the leftmost pixel
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+// 1 pixel of spacing between glyphs and lines
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+// text is drawn as the darkest shade on top of a box filled with the lightest shade
+const FOREGROUND: u8 = PALETTE[3];
+const BACKGROUND: u8 = PALETTE[0];
+
+/// Look up the bitmap for a character, lowercase letters are drawn as uppercase
+/// and anything the font doesn't know about is drawn as '?'
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// Width in pixels of `text` once drawn, not including the background box
+pub fn text_width(text: &str) -> usize {
+    match text.chars().count() {
+        0 => 0,
+        n => n * ADVANCE - 1,
+    }
+}
+
+fn put_pixel(frame_buffer: &mut [u8], x: usize, y: usize, shade: u8) {
+    if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+        return;
+    }
+    let index = (y * SCREEN_WIDTH + x) * 3;
+    frame_buffer[index..index + 3].copy_from_slice(&[shade, shade, shade]);
+}
+
+/// Draw `text` with its top left corner at (x, y) onto an RGB24 160x144 framebuffer,
+/// a 1 pixel box is drawn behind the text so it stays readable on top of any background.
+/// Anything that falls outside of the screen is clipped.
+pub fn draw_text(frame_buffer: &mut [u8], text: &str, x: usize, y: usize) {
+    let width = text_width(text);
+    if width == 0 {
+        return;
+    }
+    for box_y in y..y + GLYPH_HEIGHT + 2 {
+        for box_x in x..x + width + 2 {
+            put_pixel(frame_buffer, box_x, box_y, BACKGROUND);
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        let origin = x + 1 + i * ADVANCE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if (bits >> (GLYPH_WIDTH - 1 - col)) & 1 == 1 {
+                    put_pixel(frame_buffer, origin + col, y + 1 + row, FOREGROUND);
+                }
+            }
+        }
+    }
+}
+
+/// A line of text formatted on the stack, so the counters drawn every frame don't allocate.
+/// Anything past a screen width of characters is cut off.
+#[derive(Debug, Clone, Copy)]
+struct TextBuffer {
+    bytes: [u8; SCREEN_WIDTH / ADVANCE],
+    len: usize,
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self {
+        Self { bytes: [0; SCREEN_WIDTH / ADVANCE], len: 0 }
+    }
+}
+
+impl TextBuffer {
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Write for TextBuffer {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// On-screen display composited on top of the framebuffer before it's presented
+/// - transient messages (e.g: "State saved") stacked in the bottom left corner
+/// - an FPS counter in the top right corner
+/// - a fast-forward indicator in the top left corner
+/// - a pause indicator in the top middle
+#[derive(Debug)]
+pub struct Osd {
+    messages: Vec<(String, Instant)>,
+    pub show_fps: bool,
+    pub fast_forward: bool,
+    pub paused: bool,
+    /// Audio underruns so far, shown under the FPS counter
+    pub underruns: Option<u64>,
+    fps: f32,
+    frames: usize,
+    last_sample: Instant,
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self {
+            messages: vec![],
+            show_fps: false,
+            fast_forward: false,
+            paused: false,
+            underruns: None,
+            fps: 0.0,
+            frames: 0,
+            last_sample: Instant::now(),
+        }
+    }
+
+    /// Queue a message, it's displayed for `MESSAGE_DURATION`
+    pub fn message(&mut self, text: impl Into<String>) {
+        self.messages.push((text.into(), Instant::now() + MESSAGE_DURATION));
+    }
+
+    /// Messages that haven't expired yet, oldest first
+    pub fn messages(&self) -> impl DoubleEndedIterator<Item = &str> {
+        let now = Instant::now();
+        self.messages
+            .iter()
+            .filter(move |(_, expires)| *expires > now)
+            .map(|(text, _)| text.as_str())
+    }
+
+    /// Must be called once per presented frame, updates the FPS counter (sampled every second) and drops expired messages
+    pub fn frame(&mut self) {
+        self.frames += 1;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.last_sample = now;
+        }
+        self.messages.retain(|(_, expires)| *expires > now);
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Composite the OSD onto an RGB24 160x144 framebuffer
+    pub fn draw(&self, frame_buffer: &mut [u8]) {
+        if self.fast_forward {
+            draw_text(frame_buffer, ">>", 0, 0);
+        }
+        if self.paused {
+            let x = (SCREEN_WIDTH - text_width("PAUSED")) / 2;
+            draw_text(frame_buffer, "PAUSED", x, 0);
+        }
+        if self.show_fps {
+            let mut fps = TextBuffer::default();
+            let _ = write!(fps, "{:.0} FPS", self.fps);
+            let x = SCREEN_WIDTH.saturating_sub(text_width(fps.as_str()) + 2);
+            draw_text(frame_buffer, fps.as_str(), x, 0);
+            if let Some(underruns) = self.underruns {
+                let mut text = TextBuffer::default();
+                let _ = write!(text, "{underruns} XRUN");
+                let x = SCREEN_WIDTH.saturating_sub(text_width(text.as_str()) + 2);
+                draw_text(frame_buffer, text.as_str(), x, LINE_HEIGHT + 1);
+            }
+        }
+        let mut y = SCREEN_HEIGHT;
+        for message in self.messages().rev() {
+            y = match y.checked_sub(LINE_HEIGHT + 1) {
+                Some(y) => y,
+                None => break,
+            };
+            draw_text(frame_buffer, message, 0, y);
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn shade_at(frame_buffer: &[u8], x: usize, y: usize) -> u8 {
+        frame_buffer[(y * SCREEN_WIDTH + x) * 3]
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut frame_buffer = vec![PALETTE[2]; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        draw_text(&mut frame_buffer, "1", 10, 10);
+        // background box
+        assert_eq!(shade_at(&frame_buffer, 10, 10), BACKGROUND);
+        // top row of '1' is 010
+        assert_eq!(shade_at(&frame_buffer, 11, 11), BACKGROUND);
+        assert_eq!(shade_at(&frame_buffer, 12, 11), FOREGROUND);
+        // untouched pixels outside of the box
+        assert_eq!(shade_at(&frame_buffer, 9, 10), PALETTE[2]);
+        assert_eq!(shade_at(&frame_buffer, 15, 10), PALETTE[2]);
+    }
+
+    #[test]
+    fn test_draw_text_clips() {
+        // a row's worth of spare bytes past the screen to catch anything drawn beyond it
+        let screen = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+        let mut frame_buffer = vec![PALETTE[2]; screen + SCREEN_WIDTH * 3];
+        draw_text(&mut frame_buffer, "STATE SAVED", 150, 140);
+        // what fits on screen is drawn
+        assert_eq!(shade_at(&frame_buffer, 150, 140), BACKGROUND);
+        assert_ne!(shade_at(&frame_buffer, 159, 143), PALETTE[2]);
+        assert_eq!(shade_at(&frame_buffer, 149, 140), PALETTE[2]);
+        // nothing wraps around onto the start of the next rows or the top of the screen
+        for y in (0..4).chain(140..SCREEN_HEIGHT) {
+            for x in 0..10 {
+                assert_eq!(shade_at(&frame_buffer, x, y), PALETTE[2], "({x}, {y})");
+            }
+        }
+        assert!(frame_buffer[screen..].iter().all(|shade| *shade == PALETTE[2]));
+    }
+
+    #[test]
+    fn test_fast_forward_indicator() {
+        let mut osd = Osd::new();
+        let mut frame_buffer = vec![PALETTE[2]; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        osd.draw(&mut frame_buffer);
+        assert_eq!(shade_at(&frame_buffer, 0, 0), PALETTE[2]);
+        osd.fast_forward = true;
+        osd.draw(&mut frame_buffer);
+        assert_eq!(shade_at(&frame_buffer, 0, 0), BACKGROUND);
+        // the top row of '>' is 100
+        assert_eq!(shade_at(&frame_buffer, 1, 1), FOREGROUND);
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut osd = Osd::new();
+        osd.message("State saved");
+        osd.frame();
+        assert_eq!(osd.messages().collect::<Vec<_>>(), vec!["State saved"]);
+        let mut frame_buffer = vec![PALETTE[2]; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        osd.draw(&mut frame_buffer);
+        assert_eq!(shade_at(&frame_buffer, 0, SCREEN_HEIGHT - LINE_HEIGHT - 1), BACKGROUND);
+    }
+
+    #[test]
+    fn test_text_buffer() {
+        let mut text = TextBuffer::default();
+        write!(text, "{:.0} FPS", 59.7).unwrap();
+        assert_eq!(text.as_str(), "60 FPS");
+        // cut off at a screen width of characters
+        write!(text, "{}", "X".repeat(100)).unwrap();
+        assert_eq!(text.as_str().len(), SCREEN_WIDTH / ADVANCE);
+    }
+}
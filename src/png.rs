@@ -0,0 +1,81 @@
+use crate::hash::{adler32, crc32};
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Encode an RGB24 image as a PNG. The image data is stored uncompressed, which is fine for
+/// a 160x144 frame and saves pulling in a deflate implementation.
+/// Read more: https://www.w3.org/TR/png/
+pub fn encode_rgb(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, adaptive filtering, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+    // every row starts with its filter type, 0 is none
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks_exact(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Layout: length | type | data | crc of type and data
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream made of uncompressed deflate blocks, each holding at most 65535 bytes
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_rgb() {
+        let png = encode_rgb(2, 1, &[0xff, 0x00, 0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(&png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // IDAT: zlib header and a single final stored block holding the filtered row
+        let idat = &png[33..];
+        assert_eq!(&idat[4..8], b"IDAT");
+        assert_eq!(&idat[8..15], &[0x78, 0x01, 0x01, 0x07, 0x00, 0xf8, 0xff]);
+        assert_eq!(&idat[15..22], &[0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_zlib_stored_blocks() {
+        let data = vec![0x42; 0x10000];
+        let zlib = zlib_stored(&data);
+        // two blocks, only the second one is final
+        assert_eq!(zlib[2], 0x00);
+        assert_eq!(zlib[2 + 5 + 0xffff], 0x01);
+        assert_eq!(zlib.len(), 2 + 2 * 5 + data.len() + 4);
+    }
+}
@@ -0,0 +1,61 @@
+/// Run-length encoding for large, mostly uniform byte blocks (the 64KiB memory block, ROMs, framebuffers)
+/// Each run is stored as `(byte, length)`
+pub fn encode(bytes: &[u8]) -> Vec<(u8, u32)> {
+    let mut runs: Vec<(u8, u32)> = vec![];
+    for &byte in bytes {
+        match runs.last_mut() {
+            Some((value, length)) if *value == byte => *length += 1,
+            _ => runs.push((byte, 1)),
+        }
+    }
+    runs
+}
+
+pub fn decode(runs: &[(u8, u32)]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(runs.iter().map(|(_, length)| *length as usize).sum());
+    for &(value, length) in runs {
+        bytes.extend(std::iter::repeat_n(value, length as usize));
+    }
+    bytes
+}
+
+/// Use with `#[serde(with = "crate::rle")]` on any byte array or `Vec<u8>` field
+#[cfg(feature = "serde")]
+pub fn serialize<S, T>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: AsRef<[u8]>,
+{
+    serde::Serialize::serialize(&encode(bytes.as_ref()), serializer)
+}
+
+#[cfg(feature = "serde")]
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: TryFrom<Vec<u8>>,
+{
+    let runs: Vec<(u8, u32)> = serde::Deserialize::deserialize(deserializer)?;
+    let bytes = decode(&runs);
+    let length = bytes.len();
+    T::try_from(bytes).map_err(|_| {
+        serde::de::Error::custom(format!("run-length encoded block has the wrong length: {length}"))
+    })
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rle_round_trip() {
+        let mut bytes = vec![0u8; 0x8000];
+        bytes[0x100] = 0xc3;
+        bytes[0x101] = 0x50;
+        bytes.extend([0xff; 0x100]);
+        let runs = encode(&bytes);
+        assert_eq!(runs.len(), 5);
+        assert_eq!(runs[0], (0, 0x100));
+        assert_eq!(decode(&runs), bytes);
+        assert!(encode(&[]).is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// M-cycles in one second of emulated time
+pub const CYCLES_PER_SECOND: usize = 1 << 20;
+
+pub const RTC_SECONDS: u8 = 0x08;
+pub const RTC_MINUTES: u8 = 0x09;
+pub const RTC_HOURS: u8 = 0x0a;
+pub const RTC_DAY_LOW: u8 = 0x0b;
+pub const RTC_DAY_HIGH: u8 = 0x0c;
+
+/// How the MBC3 real-time clock advances
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RtcMode {
+    /// Follow the host's wall clock, time keeps passing while the emulator is closed.
+    /// This is the only mode that reads host time, so it has to be allowed by `DeterminismConfig`.
+    HostSync,
+    /// Advance with emulated cycles, so fast forward and pausing affect the clock
+    #[default]
+    Emulated,
+    /// Never advance on its own, the clock reads the given seconds until the game writes it.
+    /// Useful for deterministic replays and TAS.
+    Frozen(u64),
+}
+
+impl std::str::FromStr for RtcMode {
+    type Err = String;
+
+    /// `host`, `emulated`, `frozen` or `frozen:<seconds>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "host" => Ok(Self::HostSync),
+            None if s == "emulated" => Ok(Self::Emulated),
+            None if s == "frozen" => Ok(Self::Frozen(0)),
+            Some(("frozen", seconds)) => seconds
+                .parse()
+                .map(Self::Frozen)
+                .map_err(|e| format!("Invalid frozen time {seconds}: {e}")),
+            _ => Err(format!(
+                "Unknown rtc mode {s}, expected host, emulated or frozen[:seconds]"
+            )),
+        }
+    }
+}
+
+/// The MBC3 real-time clock: seconds, minutes, hours and a 9 bit day counter with a carry flag.
+/// The game reads a latched copy of the registers, writing 0 then 1 to 0x6000-0x7fff latches them.
+/// Read more: https://gbdev.io/pandocs/MBC3.html#the-clock-counter-registers
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    pub mode: RtcMode,
+    /// Total time on the clock in seconds, days overflow past 511
+    seconds: u64,
+    pub halt: bool,
+    pub day_carry: bool,
+    /// M-cycles towards the next second in emulated mode
+    cycles: usize,
+    /// Host time in seconds when the clock was last synced in host mode
+    host_time: u64,
+    latched: [u8; 5],
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new(mode: RtcMode) -> Self {
+        let mut rtc = Self {
+            mode,
+            seconds: 0,
+            halt: false,
+            day_carry: false,
+            cycles: 0,
+            host_time: 0,
+            latched: [0; 5],
+            latch_armed: false,
+        };
+        rtc.set_mode(mode);
+        rtc
+    }
+
+    /// Switch modes, the clock keeps its current time unless it's being frozen at a specific time
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        self.sync();
+        self.mode = mode;
+        if mode == RtcMode::HostSync {
+            self.host_time = host_seconds();
+        }
+        if let RtcMode::Frozen(seconds) = mode {
+            self.set_seconds(seconds);
+        }
+    }
+
+    /// Advance an emulated clock by `cycles` M-cycles
+    pub fn tick(&mut self, cycles: usize) {
+        if self.mode != RtcMode::Emulated || self.halt {
+            return;
+        }
+        self.cycles += cycles;
+        let elapsed = self.cycles / CYCLES_PER_SECOND;
+        self.cycles %= CYCLES_PER_SECOND;
+        self.advance(elapsed as u64);
+    }
+
+    /// Catch a host synced clock up with the host's wall clock
+    pub fn sync(&mut self) {
+        if self.mode != RtcMode::HostSync {
+            return;
+        }
+        let now = host_seconds();
+        if !self.halt {
+            self.advance(now.saturating_sub(self.host_time));
+        }
+        self.host_time = now;
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        self.set_seconds(self.seconds + seconds);
+    }
+
+    fn set_seconds(&mut self, seconds: u64) {
+        // the day counter is 9 bits wide, overflowing it sets the carry until the game clears it
+        const WRAP: u64 = 512 * 86400;
+        if seconds >= WRAP {
+            self.day_carry = true;
+        }
+        self.seconds = seconds % WRAP;
+    }
+
+    /// The live registers, in the order of `RTC_SECONDS..=RTC_DAY_HIGH`
+    pub fn registers(&self) -> [u8; 5] {
+        let days = self.seconds / 86400;
+        [
+            (self.seconds % 60) as u8,
+            (self.seconds / 60 % 60) as u8,
+            (self.seconds / 3600 % 24) as u8,
+            days as u8,
+            (days >> 8) as u8 & 0x01 | (self.halt as u8) << 6 | (self.day_carry as u8) << 7,
+        ]
+    }
+
+    /// Writing 0 and then 1 copies the live registers into the latched ones
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.sync();
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            RTC_SECONDS..=RTC_DAY_HIGH => self.latched[(register - RTC_SECONDS) as usize],
+            _ => 0xff,
+        }
+    }
+
+    /// Set a live register, writing the seconds also resets the sub-second counter
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync();
+        let [mut seconds, mut minutes, mut hours, day_low, day_high] = self.registers();
+        let mut days = u16::from_le_bytes([day_low, day_high & 0x01]);
+        match register {
+            RTC_SECONDS => {
+                seconds = value % 60;
+                self.cycles = 0;
+            }
+            RTC_MINUTES => minutes = value % 60,
+            RTC_HOURS => hours = value % 24,
+            RTC_DAY_LOW => days = days & 0x100 | value as u16,
+            RTC_DAY_HIGH => {
+                days = days & 0xff | (value as u16 & 0x01) << 8;
+                self.halt = value & 0x40 != 0;
+                self.day_carry = value & 0x80 != 0;
+            }
+            _ => return,
+        }
+        self.seconds =
+            days as u64 * 86400 + hours as u64 * 3600 + minutes as u64 * 60 + seconds as u64;
+    }
+}
+
+fn host_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+mod tests {
+    use super::*;
+
+    fn latch(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        [
+            rtc.read(RTC_SECONDS),
+            rtc.read(RTC_MINUTES),
+            rtc.read(RTC_HOURS),
+            rtc.read(RTC_DAY_LOW),
+            rtc.read(RTC_DAY_HIGH),
+        ]
+    }
+
+    #[test]
+    fn test_emulated() {
+        let mut rtc = Rtc::new(RtcMode::Emulated);
+        rtc.tick(CYCLES_PER_SECOND * 61 + 10);
+        assert_eq!(latch(&mut rtc), [1, 1, 0, 0, 0]);
+        // the latched registers don't move until the next latch
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(rtc.read(RTC_SECONDS), 1);
+        rtc.write(RTC_DAY_HIGH, 0x41);
+        rtc.tick(CYCLES_PER_SECOND * 10);
+        assert_eq!(latch(&mut rtc), [2, 1, 0, 0, 0x41]);
+    }
+
+    #[test]
+    fn test_day_carry() {
+        let mut rtc = Rtc::new(RtcMode::Frozen(511 * 86400 + 86399));
+        assert_eq!(latch(&mut rtc), [59, 59, 23, 0xff, 0x01]);
+        rtc.set_mode(RtcMode::Emulated);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, 0x80]);
+        rtc.write(RTC_DAY_HIGH, 0x00);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, 0x00]);
+    }
+
+    #[test]
+    fn test_frozen() {
+        let mut rtc = Rtc::new(RtcMode::Frozen(3661));
+        rtc.tick(CYCLES_PER_SECOND * 5);
+        rtc.sync();
+        assert_eq!(latch(&mut rtc), [1, 1, 1, 0, 0]);
+        rtc.write(RTC_MINUTES, 30);
+        assert_eq!(latch(&mut rtc), [1, 30, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_mbc3_mapping() {
+        use crate::{cartridge::Cartridge, memory::Memory};
+
+        let mut rom = vec![0; 0xffff];
+        // MBC3+TIMER+RAM+BATTERY with 8KiB of ram
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        let mut mem = Memory::new(Cartridge::new(rom).unwrap());
+        mem.rtc.as_mut().unwrap().set_mode(RtcMode::Frozen(0));
+        mem.write(0x4000, RTC_HOURS);
+        mem.write(0xa000, 5);
+        mem.write(0x6000, 0);
+        mem.write(0x6000, 1);
+        assert_eq!(mem.read(0xa000), 5);
+        // selecting a ram bank unmaps the clock
+        mem.write(0x4000, 0x00);
+        mem.write(0xa000, 0x42);
+        assert_eq!(mem.read(0xa000), 0x42);
+        assert_eq!(mem.rom()[0x4000], 0);
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!("host".parse(), Ok(RtcMode::HostSync));
+        assert_eq!("emulated".parse(), Ok(RtcMode::Emulated));
+        assert_eq!("frozen".parse(), Ok(RtcMode::Frozen(0)));
+        assert_eq!("frozen:86400".parse(), Ok(RtcMode::Frozen(86400)));
+        assert!("frozen:soon".parse::<RtcMode>().is_err());
+        assert!("wall".parse::<RtcMode>().is_err());
+    }
+}
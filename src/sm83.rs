@@ -0,0 +1,190 @@
+use crate::{
+    cpu::{Cpu, Flags, R16},
+    memory::{BusAccess, BusOp, Memory, MemoryBus},
+};
+
+/// Single instruction test vectors in the SingleStepTests format
+/// Read more: https://github.com/SingleStepTests/sm83
+/// Each file in `tests/sm83` is named after the opcode it covers, e.g: `cb 06.json`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Deserialize))]
+pub struct TestCase {
+    pub name: String,
+    pub initial: TestState,
+    pub r#final: TestState,
+    /// One entry per m-cycle, `(addr, value, activity)`
+    pub cycles: Vec<(Option<u16>, Option<u8>, String)>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Deserialize))]
+pub struct TestState {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub pc: u16,
+    pub sp: u16,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub ime: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+/// Generate a `#[test]` per opcode that runs its vectors from `tests/sm83`, the vectors are json
+/// so the tests only exist with the `json` feature
+/// The test name is the opcode file name, `x80` runs `80.json` and `cb_06` runs `cb 06.json`
+///
+/// ```rust,ignore
+/// gbr::sm83_tests!(x07, x0f, cb_00, cb_06);
+/// ```
+#[macro_export]
+macro_rules! sm83_tests {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[cfg(feature = "json")]
+            #[test]
+            fn $name() {
+                $crate::sm83::check(&$crate::sm83::opcode_name(stringify!($name)));
+            }
+        )*
+    };
+}
+
+/// Map a test name from `sm83_tests!` back to the name of its vector file
+pub fn opcode_name(test: &str) -> String {
+    test.trim_start_matches('x').replace('_', " ")
+}
+
+/// The file name for an opcode, prefixed opcodes are `cb xx`
+pub fn file_name(opcode: u8, prefixed: bool) -> String {
+    match prefixed {
+        true => format!("cb {opcode:02x}"),
+        false => format!("{opcode:02x}"),
+    }
+}
+
+/// Whether `tests/sm83` has vectors for an opcode
+pub fn has_vectors(opcode: u8, prefixed: bool) -> bool {
+    let name = file_name(opcode, prefixed);
+    std::path::Path::new(&format!("{}/tests/sm83/{name}.json", env!("CARGO_MANIFEST_DIR"))).exists()
+}
+
+#[cfg(feature = "json")]
+pub fn load(opcode: &str) -> Vec<TestCase> {
+    let path = format!("{}/tests/sm83/{opcode}.json", env!("CARGO_MANIFEST_DIR"));
+    let file = std::fs::read(&path).unwrap_or_else(|e| panic!("Couldn't read {path}: {e}"));
+    serde_json::from_slice(&file).unwrap_or_else(|e| panic!("Couldn't parse {path}: {e}"))
+}
+
+/// Run every vector for `opcode`, panicking with all of the mismatches
+#[cfg(feature = "json")]
+pub fn check(opcode: &str) {
+    let failures: Vec<String> = load(opcode)
+        .iter()
+        .filter_map(|case| run(case).err())
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Execute a single instruction from `case.initial` and compare the result against `case.final`
+pub fn run(case: &TestCase) -> Result<(), String> {
+    let (mut cpu, mut mem) = setup(&case.initial);
+    let cycles = cpu.execute(&mut mem).map_err(|e| format!("{}: {e:?}", case.name))?;
+    let expected = &case.r#final;
+    let mut flags = Flags::default();
+    flags.set(expected.f);
+    let mut mismatches = vec![];
+    for (name, found, wanted) in [
+        ("a", cpu.registers.a, expected.a),
+        ("b", cpu.registers.b, expected.b),
+        ("c", cpu.registers.c, expected.c),
+        ("d", cpu.registers.d, expected.d),
+        ("e", cpu.registers.e, expected.e),
+        ("h", cpu.registers.h, expected.h),
+        ("l", cpu.registers.l, expected.l),
+    ] {
+        if found != wanted {
+            mismatches.push(format!("{name} is 0x{found:02x}, expected 0x{wanted:02x}"));
+        }
+    }
+    for (name, found, wanted) in [
+        ("bc", cpu.registers.bc, u16::from_be_bytes([expected.b, expected.c])),
+        ("de", cpu.registers.de, u16::from_be_bytes([expected.d, expected.e])),
+        ("hl", cpu.registers.hl, u16::from_be_bytes([expected.h, expected.l])),
+        ("sp", cpu.registers.sp, expected.sp),
+        ("pc", cpu.registers.pc, expected.pc),
+    ] {
+        if found != wanted {
+            mismatches.push(format!("{name} is 0x{found:04x}, expected 0x{wanted:04x}"));
+        }
+    }
+    // EI only takes effect after the next instruction but the vectors already expect IME to be set
+    let ime = cpu.ime || cpu.ime_scheduled;
+    if ime != (expected.ime == 1) {
+        mismatches.push(format!("ime is {ime}, expected {}", expected.ime == 1));
+    }
+    if cpu.registers.flags != flags {
+        mismatches.push(format!("flags are {:?}, expected {flags:?}", cpu.registers.flags));
+    }
+    for &(addr, wanted) in &expected.ram {
+        let found = mem.get(addr);
+        if found != wanted {
+            mismatches.push(format!(
+                "[0x{addr:04x}] is 0x{found:02x}, expected 0x{wanted:02x}"
+            ));
+        }
+    }
+    let bus = mem.bus_log.take().unwrap_or_default();
+    let expected_bus = expected_bus(case);
+    if bus != expected_bus {
+        mismatches.push(format!("bus was {bus:?}, expected {expected_bus:?}"));
+    }
+    if cycles as usize != case.cycles.len() {
+        mismatches.push(format!(
+            "took {cycles} cycles, expected {}",
+            case.cycles.len()
+        ));
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{}: {}", case.name, mismatches.join(", ")))
+    }
+}
+
+/// The reads and writes in `case.cycles`, in order. The cpu doesn't tick the bus every m-cycle
+/// so where the idle cycles fall can't be checked, only the total number of cycles.
+pub fn expected_bus(case: &TestCase) -> Vec<BusAccess> {
+    case.cycles
+        .iter()
+        .filter_map(|(addr, value, activity)| {
+            let op = match activity.as_str() {
+                "r-m" => BusOp::Read,
+                "-wm" => BusOp::Write,
+                _ => return None,
+            };
+            Some(BusAccess { addr: (*addr)?, value: (*value)?, op })
+        })
+        .collect()
+}
+
+fn setup(state: &TestState) -> (Cpu, Memory) {
+    let mut cpu = Cpu::default();
+    cpu.registers.set_r16(R16::AF, u16::from_be_bytes([state.a, state.f]));
+    cpu.registers.set_r16(R16::BC, u16::from_be_bytes([state.b, state.c]));
+    cpu.registers.set_r16(R16::DE, u16::from_be_bytes([state.d, state.e]));
+    cpu.registers.set_r16(R16::HL, u16::from_be_bytes([state.h, state.l]));
+    cpu.registers.sp = state.sp;
+    cpu.registers.pc = state.pc;
+    cpu.ime = state.ime == 1;
+    let mut mem = Memory::flat();
+    for &(addr, value) in &state.ram {
+        mem.set(addr, value);
+    }
+    mem.bus_log = Some(vec![]);
+    (cpu, mem)
+}
@@ -0,0 +1,613 @@
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    clock::Clock,
+    cpu::{Cpu, R8, R16},
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    errors::StateError,
+    memory::{Memory, regions::*, registers::*},
+};
+
+pub const SLOT_COUNT: u8 = 10;
+pub const STATE_MAGIC: &[u8; 4] = b"GBRS";
+/// Bumped whenever the encoded layout changes, states with a different format are refused
+pub const STATE_FORMAT: u8 = 6;
+/// Version of the emulator that created a state, a different version only produces a warning
+pub const EMULATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const INDEX_FILE: &str = "index.json";
+
+/// A snapshot of everything needed to resume emulation: cpu registers, clock and the memory block.
+/// The cartridge itself isn't part of the state, it's expected to be loaded already,
+/// its global checksum is recorded so a state can't be loaded into a different ROM.
+#[derive(Debug, Clone)]
+pub struct SaveState {
+    pub global_checksum: u16,
+    pub version: String,
+    pub cpu: Cpu,
+    pub clock: Clock,
+    /// Frames completed when the state was created, movies use it to seek and count re-records
+    pub frame: u64,
+    /// Dots into the scanline the PPU was on
+    pub dot: u16,
+    pub block: Vec<u8>,
+}
+
+impl SaveState {
+    pub fn new(cpu: &Cpu, clock: &Clock, mem: &Memory) -> Self {
+        Self {
+            global_checksum: mem.cartridge.global_checksum,
+            version: EMULATOR_VERSION.to_string(),
+            cpu: cpu.clone(),
+            clock: clock.clone(),
+            frame: 0,
+            dot: 0,
+            block: mem.snapshot(),
+        }
+    }
+
+    /// Restore the state, refusing to do so if it was created from a different ROM
+    pub fn restore(
+        &self,
+        cpu: &mut Cpu,
+        clock: &mut Clock,
+        mem: &mut Memory,
+    ) -> Result<(), StateError> {
+        let expected = mem.cartridge.global_checksum;
+        if self.global_checksum != expected {
+            return Err(StateError::RomMismatch {
+                expected,
+                found: self.global_checksum,
+            });
+        }
+        // the history is the running cpu's, it's kept at the same length but starts over
+        let mut pc_history = std::mem::take(&mut cpu.pc_history);
+        pc_history.clear();
+        *cpu = self.cpu.clone();
+        cpu.pc_history = pc_history;
+        *clock = self.clock.clone();
+        mem.restore_snapshot(&self.block);
+        clock.tac = mem.io.get(TAC);
+        Ok(())
+    }
+
+    /// States created by another version of the emulator are loaded on a best-effort basis
+    pub fn is_foreign(&self) -> bool {
+        self.version != EMULATOR_VERSION
+    }
+
+    /// Layout (little-endian):
+    /// magic | format | global checksum | version length | version | a b c d e h l | sp pc | z n h c | ime ime_scheduled halted halt_bug | t_cycles | divider | frame | dot | block
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let registers = &self.cpu.registers;
+        let flags = &registers.flags;
+        let mut bytes = Vec::with_capacity(self.block.len() + 64);
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_FORMAT);
+        bytes.extend_from_slice(&self.global_checksum.to_le_bytes());
+        bytes.push(self.version.len() as u8);
+        bytes.extend_from_slice(self.version.as_bytes());
+        bytes.extend_from_slice(&[
+            registers.a,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+        ]);
+        bytes.extend_from_slice(&registers.sp.to_le_bytes());
+        bytes.extend_from_slice(&registers.pc.to_le_bytes());
+        bytes.extend_from_slice(&[
+            flags.zero as u8,
+            flags.subtraction as u8,
+            flags.half_carry as u8,
+            flags.carry as u8,
+            self.cpu.ime as u8,
+            self.cpu.ime_scheduled as u8,
+            self.cpu.halted as u8,
+            self.cpu.halt_bug as u8,
+        ]);
+        bytes.extend_from_slice(&self.clock.t_cycles.to_le_bytes());
+        bytes.extend_from_slice(&self.clock.divider.to_le_bytes());
+        bytes.extend_from_slice(&self.frame.to_le_bytes());
+        bytes.extend_from_slice(&self.dot.to_le_bytes());
+        bytes.extend_from_slice(&self.block);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut bytes = bytes;
+        if take::<4>(&mut bytes).map_err(|_| StateError::NotAState)? != *STATE_MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let [format] = take::<1>(&mut bytes)?;
+        if format != STATE_FORMAT {
+            return Err(StateError::UnsupportedFormat(format));
+        }
+        let global_checksum = u16::from_le_bytes(take(&mut bytes)?);
+        let [length] = take::<1>(&mut bytes)?;
+        let version = bytes
+            .get(..length as usize)
+            .ok_or(StateError::Truncated)?;
+        let version = String::from_utf8_lossy(version).into_owned();
+        bytes = &bytes[length as usize..];
+        let [a, b, c, d, e, h, l] = take::<7>(&mut bytes)?;
+        let sp = u16::from_le_bytes(take(&mut bytes)?);
+        let pc = u16::from_le_bytes(take(&mut bytes)?);
+        let [zero, subtraction, half_carry, carry, ime, ime_scheduled, halted, halt_bug] =
+            take::<8>(&mut bytes)?;
+        let t_cycles = u64::from_le_bytes(take(&mut bytes)?);
+        let divider = u16::from_le_bytes(take(&mut bytes)?);
+        let frame = u64::from_le_bytes(take(&mut bytes)?);
+        let dot = u16::from_le_bytes(take(&mut bytes)?);
+        if bytes.len() != 65536 {
+            return Err(StateError::Truncated);
+        }
+
+        let mut cpu = Cpu::default();
+        cpu.registers.set_r8(R8::A, a);
+        cpu.registers.set_r16(R16::BC, u16::from_be_bytes([b, c]));
+        cpu.registers.set_r16(R16::DE, u16::from_be_bytes([d, e]));
+        cpu.registers.set_r16(R16::HL, u16::from_be_bytes([h, l]));
+        cpu.registers.set_r16(R16::SP, sp);
+        cpu.registers.set_r16(R16::PC, pc);
+        cpu.registers.flags.zero = zero == 1;
+        cpu.registers.flags.subtraction = subtraction == 1;
+        cpu.registers.flags.half_carry = half_carry == 1;
+        cpu.registers.flags.carry = carry == 1;
+        cpu.ime = ime == 1;
+        cpu.ime_scheduled = ime_scheduled == 1;
+        cpu.halted = halted == 1;
+        cpu.halt_bug = halt_bug == 1;
+        Ok(Self {
+            global_checksum,
+            version,
+            cpu,
+            // TAC is in the block, `restore` mirrors it into the clock
+            clock: Clock { t_cycles, divider, tac: 0 },
+            frame,
+            dot,
+            block: bytes.to_vec(),
+        })
+    }
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], StateError> {
+    if bytes.len() < N {
+        return Err(StateError::Truncated);
+    }
+    let (head, tail) = bytes.split_at(N);
+    *bytes = tail;
+    Ok(head.try_into().unwrap())
+}
+
+/// IO registers listed by name in a `StateDiff`, the rest are listed by address
+const IO_NAMES: [(usize, &str); 28] = [
+    (JOYP, "JOYP"),
+    (SB, "SB"),
+    (SC, "SC"),
+    (DIV, "DIV"),
+    (TIMA, "TIMA"),
+    (TMA, "TMA"),
+    (TAC, "TAC"),
+    (IF, "IF"),
+    (NR10, "NR10"),
+    (NR12, "NR12"),
+    (NR22, "NR22"),
+    (NR30, "NR30"),
+    (NR42, "NR42"),
+    (NR50, "NR50"),
+    (NR51, "NR51"),
+    (NR52, "NR52"),
+    (LCDC, "LCDC"),
+    (STAT, "STAT"),
+    (SCY, "SCY"),
+    (SCX, "SCX"),
+    (LY, "LY"),
+    (LYC, "LYC"),
+    (DMA, "DMA"),
+    (BGP, "BGP"),
+    (OGBP0, "OBP0"),
+    (OGBP1, "OBP1"),
+    (WY, "WY"),
+    (WX, "WX"),
+];
+
+/// Memory regions a differing range is reported in, ranges never cross from one to the next
+const REGIONS: [(usize, usize, &str); 7] = [
+    (ROM_BANK_0_START, ROM_BANK_1_END, "ROM"),
+    (VRAM_START, VRAM_END, "VRAM"),
+    (EXTERNAL_RAM_START, EXTERNAL_RAM_END, "External RAM"),
+    (WRAM_1_START, WRAM_2_END, "WRAM"),
+    (ECHO_RAM_START, ECHO_RAM_END, "Echo RAM"),
+    (OAM_START, 0xfeff, "OAM"),
+    (HRAM_START, HRAM_END, "HRAM"),
+];
+
+/// A run of differing bytes within one memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeDiff {
+    pub region: &'static str,
+    pub start: u16,
+    /// Inclusive
+    pub end: u16,
+}
+
+/// Everything that differs between two save states, see `SaveState::diff`.
+/// Printing it lists one difference per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Cpu registers, flags and clock counters as (name, ours, theirs)
+    pub registers: Vec<(&'static str, u64, u64)>,
+    /// IO registers and IE as (address, ours, theirs)
+    pub io: Vec<(u16, u8, u8)>,
+    /// Everything else, as runs of differing bytes
+    pub ranges: Vec<RangeDiff>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.io.is_empty() && self.ranges.is_empty()
+    }
+}
+
+impl std::fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The states are identical");
+        }
+        for (name, ours, theirs) in &self.registers {
+            writeln!(f, "{name}: 0x{ours:x} -> 0x{theirs:x}")?;
+        }
+        for &(addr, ours, theirs) in &self.io {
+            match IO_NAMES.iter().find(|(io, _)| *io == addr as usize) {
+                Some((_, name)) => write!(f, "{name}")?,
+                None if addr as usize == IE => write!(f, "IE")?,
+                None => write!(f, "${addr:04X}")?,
+            }
+            writeln!(f, ": 0x{ours:02x} -> 0x{theirs:02x}")?;
+        }
+        for range in &self.ranges {
+            let length = (range.end - range.start) as usize + 1;
+            write!(f, "{} ${:04X}", range.region, range.start)?;
+            match length {
+                1 => writeln!(f, " (1 byte)")?,
+                _ => writeln!(f, "-${:04X} ({length} bytes)", range.end)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SaveState {
+    /// What changed from this state to `other`, states from different ROMs can be compared too
+    pub fn diff(&self, other: &Self) -> StateDiff {
+        let (ours, theirs) = (&self.cpu.registers, &other.cpu.registers);
+        let registers = [
+            ("A", ours.a as u64, theirs.a as u64),
+            ("B", ours.b as u64, theirs.b as u64),
+            ("C", ours.c as u64, theirs.c as u64),
+            ("D", ours.d as u64, theirs.d as u64),
+            ("E", ours.e as u64, theirs.e as u64),
+            ("H", ours.h as u64, theirs.h as u64),
+            ("L", ours.l as u64, theirs.l as u64),
+            ("SP", ours.sp as u64, theirs.sp as u64),
+            ("PC", ours.pc as u64, theirs.pc as u64),
+            ("Z flag", ours.flags.zero as u64, theirs.flags.zero as u64),
+            ("N flag", ours.flags.subtraction as u64, theirs.flags.subtraction as u64),
+            ("H flag", ours.flags.half_carry as u64, theirs.flags.half_carry as u64),
+            ("C flag", ours.flags.carry as u64, theirs.flags.carry as u64),
+            ("IME", self.cpu.ime as u64, other.cpu.ime as u64),
+            ("IME scheduled", self.cpu.ime_scheduled as u64, other.cpu.ime_scheduled as u64),
+            ("T-cycles", self.clock.t_cycles, other.clock.t_cycles),
+            ("Divider", self.clock.divider as u64, other.clock.divider as u64),
+            ("Frame", self.frame, other.frame),
+            ("Dot", self.dot as u64, other.dot as u64),
+        ];
+        let registers = registers.into_iter().filter(|(_, a, b)| a != b).collect();
+        let io = (IO_REGISTER_START..=IO_REGISTER_END)
+            .chain([IE])
+            .filter(|&addr| self.block[addr] != other.block[addr])
+            .map(|addr| (addr as u16, self.block[addr], other.block[addr]))
+            .collect();
+        let mut ranges = vec![];
+        for &(start, end, region) in &REGIONS {
+            let mut run: Option<RangeDiff> = None;
+            for addr in start..=end {
+                if self.block[addr] == other.block[addr] {
+                    ranges.extend(run.take());
+                    continue;
+                }
+                let range = run.get_or_insert(RangeDiff {
+                    region,
+                    start: addr as u16,
+                    end: addr as u16,
+                });
+                range.end = addr as u16;
+            }
+            ranges.extend(run);
+        }
+        StateDiff { registers, io, ranges }
+    }
+}
+
+/// Stored in the slot index next to the state files
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotMetadata {
+    pub slot: u8,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub title: String,
+    pub global_checksum: u16,
+    /// File name of the 160x144 .ppm thumbnail, relative to the slot directory
+    pub thumbnail: String,
+}
+
+/// Numbered save state slots for a single ROM, stored in `<rom>.states/`:
+/// - `slotN.state` the encoded `SaveState`
+/// - `slotN.ppm` a thumbnail of the frame at the time of saving
+/// - `index.json` metadata for every used slot
+/// - `emergency.state` the state at the time of a crash or interrupt
+#[derive(Debug, Clone)]
+pub struct StateSlots {
+    pub dir: PathBuf,
+}
+
+impl StateSlots {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn for_rom(rom: impl AsRef<Path>) -> Self {
+        let mut dir = rom.as_ref().as_os_str().to_owned();
+        dir.push(".states");
+        Self::new(dir)
+    }
+
+    pub fn state_path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("slot{slot}.state"))
+    }
+
+    #[cfg(feature = "json")]
+    pub fn index(&self) -> Result<Vec<SlotMetadata>, StateError> {
+        match std::fs::read(self.dir.join(INDEX_FILE)) {
+            Ok(index) => Ok(serde_json::from_slice(&index)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    pub fn metadata(&self, slot: u8) -> Result<Option<SlotMetadata>, StateError> {
+        Ok(self.index()?.into_iter().find(|entry| entry.slot == slot))
+    }
+
+    /// Write the state, its thumbnail (an RGB24 160x144 frame) and update the index
+    #[cfg(feature = "json")]
+    pub fn save(
+        &self,
+        slot: u8,
+        state: &SaveState,
+        title: &str,
+        frame_buffer: &[u8],
+    ) -> Result<SlotMetadata, StateError> {
+        if slot >= SLOT_COUNT {
+            return Err(StateError::InvalidSlot(slot));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.state_path(slot), state.to_bytes())?;
+        let thumbnail = format!("slot{slot}.ppm");
+        write_ppm(&self.dir.join(&thumbnail), frame_buffer)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let metadata = SlotMetadata {
+            slot,
+            timestamp,
+            title: title.trim_end_matches('\0').to_string(),
+            global_checksum: state.global_checksum,
+            thumbnail,
+        };
+        let mut index = self.index()?;
+        index.retain(|entry| entry.slot != slot);
+        index.push(metadata.clone());
+        index.sort_by_key(|entry| entry.slot);
+        std::fs::write(self.dir.join(INDEX_FILE), serde_json::to_vec_pretty(&index)?)?;
+        Ok(metadata)
+    }
+
+    pub fn load(&self, slot: u8) -> Result<SaveState, StateError> {
+        SaveState::from_bytes(&std::fs::read(self.state_path(slot))?)
+    }
+
+    /// Where the state written when the emulator crashes or is interrupted goes, it's kept
+    /// out of the numbered slots so it never overwrites one
+    pub fn emergency_path(&self) -> PathBuf {
+        self.dir.join("emergency.state")
+    }
+
+    pub fn save_emergency(&self, state: &SaveState) -> Result<PathBuf, StateError> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.emergency_path(), state.to_bytes())?;
+        Ok(self.emergency_path())
+    }
+}
+
+/// Write an RGB24 160x144 frame as a binary .ppm
+fn write_ppm(path: &Path, frame_buffer: &[u8]) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    write!(file, "P6\n{SCREEN_WIDTH} {SCREEN_HEIGHT}\n255\n")?;
+    file.write_all(frame_buffer)
+}
+
+/// Format seconds since the unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+/// Read more: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut cpu = Cpu::default();
+        let mut clock = Clock::new();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        cpu.registers.set_r16(R16::HL, 0x1234);
+        cpu.registers.flags.carry = false;
+        cpu.ime = true;
+        clock.t_cycles = 456;
+        clock.divider = 0xabcd;
+        mem.write(0xc000, 0x42);
+        let mut state = SaveState::new(&cpu, &clock, &mem);
+        state.frame = 1234;
+        state.dot = 200;
+        let bytes = state.to_bytes();
+
+        let state = SaveState::from_bytes(&bytes).unwrap();
+        assert!(!state.is_foreign());
+        assert_eq!((state.frame, state.dot), (1234, 200));
+        let mut restored_cpu = Cpu::default();
+        let mut restored_clock = Clock::new();
+        let mut restored_mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        state
+            .restore(&mut restored_cpu, &mut restored_clock, &mut restored_mem)
+            .unwrap();
+        assert_eq!(restored_cpu.registers.hl, 0x1234);
+        assert_eq!(restored_cpu.registers.l, 0x34);
+        assert_eq!(restored_cpu.registers.flags, cpu.registers.flags);
+        assert!(restored_cpu.ime);
+        assert_eq!((restored_clock.t_cycles, restored_clock.divider), (456, 0xabcd));
+        assert_eq!(restored_mem.read(0xc000), 0x42);
+    }
+
+    #[test]
+    fn test_diff() {
+        let cpu = Cpu::default();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let before = SaveState::new(&cpu, &Clock::new(), &mem);
+        assert!(before.diff(&before).is_empty());
+        let mut cpu = cpu.clone();
+        cpu.registers.set_r8(R8::A, 0x42);
+        cpu.registers.flags.zero = false;
+        mem.io.set(LCDC, 0x11);
+        mem.io.set(0xff4d, 0x01);
+        mem.write(0xc000, 1);
+        mem.write(0xc001, 2);
+        mem.write(0xc003, 3);
+        mem.write(0xff80, 4);
+        let after = SaveState::new(&cpu, &Clock::new(), &mem);
+        let diff = before.diff(&after);
+        assert_eq!(diff.registers, vec![("A", 0x01, 0x42), ("Z flag", 1, 0)]);
+        assert_eq!(
+            diff.ranges,
+            vec![
+                RangeDiff { region: "WRAM", start: 0xc000, end: 0xc001 },
+                RangeDiff { region: "WRAM", start: 0xc003, end: 0xc003 },
+                RangeDiff { region: "HRAM", start: 0xff80, end: 0xff80 },
+            ]
+        );
+        let (lcdc, key1) = (before.block[LCDC], before.block[0xff4d]);
+        assert_eq!(
+            diff.to_string(),
+            format!(
+                "A: 0x1 -> 0x42\nZ flag: 0x1 -> 0x0\n\
+                 LCDC: 0x{lcdc:02x} -> 0x11\n$FF4D: 0x{key1:02x} -> 0x01\n\
+                 WRAM $C000-$C001 (2 bytes)\nWRAM $C003 (1 byte)\nHRAM $FF80 (1 byte)\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_truncated_state() {
+        assert!(SaveState::from_bytes(b"GBRS").is_err());
+        assert!(SaveState::from_bytes(b"nope").is_err());
+        assert!(matches!(
+            SaveState::from_bytes(b"GBRS\xff"),
+            Err(StateError::UnsupportedFormat(0xff))
+        ));
+    }
+
+    #[test]
+    fn test_rom_mismatch() {
+        let mut cpu = Cpu::default();
+        let mut clock = Clock::new();
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut state = SaveState::new(&cpu, &clock, &mem);
+        state.global_checksum = 0xbeef;
+        let state = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert!(matches!(
+            state.restore(&mut cpu, &mut clock, &mut mem),
+            Err(StateError::RomMismatch {
+                expected: 0,
+                found: 0xbeef
+            })
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_slots() {
+        let dir = std::env::temp_dir().join(format!("gbr-slots-{}", std::process::id()));
+        let slots = StateSlots::new(&dir);
+        let cpu = Cpu::default();
+        let clock = Clock::new();
+        let mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let mut state = SaveState::new(&cpu, &clock, &mem);
+        state.global_checksum = 0xbeef;
+        let frame_buffer = vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        assert!(slots.metadata(3).unwrap().is_none());
+        slots.save(3, &state, "TETRIS\0\0", &frame_buffer).unwrap();
+        let metadata = slots.metadata(3).unwrap().unwrap();
+        assert_eq!(metadata.title, "TETRIS");
+        assert_eq!(metadata.global_checksum, 0xbeef);
+        assert!(dir.join(metadata.thumbnail).exists());
+        assert_eq!(slots.load(3).unwrap().cpu.registers.pc, 0x0100);
+        assert!(slots.save(SLOT_COUNT, &state, "", &frame_buffer).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(all(feature = "serde", feature = "json"))]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut cpu = Cpu::default();
+        cpu.registers.set_r16(R16::HL, 0x1234);
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.write(0xc000, 0x42);
+        let restored_cpu: Cpu =
+            serde_json::from_str(&serde_json::to_string(&cpu).unwrap()).unwrap();
+        let mut restored_mem: Memory =
+            serde_json::from_str(&serde_json::to_string(&mem).unwrap()).unwrap();
+        assert_eq!(restored_cpu.registers.hl, 0x1234);
+        assert_eq!(restored_mem.read(0xc000), 0x42);
+        assert_eq!(restored_mem.block, mem.block);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20 UTC");
+    }
+}
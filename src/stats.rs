@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use crate::display::PpuMode;
+
+/// Frames the hardware draws every second, 4194304 Hz / 70224 dots per frame
+pub const FRAME_RATE: f64 = 4_194_304.0 / 70_224.0;
+
+/// How the emulated cycles were split between the PPU modes, as fractions adding up to 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct PpuModeTime {
+    pub horizontal_blank: f64,
+    pub vertical_blank: f64,
+    pub oam_scan: f64,
+    pub drawing: f64,
+}
+
+/// A snapshot of how fast the emulator has been running since power on, see `System::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Stats {
+    pub instructions: u64,
+    pub frames: u64,
+    /// Real time since power on
+    pub elapsed_secs: f64,
+    pub instructions_per_sec: f64,
+    /// Emulated time over real time, 1.0 is full speed and 2.0 twice as fast
+    pub speed: f64,
+    pub ppu_modes: PpuModeTime,
+    pub audio_underruns: u64,
+    /// Real time the frontend spent waiting instead of emulating, e.g. while paused
+    pub idle_secs: f64,
+}
+
+/// Counters kept up to date by the core. Host time is only read to report it, it never
+/// feeds back into emulation.
+#[derive(Debug, Clone)]
+pub struct StatsCounters {
+    started: Instant,
+    instructions: u64,
+    // cycles spent in each mode, indexed like `mode_index`
+    mode_cycles: [u64; 4],
+    idle: Duration,
+}
+
+impl Default for StatsCounters {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            instructions: 0,
+            mode_cycles: [0; 4],
+            idle: Duration::ZERO,
+        }
+    }
+}
+
+fn mode_index(mode: PpuMode) -> usize {
+    match mode {
+        PpuMode::HorizontalBlank => 0,
+        PpuMode::VerticalBlank => 1,
+        PpuMode::OAMScan => 2,
+        PpuMode::Drawing => 3,
+    }
+}
+
+impl StatsCounters {
+    /// Count an executed instruction and the mode the PPU was in while it ran
+    pub fn instruction(&mut self, cycles: usize, mode: PpuMode) {
+        self.instructions += 1;
+        self.mode_cycles[mode_index(mode)] += cycles as u64;
+    }
+
+    /// Add time the frontend spent sleeping or waiting for input
+    pub fn idle(&mut self, duration: Duration) {
+        self.idle += duration;
+    }
+
+    pub fn snapshot(&self, frames: u64, audio_underruns: u64) -> Stats {
+        self.snapshot_at(self.started.elapsed(), frames, audio_underruns)
+    }
+
+    fn snapshot_at(&self, elapsed: Duration, frames: u64, audio_underruns: u64) -> Stats {
+        let elapsed_secs = elapsed.as_secs_f64();
+        let per_sec = |count: f64| match elapsed_secs > 0.0 {
+            true => count / elapsed_secs,
+            false => 0.0,
+        };
+        let total = self.mode_cycles.iter().sum::<u64>().max(1) as f64;
+        let share = |mode| self.mode_cycles[mode_index(mode)] as f64 / total;
+        Stats {
+            instructions: self.instructions,
+            frames,
+            elapsed_secs,
+            instructions_per_sec: per_sec(self.instructions as f64),
+            speed: per_sec(frames as f64 / FRAME_RATE),
+            ppu_modes: PpuModeTime {
+                horizontal_blank: share(PpuMode::HorizontalBlank),
+                vertical_blank: share(PpuMode::VerticalBlank),
+                oam_scan: share(PpuMode::OAMScan),
+                drawing: share(PpuMode::Drawing),
+            },
+            audio_underruns,
+            idle_secs: self.idle.as_secs_f64(),
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let mut counters = StatsCounters::default();
+        counters.instruction(3, PpuMode::Drawing);
+        counters.instruction(1, PpuMode::VerticalBlank);
+        counters.idle(Duration::from_millis(500));
+        let stats = counters.snapshot_at(Duration::from_secs(2), 120, 4);
+        assert_eq!(stats.instructions, 2);
+        assert_eq!(stats.instructions_per_sec, 1.0);
+        // 120 frames is a little over 2 seconds of emulated time
+        assert!((stats.speed - 120.0 / FRAME_RATE / 2.0).abs() < 1e-9);
+        assert_eq!(stats.ppu_modes.drawing, 0.75);
+        assert_eq!(stats.ppu_modes.vertical_blank, 0.25);
+        assert_eq!(stats.ppu_modes.oam_scan, 0.0);
+        assert_eq!((stats.audio_underruns, stats.idle_secs), (4, 0.5));
+        // nothing is divided by zero before any time has passed
+        assert_eq!(StatsCounters::default().snapshot_at(Duration::ZERO, 0, 0).speed, 0.0);
+    }
+}
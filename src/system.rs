@@ -1,135 +1,1397 @@
-use sdl3::{
-    event::Event,
-    keyboard::Keycode,
-    pixels::{Color, PixelFormat},
-    render::{FRect, TextureCreator},
-    sys::pixels::SDL_PIXELFORMAT_RGB24,
+use std::{
+    collections::{BTreeSet, VecDeque},
+    path::PathBuf,
+    sync::{Arc, atomic::AtomicBool, mpsc::Receiver},
+    time::Duration,
 };
 
 use crate::{
-    apu::Apu,
+    accuracy::Accuracy,
+    apu::{self, Apu},
+    audio::{AudioConfig, AudioQueue, Resampler},
+    bundle::{self, BUNDLE_TRACE_LENGTH},
     cartridge::Cartridge,
     clock::Clock,
-    cpu::Cpu,
-    display::{Ppu, PpuMode},
-    errors::SystemError,
-    instructions::jumps::call_n16,
+    config::GameConfig,
+    cpu::{Cpu, PC_HISTORY_LENGTH},
+    crash::CrashReport,
+    determinism::{DeterminismConfig, Rng},
+    display::{FRAME_SCANLINES, Ppu, PpuMode, SCANLINE_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH},
+    io::{
+        IoEvent,
+        infrared::{self, IrDevice},
+        joypad::{Button, ButtonEdge, Buttons, InputProvider, Joypad},
+        serial::{Serial, SerialDevice},
+    },
+    errors::{CartridgeError, StateError, SystemError},
+    events::{CoreEvent, EventBus},
+    gamedb::{GameDb, HeaderMismatch},
+    hash::hex,
+    instructions::{DecodedInstruction, stack::push_stack},
     interrupts::Interrupt,
-    memory::{Memory, registers::LY},
+    memory::{Memory, registers::{DIV, LY, TAC}},
+    memory_editor::MemoryEditor,
+    model::Model,
+    movie::{Movie, MovieMode},
+    netplay::Netplay,
+    observation::{self, Observation},
+    rtc::RtcMode,
+    osd::Osd,
+    png,
+    state::{EMULATOR_VERSION, SaveState, StateSlots},
+    stats::{Stats, StatsCounters},
+    trace::{Divergence, Trace, TraceEntry, TraceHistory},
+    watchdog::{Stall, Watchdog},
 };
 
+mod link;
+#[cfg(feature = "sdl")]
+mod sdl;
+
+pub use link::LinkedSystems;
+#[cfg(feature = "sdl")]
+pub use sdl::SdlVideo;
+
 pub struct System {
     pub cpu: Cpu,
     pub apu: Apu,
     pub ppu: Ppu,
     pub clock: Clock,
     pub mem: Memory,
+    pub osd: Osd,
+    pub joypad: Joypad,
+    /// Frames completed since power on
+    pub frame: u64,
+    /// The movie being recorded or played back
+    pub movie: Option<Movie>,
+    /// Pause once movie playback runs out of inputs
+    pub pause_on_movie_end: bool,
+    /// The last instructions executed, for bug bundles
+    pub trace: TraceHistory,
+    /// Whatever the CGB infrared port is pointed at, no light is received without one
+    pub ir: Option<Box<dyn IrDevice>>,
+    /// Polled once per frame for the buttons to hold, replacing whatever was held before
+    pub input: Option<Box<dyn InputProvider>>,
+    /// Whatever is plugged into the link port, transfers shift in 0xff without one
+    pub link: Option<Box<dyn SerialDevice>>,
+    serial: Serial,
+    /// The other instance of a lockstep netplay session
+    pub netplay: Option<Netplay>,
+    audio_config: AudioConfig,
+    /// Brings the APU's output down to `audio_config.sample_rate`
+    resampler: Resampler,
+    /// The last chunk of resampled audio, kept to reuse its allocation
+    resampled: Vec<f32>,
+    /// Samples waiting for the audio device
+    pub audio: AudioQueue,
+    determinism: DeterminismConfig,
+    accuracy: Accuracy,
+    model: Model,
+    /// Randomness for frontend features, seeded by `DeterminismConfig::seed`
+    pub rng: Rng,
+    pub paused: bool,
+    /// Run as fast as possible instead of at 59.7 frames a second, see `set_fast_forward`
+    pub fast_forward: bool,
+    /// Average the last two frames like a DMG LCD's slow pixels, games that flicker objects
+    /// every other frame rely on it for transparency
+    pub frame_blend: bool,
+    // the last two completed frames, only kept up to date while blending
+    last_frame: Vec<u8>,
+    previous_frame: Vec<u8>,
+    // a single frame has been requested while paused
+    frame_advance: bool,
+    /// Where numbered save states are kept, slot hotkeys do nothing without it
+    pub state_slots: Option<StateSlots>,
+    pub slot: u8,
+    /// Hex editor pane toggled with F1
+    pub memory_editor: MemoryEditor,
+    /// Where battery backed cartridge ram is kept, nothing is saved without it
+    pub battery_path: Option<PathBuf>,
+    /// Makes `run` return once set, e.g. from a signal handler
+    pub quit: Arc<AtomicBool>,
+    counters: StatsCounters,
+    // M-cycles since power on
+    cycles: u64,
+    // scheduled by the frontend, oldest first
+    button_edges: VecDeque<ButtonEdge>,
+    // set through `set_buttons`, held from the next frame on
+    next_buttons: Option<Buttons>,
+    // addresses `observe` reports
+    watched: Vec<u16>,
+    // M-cycles the LCD has been off for since the last frame, there's no VBlank to end one
+    lcd_off_cycles: u64,
+    events: EventBus,
+    /// Addresses that emit `CoreEvent::Breakpoint` when the cpu is about to execute them
+    pub breakpoints: BTreeSet<u16>,
+    /// Flags the cpu getting stuck with `CoreEvent::Stalled`, nothing is checked without one
+    pub watchdog: Option<Watchdog>,
 }
 
+/// Interleaved APU samples collected before they're resampled, about 1ms worth
+const AUDIO_CHUNK: usize = 2048;
+
+// the core holds nothing tied to a thread, so instances can be stepped on any of them
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<System>();
+};
+
 impl System {
-    pub fn new(game: Vec<u8>) -> Result<Self, SystemError> {
+    /// A system without a window, frames are only available through `framebuffer` until `run`
+    /// opens one
+    pub fn headless(game: Vec<u8>) -> Result<Self, SystemError> {
+        Self::with_ppu(game, Ppu::headless())
+    }
+
+    /// A system drawing with an existing `ppu`, e.g. one with its palette already set
+    pub fn with_ppu(game: Vec<u8>, ppu: Ppu) -> Result<Self, SystemError> {
         let cartridge = Cartridge::new(game.clone()).map_err(|_| SystemError::CartridgeError)?;
+        let model = Model::detect(&cartridge);
+        let mut cpu = Cpu::default();
         let mut mem = Memory::new(cartridge);
-        Ok(Self {
-            cpu: Cpu::default(),
+        model.power_up(&mut cpu, &mut mem);
+        let mut system = Self {
+            cpu,
             apu: Apu::default(),
-            ppu: Ppu::new(),
+            ppu,
             clock: Clock::new(),
             mem,
-        })
+            osd: Osd::new(),
+            joypad: Joypad::default(),
+            frame: 0,
+            movie: None,
+            pause_on_movie_end: false,
+            trace: TraceHistory::new(BUNDLE_TRACE_LENGTH),
+            ir: None,
+            input: None,
+            link: None,
+            serial: Serial::default(),
+            netplay: None,
+            audio_config: AudioConfig::default(),
+            resampler: Resampler::new(
+                apu::OUTPUT_RATE,
+                AudioConfig::default().sample_rate,
+                AudioConfig::default().resampling,
+            ),
+            resampled: vec![],
+            audio: AudioQueue::new(&AudioConfig::default()),
+            determinism: DeterminismConfig::default(),
+            accuracy: Accuracy::default(),
+            model,
+            rng: Rng::new(0),
+            paused: false,
+            fast_forward: false,
+            frame_blend: false,
+            last_frame: vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            previous_frame: vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            frame_advance: false,
+            state_slots: None,
+            slot: 0,
+            memory_editor: MemoryEditor::default(),
+            battery_path: None,
+            quit: Arc::default(),
+            counters: StatsCounters::default(),
+            cycles: 0,
+            button_edges: VecDeque::new(),
+            next_buttons: None,
+            watched: vec![],
+            lcd_off_cycles: 0,
+            events: EventBus::default(),
+            breakpoints: BTreeSet::new(),
+            watchdog: None,
+        };
+        system.apu.reset(&system.mem);
+        system.clock.divider = (system.mem.io.get(DIV) as u16) << 8;
+        system.clock.tac = system.mem.io.get(TAC);
+        system.apply_quirks();
+        if let Some(mismatch) = system.mem.cartridge.size_mismatch() {
+            system.osd.message(mismatch.to_string());
+        }
+        Ok(system)
+    }
+
+    /// The last completed frame as RGB24 160x144, without the OSD
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.ppu.frame_buffer
+    }
+
+    /// Copy the last completed frame into `out`, blended with the one before it if enabled,
+    /// and composite the OSD on top of it
+    pub fn composite_frame(&self, out: &mut [u8]) {
+        if self.frame_blend {
+            let frames = self.last_frame.iter().zip(&self.previous_frame);
+            for (out, (last, previous)) in out.iter_mut().zip(frames) {
+                *out = ((*last as u16 + *previous as u16) / 2) as u8;
+            }
+        } else {
+            out.copy_from_slice(&self.ppu.frame_buffer);
+        }
+        self.osd.draw(out);
+        if self.memory_editor.open {
+            self.memory_editor.draw(out, &self.mem);
+        }
+    }
+
+    pub fn toggle_frame_blend(&mut self) {
+        self.frame_blend = !self.frame_blend;
+        // start over from the current frame rather than blending with a stale one
+        self.last_frame.copy_from_slice(&self.ppu.frame_buffer);
+        self.previous_frame.copy_from_slice(&self.ppu.frame_buffer);
+        let message = match self.frame_blend {
+            true => "Frame blending on",
+            false => "Frame blending off",
+        };
+        self.osd.message(message);
     }
     /// The following interrupt service routine is executed when control is being transferred to an interrupt handler:
     /// Two wait states are executed (2 M-cycles pass while nothing happens; presumably the CPU is executing nops during this time).
     /// The current value of the PC register is pushed onto the stack, consuming 2 more M-cycles.
     /// The PC register is set to the address of the handler (one of: $40, $48, $50, $58, $60). This consumes one last M-cycle.
     /// Read more: https://gbdev.io/pandocs/Interrupts.html
-    fn handle_interrupt(&mut self) -> Result<(), SystemError> {
-        if let Some(interrupt) = Interrupt::get_interrupt(self.mem.get_interrupt_registers()) {
-            // https://gbdev.io/pandocs/Interrupt_Sources.html
-            let handler = match interrupt {
-                Interrupt::VBlank => 0x40,
-                Interrupt::Stat => 0x48,
-                Interrupt::Timer => 0x50,
-                Interrupt::Serial => 0x58,
-                Interrupt::Joypad => 0x60,
-            };
-            call_n16(handler, &mut self.cpu, &mut self.mem)
-                .map_err(|_| SystemError::InterruptHandlerError(interrupt, handler))?;
+    fn handle_interrupt(&mut self) -> usize {
+        if let Some(interrupt) = Interrupt::highest_priority(self.mem.pending_interrupts()) {
+            self.mem.acknowledge_interrupt(interrupt);
+            self.cpu.ime = false;
+            self.cpu.halted = false;
+            // two wait states and the final jump on top of pushing pc
+            let cycles = 3 + push_stack(self.cpu.registers.pc, &mut self.cpu, &mut self.mem);
+            self.cpu.registers.pc = interrupt.handler();
+            self.tick_clock(cycles as usize);
+            if interrupt == Interrupt::VBLANK {
+                self.events.emit(CoreEvent::VBlank);
+            }
+            return cycles as usize;
         }
-        Ok(())
+        0
+    }
+
+    /// Advance the clock by `cycles` M-cycles, DIV follows the divider and the APU's frame
+    /// sequencer steps each time DIV-APU falls. OAM DMA runs off the same clock.
+    fn tick_clock(&mut self, cycles: usize) {
+        let edges = self.clock.tick(cycles);
+        for _ in 0..edges.div_apu {
+            self.apu.div_apu(&mut self.mem);
+        }
+        for _ in 0..edges.timer {
+            self.mem.inc_tima();
+        }
+        self.clock_serial(edges.serial);
+        self.mem.io.set(DIV, self.clock.div());
+        self.mem.tick_oam_dma(cycles);
+    }
+
+    /// Shift `bits` of an internally clocked transfer, or check for an externally clocked one
+    fn clock_serial(&mut self, bits: usize) {
+        if let Some((sent, received)) =
+            self.serial.tick(bits, self.link.as_deref_mut(), &mut self.mem)
+        {
+            self.events.emit(CoreEvent::SerialByte { sent, received });
+        }
+    }
+
+    fn dispatch_io_events(&mut self) {
+        let events: Vec<IoEvent> = self.mem.io.drain_events().collect();
+        for event in events {
+            match event {
+                IoEvent::DivReset | IoEvent::Timer(..) => {
+                    // clearing the divider or changing TAC can step the frame sequencer,
+                    // shift a serial bit or count TIMA early
+                    let edges = self.clock.io_event(event);
+                    if edges.div_apu > 0 {
+                        self.apu.div_apu(&mut self.mem);
+                    }
+                    if edges.timer > 0 {
+                        self.mem.inc_tima();
+                    }
+                    self.clock_serial(edges.serial);
+                    self.mem.io.set(DIV, self.clock.div());
+                }
+                IoEvent::Lcd(..) => self.ppu.io_event(event, &mut self.mem),
+                IoEvent::Apu(addr, value) => self.apu.io_event(addr, value, &mut self.mem),
+                IoEvent::Infrared(value) => {
+                    if let Some(ir) = &mut self.ir {
+                        infrared::write_rp(ir.as_mut(), value);
+                    }
+                }
+                IoEvent::Serial => self.serial.write(self.link.as_deref_mut(), &self.mem),
+            }
+        }
+    }
+
+    /// Execute a single instruction and advance the rest of the system alongside it.
+    /// Returns true once the ppu enters VBlank, the last visible scanline having been drawn.
+    /// Panics with a `CrashReport` if the cpu can't execute it, see `try_step`.
+    pub fn step(&mut self) -> bool {
+        self.step_instruction().1
+    }
+
+    /// `step`, returning a report instead of panicking when the cpu crashes
+    pub fn try_step(&mut self) -> Result<bool, CrashReport> {
+        Ok(self.try_step_instruction()?.1)
+    }
+
+    /// Step forever, yielding `(pc, instruction, cycles)` for every instruction executed so
+    /// coverage or control flow tools can watch execution without patching the core
+    pub fn instruction_stream(&mut self) -> InstructionStream<'_> {
+        InstructionStream { system: self }
     }
 
-    pub fn run(&mut self) {
-        let mut texture_creator = self.ppu.canvas.texture_creator();
-        let mut texture = texture_creator
-            .create_texture_streaming(
-                PixelFormat::try_from(SDL_PIXELFORMAT_RGB24).unwrap(),
-                160,
-                144,
-            )
-            .unwrap();
-        self.ppu.canvas.set_draw_color(Color::WHITE);
-        self.ppu.canvas.clear();
-        'running: loop {
-            // execute instructions
-            self.clock.m_cycles += self.cpu.execute(&mut self.mem).unwrap() as usize;
-            // advance the clock
-            self.clock.tick(&mut self.mem);
-            // process audio
-            self.apu.process();
-            // handle interrupts
-            if self.cpu.ime {
-                self.handle_interrupt();
-            }
-            let scanline = self.mem.read(LY);
-            let lcdc = self.mem.lcd_control();
-            // scanline 144 is the beginning of vblank
-            if scanline <= 143 && lcdc.lcd_ppu_enable {
-                let pixels = self.ppu.update_scanline(&mut self.mem, &self.clock, &lcdc, scanline);
-                texture.with_lock(None, |buffer: &mut [u8], _: usize| {
-                    let start = (scanline as usize * 480) as usize;
-                    let end = start + 480;
-                    buffer[start..end].copy_from_slice(&pixels);
-                });
-                self.ppu.canvas
-                    .copy(&texture, None, Some(FRect::new(0.0, 0.0, 160.0, 144.0)))
-                    .unwrap();
-                self.clock.dots += 4;
-            }
-
-            match scanline {
-                143 => self.ppu.mode = PpuMode::VerticalBlank,
-                _ => (),
-            };
-            match self.clock.dots {
-                0..=80 => {
-                    // self.oam_scan(mem, scanline);
-                    self.ppu.mode = PpuMode::OAMScan;
+    /// Step until the cpu disagrees with `reference`, which is first aligned on the current
+    /// state the way `trace::diff` does so a reference starting in the boot ROM still lines up.
+    /// `Divergence::a` is the number of instructions executed and `b` indexes
+    /// `reference.entries`, `None` means every entry of the reference matched.
+    pub fn compare_trace(&mut self, reference: &Trace) -> Result<Option<Divergence>, CrashReport> {
+        let first = TraceEntry::from_cpu(&self.cpu).with_bank(&self.mem);
+        let start = reference
+            .entries
+            .iter()
+            .position(|(_, entry)| entry.mismatches(&first).is_empty())
+            .unwrap_or(0);
+        for (executed, (index, (_, expected))) in
+            reference.entries.iter().enumerate().skip(start).enumerate()
+        {
+            let fields = TraceEntry::from_cpu(&self.cpu).with_bank(&self.mem).mismatches(expected);
+            if !fields.is_empty() {
+                return Ok(Some(Divergence { a: executed, b: index, fields }));
+            }
+            self.try_step()?;
+        }
+        Ok(None)
+    }
+
+    fn step_instruction(&mut self) -> ((u16, DecodedInstruction, u8), bool) {
+        self.try_step_instruction().unwrap_or_else(|report| panic!("{report}"))
+    }
+
+    fn try_step_instruction(
+        &mut self,
+    ) -> Result<((u16, DecodedInstruction, u8), bool), CrashReport> {
+        if self.cycles == 0 {
+            if let Some(mismatch) = self.mem.cartridge.size_mismatch() {
+                self.events.emit(CoreEvent::RomSizeMismatch(mismatch));
+            }
+        }
+        self.trace.push(TraceEntry::from_cpu(&self.cpu).with_bank(&self.mem));
+        let pc = self.cpu.registers.pc;
+        if self.breakpoints.contains(&pc) {
+            self.events.emit(CoreEvent::Breakpoint(pc));
+        }
+        // execute instructions
+        self.mem.io_accessed = false;
+        let (instruction, cycles) = match self.cpu.execute_decoded(&mut self.mem) {
+            Ok(executed) => executed,
+            Err(e) => {
+                return Err(CrashReport::new(e, self.frame, &self.cpu, &self.mem));
+            }
+        };
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.instruction(pc, self.cpu.ime, self.mem.io_accessed);
+        }
+        let executed = (pc, instruction, cycles);
+        let cycles = cycles as usize;
+        self.cycles += cycles as u64;
+        self.tick_clock(cycles);
+        if let Some(rtc) = &mut self.mem.rtc {
+            rtc.tick(cycles);
+        }
+        // let the components know about any io registers written by the instruction
+        self.dispatch_io_events();
+        self.apply_button_edges();
+        // mirror the buttons into JOYP for whichever group the game selected
+        self.joypad.update(&mut self.mem);
+        if let Some(ir) = &mut self.ir {
+            infrared::update_rp(ir.as_mut(), &mut self.mem);
+        }
+        // process audio
+        self.apu.process(cycles, &mut self.mem);
+        if self.apu.output.len() >= AUDIO_CHUNK {
+            self.flush_audio();
+        }
+        // handle interrupts
+        let interrupt_cycles = match self.cpu.ime {
+            true => self.handle_interrupt(),
+            false => 0,
+        };
+        let lcdc = self.mem.lcd_control();
+        let mut frame_done =
+            self.ppu.tick(&mut self.mem, cycles + interrupt_cycles, lcdc.lcd_ppu_enable);
+        // with the LCD off a frame still goes by every 154 scanlines' worth of cycles
+        match lcdc.lcd_ppu_enable {
+            true => self.lcd_off_cycles = 0,
+            false => {
+                self.lcd_off_cycles += (cycles + interrupt_cycles) as u64;
+                if self.lcd_off_cycles >= FRAME_SCANLINES * SCANLINE_CYCLES {
+                    self.lcd_off_cycles -= FRAME_SCANLINES * SCANLINE_CYCLES;
+                    frame_done = true;
                 }
-                81..=252 => {
-                    self.ppu.mode = PpuMode::Drawing;
-                    self.mem.oam_accessible = false;
-                    self.mem.vram_accessible = false;
-                    if lcdc.window_enable {}
-                    // TODO: add obj penalty variable mode length algorithm
-                    if lcdc.bg_window_enable {}
+            }
+        }
+        let scanline = self.mem.read(LY);
+        // scanline 144 is the beginning of vblank
+        if scanline <= 143 && lcdc.lcd_ppu_enable {
+            let pixels = self.ppu.update_scanline(&mut self.mem, &self.clock, &lcdc, scanline);
+            let start = scanline as usize * SCREEN_WIDTH * 3;
+            self.ppu.frame_buffer[start..start + pixels.len()].copy_from_slice(&pixels);
+        }
+        self.ppu.update_stat(&mut self.mem, lcdc.lcd_ppu_enable);
+        self.counters.instruction(cycles, self.ppu.mode);
+        if frame_done {
+            if self.frame_blend {
+                std::mem::swap(&mut self.last_frame, &mut self.previous_frame);
+                self.last_frame.copy_from_slice(&self.ppu.frame_buffer);
+            }
+            self.frame += 1;
+            self.memory_editor.apply_freezes(&mut self.mem);
+            self.joypad.frame();
+            if let Some(input) = &mut self.input {
+                self.next_buttons = Some(input.poll());
+            }
+            if let Some(buttons) = self.next_buttons.take() {
+                self.joypad.set_held(buttons.bits());
+            }
+            self.movie_frame();
+            self.netplay_frame();
+            self.events.emit(CoreEvent::FrameDone(self.frame));
+            if let Some(stall) = self.watchdog.as_mut().and_then(Watchdog::frame) {
+                self.events.emit(CoreEvent::Stalled(stall));
+                self.osd.message(stall.to_string());
+                if self.watchdog.as_ref().is_some_and(|watchdog| watchdog.pause) && !self.paused {
+                    self.toggle_pause();
                 }
-                _ => {
-                    self.mem.oam_accessible = true;
-                    self.mem.vram_accessible = true;
+            }
+        }
+        Ok((executed, frame_done))
+    }
+
+    /// Run until the current frame has been completed
+    pub fn step_frame(&mut self) {
+        while !self.step() {}
+    }
+
+    /// `step_frame`, returning a report instead of panicking when the cpu crashes
+    pub fn try_step_frame(&mut self) -> Result<(), CrashReport> {
+        while !self.try_step()? {}
+        Ok(())
+    }
+
+    /// Run for `lines` scanlines worth of cycles, stopping early once the current frame has
+    /// been completed. Returns whether it was.
+    pub fn step_scanlines(&mut self, lines: u64) -> bool {
+        let end = self.cycles + lines * SCANLINE_CYCLES;
+        while self.cycles < end {
+            if self.step() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// M-cycles emulated since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Press or release a button once emulation reaches `edge.cycle`, so input polled between
+    /// batches of scanlines lands when it happened rather than all at the start of the next one.
+    /// Edges have to be scheduled in order, ones already in the past apply before the next
+    /// instruction.
+    pub fn schedule_button(&mut self, edge: ButtonEdge) {
+        self.button_edges.push_back(edge);
+    }
+
+    /// Hold exactly `buttons` from the next frame boundary on, replacing whatever was held.
+    /// Movies record it like any other input and playing one back still overrides it.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.next_buttons = Some(buttons);
+    }
+
+    /// The buttons currently held, without autofire or netplay applied
+    pub fn buttons(&self) -> Buttons {
+        Buttons::from_bits_retain(self.joypad.held())
+    }
+
+    /// A receiver for every `CoreEvent` from now on, for reacting to the core without polling
+    /// its registers. Events queue up until drained with `try_iter`.
+    pub fn subscribe(&mut self) -> Receiver<CoreEvent> {
+        self.events.subscribe()
+    }
+
+    /// Choose the addresses `observe` reports, replacing any watched before
+    pub fn watch(&mut self, addrs: impl IntoIterator<Item = u16>) {
+        self.watched = addrs.into_iter().collect();
+    }
+
+    /// The screen in grayscale, held buttons, frame counter and watched bytes in one struct, so
+    /// reinforcement learning loops only have to cross into the emulator once per step
+    pub fn observe(&self) -> Observation {
+        let mut observation = Observation::default();
+        self.observe_into(&mut observation);
+        observation
+    }
+
+    /// `observe` reusing the buffers of an earlier observation
+    pub fn observe_into(&self, observation: &mut Observation) {
+        observation.pixels.resize(SCREEN_WIDTH * SCREEN_HEIGHT, 0);
+        observation::grayscale(&self.ppu.frame_buffer, &mut observation.pixels);
+        observation.buttons = self.buttons();
+        observation.frame = self.frame;
+        observation.ram.clear();
+        let ram = self.watched.iter().map(|&addr| self.mem.peek(addr as usize));
+        observation.ram.extend(ram);
+    }
+
+    fn apply_button_edges(&mut self) {
+        while let Some(edge) = self.button_edges.front().filter(|edge| edge.cycle <= self.cycles) {
+            match edge.pressed {
+                true => self.joypad.press(edge.button),
+                false => self.joypad.release(edge.button),
+            }
+            self.button_edges.pop_front();
+        }
+    }
+
+    /// How fast emulation has been running since power on
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot(self.frame, self.audio.underruns())
+    }
+
+    /// Count time a frontend spent waiting instead of emulating towards the idle time in `stats`
+    pub fn add_idle(&mut self, duration: Duration) {
+        self.counters.idle(duration);
+    }
+
+    /// Pause or resume emulation, the run loop keeps polling events and presenting while paused
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.osd.paused = self.paused;
+    }
+
+    /// Stop or start holding frames to the Game Boy's frame rate, the OSD shows ">>" meanwhile
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+        self.osd.fast_forward = fast_forward;
+    }
+
+    /// While paused, run exactly one more frame and pause again
+    pub fn frame_advance(&mut self) {
+        if self.paused {
+            self.frame_advance = true;
+        }
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        let mut state = SaveState::new(&self.cpu, &self.clock, &self.mem);
+        state.frame = self.frame;
+        state.dot = self.ppu.dot;
+        state
+    }
+
+    /// Read cartridge ram back from `battery_path`, a missing file is a fresh save
+    pub fn load_battery(&mut self) -> std::io::Result<()> {
+        let (true, Some(path)) = (self.mem.cartridge.cartridge_type.battery(), &self.battery_path)
+        else {
+            return Ok(());
+        };
+        match std::fs::read(path) {
+            Ok(ram) => self.mem.load_cartridge_ram(&ram),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Write cartridge ram to `battery_path`, returns whether anything was written
+    pub fn flush_battery(&self) -> std::io::Result<bool> {
+        let (true, Some(path)) = (self.mem.cartridge.cartridge_type.battery(), &self.battery_path)
+        else {
+            return Ok(false);
+        };
+        std::fs::write(path, self.mem.cartridge_ram())?;
+        Ok(true)
+    }
+
+    /// Restore a state, a movie in progress seeks to the frame the state was created in
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        state.restore(&mut self.cpu, &mut self.clock, &mut self.mem)?;
+        self.apu.reset(&self.mem);
+        self.ppu.dot = state.dot;
+        self.frame = state.frame;
+        self.lcd_off_cycles = 0;
+        if let Some(movie) = &mut self.movie {
+            movie.seek(state.frame as usize, &mut self.joypad);
+        }
+        Ok(())
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        self.accuracy
+    }
+
+    /// Trade what only debugging needs for speed, `Fast` stops recording the cpu's pc history
+    /// while `Accurate` brings it back at its default length
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        self.cpu.pc_history.set_capacity(match accuracy {
+            Accuracy::Fast => 0,
+            Accuracy::Accurate => PC_HISTORY_LENGTH,
+        });
+        self.apply_quirks();
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Power up as another model than the one detected from the cartridge header, only
+    /// meaningful before the first instruction runs
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        model.power_up(&mut self.cpu, &mut self.mem);
+        self.apu.reset(&self.mem);
+        self.clock.divider = (self.mem.io.get(DIV) as u16) << 8;
+        self.clock.tac = self.mem.io.get(TAC);
+        self.apply_quirks();
+    }
+
+    /// Hardware bugs only the emulated model has, skipped at accuracy level fast
+    fn apply_quirks(&mut self) {
+        let accurate = self.accuracy == Accuracy::Accurate;
+        self.ppu.stat_write_bug = accurate && self.model.stat_write_bug();
+        self.apu.wave_trigger_bug = accurate && self.model.wave_trigger_bug();
+    }
+
+    pub fn determinism(&self) -> DeterminismConfig {
+        self.determinism
+    }
+
+    /// Reseed `rng` and take host time away from the core unless the config allows it
+    pub fn set_determinism(&mut self, config: DeterminismConfig) {
+        self.determinism = config;
+        self.rng = Rng::new(config.seed);
+        if let Some(rtc) = &mut self.mem.rtc {
+            if rtc.mode == RtcMode::HostSync && !config.host_time {
+                rtc.set_mode(RtcMode::Emulated);
+            }
+        }
+    }
+
+    /// Choose how the cartridge's real-time clock advances, does nothing without one.
+    /// Following the host's clock falls back to emulated time unless
+    /// `DeterminismConfig::host_time` is set.
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        let mode = match mode {
+            RtcMode::HostSync if !self.determinism.host_time => RtcMode::Emulated,
+            mode => mode,
+        };
+        if let Some(rtc) = &mut self.mem.rtc {
+            rtc.set_mode(mode);
+        }
+    }
+
+    /// Start recording or playing back a movie, movies are expected to start from power on
+    pub fn start_movie(&mut self, mut movie: Movie) {
+        movie.frame(&mut self.joypad);
+        self.movie = Some(movie);
+    }
+
+    /// Stop playing back the movie and record live input from the current frame on
+    pub fn take_over_movie(&mut self) {
+        if let Some(movie) = &mut self.movie {
+            movie.take_over();
+            self.osd.message(format!("Recording, {} re-records", movie.metadata.rerecords));
+        }
+    }
+
+    /// Play in lockstep with another instance from the current frame on,
+    /// the first frame is played without any buttons since nothing has been exchanged yet
+    pub fn start_netplay(&mut self, netplay: Netplay) {
+        self.joypad.latch(Some(0));
+        self.netplay = Some(netplay);
+    }
+
+    fn netplay_frame(&mut self) {
+        let Some(netplay) = &mut self.netplay else {
+            return;
+        };
+        let local = self.joypad.local();
+        match netplay.exchange(local) {
+            Ok(remote) => self.joypad.latch(Some(local | remote)),
+            Err(e) => {
+                self.netplay = None;
+                self.joypad.latch(None);
+                self.osd.message(format!("Netplay disconnected: {e}"));
+            }
+        }
+    }
+
+    /// Whether keys reach the joypad, a movie being played back owns it
+    pub(crate) fn live_input(&self) -> bool {
+        self.movie
+            .as_ref()
+            .is_none_or(|movie| movie.mode != MovieMode::Playback)
+    }
+
+    fn movie_frame(&mut self) {
+        let Some(movie) = &mut self.movie else {
+            return;
+        };
+        if movie.frame(&mut self.joypad) {
+            self.osd.message("Movie ended");
+            if self.pause_on_movie_end && !self.paused {
+                self.toggle_pause();
+            }
+        }
+    }
+
+    /// Save to the selected slot, the outcome is reported through the OSD
+    #[cfg(feature = "json")]
+    pub fn save_slot(&mut self) {
+        let Some(slots) = &self.state_slots else {
+            return;
+        };
+        let message = match slots.save(
+            self.slot,
+            &self.save_state(),
+            &self.mem.cartridge.title,
+            &self.ppu.frame_buffer,
+        ) {
+            Ok(_) => format!("State saved to slot {}", self.slot),
+            Err(e) => format!("Saving slot {} failed: {e}", self.slot),
+        };
+        self.osd.message(message);
+    }
+
+    /// Load the selected slot, the outcome is reported through the OSD
+    #[cfg(feature = "json")]
+    pub fn load_slot(&mut self) {
+        let Some(slots) = &self.state_slots else {
+            return;
+        };
+        let message = match slots.load(self.slot) {
+            Ok(state) => match self.load_state(&state) {
+                Ok(()) if state.is_foreign() => {
+                    format!("Slot {} loaded from gbr {}", self.slot, state.version)
                 }
+                Ok(()) => format!("State loaded from slot {}", self.slot),
+                Err(e) => format!("Loading slot {} failed: {e}", self.slot),
+            },
+            Err(StateError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                format!("Slot {} is empty", self.slot)
+            }
+            Err(e) => format!("Loading slot {} failed: {e}", self.slot),
+        };
+        self.osd.message(message);
+    }
+
+    /// Toggle autofire on a button, the outcome is reported through the OSD
+    pub fn toggle_turbo(&mut self, button: Button) {
+        let message = match self.joypad.toggle_turbo(button) {
+            true => format!("Turbo {button} on"),
+            false => format!("Turbo {button} off"),
+        };
+        self.osd.message(message);
+    }
+
+    pub fn select_slot(&mut self, slot: u8) {
+        self.slot = slot;
+        self.osd.message(format!("Slot {slot}"));
+    }
+
+    pub fn audio_config(&self) -> AudioConfig {
+        self.audio_config
+    }
+
+    /// Apply the per-game overrides from a config file
+    pub fn configure(&mut self, game: &GameConfig) {
+        if let Some(palette) = game.palette {
+            self.ppu.palette = palette;
+        }
+        if let Some(accuracy) = game.accuracy {
+            self.set_accuracy(accuracy);
+        }
+        if let Some(model) = game.model {
+            self.set_model(model);
+        }
+        game.apply(&mut self.mem.cartridge);
+    }
+
+    /// Look the ROM up in a game database, if the header lies about the hardware warn about it
+    /// and use what the database says instead
+    pub fn verify_rom(&mut self, db: &GameDb) -> Result<Vec<HeaderMismatch>, CartridgeError> {
+        let Some(entry) = db.lookup(&self.mem.cartridge) else {
+            return Ok(vec![]);
+        };
+        let mismatches = entry.mismatches(&self.mem.cartridge.rom);
+        if !mismatches.is_empty() {
+            // the ram is resized to the corrected size along with the mapper
+            self.mem.cartridge.ram_size = entry.ram_size()?;
+            self.mem.set_cartridge_type(entry.cartridge_type()?);
+            for mismatch in &mismatches {
+                self.osd.message(mismatch.to_string());
             }
-            for event in self.ppu.event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    _ => {}
+        }
+        Ok(mismatches)
+    }
+
+    /// A zip of everything needed to reproduce a bug: a save state, the last instructions
+    /// executed, the current frame as a PNG and the settings the emulator runs with
+    #[cfg(feature = "json")]
+    pub fn bug_bundle(&self) -> Vec<u8> {
+        let cartridge = &self.mem.cartridge;
+        let config = serde_json::json!({
+            "version": EMULATOR_VERSION,
+            "title": cartridge.title.trim_end_matches('\0'),
+            "global_checksum": cartridge.global_checksum,
+            "crc32": format!("{:08x}", cartridge.crc32()),
+            "sha1": hex(&cartridge.sha1()),
+            "cartridge_type": format!("{:?}", cartridge.cartridge_type),
+            "frame": self.frame,
+            "seed": self.determinism.seed,
+            "host_time": self.determinism.host_time,
+            "rtc": self.mem.rtc.as_ref().map(|rtc| format!("{:?}", rtc.mode)),
+            "sample_rate": self.audio_config.sample_rate,
+            "audio_buffer": self.audio_config.buffer_size,
+            "resampling": format!("{:?}", self.audio_config.resampling),
+            "frame_blend": self.frame_blend,
+            "palette": self.ppu.palette,
+            "movie": self.movie.as_ref().map(|movie| &movie.metadata),
+            "netplay": self.netplay.is_some(),
+        });
+        let frame = png::encode_rgb(SCREEN_WIDTH, SCREEN_HEIGHT, &self.ppu.frame_buffer);
+        bundle::zip(&[
+            ("state.gbs", self.save_state().to_bytes()),
+            ("trace.log", self.trace.log().into_bytes()),
+            ("frame.png", frame),
+            ("config.json", serde_json::to_vec_pretty(&config).unwrap()),
+        ])
+    }
+
+    /// Write a bug bundle to the working directory, the outcome is reported through the OSD
+    #[cfg(feature = "json")]
+    pub fn save_bug_bundle(&mut self) {
+        let path = format!("gbr-bug-{:08x}-{}.zip", self.mem.cartridge.crc32(), self.frame);
+        let message = match std::fs::write(&path, self.bug_bundle()) {
+            Ok(_) => format!("Bug bundle saved to {path}"),
+            Err(e) => format!("Saving bug bundle failed: {e}"),
+        };
+        self.osd.message(message);
+    }
+
+    /// Takes effect the next time `run` opens the audio device, queued samples are dropped
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        self.audio_config = config;
+        self.resampler = Resampler::new(apu::OUTPUT_RATE, config.sample_rate, config.resampling);
+        self.audio = AudioQueue::new(&config);
+    }
+
+    /// Resample what the APU has mixed so far and queue it for the audio device
+    fn flush_audio(&mut self) {
+        self.resampled.clear();
+        self.resampler.process(&self.apu.output, &mut self.resampled);
+        self.audio.push(&self.resampled);
+        self.apu.output.clear();
+    }
+}
+
+/// Iterator returned by `System::instruction_stream`, it never ends on its own
+pub struct InstructionStream<'a> {
+    system: &'a mut System,
+}
+
+impl Iterator for InstructionStream<'_> {
+    type Item = (u16, DecodedInstruction, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.system.step_instruction().0)
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::{Mnemonic, cartridge::RomSizeMismatch, crash::CRASH_STACK_BYTES, errors::CpuError};
+
+    /// FNV-1a, enough to tell frames apart without pulling in a hashing crate
+    fn frame_hash(frame: &[u8]) -> u64 {
+        frame.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Run `system` until it completes a frame with the LCD on, golden.gb spends its first few
+    /// filling VRAM with the LCD off
+    fn skip_lcd_off(system: &mut System) {
+        system.step_frame();
+        while !system.mem.lcd_control().lcd_ppu_enable {
+            system.step_frame();
+        }
+    }
+
+    /// Run the first frames of `tests/roms/golden.gb` (built from `asm/golden.asm`) and compare
+    /// each frame against a known good hash, set `GBR_BLESS=1` to print the new hashes after an
+    /// intended rendering change
+    #[test]
+    fn test_golden_frames() {
+        // the rom draws a static screen, so every frame after the LCD is turned on is the same
+        const GOLDEN: [u64; 3] = [0xaf65fb58fda5f525; 3];
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let rom = std::fs::read(path).unwrap();
+        let mut system = System::headless(rom).unwrap();
+        skip_lcd_off(&mut system);
+        let hashes: Vec<u64> = GOLDEN
+            .iter()
+            .map(|_| {
+                system.step_frame();
+                frame_hash(system.framebuffer())
+            })
+            .collect();
+        if std::env::var("GBR_BLESS").is_ok() {
+            println!("{hashes:#018x?}");
+            return;
+        }
+        for (frame, (found, wanted)) in hashes.iter().zip(GOLDEN).enumerate() {
+            assert_eq!(*found, wanted, "frame {frame} differs from the golden frame");
+        }
+    }
+
+    #[test]
+    fn test_frame_blend() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        system.step_frame();
+        let mut out = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        system.toggle_frame_blend();
+        // pretend the frame before was black, blending it with a white pixel ends up in between
+        system.last_frame.fill(0x00);
+        system.step_frame();
+        assert_eq!(system.framebuffer()[0], 0xff);
+        system.composite_frame(&mut out);
+        assert_eq!(out[0], 0x7f);
+        // the black frame is gone after another one
+        system.step_frame();
+        system.composite_frame(&mut out);
+        assert_eq!(out[0], 0xff);
+        system.toggle_frame_blend();
+        system.composite_frame(&mut out);
+        assert_eq!(out[0], 0xff);
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut rom = vec![0; 0xffff];
+        // MBC1+RAM+BATTERY with 8 KiB of ram
+        rom[crate::cartridge::CARTRIDGE_TYPE] = 0x03;
+        rom[crate::cartridge::RAM_SIZE] = 0x02;
+        let path = std::env::temp_dir().join(format!("gbr-battery-{}.sav", std::process::id()));
+        let mut system = System::headless(rom.clone()).unwrap();
+        system.battery_path = Some(path.clone());
+        system.mem.write(0xa123, 0x42);
+        assert!(system.flush_battery().unwrap());
+        assert_eq!(std::fs::read(&path).unwrap().len(), 0x2000);
+        let mut restored = System::headless(rom).unwrap();
+        restored.battery_path = Some(path.clone());
+        restored.load_battery().unwrap();
+        assert_eq!(restored.mem.peek(0xa123), 0x42);
+        std::fs::remove_file(path).unwrap();
+        // nothing is saved for cartridges without a battery
+        let mut plain = System::headless(vec![0; 0xffff]).unwrap();
+        plain.battery_path = Some(std::env::temp_dir().join("gbr-no-battery.sav"));
+        assert!(!plain.flush_battery().unwrap());
+    }
+
+    #[test]
+    fn test_stats() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        skip_lcd_off(&mut system);
+        let lcd_off_frames = system.frame;
+        system.step_frame();
+        system.step_frame();
+        let stats = system.stats();
+        assert_eq!(stats.frames, lcd_off_frames + 2);
+        assert!(stats.instructions > 0);
+        let modes = stats.ppu_modes;
+        let total = modes.horizontal_blank + modes.vertical_blank + modes.oam_scan + modes.drawing;
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(modes.vertical_blank > 0.0);
+    }
+
+    #[test]
+    fn test_button_edges() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        let a = Button::Action(crate::io::joypad::Action::A);
+        let start = system.cycles();
+        system.schedule_button(ButtonEdge { button: a, pressed: true, cycle: start + 300 });
+        system.schedule_button(ButtonEdge { button: a, pressed: false, cycle: start + 600 });
+        while system.cycles() < start + 300 {
+            assert_eq!(system.joypad.held(), 0);
+            system.step();
+        }
+        assert_eq!(system.joypad.held(), a.bit());
+        // runs the whole batch unless a frame completes first
+        let before = system.cycles();
+        let frame_done = system.step_scanlines(8);
+        assert!(frame_done || system.cycles() >= before + 8 * SCANLINE_CYCLES);
+        while system.cycles() < start + 600 {
+            system.step_scanlines(8);
+        }
+        assert_eq!(system.joypad.held(), 0);
+    }
+
+    #[test]
+    fn test_set_buttons() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        system.set_buttons(Buttons::START | Buttons::UP);
+        system.step();
+        assert_eq!(system.buttons(), Buttons::empty());
+        system.step_frame();
+        assert_eq!(system.buttons(), Buttons::START | Buttons::UP);
+        // recorded like any other input
+        system.start_movie(Movie::record(&system.mem.cartridge, ""));
+        system.set_buttons(Buttons::A);
+        system.step_frame();
+        let movie = system.movie.as_ref().unwrap();
+        assert_eq!(movie.inputs, vec![(Buttons::START | Buttons::UP).bits(), Buttons::A.bits()]);
+    }
+
+    #[test]
+    fn test_input_provider() {
+        // presses A on every other frame
+        struct Alternating(u64);
+        impl InputProvider for Alternating {
+            fn poll(&mut self) -> Buttons {
+                self.0 += 1;
+                match self.0 % 2 {
+                    0 => Buttons::A,
+                    _ => Buttons::empty(),
                 }
             }
-            self.ppu.canvas.present();
         }
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        system.input = Some(Box::new(Alternating(0)));
+        let held: Vec<Buttons> = (0..4)
+            .map(|_| {
+                system.step_frame();
+                system.buttons()
+            })
+            .collect();
+        assert_eq!(held, [Buttons::empty(), Buttons::A, Buttons::empty(), Buttons::A]);
+    }
+
+    #[test]
+    fn test_observe() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        system.watch([0xc000, 0xff44]);
+        system.mem.write(0xc000, 0x5a);
+        system.set_buttons(Buttons::B);
+        system.step_frame();
+        let observation = system.observe();
+        assert_eq!(observation.frame, 1);
+        assert_eq!(observation.buttons, Buttons::B);
+        assert_eq!(observation.ram, vec![0x5a, system.mem.peek(0xff44)]);
+        let shade = |x: usize, y: usize| system.framebuffer()[(y * SCREEN_WIDTH + x) * 3];
+        assert_eq!(observation.pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(observation.pixels[SCREEN_WIDTH * 10 + 20], shade(20, 10));
+        let mut reused = Observation::default();
+        system.observe_into(&mut reused);
+        assert_eq!(reused, observation);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut rom = vec![0; 0x8000];
+        // RETI from the vblank handler
+        rom[0x40] = 0xd9;
+        // JP $0150
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        rom[0x150..0x15e].copy_from_slice(&[
+            0x3e, 0x01, // LD A, $01
+            0xe0, 0xff, // LDH [IE], A
+            0x3e, 0x42, // LD A, $42
+            0xe0, 0x01, // LDH [SB], A
+            0x3e, 0x81, // LD A, $81
+            0xe0, 0x02, // LDH [SC], A
+            0xfb, // EI
+            0x00, // NOP
+        ]);
+        // JP $015D
+        rom[0x15e..0x161].copy_from_slice(&[0xc3, 0x5d, 0x01]);
+        let mut system = System::headless(rom).unwrap();
+        system.breakpoints.insert(0x015c);
+        let events = system.subscribe();
+        for _ in 0..3 {
+            system.step_frame();
+        }
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events.iter().filter(|e| **e == CoreEvent::Breakpoint(0x015c)).count(), 1);
+        assert!(events.contains(&CoreEvent::SerialByte { sent: 0x42, received: 0xff }));
+        assert!(events.contains(&CoreEvent::VBlank));
+        let frames: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                CoreEvent::FrameDone(frame) => Some(*frame),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frames, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_crash_report() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[
+            0x00, // NOP
+            0xd3, // not an instruction
+            0x00,
+            0x00,
+        ]);
+        let mut system = System::headless(rom).unwrap();
+        system.mem.write(0xfffe, 0x12);
+        let report = system.try_step_frame().unwrap_err();
+        assert!(matches!(report.error, CpuError::Decode(0xd3, _)));
+        assert_eq!(report.registers.pc, Some(0x101));
+        assert_eq!(report.history, vec![(0x100, 0x00), (0x101, 0xd3)]);
+        assert_eq!(report.stack[0], 0x12);
+        assert_eq!(report.stack.len(), CRASH_STACK_BYTES);
+        let text = report.to_string();
+        assert!(text.starts_with("The cpu crashed in frame 0: Couldn't decode opcode 0xd3"));
+        assert!(text.contains("Stack at FFFE: 12"));
+        // nothing is recorded at accuracy level fast
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0xd3;
+        let mut system = System::headless(rom).unwrap();
+        system.set_accuracy(Accuracy::Fast);
+        assert!(system.try_step().unwrap_err().history.is_empty());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[
+            0xf3, // DI
+            0xc3, 0x01, 0x01, // JP $0101
+        ]);
+        let mut system = System::headless(rom).unwrap();
+        let mut watchdog = Watchdog::new(2);
+        watchdog.pause = true;
+        system.watchdog = Some(watchdog);
+        let events = system.subscribe();
+        for _ in 0..4 {
+            system.step_frame();
+        }
+        let stalls: Vec<_> = events
+            .try_iter()
+            .filter(|event| matches!(event, CoreEvent::Stalled(_)))
+            .collect();
+        let stall = Stall { low: 0x100, high: 0x101, frames: 2 };
+        assert_eq!(stalls, vec![CoreEvent::Stalled(stall)]);
+        assert!(system.paused);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_bug_bundle() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        skip_lcd_off(&mut system);
+        system.step_frame();
+        assert_eq!(system.trace.entries().count(), BUNDLE_TRACE_LENGTH);
+        let bundle = system.bug_bundle();
+        for name in ["state.gbs", "trace.log", "frame.png", "config.json"] {
+            assert!(bundle.windows(name.len()).any(|window| window == name.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_instruction_stream() {
+        let mut rom = vec![0; 0xffff];
+        // NOP, JP $0150, LD A, $42
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x150..0x152].copy_from_slice(&[0x3e, 0x42]);
+        let mut system = System::headless(rom).unwrap();
+        let executed: Vec<_> = system.instruction_stream().take(3).collect();
+        assert_eq!(
+            executed,
+            vec![
+                (0x100, DecodedInstruction::new(Mnemonic::NOP, &[0x00]), 1),
+                (0x101, DecodedInstruction::new(Mnemonic::JP, &[0xc3, 0x50, 0x01]), 4),
+                (0x150, DecodedInstruction::new(Mnemonic::LD, &[0x3e, 0x42]), 2),
+            ]
+        );
+        assert_eq!(system.cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn test_stat_reads() {
+        use crate::memory::registers::{LCDC, LYC, STAT};
+        let mut rom = vec![0; 0xffff];
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        // LDH A, [STAT], JR -4
+        rom[0x150..0x154].copy_from_slice(&[0xf0, 0x41, 0x18, 0xfc]);
+        let mut system = System::headless(rom).unwrap();
+        system.mem.write(LYC, 0x40);
+        // the cpu can't change the mode or LY=LYC bits
+        system.mem.write(STAT, 0x07);
+        let mut modes = [false; 4];
+        for _ in 0..20000 {
+            let stat = system.mem.read(STAT);
+            let coincidence = system.mem.read(LY) == 0x40;
+            let ((pc, ..), _) = system.step_instruction();
+            if pc == 0x150 {
+                let a = system.cpu.registers.a;
+                assert_eq!(a, stat);
+                assert_eq!(a & 0x04 != 0, coincidence);
+                modes[(a & 0x03) as usize] = true;
+            }
+        }
+        assert_eq!(modes, [true; 4]);
+        // with the LCD off the mode reads 0
+        system.mem.write(LCDC, 0x00);
+        system.step_instruction();
+        system.step_instruction();
+        assert_eq!(system.cpu.registers.a & 0x03, 0);
+    }
+
+    #[test]
+    fn test_rom_size_mismatch() {
+        let mut system = System::headless(vec![0; 0xc000]).unwrap();
+        let events = system.subscribe();
+        system.step();
+        system.step();
+        let mismatches: Vec<_> = events
+            .try_iter()
+            .filter(|event| matches!(event, CoreEvent::RomSizeMismatch(_)))
+            .collect();
+        let mismatch = RomSizeMismatch { banks: 2, found: 0xc000 };
+        assert_eq!(mismatches, vec![CoreEvent::RomSizeMismatch(mismatch)]);
+        assert!(system.osd.messages().any(|message| message.contains("overdump")));
+    }
+
+    #[test]
+    fn test_compare_trace() {
+        let rom = || {
+            let mut rom = vec![0; 0x8000];
+            rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+            // INC A, INC A, JR -4
+            rom[0x150..0x154].copy_from_slice(&[0x3c, 0x3c, 0x18, 0xfc]);
+            rom
+        };
+        let mut system = System::headless(rom()).unwrap();
+        for _ in 0..8 {
+            system.step();
+        }
+        let mut entries: Vec<TraceEntry> = system.trace.entries().copied().collect();
+        // a reference that starts earlier, in the boot ROM, is lined up on its first match
+        let boot = TraceEntry { pc: Some(0xfe), ..Default::default() };
+        let log = |entries: &[TraceEntry]| {
+            let lines: String = entries.iter().map(|entry| format!("{entry}\n")).collect();
+            format!("{boot}\n{lines}")
+        };
+        let mut system = System::headless(rom()).unwrap();
+        assert_eq!(system.compare_trace(&Trace::parse(&log(&entries))).unwrap(), None);
+
+        entries[4].a = entries[4].a.map(|a| a ^ 0x80);
+        let mut system = System::headless(rom()).unwrap();
+        let divergence = system.compare_trace(&Trace::parse(&log(&entries))).unwrap();
+        assert_eq!(divergence, Some(Divergence { a: 4, b: 5, fields: vec!["A"] }));
+        assert_eq!(system.cpu.registers.pc, entries[4].pc.unwrap());
+    }
+
+    #[test]
+    fn test_div() {
+        let mut rom = vec![0; 0x8000];
+        // JP $0150
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        // JR -2
+        rom[0x150..0x152].copy_from_slice(&[0x18, 0xfe]);
+        let mut system = System::headless(rom).unwrap();
+        let start = (system.mem.read(DIV) as u64) << 8;
+        system.step_scanlines(10);
+        assert_eq!(system.mem.read(DIV), ((start + system.clock.t_cycles) >> 8) as u8);
+        // a length about to run out
+        let channel = &mut system.apu.channels[1];
+        (channel.enabled, channel.dac) = (true, true);
+        (channel.length, channel.length_enabled) = (1, true);
+        // clearing the divider only steps the frame sequencer when it makes DIV-APU fall, twice
+        // so one of the steps clocks the length
+        for divider in [0x0000, 0x0000] {
+            system.clock.divider = divider;
+            system.mem.write(DIV, 0x42);
+            system.step();
+        }
+        assert!(system.apu.channels[1].enabled);
+        for divider in [0x1000, 0x1000] {
+            system.clock.divider = divider;
+            system.mem.write(DIV, 0x42);
+            system.step();
+        }
+        assert!(!system.apu.channels[1].enabled);
+        assert_eq!(system.mem.read(DIV), 0x00);
+    }
+
+    #[test]
+    fn test_verify_rom_resizes_ram() {
+        use crate::gamedb::GameEntry;
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        let mut system = System::headless(rom).unwrap();
+        assert!(system.mem.ram.is_empty());
+        // the header claims ROM only without RAM, the game is MBC1 with 8KiB of it
+        let db = GameDb {
+            entries: vec![GameEntry {
+                crc32: format!("{:08x}", system.mem.cartridge.crc32()),
+                title: "Bootleg".into(),
+                cartridge_type: 0x03,
+                ram_size: 0x02,
+            }],
+        };
+        assert_eq!(system.verify_rom(&db).unwrap().len(), 2);
+        assert_eq!(system.mem.ram.len(), 0x2000);
+    }
+
+    #[test]
+    fn test_lcd_off_frames() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        rom[0x150..0x155].copy_from_slice(&[
+            0xaf, // XOR A
+            0xe0, 0x40, // LDH [LCDC], A
+            0x18, 0xfe, // JR -2
+        ]);
+        let mut system = System::headless(rom).unwrap();
+        system.step_frame();
+        let start = system.cycles();
+        system.step_frame();
+        // a frame still takes as long as one with the LCD on
+        let elapsed = system.cycles() - start;
+        assert!(elapsed.abs_diff(FRAME_SCANLINES * SCANLINE_CYCLES) <= 4, "{elapsed}");
+        assert_eq!(system.frame, 2);
+    }
+
+    #[test]
+    fn test_timer() {
+        use crate::memory::registers::{IF, TIMA, TMA};
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        // JR -2
+        rom[0x150..0x152].copy_from_slice(&[0x18, 0xfe]);
+        let mut system = System::headless(rom).unwrap();
+        system.mem.write(TMA, 0xf0);
+        system.mem.write(TIMA, 0xfe);
+        // 262144 Hz, TIMA counts every 4 M-cycles
+        system.mem.write(TAC, 0x05);
+        system.mem.io.set(IF, 0xe0);
+        let start = system.cycles();
+        while system.cycles() < start + 12 {
+            system.step();
+        }
+        // it overflowed into TMA and requested the interrupt
+        assert!((0xf0..0xf3).contains(&system.mem.io.get(TIMA)));
+        assert_ne!(system.mem.io.get(IF) & Interrupt::TIMER.bits(), 0);
     }
 }
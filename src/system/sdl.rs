@@ -0,0 +1,425 @@
+//! The SDL frontend: a window, audio output and keyboard input
+use std::{sync::atomic::Ordering, time::Duration};
+
+use sdl3::{
+    audio::{AudioFormat, AudioSpec, AudioStreamWithCallback},
+    event::Event,
+    keyboard::{Keycode, Mod},
+    pixels::{Color, PixelFormat},
+    render::{Canvas, FRect, Texture, TextureCreator},
+    sys::pixels::SDL_PIXELFORMAT_RGB24,
+    video::{Window, WindowContext},
+};
+
+use super::{LinkedSystems, System};
+use crate::{
+    audio::AudioQueue,
+    cheats::Comparison,
+    display::{SCANLINE_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH, Screen, setup_ctx},
+    errors::VideoError,
+    io::joypad::{Action, Button, ButtonEdge, Direction},
+    memory_editor::{ROW_BYTES, ROWS},
+    video::{FramePacer, VideoBackend},
+};
+
+/// Events are polled and the buttons pressed in between scheduled once per frame, or every
+/// frame's worth of scanlines while the LCD is off and no frames are completed
+const POLL_SCANLINES: u64 = 154;
+
+/// SDL's clock, the one event timestamps are taken from
+fn ticks_ns() -> u64 {
+    unsafe { sdl3::sys::timer::SDL_GetTicksNS() }
+}
+
+/// An SDL window's canvas, the window itself and its events belong to the run loop
+pub struct SdlVideo<'a> {
+    canvas: &'a mut Canvas<Window>,
+    texture: Texture<'a>,
+}
+
+impl<'a> SdlVideo<'a> {
+    pub fn new(
+        canvas: &'a mut Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<Self, VideoError> {
+        let format = PixelFormat::try_from(SDL_PIXELFORMAT_RGB24)
+            .map_err(|e| VideoError::Window(e.to_string()))?;
+        let texture = texture_creator
+            .create_texture_streaming(format, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|e| VideoError::Window(e.to_string()))?;
+        Ok(Self { canvas, texture })
+    }
+}
+
+impl VideoBackend for SdlVideo<'_> {
+    fn present(&mut self, frame: &[u8]) -> Result<bool, VideoError> {
+        let present = |e: sdl3::Error| VideoError::Present(e.to_string());
+        self.texture
+            .update(None, frame, SCREEN_WIDTH * 3)
+            .map_err(|e| VideoError::Present(e.to_string()))?;
+        let rect = FRect::new(0.0, 0.0, SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32);
+        self.canvas.copy(&self.texture, None, Some(rect)).map_err(present)?;
+        self.canvas.present();
+        Ok(true)
+    }
+}
+
+impl System {
+    fn present_frame(&mut self, video: &mut SdlVideo, frame: &mut [u8]) {
+        self.osd.underruns = Some(self.audio.underruns());
+        self.composite_frame(frame);
+        video.present(frame).unwrap();
+    }
+
+    /// Open a stereo playback stream fed from `self.audio`
+    fn open_audio(&self) -> Result<AudioStreamWithCallback<AudioQueue>, sdl3::Error> {
+        let config = self.audio_config;
+        // the hint has to be set before the device is opened
+        sdl3::hint::set("SDL_AUDIO_DEVICE_SAMPLE_FRAMES", &config.buffer_size.to_string());
+        let spec = AudioSpec {
+            freq: Some(config.sample_rate as i32),
+            channels: Some(2),
+            format: Some(AudioFormat::f32_sys()),
+        };
+        let stream = sdl3::init()?.audio()?.open_playback_stream(&spec, self.audio.clone())?;
+        stream.resume()?;
+        Ok(stream)
+    }
+
+    /// Arrows and page up/down move the cursor, Tab jumps to the next bookmark, hex digits
+    /// edit the byte under the cursor and Space freezes it. F2 switches to the cheat search,
+    /// where the arrows keep bytes that went up, down, stayed the same (left) or changed (right),
+    /// Enter starts over and Space freezes the matches. Returns whether the key was used.
+    fn memory_editor_key(&mut self, keycode: Keycode) -> bool {
+        let page = (ROWS * ROW_BYTES) as i32;
+        let editor = &mut self.memory_editor;
+        if editor.search_view {
+            match keycode {
+                Keycode::Up => editor.filter(&self.mem, Comparison::Increased),
+                Keycode::Down => editor.filter(&self.mem, Comparison::Decreased),
+                Keycode::Left => editor.filter(&self.mem, Comparison::Equal),
+                Keycode::Right => editor.filter(&self.mem, Comparison::Changed),
+                Keycode::Return => editor.restart_search(&self.mem),
+                Keycode::Space => match editor.freeze_matches(&self.mem) {
+                    0 => self.osd.message("Too many matches to freeze"),
+                    frozen => self.osd.message(format!("Froze {frozen} addresses")),
+                },
+                Keycode::F2 => editor.toggle_search(&self.mem),
+                _ => match hex_key(keycode) {
+                    Some(digit) => editor.input(digit, &mut self.mem),
+                    None => return false,
+                },
+            }
+            return true;
+        }
+        match keycode {
+            Keycode::F2 => editor.toggle_search(&self.mem),
+            Keycode::Up => editor.move_cursor(-(ROW_BYTES as i32)),
+            Keycode::Down => editor.move_cursor(ROW_BYTES as i32),
+            Keycode::Left => editor.move_cursor(-1),
+            Keycode::Right => editor.move_cursor(1),
+            Keycode::PageUp => editor.move_cursor(-page),
+            Keycode::PageDown => editor.move_cursor(page),
+            Keycode::Tab => editor.next_bookmark(),
+            Keycode::Space => editor.toggle_freeze(&self.mem),
+            _ => match hex_key(keycode) {
+                Some(digit) => editor.input(digit, &mut self.mem),
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// Open a window and play until it's closed
+    pub fn run(&mut self) {
+        let mut screen = Screen::new().unwrap();
+        self.run_on(&mut screen);
+    }
+
+    /// Play in a window that's already open, such as the launcher's
+    pub fn run_on(&mut self, screen: &mut Screen) {
+        let Screen { canvas, event_pump } = screen;
+        canvas.set_draw_color(Color::WHITE);
+        canvas.clear();
+        let texture_creator = canvas.texture_creator();
+        let mut video = SdlVideo::new(canvas, &texture_creator).unwrap();
+        let mut frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        // dropping the stream closes the device
+        let _stream = match self.open_audio() {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                self.osd.message(format!("No audio: {e}"));
+                None
+            }
+        };
+        let mut polled_at = ticks_ns();
+        let mut pacer = FramePacer::default();
+        'running: loop {
+            if self.quit.load(Ordering::Relaxed) {
+                break 'running;
+            }
+            if !self.paused || self.frame_advance {
+                // the ppu entered vblank, composite the osd and present the frame, once per frame
+                if self.step_scanlines(POLL_SCANLINES) {
+                    self.frame_advance = false;
+                    self.osd.frame();
+                    self.present_frame(&mut video, &mut frame);
+                    // the canvas doesn't wait for vsync, hold the game to its own frame rate
+                    if !self.fast_forward {
+                        let idle = pacer.wait();
+                        self.add_idle(idle);
+                    }
+                }
+            } else {
+                // nothing is being emulated, keep the osd up to date without spinning
+                self.osd.frame();
+                self.present_frame(&mut video, &mut frame);
+                std::thread::sleep(Duration::from_millis(16));
+                self.add_idle(Duration::from_millis(16));
+            }
+            // button edges land in the next batch as far into it as they happened since the last
+            // poll, so a quick tap still lasts as long as it did on the keyboard
+            let (start, now, next) = (polled_at, ticks_ns(), self.cycles());
+            let edge_cycle = move |timestamp: u64| {
+                let into = timestamp.clamp(start, now) - start;
+                next + into * POLL_SCANLINES * SCANLINE_CYCLES / (now - start).max(1)
+            };
+            polled_at = now;
+            let events: Vec<Event> = event_pump.poll_iter().collect();
+            for event in events {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F1),
+                        repeat: false,
+                        ..
+                    } => self.memory_editor.toggle(),
+                    // the memory editor takes the keyboard while it's open
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } if self.memory_editor.open && self.memory_editor_key(keycode) => {}
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F),
+                        ..
+                    } => self.osd.show_fps = !self.osd.show_fps,
+                    // fast forward while space is held
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Space),
+                        ..
+                    } => self.set_fast_forward(true),
+                    Event::KeyUp {
+                        keycode: Some(Keycode::Space),
+                        ..
+                    } => self.set_fast_forward(false),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::G),
+                        repeat: false,
+                        ..
+                    } => self.toggle_frame_blend(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::P),
+                        repeat: false,
+                        ..
+                    } => self.toggle_pause(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::N),
+                        ..
+                    } => self.frame_advance(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        ..
+                    } => self.save_slot(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F8),
+                        ..
+                    } => self.load_slot(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F12),
+                        repeat: false,
+                        ..
+                    } => self.save_bug_bundle(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::T),
+                        repeat: false,
+                        ..
+                    } => self.take_over_movie(),
+                    Event::KeyDown {
+                        timestamp,
+                        keycode: Some(keycode),
+                        keymod,
+                        repeat,
+                        ..
+                    } => {
+                        if let Some(button) = button_key(keycode) {
+                            // shift + button toggles autofire instead of pressing it
+                            if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                if !repeat {
+                                    self.toggle_turbo(button);
+                                }
+                            } else if self.live_input() {
+                                let cycle = edge_cycle(timestamp);
+                                self.schedule_button(ButtonEdge { button, pressed: true, cycle });
+                            }
+                        } else if let Some(slot) = slot_key(keycode) {
+                            self.select_slot(slot);
+                        }
+                    }
+                    Event::KeyUp {
+                        timestamp,
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(button) = button_key(keycode) {
+                            if self.live_input() {
+                                let cycle = edge_cycle(timestamp);
+                                self.schedule_button(ButtonEdge { button, pressed: false, cycle });
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl LinkedSystems {
+    /// Both screens side by side in one window, only the left system is heard. The right
+    /// system takes the usual keys, the left one is played with `left_button_key`.
+    pub fn run(&mut self) {
+        let width = 2 * SCREEN_WIDTH as u32;
+        let (mut canvas, mut event_pump) = setup_ctx(width, SCREEN_HEIGHT as u32).unwrap();
+        let texture_creator = canvas.texture_creator();
+        let format = PixelFormat::try_from(SDL_PIXELFORMAT_RGB24).unwrap();
+        let texture = || {
+            texture_creator
+                .create_texture_streaming(format, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+                .unwrap()
+        };
+        let mut textures = [texture(), texture()];
+        let _stream = match self.left.open_audio() {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                self.left.osd.message(format!("No audio: {e}"));
+                None
+            }
+        };
+        let mut pacer = FramePacer::default();
+        'running: loop {
+            if self.left.quit.load(Ordering::Relaxed) || self.right.quit.load(Ordering::Relaxed) {
+                break 'running;
+            }
+            self.step_frame();
+            let systems = [&mut self.left, &mut self.right];
+            for (index, (system, texture)) in systems.into_iter().zip(&mut textures).enumerate() {
+                system.osd.frame();
+                texture
+                    .with_lock(None, |buffer: &mut [u8], _: usize| system.composite_frame(buffer))
+                    .unwrap();
+                let x = (index * SCREEN_WIDTH) as f32;
+                let rect = FRect::new(x, 0.0, SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32);
+                canvas.copy(texture, None, Some(rect)).unwrap();
+            }
+            canvas.present();
+            let idle = pacer.wait();
+            self.left.add_idle(idle);
+            self.right.add_idle(idle);
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(button) = left_button_key(keycode) {
+                            self.left.joypad.press(button);
+                        } else if let Some(button) = button_key(keycode) {
+                            self.right.joypad.press(button);
+                        }
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(button) = left_button_key(keycode) {
+                            self.left.joypad.release(button);
+                        } else if let Some(button) = button_key(keycode) {
+                            self.right.joypad.release(button);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// 0-9 and A-F as the digit they type
+fn hex_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::A => Some(0xa),
+        Keycode::B => Some(0xb),
+        Keycode::C => Some(0xc),
+        Keycode::D => Some(0xd),
+        Keycode::E => Some(0xe),
+        Keycode::F => Some(0xf),
+        _ => slot_key(keycode),
+    }
+}
+
+/// Arrow keys are the d-pad, X and Z are A and B, Enter and Backspace are Start and Select
+fn button_key(keycode: Keycode) -> Option<Button> {
+    match keycode {
+        Keycode::Up => Some(Button::Direction(Direction::Up)),
+        Keycode::Down => Some(Button::Direction(Direction::Down)),
+        Keycode::Left => Some(Button::Direction(Direction::Left)),
+        Keycode::Right => Some(Button::Direction(Direction::Right)),
+        Keycode::X => Some(Button::Action(Action::A)),
+        Keycode::Z => Some(Button::Action(Action::B)),
+        Keycode::Return => Some(Button::Action(Action::Start)),
+        Keycode::Backspace => Some(Button::Action(Action::Select)),
+        _ => None,
+    }
+}
+
+/// The left player of a linked session: WASD is the d-pad, E and Q are A and B, 1 and Tab are
+/// Start and Select
+fn left_button_key(keycode: Keycode) -> Option<Button> {
+    match keycode {
+        Keycode::W => Some(Button::Direction(Direction::Up)),
+        Keycode::S => Some(Button::Direction(Direction::Down)),
+        Keycode::A => Some(Button::Direction(Direction::Left)),
+        Keycode::D => Some(Button::Direction(Direction::Right)),
+        Keycode::E => Some(Button::Action(Action::A)),
+        Keycode::Q => Some(Button::Action(Action::B)),
+        Keycode::_1 => Some(Button::Action(Action::Start)),
+        Keycode::Tab => Some(Button::Action(Action::Select)),
+        _ => None,
+    }
+}
+
+/// Number keys select the matching save state slot
+fn slot_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::_0 => Some(0),
+        Keycode::_1 => Some(1),
+        Keycode::_2 => Some(2),
+        Keycode::_3 => Some(3),
+        Keycode::_4 => Some(4),
+        Keycode::_5 => Some(5),
+        Keycode::_6 => Some(6),
+        Keycode::_7 => Some(7),
+        Keycode::_8 => Some(8),
+        Keycode::_9 => Some(9),
+        _ => None,
+    }
+}
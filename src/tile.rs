@@ -0,0 +1,248 @@
+use crate::{decode_tile, memory::regions::VRAM_START};
+
+/// Bytes per tile, 8 rows of 2 bytes
+pub const TILE_SIZE: usize = 16;
+/// Tiles in the tile data area, 0x8000-0x97ff
+pub const TILE_COUNT: usize = 384;
+
+/// An entry of a tile map or the tile of an object, which tile it points at depends on the
+/// addressing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TileId(pub u8);
+
+/// LCDC.4, where the background and window look for their tiles. Objects always use `Unsigned`.
+/// Read more: https://gbdev.io/pandocs/Tile_Data.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileAddressing {
+    /// "$8000 method", ids 0-255 index from 0x8000
+    Unsigned,
+    /// "$8800 method", ids are signed and index from 0x9000, so 128-255 land in 0x8800-0x8fff
+    Signed,
+}
+
+impl TileAddressing {
+    /// Address of the first byte of `id`
+    pub fn address(self, id: TileId) -> usize {
+        match self {
+            Self::Unsigned => 0x8000 + id.0 as usize * TILE_SIZE,
+            Self::Signed => (0x9000 + id.0 as i8 as isize * TILE_SIZE as isize) as usize,
+        }
+    }
+}
+
+/// LCDC.3 and LCDC.6 pick one of the two 32x32 tile maps
+/// Read more: https://gbdev.io/pandocs/Tile_Maps.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileMap {
+    Low,
+    High,
+}
+
+impl TileMap {
+    pub fn base(self) -> usize {
+        match self {
+            Self::Low => 0x9800,
+            Self::High => 0x9c00,
+        }
+    }
+
+    /// Address of the entry for the tile at column `x` and row `y`, both wrap at 32
+    pub fn address(self, x: usize, y: usize) -> usize {
+        self.base() + (y % 32) * 32 + x % 32
+    }
+}
+
+/// A decoded 8x8 tile, already shaded through `PALETTE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    /// `rows[y][x]` with x = 0 being the rightmost pixel, the way `decode_tile` lays them out
+    pub rows: [[u8; 8]; 8],
+}
+
+impl Tile {
+    pub fn decode(bytes: &[u8]) -> Self {
+        Self {
+            rows: decode_tile(bytes),
+        }
+    }
+}
+
+/// Decoded copies of every tile in VRAM. Writes mark their tile dirty and `sync` decodes only
+/// the dirty ones again, instead of decoding 20 tiles for a single row of each every scanline.
+#[derive(Debug, Clone)]
+pub struct TileCache {
+    tiles: Vec<Tile>,
+    // one bit per tile
+    dirty: [u64; TILE_COUNT / 64],
+}
+
+impl Default for TileCache {
+    /// Everything starts out dirty so the first `sync` decodes the whole tile data area
+    fn default() -> Self {
+        Self {
+            tiles: vec![Tile { rows: [[0; 8]; 8] }; TILE_COUNT],
+            dirty: [u64::MAX; TILE_COUNT / 64],
+        }
+    }
+}
+
+impl TileCache {
+    /// The tile `addr` belongs to, if it's in the tile data area
+    fn index(addr: usize) -> Option<usize> {
+        let index = addr.checked_sub(VRAM_START)? / TILE_SIZE;
+        (index < TILE_COUNT).then_some(index)
+    }
+
+    fn is_dirty(&self, index: usize) -> bool {
+        self.dirty[index / 64] & 1 << (index % 64) != 0
+    }
+
+    /// Call for every write to `addr`, anything outside of the tile data area is ignored
+    pub fn mark(&mut self, addr: usize) {
+        if let Some(index) = Self::index(addr) {
+            self.dirty[index / 64] |= 1 << (index % 64);
+        }
+    }
+
+    pub fn mark_all(&mut self) {
+        self.dirty = [u64::MAX; TILE_COUNT / 64];
+    }
+
+    /// Decode the dirty tiles again from the whole address space in `block`
+    pub fn sync(&mut self, block: &[u8]) {
+        for (word, bits) in self.dirty.iter_mut().enumerate() {
+            while *bits != 0 {
+                let index = word * 64 + bits.trailing_zeros() as usize;
+                let start = VRAM_START + index * TILE_SIZE;
+                self.tiles[index] = Tile::decode(&block[start..start + TILE_SIZE]);
+                *bits &= *bits - 1;
+            }
+        }
+    }
+
+    /// The tile starting at `addr`, `None` if it has changed since the last `sync`
+    pub fn get(&self, addr: usize) -> Option<&Tile> {
+        let index = Self::index(addr)?;
+        (!self.is_dirty(index)).then(|| &self.tiles[index])
+    }
+}
+
+/// VRAM written since the ppu last looked, by tile and by row of either tile map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VramWrites {
+    tiles: [u64; TILE_COUNT / 64],
+    // 32 rows of the low map followed by 32 rows of the high one
+    map_rows: u64,
+}
+
+impl VramWrites {
+    pub fn all() -> Self {
+        Self {
+            tiles: [u64::MAX; TILE_COUNT / 64],
+            map_rows: u64::MAX,
+        }
+    }
+
+    /// Call for every write to `addr`, anything outside of vram is ignored
+    pub fn mark(&mut self, addr: usize) {
+        if let Some(index) = TileCache::index(addr) {
+            self.tiles[index / 64] |= 1 << (index % 64);
+        } else if let Some(row) = addr.checked_sub(TileMap::Low.base()).map(|offset| offset / 32) {
+            if row < 64 {
+                self.map_rows |= 1 << row;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the tile starting at `addr` was written
+    pub fn tile(&self, addr: usize) -> bool {
+        TileCache::index(addr).is_some_and(|index| self.tiles[index / 64] & 1 << (index % 64) != 0)
+    }
+
+    /// Whether any entry of tile row `row` of `map` was written
+    pub fn map_row(&self, map: TileMap, row: usize) -> bool {
+        let row = (map.base() - TileMap::Low.base()) / 32 + row % 32;
+        self.map_rows & 1 << row != 0
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::{cartridge::Cartridge, memory::Memory};
+
+    #[test]
+    fn test_addressing() {
+        assert_eq!(TileAddressing::Unsigned.address(TileId(0)), 0x8000);
+        assert_eq!(TileAddressing::Unsigned.address(TileId(255)), 0x8ff0);
+        assert_eq!(TileAddressing::Signed.address(TileId(0)), 0x9000);
+        assert_eq!(TileAddressing::Signed.address(TileId(127)), 0x97f0);
+        assert_eq!(TileAddressing::Signed.address(TileId(128)), 0x8800);
+        assert_eq!(TileAddressing::Signed.address(TileId(255)), 0x8ff0);
+        assert_eq!(TileMap::High.address(33, 1), 0x9c21);
+    }
+
+    #[test]
+    fn test_tile_at() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        mem.block[TileMap::Low.address(3, 2)] = 0x10;
+        // the first row of tile 0x10 in signed mode is fully set
+        mem.block[0x9100] = 0xff;
+        mem.block[0x9101] = 0xff;
+        assert_eq!(mem.tile_id(TileMap::Low, 3, 2), TileId(0x10));
+        let tile = mem.tile_at(TileMap::Low, 3, 2, TileAddressing::Signed);
+        assert_eq!(tile.rows[0], [crate::PALETTE[3]; 8]);
+        assert_eq!(tile.rows[1], [crate::PALETTE[0]; 8]);
+        let tile = mem.tile_at(TileMap::Low, 3, 2, TileAddressing::Unsigned);
+        assert_eq!(tile.rows[0], [crate::PALETTE[0]; 8]);
+    }
+
+    #[test]
+    fn test_decode_rows() {
+        // the lookup table against decoding a bit at a time
+        let bytes: Vec<u8> = (0..16).map(|i| (i * 0x35 + 0x9c) as u8).collect();
+        let tile = decode_tile(&bytes);
+        for (i, row) in tile.iter().enumerate() {
+            for (j, shade) in row.iter().enumerate() {
+                let color = (bytes[i * 2] >> j) & 1 | ((bytes[i * 2 + 1] >> j) & 1) << 1;
+                assert_eq!(*shade, crate::PALETTE[color as usize]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0xffff]).unwrap());
+        let addr = TileAddressing::Unsigned.address(TileId(3));
+        // nothing is cached before the first sync
+        assert!(mem.tiles.get(addr).is_none());
+        mem.sync_tiles();
+        assert_eq!(mem.tiles.get(addr).unwrap().rows[7], [crate::PALETTE[0]; 8]);
+        mem.write(addr + 14, 0xff);
+        assert!(mem.tiles.get(addr).is_none());
+        // a dirty tile is still read straight from vram
+        assert_eq!(mem.tile(TileId(3), TileAddressing::Unsigned).rows[7], [crate::PALETTE[1]; 8]);
+        mem.sync_tiles();
+        assert_eq!(mem.tiles.get(addr).unwrap().rows[7], [crate::PALETTE[1]; 8]);
+        // the neighbours weren't touched
+        assert!(mem.tiles.get(addr + TILE_SIZE).is_some());
+        assert!(mem.tiles.get(0x9800).is_none());
+    }
+
+    #[test]
+    fn test_vram_writes() {
+        let mut writes = VramWrites::default();
+        writes.mark(0x8012);
+        writes.mark(TileMap::High.address(5, 31));
+        writes.mark(0xa000);
+        assert!(writes.tile(0x8010));
+        assert!(!writes.tile(0x8000));
+        assert!(writes.map_row(TileMap::High, 31));
+        assert!(!writes.map_row(TileMap::Low, 31));
+        assert!(VramWrites::all().map_row(TileMap::Low, 0));
+        assert!(!writes.is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::VecDeque;
+
+use crate::{banked::BankedAddr, cpu::Cpu, memory::Memory};
+
+/// Registers of one executed instruction in a trace log, fields missing from a log format are `None`
+/// Supported formats:
+/// - Gameboy Doctor: `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+/// - BGB: `A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100`
+/// - SameBoy: `AF = $01B0 BC = $0013 DE = $00D8 HL = $014D SP = $FFFE PC = $0100`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub a: Option<u8>,
+    pub f: Option<u8>,
+    pub b: Option<u8>,
+    pub c: Option<u8>,
+    pub d: Option<u8>,
+    pub e: Option<u8>,
+    pub h: Option<u8>,
+    pub l: Option<u8>,
+    pub sp: Option<u16>,
+    pub pc: Option<u16>,
+    /// The bank pc points into, only gbr's own logs record it: `PC:01:4000`
+    pub bank: Option<u16>,
+}
+
+impl TraceEntry {
+    /// The state of the cpu before executing the instruction at pc
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        let flags = cpu.registers.flags;
+        let f = (flags.zero as u8) << 7
+            | (flags.subtraction as u8) << 6
+            | (flags.half_carry as u8) << 5
+            | (flags.carry as u8) << 4;
+        Self {
+            a: Some(cpu.registers.a),
+            f: Some(f),
+            b: Some(cpu.registers.b),
+            c: Some(cpu.registers.c),
+            d: Some(cpu.registers.d),
+            e: Some(cpu.registers.e),
+            h: Some(cpu.registers.h),
+            l: Some(cpu.registers.l),
+            sp: Some(cpu.registers.sp),
+            pc: Some(cpu.registers.pc),
+            bank: None,
+        }
+    }
+
+    /// Record which bank pc points into
+    pub fn with_bank(self, mem: &Memory) -> Self {
+        Self {
+            bank: self.pc.map(|pc| mem.banked(pc).bank),
+            ..self
+        }
+    }
+
+    /// Where the instruction was, if the log recorded the bank
+    pub fn location(&self) -> Option<BankedAddr> {
+        Some(BankedAddr::new(self.bank?, self.pc?))
+    }
+
+    /// A Gameboy Doctor line, which most reference emulators can be patched to produce
+    pub fn doctor_line(cpu: &Cpu, mem: &mut Memory) -> String {
+        let entry = Self::from_cpu(cpu);
+        let pc = cpu.registers.pc as usize;
+        let pcmem: Vec<String> = (0..4)
+            .map(|offset| format!("{:02X}", mem.read((pc + offset) & 0xffff)))
+            .collect();
+        format!("{entry} PCMEM:{}", pcmem.join(","))
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut entry = Self::default();
+        // `KEY = $VALUE` is turned into `KEY:VALUE` so every format tokenizes the same way
+        let line = line.replace(" = $", ":").replace(',', " ");
+        for token in line.split_whitespace() {
+            let Some((key, value)) = token.split_once(':') else {
+                continue;
+            };
+            let byte = || u8::from_str_radix(value, 16).ok();
+            let word = || u16::from_str_radix(value, 16).ok();
+            match key.to_ascii_uppercase().as_str() {
+                "A" => entry.a = byte(),
+                "F" => entry.f = byte().or_else(|| parse_flags(value)),
+                "B" => entry.b = byte(),
+                "C" => entry.c = byte(),
+                "D" => entry.d = byte(),
+                "E" => entry.e = byte(),
+                "H" => entry.h = byte(),
+                "L" => entry.l = byte(),
+                "AF" => (entry.a, entry.f) = split(word()),
+                "BC" => (entry.b, entry.c) = split(word()),
+                "DE" => (entry.d, entry.e) = split(word()),
+                "HL" => (entry.h, entry.l) = split(word()),
+                "SP" => entry.sp = word(),
+                "PC" => match value.parse::<BankedAddr>() {
+                    Ok(location) if value.contains(':') => {
+                        (entry.bank, entry.pc) = (Some(location.bank), Some(location.addr))
+                    }
+                    _ => entry.pc = word(),
+                },
+                _ => {}
+            }
+        }
+        entry.pc.map(|_| entry)
+    }
+
+    /// Fields that are present in both entries but differ
+    pub fn mismatches(&self, other: &Self) -> Vec<&'static str> {
+        let bytes = [
+            ("A", self.a, other.a),
+            ("F", self.f, other.f),
+            ("B", self.b, other.b),
+            ("C", self.c, other.c),
+            ("D", self.d, other.d),
+            ("E", self.e, other.e),
+            ("H", self.h, other.h),
+            ("L", self.l, other.l),
+        ];
+        let words = [
+            ("SP", self.sp, other.sp),
+            ("PC", self.pc, other.pc),
+            ("BANK", self.bank, other.bank),
+        ];
+        let bytes = bytes
+            .into_iter()
+            .filter(|(_, a, b)| a.is_some() && b.is_some() && a != b)
+            .map(|(name, ..)| name);
+        let words = words
+            .into_iter()
+            .filter(|(_, a, b)| a.is_some() && b.is_some() && a != b)
+            .map(|(name, ..)| name);
+        bytes.chain(words).collect()
+    }
+}
+
+impl std::fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let byte = |value: Option<u8>| value.map_or("--".to_string(), |v| format!("{v:02X}"));
+        let word = |value: Option<u16>| value.map_or("----".to_string(), |v| format!("{v:04X}"));
+        write!(
+            f,
+            "A:{} F:{} B:{} C:{} D:{} E:{} H:{} L:{} SP:{} PC:{}{}",
+            byte(self.a),
+            byte(self.f),
+            byte(self.b),
+            byte(self.c),
+            byte(self.d),
+            byte(self.e),
+            byte(self.h),
+            byte(self.l),
+            word(self.sp),
+            self.bank.map_or(String::new(), |bank| format!("{bank:02X}:")),
+            word(self.pc)
+        )
+    }
+}
+
+/// BGB prints flags as letters, e.g: `Z-HC`
+fn parse_flags(value: &str) -> Option<u8> {
+    if value.len() != 4 {
+        return None;
+    }
+    let mut f = 0;
+    for (i, flag) in value.chars().enumerate() {
+        match flag {
+            '-' => {}
+            'Z' | 'N' | 'H' | 'C' => f |= 0x80 >> i,
+            _ => return None,
+        }
+    }
+    Some(f)
+}
+
+fn split(word: Option<u16>) -> (Option<u8>, Option<u8>) {
+    match word {
+        Some(word) => (Some((word >> 8) as u8), Some(word as u8)),
+        None => (None, None),
+    }
+}
+
+/// The most recently executed instructions, oldest first
+#[derive(Debug, Clone)]
+pub struct TraceHistory {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl TraceHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    /// One line per instruction, in the format `Trace::parse` reads back
+    pub fn log(&self) -> String {
+        self.entries.iter().map(|entry| format!("{entry}\n")).collect()
+    }
+}
+
+/// A parsed trace, lines that don't contain a pc are skipped but their line numbers are kept
+pub struct Trace {
+    pub entries: Vec<(usize, TraceEntry)>,
+}
+
+impl Trace {
+    pub fn parse(log: &str) -> Self {
+        let entries = log
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| TraceEntry::parse(line).map(|entry| (i + 1, entry)))
+            .collect();
+        Self { entries }
+    }
+}
+
+/// The first point where two traces disagree, indices are into `Trace::entries`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub a: usize,
+    pub b: usize,
+    /// Mismatching registers, empty when one of the traces ended early
+    pub fields: Vec<&'static str>,
+}
+
+/// Align both traces on their first common entry and walk them in lockstep until they disagree.
+/// Aligning lets a trace that starts in the boot ROM be compared to one that starts at 0x0100.
+pub fn diff(a: &Trace, b: &Trace) -> Option<Divergence> {
+    let (start_a, start_b) = align(a, b)?;
+    let pairs = a.entries[start_a..].iter().zip(&b.entries[start_b..]);
+    for (offset, ((_, entry_a), (_, entry_b))) in pairs.enumerate() {
+        let fields = entry_a.mismatches(entry_b);
+        if !fields.is_empty() {
+            return Some(Divergence {
+                a: start_a + offset,
+                b: start_b + offset,
+                fields,
+            });
+        }
+    }
+    let (rest_a, rest_b) = (a.entries.len() - start_a, b.entries.len() - start_b);
+    if rest_a == rest_b {
+        return None;
+    }
+    let len = rest_a.min(rest_b);
+    Some(Divergence {
+        a: start_a + len,
+        b: start_b + len,
+        fields: vec![],
+    })
+}
+
+fn align(a: &Trace, b: &Trace) -> Option<(usize, usize)> {
+    let (_, first_a) = a.entries.first()?;
+    let (_, first_b) = b.entries.first()?;
+    if let Some(i) = b.entries.iter().position(|(_, e)| e.mismatches(first_a).is_empty()) {
+        return Some((0, i));
+    }
+    if let Some(i) = a.entries.iter().position(|(_, e)| e.mismatches(first_b).is_empty()) {
+        return Some((i, 0));
+    }
+    Some((0, 0))
+}
+
+mod tests {
+    use super::*;
+
+    const DOCTOR: &str = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02";
+
+    #[test]
+    fn test_parse_formats() {
+        let doctor = TraceEntry::parse(DOCTOR).unwrap();
+        let bgb = TraceEntry::parse("A:01 F:Z-HC BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100").unwrap();
+        let sameboy =
+            TraceEntry::parse("AF = $01B0, BC = $0013, DE = $00D8, HL = $014D, SP = $FFFE, PC = $0100")
+                .unwrap();
+        assert_eq!(doctor, bgb);
+        assert_eq!(doctor, sameboy);
+        assert_eq!(doctor.to_string(), DOCTOR[..DOCTOR.find(" PCMEM").unwrap()]);
+        assert_eq!(TraceEntry::parse("gbr trace"), None);
+    }
+
+    #[test]
+    fn test_from_cpu() {
+        let cpu = Cpu::default();
+        let entry = TraceEntry::from_cpu(&cpu);
+        assert_eq!(entry.pc, Some(0x100));
+        assert_eq!(entry.sp, Some(0xfffe));
+        assert_eq!(TraceEntry::parse(&entry.to_string()), Some(entry));
+    }
+
+    #[test]
+    fn test_banked_pc() {
+        let mut cpu = Cpu::default();
+        cpu.registers.pc = 0x4123;
+        let mem = Memory::new(crate::cartridge::Cartridge::new(vec![0; 0xffff]).unwrap());
+        let entry = TraceEntry::from_cpu(&cpu).with_bank(&mem);
+        assert_eq!(entry.location(), Some(BankedAddr { bank: 1, addr: 0x4123 }));
+        assert!(entry.to_string().ends_with("PC:01:4123"));
+        assert_eq!(TraceEntry::parse(&entry.to_string()), Some(entry));
+        let other_bank = TraceEntry { bank: Some(2), ..entry };
+        assert_eq!(entry.mismatches(&other_bank), vec!["BANK"]);
+        // a log without banks can still be compared against
+        let doctor = TraceEntry { bank: None, ..other_bank };
+        assert!(entry.mismatches(&doctor).is_empty());
+        assert_eq!(doctor.location(), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let ours = Trace::parse(
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100\n\
+             A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101\n\
+             A:02 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102",
+        );
+        // the reference trace starts in the boot ROM and has a header
+        let reference = Trace::parse(
+            "reference log\n\
+             AF = $0000, BC = $0000, DE = $0000, HL = $0000, SP = $FFFE, PC = $00FE\n\
+             AF = $01B0, BC = $0013, DE = $00D8, HL = $014D, SP = $FFFE, PC = $0100\n\
+             AF = $01B0, BC = $0013, DE = $00D8, HL = $014D, SP = $FFFE, PC = $0101\n\
+             AF = $01B0, BC = $0013, DE = $00D8, HL = $014D, SP = $FFFE, PC = $0102",
+        );
+        let divergence = diff(&ours, &reference).unwrap();
+        assert_eq!(divergence, Divergence { a: 2, b: 3, fields: vec!["A"] });
+        assert_eq!(reference.entries[divergence.b].0, 5);
+        assert_eq!(diff(&reference, &reference), None);
+
+        let short = Trace::parse(DOCTOR);
+        assert_eq!(diff(&short, &ours), Some(Divergence { a: 1, b: 1, fields: vec![] }));
+    }
+}
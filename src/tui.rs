@@ -0,0 +1,316 @@
+use std::{sync::atomic::Ordering, time::Instant};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::Color,
+    text::Line,
+    widgets::{Block, Paragraph, Widget},
+};
+
+use crate::{
+    analysis::{disassemble, instruction_length},
+    debugger::DebugCommand,
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    io::joypad::{Action, Button, Direction},
+    system::System,
+    video::FRAME_DURATION,
+};
+
+/// Terminals only report key presses, a button is let go this many frames after its last repeat
+pub const HOLD_FRAMES: u8 = 8;
+/// Instructions shown in the disassembly panel
+const DISASSEMBLY_LINES: usize = 24;
+/// Recently executed instructions shown in the history panel
+const HISTORY_LINES: usize = 8;
+
+/// The framebuffer drawn with `▀`, every cell holds two pixels: the top one as the foreground
+/// and the bottom one as the background. The whole screen takes 160x72 cells.
+pub struct Screen<'a>(pub &'a [u8]);
+
+impl Widget for Screen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let pixel = |x: usize, y: usize| {
+            let i = (y * SCREEN_WIDTH + x) * 3;
+            Color::Rgb(self.0[i], self.0[i + 1], self.0[i + 2])
+        };
+        let width = area.width.min(SCREEN_WIDTH as u16);
+        let height = area.height.min(SCREEN_HEIGHT as u16 / 2);
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (x as usize, y as usize * 2);
+                buf[(area.x + x, area.y + y)]
+                    .set_char('▀')
+                    .set_fg(pixel(px, py))
+                    .set_bg(pixel(px, py + 1));
+            }
+        }
+    }
+}
+
+fn registers(system: &System) -> Vec<Line<'static>> {
+    let r = &system.cpu.registers;
+    let flag = |set: bool, name: char| if set { name } else { '-' };
+    let flags: String = [
+        flag(r.flags.zero, 'Z'),
+        flag(r.flags.subtraction, 'N'),
+        flag(r.flags.half_carry, 'H'),
+        flag(r.flags.carry, 'C'),
+    ]
+    .iter()
+    .collect();
+    let pc = system.mem.banked(r.pc);
+    vec![
+        Line::from(format!("A  {:02X}  F  {flags}", r.a)),
+        Line::from(format!("B  {:02X}  C  {:02X}", r.b, r.c)),
+        Line::from(format!("D  {:02X}  E  {:02X}", r.d, r.e)),
+        Line::from(format!("H  {:02X}  L  {:02X}", r.h, r.l)),
+        Line::from(format!("SP {:04X}", r.sp)),
+        Line::from(format!("PC {pc}")),
+        Line::from(format!("IME {}", system.cpu.ime as u8)),
+        Line::from(format!("frame {}", system.frame)),
+    ]
+}
+
+/// The instructions from pc onwards
+fn disassembly(system: &System) -> Vec<Line<'static>> {
+    let mut pc = system.cpu.registers.pc;
+    let mut lines = vec![];
+    for _ in 0..DISASSEMBLY_LINES {
+        let len = instruction_length(system.mem.peek(pc as usize)) as u16;
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| system.mem.peek(pc.wrapping_add(i) as usize))
+            .collect();
+        lines.push(Line::from(format!("{pc:04X} {}", disassemble(&bytes, pc))));
+        pc = pc.wrapping_add(len);
+    }
+    lines
+}
+
+/// A line per audio channel: frequency, volume, duty and length left
+fn audio(system: &System) -> Vec<Line<'static>> {
+    let state = system.apu.state(&system.mem);
+    state
+        .channels
+        .iter()
+        .enumerate()
+        .map(|(index, channel)| {
+            if !channel.enabled {
+                return Line::from(format!("CH{} off", index + 1));
+            }
+            let duty = channel.duty.map_or(String::new(), |duty| format!(" D{duty}"));
+            Line::from(format!(
+                "CH{} {:>7.1}Hz V{:<2}{duty} L{}",
+                index + 1,
+                channel.frequency,
+                channel.volume,
+                channel.length
+            ))
+        })
+        .collect()
+}
+
+/// The last instructions from the cpu's pc history, newest at the bottom
+fn history(system: &System) -> Vec<Line<'static>> {
+    let mut lines: Vec<_> = system
+        .cpu
+        .pc_history
+        .iter()
+        .rev()
+        .take(HISTORY_LINES)
+        .map(|(pc, opcode)| Line::from(format!("{pc:04X} {opcode:02X}")))
+        .collect();
+    lines.reverse();
+    lines
+}
+
+/// The `:` prompt for debugger commands, showing the outcome of the last one while closed
+#[derive(Debug, Default)]
+struct Prompt {
+    input: Option<String>,
+    output: String,
+}
+
+impl Prompt {
+    fn line(&self) -> Line<'static> {
+        match &self.input {
+            Some(input) => Line::from(format!(":{input}")),
+            None => Line::from(self.output.clone()),
+        }
+    }
+
+    /// Close the prompt and run what was typed
+    fn submit(&mut self, system: &mut System) {
+        let Some(input) = self.input.take() else {
+            return;
+        };
+        self.output = match input.parse::<DebugCommand>() {
+            Ok(command) => command.run(&mut system.mem).unwrap_or_else(|e| e.to_string()),
+            Err(e) => e,
+        };
+    }
+
+    /// Edit the command being typed, returns false while the prompt is closed
+    fn key(&mut self, code: KeyCode, system: &mut System) -> bool {
+        let Some(input) = &mut self.input else {
+            return false;
+        };
+        match code {
+            KeyCode::Enter => self.submit(system),
+            KeyCode::Esc => self.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        true
+    }
+}
+
+fn draw(frame: &mut Frame, system: &System, prompt: &Prompt) {
+    let [screen, side] =
+        Layout::horizontal([Constraint::Length(SCREEN_WIDTH as u16), Constraint::Min(24)])
+            .areas(frame.area());
+    let [registers_area, history_area, audio_area, disassembly_area, prompt_area] =
+        Layout::vertical([
+            Constraint::Length(10),
+            Constraint::Length(HISTORY_LINES as u16 + 2),
+            Constraint::Length(6),
+            Constraint::Min(0),
+            Constraint::Length(3),
+        ])
+        .areas(side);
+    frame.render_widget(Screen(&system.ppu.frame_buffer), screen);
+    frame.render_widget(
+        Paragraph::new(registers(system)).block(Block::bordered().title("registers")),
+        registers_area,
+    );
+    frame.render_widget(
+        Paragraph::new(history(system)).block(Block::bordered().title("history")),
+        history_area,
+    );
+    frame.render_widget(
+        Paragraph::new(audio(system)).block(Block::bordered().title("audio")),
+        audio_area,
+    );
+    frame.render_widget(
+        Paragraph::new(disassembly(system)).block(Block::bordered().title("disassembly")),
+        disassembly_area,
+    );
+    frame.render_widget(
+        Paragraph::new(prompt.line()).block(Block::bordered().title("command")),
+        prompt_area,
+    );
+}
+
+/// Arrow keys are the d-pad, X and Z are A and B, Enter and Backspace are Start and Select
+fn button_key(code: KeyCode) -> Option<Button> {
+    match code {
+        KeyCode::Up => Some(Button::Direction(Direction::Up)),
+        KeyCode::Down => Some(Button::Direction(Direction::Down)),
+        KeyCode::Left => Some(Button::Direction(Direction::Left)),
+        KeyCode::Right => Some(Button::Direction(Direction::Right)),
+        KeyCode::Char('x') => Some(Button::Action(Action::A)),
+        KeyCode::Char('z') => Some(Button::Action(Action::B)),
+        KeyCode::Enter => Some(Button::Action(Action::Start)),
+        KeyCode::Backspace => Some(Button::Action(Action::Select)),
+        _ => None,
+    }
+}
+
+/// Run `system` in the terminal until Q or Escape is pressed or `quit` is set, P pauses and
+/// `:` opens a prompt for `DebugCommand`s. The screen needs a terminal of at least 184x72 cells
+/// to be shown whole.
+pub fn run(system: &mut System) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_loop(system, &mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn run_loop(system: &mut System, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+    let mut held: Vec<(Button, u8)> = vec![];
+    let mut prompt = Prompt::default();
+    loop {
+        if system.quit.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let start = Instant::now();
+        if !system.paused {
+            system.step_frame();
+        }
+        terminal.draw(|frame| draw(frame, system, &prompt))?;
+        for (button, frames) in &mut held {
+            *frames -= 1;
+            if *frames == 0 {
+                system.joypad.release(*button);
+            }
+        }
+        held.retain(|(_, frames)| *frames > 0);
+        let waiting = Instant::now();
+        while event::poll(FRAME_DURATION.saturating_sub(start.elapsed()))? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release || prompt.key(key.code, system) {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char(':') => prompt.input = Some(String::new()),
+                // raw mode turns ctrl+c into a key press instead of SIGINT
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    system.quit.store(true, Ordering::Relaxed);
+                }
+                KeyCode::Char('p') => system.toggle_pause(),
+                code => {
+                    if let (Some(button), true) = (button_key(code), system.live_input()) {
+                        system.joypad.press(button);
+                        held.retain(|(held, _)| *held != button);
+                        held.push((button, HOLD_FRAMES));
+                    }
+                }
+            }
+        }
+        system.add_idle(waiting.elapsed());
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen() {
+        let mut frame_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        // pixel (1, 1) is the bottom half of cell (1, 0)
+        let i = (SCREEN_WIDTH + 1) * 3;
+        frame_buffer[i..i + 3].copy_from_slice(&[1, 2, 3]);
+        let area = Rect::new(0, 0, 4, 2);
+        let mut buf = Buffer::empty(area);
+        Screen(&frame_buffer).render(area, &mut buf);
+        let cell = &buf[(1, 0)];
+        assert_eq!(cell.symbol(), "▀");
+        assert_eq!(cell.fg, Color::Rgb(0, 0, 0));
+        assert_eq!(cell.bg, Color::Rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn test_prompt() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        let mut prompt = Prompt::default();
+        assert!(!prompt.key(KeyCode::Char('p'), &mut system));
+        prompt.input = Some(String::new());
+        for c in "memdup".chars() {
+            prompt.key(KeyCode::Char(c), &mut system);
+        }
+        prompt.key(KeyCode::Backspace, &mut system);
+        assert_eq!(prompt.line(), Line::from(":memdu"));
+        prompt.key(KeyCode::Enter, &mut system);
+        assert_eq!(prompt.input, None);
+        assert_eq!(prompt.output, "Unknown command memdu, expected memdump or memload");
+    }
+}
@@ -0,0 +1,220 @@
+//! Where completed frames are shown. The SDL frontend draws through `SdlVideo`, builds without
+//! SDL can open a winit window with the `softbuffer` feature or run without one at all.
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    display::{FRAME_SCANLINES, SCREEN_HEIGHT, SCREEN_WIDTH},
+    errors::VideoError,
+    system::System,
+    y4m::Y4mWriter,
+};
+
+#[cfg(feature = "softbuffer")]
+mod software;
+#[cfg(feature = "softbuffer")]
+pub use software::SoftbufferVideo;
+
+/// How long a frame should take, 59.7 frames per second
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+/// Keeps presented frames `FRAME_DURATION` apart. A frame that's late moves the deadline along
+/// instead of the frames after it being rushed to catch up.
+#[derive(Debug)]
+pub struct FramePacer {
+    deadline: Instant,
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self {
+            deadline: Instant::now(),
+        }
+    }
+}
+
+impl FramePacer {
+    /// Sleep until the next frame is due, returns how long that took
+    pub fn wait(&mut self) -> Duration {
+        self.deadline += FRAME_DURATION;
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(wait) => {
+                std::thread::sleep(wait);
+                wait
+            }
+            // running behind, don't try to catch up
+            None => {
+                self.deadline = Instant::now();
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+/// Something that shows 160x144 RGB24 frames
+pub trait VideoBackend {
+    /// Show a completed frame, returns false once the window has been closed
+    fn present(&mut self, frame: &[u8]) -> Result<bool, VideoError>;
+    /// Whether frames should be presented at the Game Boy's own speed rather than as fast as
+    /// they can be emulated
+    fn realtime(&self) -> bool {
+        true
+    }
+}
+
+/// Drops every frame, for running without a window
+#[derive(Debug, Default)]
+pub struct NullVideo;
+
+impl VideoBackend for NullVideo {
+    fn present(&mut self, _: &[u8]) -> Result<bool, VideoError> {
+        Ok(true)
+    }
+}
+
+/// Renders into the framebuffer as fast as it can with no window at all, for CI and machines
+/// without a display. Can stop after a number of frames and encode every frame to a video.
+#[derive(Default)]
+pub struct OffscreenVideo {
+    /// Frames presented so far
+    pub frames: u64,
+    limit: Option<u64>,
+    encoder: Option<Y4mWriter<BufWriter<File>>>,
+}
+
+impl OffscreenVideo {
+    /// Stop after `limit` frames, or never
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Also write every frame to `path` as a Y4M video, see `Y4mWriter`
+    pub fn encode_to(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.encoder = Some(Y4mWriter::new(BufWriter::new(File::create(path)?))?);
+        Ok(())
+    }
+}
+
+impl VideoBackend for OffscreenVideo {
+    fn present(&mut self, frame: &[u8]) -> Result<bool, VideoError> {
+        let encode_error = |e: std::io::Error| VideoError::Present(e.to_string());
+        if let Some(encoder) = &mut self.encoder {
+            encoder.write_frame(frame).map_err(encode_error)?;
+        }
+        self.frames += 1;
+        let done = self.limit.is_some_and(|limit| self.frames >= limit);
+        if let (true, Some(encoder)) = (done, &mut self.encoder) {
+            encoder.flush().map_err(encode_error)?;
+        }
+        Ok(!done)
+    }
+
+    fn realtime(&self) -> bool {
+        false
+    }
+}
+
+/// The backends this build comes with, `gbr --video`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Video {
+    #[cfg(feature = "sdl")]
+    Sdl,
+    #[cfg(feature = "softbuffer")]
+    Softbuffer,
+    Offscreen,
+    Null,
+}
+
+impl std::str::FromStr for Video {
+    type Err = String;
+
+    /// `sdl`, `softbuffer`, `offscreen` or `none`, as long as the feature it needs was built
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "sdl")]
+            "sdl" => Ok(Self::Sdl),
+            #[cfg(feature = "softbuffer")]
+            "softbuffer" => Ok(Self::Softbuffer),
+            "offscreen" => Ok(Self::Offscreen),
+            "none" => Ok(Self::Null),
+            other => Err(match other {
+                "sdl" | "softbuffer" => format!("gbr was built without the {s} feature"),
+                _ => format!(
+                    "Unknown video backend {s}, expected sdl, softbuffer, offscreen or none"
+                ),
+            }),
+        }
+    }
+}
+
+/// Play until `quit` is set or the window is closed, presenting a frame at 59.7 frames a second
+/// unless the backend isn't `realtime` or the system is fast forwarding. With the LCD off a
+/// frame's worth of scanlines goes by between presents so the window stays responsive. Buttons
+/// come from `System::input`, the backends don't read the keyboard.
+pub fn run(system: &mut System, video: &mut dyn VideoBackend) -> Result<(), VideoError> {
+    let mut frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let mut pacer = FramePacer::default();
+    while !system.quit.load(Ordering::Relaxed) {
+        if !system.paused {
+            system.step_scanlines(FRAME_SCANLINES);
+        }
+        system.osd.frame();
+        system.composite_frame(&mut frame);
+        if !video.present(&frame)? {
+            return Ok(());
+        }
+        if !video.realtime() || system.fast_forward {
+            continue;
+        }
+        let idle = pacer.wait();
+        system.add_idle(idle);
+    }
+    Ok(())
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_video() {
+        assert_eq!("none".parse(), Ok(Video::Null));
+        #[cfg(feature = "sdl")]
+        assert_eq!("sdl".parse(), Ok(Video::Sdl));
+        #[cfg(not(feature = "softbuffer"))]
+        assert_eq!(
+            "softbuffer".parse::<Video>(),
+            Err("gbr was built without the softbuffer feature".to_string())
+        );
+        assert!("vulkan".parse::<Video>().is_err());
+    }
+
+    #[test]
+    fn test_frame_pacer() {
+        let mut pacer = FramePacer::default();
+        let start = Instant::now();
+        pacer.wait();
+        pacer.wait();
+        assert!(start.elapsed() >= FRAME_DURATION * 2);
+        // a late frame doesn't make the next ones come early
+        std::thread::sleep(FRAME_DURATION * 3);
+        assert_eq!(pacer.wait(), Duration::ZERO);
+        assert!(pacer.wait() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_offscreen() {
+        let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+        let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+        let mut video = OffscreenVideo::new(Some(3));
+        run(&mut system, &mut video).unwrap();
+        assert_eq!(video.frames, 3);
+    }
+}
@@ -0,0 +1,120 @@
+//! A winit window drawn into with softbuffer, for platforms SDL3 doesn't build on
+use std::{num::NonZeroU32, rc::Rc, time::Duration};
+
+use softbuffer::{Context, Surface};
+use winit::{
+    application::ApplicationHandler,
+    dpi::LogicalSize,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    platform::pump_events::{EventLoopExtPumpEvents, PumpStatus},
+    window::{Window, WindowId},
+};
+
+use super::VideoBackend;
+use crate::{
+    display::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    errors::VideoError,
+};
+
+/// The window starts out at this multiple of the screen size
+const SCALE: u32 = 3;
+
+/// Frames are scaled to whatever size the window is, nearest neighbour
+pub struct SoftbufferVideo {
+    event_loop: EventLoop<()>,
+    window: WindowState,
+}
+
+#[derive(Default)]
+struct WindowState {
+    surface: Option<Surface<Rc<Window>, Rc<Window>>>,
+    error: Option<VideoError>,
+    closed: bool,
+}
+
+impl WindowState {
+    fn open(&mut self, event_loop: &ActiveEventLoop) -> Result<(), Box<dyn std::error::Error>> {
+        let size = LogicalSize::new(SCREEN_WIDTH as u32 * SCALE, SCREEN_HEIGHT as u32 * SCALE);
+        let attributes = Window::default_attributes().with_title("gbr").with_inner_size(size);
+        let window = Rc::new(event_loop.create_window(attributes)?);
+        let context = Context::new(window.clone())?;
+        self.surface = Some(Surface::new(&context, window)?);
+        Ok(())
+    }
+}
+
+impl ApplicationHandler for WindowState {
+    // the window can only be created once the event loop is running
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.surface.is_none() {
+            if let Err(e) = self.open(event_loop) {
+                self.error = Some(VideoError::Window(e.to_string()));
+            }
+        }
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        if event == WindowEvent::CloseRequested {
+            self.closed = true;
+        }
+    }
+}
+
+impl SoftbufferVideo {
+    pub fn new() -> Result<Self, VideoError> {
+        let event_loop = EventLoop::new().map_err(|e| VideoError::Window(e.to_string()))?;
+        let mut video = Self {
+            event_loop,
+            window: WindowState::default(),
+        };
+        video.pump();
+        match video.window.error.take() {
+            Some(e) => Err(e),
+            None => Ok(video),
+        }
+    }
+
+    /// Handle whatever happened to the window since the last frame without blocking
+    fn pump(&mut self) {
+        let status = self.event_loop.pump_app_events(Some(Duration::ZERO), &mut self.window);
+        if let PumpStatus::Exit(_) = status {
+            self.window.closed = true;
+        }
+    }
+}
+
+impl VideoBackend for SoftbufferVideo {
+    fn present(&mut self, frame: &[u8]) -> Result<bool, VideoError> {
+        self.pump();
+        if let Some(e) = self.window.error.take() {
+            return Err(e);
+        }
+        if self.window.closed {
+            return Ok(false);
+        }
+        let Some(surface) = &mut self.window.surface else {
+            return Ok(true);
+        };
+        let size = surface.window().inner_size();
+        // nothing to draw into while minimized
+        let (width, height) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height));
+        let (Some(width), Some(height)) = (width, height) else {
+            return Ok(true);
+        };
+        let present = |e: softbuffer::SoftBufferError| VideoError::Present(e.to_string());
+        surface.resize(width, height).map_err(present)?;
+        let mut buffer = surface.buffer_mut().map_err(present)?;
+        let (width, height) = (width.get() as usize, height.get() as usize);
+        for y in 0..height {
+            let row = y * SCREEN_HEIGHT / height * SCREEN_WIDTH;
+            for x in 0..width {
+                let pixel = (row + x * SCREEN_WIDTH / width) * 3;
+                let [r, g, b] = [frame[pixel], frame[pixel + 1], frame[pixel + 2]];
+                buffer[y * width + x] = u32::from_be_bytes([0, r, g, b]);
+            }
+        }
+        buffer.present().map_err(present)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,145 @@
+//! Pictures of what's in VRAM, `gbr export-tiles`. The tile data area comes out as a sheet of
+//! every tile and each tile map as the full 256x256 background it describes, both shaded the
+//! way the background would be with the current BGP.
+use crate::{
+    PALETTE,
+    memory::{Memory, registers::BGP},
+    png,
+    tile::{TILE_COUNT, TileAddressing, TileId, TileMap},
+};
+
+/// Tiles per row of the tile sheet, 16 puts each 128 tile block on 8 rows of its own
+pub const SHEET_COLUMNS: usize = 16;
+
+/// An RGB24 picture of part of VRAM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VramImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl VramImage {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// Copy row `y` of a tile into the picture with its leftmost pixel at `left`, `top`
+    fn blit_row(&mut self, row: &[u8; 8], left: usize, top: usize, colors: &[[u8; 3]; 4]) {
+        for x in 0..8 {
+            // rows are stored rightmost pixel first
+            let shade = row[7 - x];
+            let color = PALETTE.iter().position(|s| *s == shade).unwrap_or(0);
+            let start = (top * self.width + left + x) * 3;
+            self.pixels[start..start + 3].copy_from_slice(&colors[color]);
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_rgb(self.width, self.height, &self.pixels)
+    }
+}
+
+/// The RGB each color id ends up as, BGP picks a shade for it and `shades` gives the shade's RGB
+fn bg_colors(mem: &Memory, shades: &[[u8; 3]; 4]) -> [[u8; 3]; 4] {
+    let bgp = mem.io.get(BGP);
+    std::array::from_fn(|color| shades[(bgp >> (color * 2) & 0x03) as usize])
+}
+
+/// Every tile from 0x8000 to 0x97ff in order, `SHEET_COLUMNS` to a row
+pub fn tile_sheet(mem: &Memory, shades: &[[u8; 3]; 4]) -> VramImage {
+    let colors = bg_colors(mem, shades);
+    let rows = TILE_COUNT / SHEET_COLUMNS;
+    let mut image = VramImage::new(SHEET_COLUMNS * 8, rows * 8);
+    for index in 0..TILE_COUNT {
+        // ids 0-255 from 0x8000 and then 0-127 from 0x9000 cover the whole area
+        let tile = match index {
+            0..256 => mem.tile(TileId(index as u8), TileAddressing::Unsigned),
+            _ => mem.tile(TileId(index as u8), TileAddressing::Signed),
+        };
+        let (left, top) = (index % SHEET_COLUMNS * 8, index / SHEET_COLUMNS * 8);
+        for (y, row) in tile.rows.iter().enumerate() {
+            image.blit_row(row, left, top + y, &colors);
+        }
+    }
+    image
+}
+
+/// All 32x32 tiles of `map`, looked up with `addressing`
+pub fn tile_map(
+    mem: &Memory,
+    map: TileMap,
+    addressing: TileAddressing,
+    shades: &[[u8; 3]; 4],
+) -> VramImage {
+    let colors = bg_colors(mem, shades);
+    let mut image = VramImage::new(256, 256);
+    for ty in 0..32 {
+        for tx in 0..32 {
+            let tile = mem.tile_at(map, tx, ty, addressing);
+            for (y, row) in tile.rows.iter().enumerate() {
+                image.blit_row(row, tx * 8, ty * 8 + y, &colors);
+            }
+        }
+    }
+    image
+}
+
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    const SHADES: [[u8; 3]; 4] = [[255; 3], [170; 3], [85; 3], [0; 3]];
+
+    fn pixel(image: &VramImage, x: usize, y: usize) -> [u8; 3] {
+        let start = (y * image.width + x) * 3;
+        image.pixels[start..start + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_tile_sheet() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0x8000]).unwrap());
+        // identity palette
+        mem.io.set(BGP, 0xe4);
+        // tile 17's top row: leftmost pixel color 3, the rest color 1
+        mem.block[0x8110] = 0xff;
+        mem.block[0x8111] = 0x80;
+        // the last tile, at 0x97f0, all color 2
+        for row in 0..8 {
+            mem.block[0x97f1 + row * 2] = 0xff;
+        }
+        let sheet = tile_sheet(&mem, &SHADES);
+        assert_eq!((sheet.width, sheet.height), (128, 192));
+        assert_eq!(pixel(&sheet, 8, 8), [0; 3]);
+        assert_eq!(pixel(&sheet, 9, 8), [170; 3]);
+        assert_eq!(pixel(&sheet, 8, 9), [255; 3]);
+        assert_eq!(pixel(&sheet, 127, 191), [85; 3]);
+        // BGP is applied, color 0 shown as black
+        mem.io.set(BGP, 0xe7);
+        assert_eq!(pixel(&tile_sheet(&mem, &SHADES), 0, 0), [0; 3]);
+    }
+
+    #[test]
+    fn test_tile_map() {
+        let mut mem = Memory::new(Cartridge::new(vec![0; 0x8000]).unwrap());
+        mem.io.set(BGP, 0xe4);
+        // tile -1 with signed addressing lives at 0x8ff0, color 3 throughout
+        mem.block[0x8ff0..0x9000].fill(0xff);
+        mem.block[TileMap::High.address(31, 2)] = 0xff;
+        let map = tile_map(&mem, TileMap::High, TileAddressing::Signed, &SHADES);
+        assert_eq!((map.width, map.height), (256, 256));
+        assert_eq!(pixel(&map, 248, 16), [0; 3]);
+        assert_eq!(pixel(&map, 255, 23), [0; 3]);
+        assert_eq!(pixel(&map, 247, 16), [255; 3]);
+        // unsigned, id 255 is at 0x8ff0 as well
+        let map = tile_map(&mem, TileMap::High, TileAddressing::Unsigned, &SHADES);
+        assert_eq!(pixel(&map, 248, 16), [0; 3]);
+        // the other map is still all tile 0
+        let map = tile_map(&mem, TileMap::Low, TileAddressing::Signed, &SHADES);
+        assert_eq!(pixel(&map, 248, 16), [255; 3]);
+    }
+}
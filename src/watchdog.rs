@@ -0,0 +1,102 @@
+/// A loop spanning at most this many bytes counts as tiny
+pub const LOOP_BYTES: u16 = 16;
+
+/// Where execution got stuck, see `Watchdog`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    /// Lowest and highest pc seen in the loop
+    pub low: u16,
+    pub high: u16,
+    pub frames: u32,
+}
+
+impl std::fmt::Display for Stall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Stuck at ${:04X}-${:04X} for {} frames with interrupts off and no IO",
+            self.low, self.high, self.frames
+        )
+    }
+}
+
+/// Notices the classic crash symptom behind black screen reports: the cpu spinning in a tiny
+/// loop with interrupts disabled and without touching an IO register, which nothing but a
+/// reset gets it out of
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    /// Frames the cpu has to be stuck for before it's flagged
+    pub frames: u32,
+    /// Pause emulation and show the diagnostic once it trips
+    pub pause: bool,
+    // pc range and whether anything kept the frame from counting as stuck
+    low: u16,
+    high: u16,
+    active: bool,
+    stuck: u32,
+}
+
+impl Watchdog {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            pause: false,
+            low: u16::MAX,
+            high: 0,
+            active: false,
+            stuck: 0,
+        }
+    }
+
+    /// Note an instruction about to be executed at `pc`
+    pub fn instruction(&mut self, pc: u16, ime: bool, io_accessed: bool) {
+        self.low = self.low.min(pc);
+        self.high = self.high.max(pc);
+        self.active |= ime || io_accessed;
+    }
+
+    /// Close a frame, returns the stall once the cpu has been stuck for `frames` frames in a
+    /// row and then not again until it gets going
+    pub fn frame(&mut self) -> Option<Stall> {
+        let stuck = !self.active && self.high.saturating_sub(self.low) < LOOP_BYTES;
+        self.active = false;
+        if !stuck {
+            self.stuck = 0;
+            (self.low, self.high) = (u16::MAX, 0);
+            return None;
+        }
+        // the range carries over between frames so a loop drifting through memory isn't stuck
+        self.stuck += 1;
+        (self.stuck == self.frames).then_some(Stall {
+            low: self.low,
+            high: self.high,
+            frames: self.stuck,
+        })
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        let mut watchdog = Watchdog::new(3);
+        let frame = |watchdog: &mut Watchdog, pcs: &[u16], io: bool| {
+            for &pc in pcs {
+                watchdog.instruction(pc, false, io);
+            }
+            watchdog.frame()
+        };
+        // polling a register in a tight loop is waiting, not stuck
+        assert_eq!(frame(&mut watchdog, &[0x150, 0x152], true), None);
+        assert_eq!(frame(&mut watchdog, &[0x150, 0x152], false), None);
+        assert_eq!(frame(&mut watchdog, &[0x150, 0x152], false), None);
+        let stall = frame(&mut watchdog, &[0x150, 0x152], false).unwrap();
+        assert_eq!(stall, Stall { low: 0x150, high: 0x152, frames: 3 });
+        assert_eq!(frame(&mut watchdog, &[0x150, 0x152], false), None);
+        // a wide range of code gets it going again
+        assert_eq!(frame(&mut watchdog, &[0x150, 0x4000], false), None);
+        watchdog.instruction(0x200, true, false);
+        assert_eq!(watchdog.frame(), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use crate::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Uncompressed YUV4MPEG2 video of the screen, which ffmpeg and most players read directly and
+/// saves pulling in an encoder. Frames are stored 4:4:4 so no colour is lost to subsampling.
+/// Read more: https://wiki.multimedia.cx/index.php/YUV4MPEG2
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    // the Y, U and V planes of the frame being written
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Write the stream header, the frame rate is the DMG's exact 4194304 / 70224 Hz
+    pub fn new(mut out: W) -> std::io::Result<Self> {
+        writeln!(
+            out,
+            "YUV4MPEG2 W{SCREEN_WIDTH} H{SCREEN_HEIGHT} F4194304:70224 Ip A1:1 C444"
+        )?;
+        Ok(Self {
+            out,
+            planes: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+        })
+    }
+
+    /// Append an RGB24 frame, converted to limited range BT.601
+    pub fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        let size = SCREEN_WIDTH * SCREEN_HEIGHT;
+        for (i, pixel) in frame.chunks_exact(3).take(size).enumerate() {
+            let [y, u, v] = rgb_to_yuv(pixel[0], pixel[1], pixel[2]);
+            self.planes[i] = y;
+            self.planes[size + i] = u;
+            self.planes[2 * size + i] = v;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_frame() {
+        let mut writer = Y4mWriter::new(vec![]).unwrap();
+        let mut frame = vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        frame[..3].copy_from_slice(&[0, 0, 0]);
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let out = writer.out;
+        let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\n";
+        assert!(out.starts_with(header));
+        let frame_len = b"FRAME\n".len() + SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+        assert_eq!(out.len(), header.len() + 2 * frame_len);
+        let planes = &out[header.len() + 6..];
+        // black and white land on the ends of the limited range, both without colour
+        assert_eq!((planes[0], planes[1]), (16, 235));
+        let size = SCREEN_WIDTH * SCREEN_HEIGHT;
+        assert_eq!((planes[size], planes[2 * size]), (128, 128));
+        assert_eq!((planes[size + 1], planes[2 * size + 1]), (128, 128));
+    }
+}
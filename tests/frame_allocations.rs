@@ -0,0 +1,73 @@
+//! Headless frames mustn't touch the allocator once the emulator has warmed up, the counting
+//! allocator below is installed for this test binary only.
+use std::{
+    alloc::{GlobalAlloc, Layout, System as Host},
+    cell::Cell,
+};
+
+use gbr::{
+    memory::registers::LCDC,
+    oam::{OamAttributes, OamEntry},
+    system::System,
+};
+
+thread_local! {
+    // const so reading it never allocates from inside the allocator
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { Host.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { Host.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { Host.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[test]
+fn test_zero_allocations_per_frame() {
+    let path = format!("{}/tests/roms/golden.gb", env!("CARGO_MANIFEST_DIR"));
+    let mut system = System::headless(std::fs::read(path).unwrap()).unwrap();
+    // the rom fills VRAM with the LCD off, the frames after fill the trace history and the
+    // tile caches
+    system.step_frame();
+    while !system.mem.lcd_control().lcd_ppu_enable {
+        system.step_frame();
+    }
+    for _ in 0..3 {
+        system.step_frame();
+    }
+    // put objects on screen, the rom doesn't draw any itself
+    for index in 0..12 {
+        let object = OamEntry { y: 40, x: 8 * index as u8, tile: 1, attrs: OamAttributes::empty() };
+        system.mem.set_oam_entry(index, object);
+    }
+    let lcdc = system.mem.io.get(LCDC);
+    system.mem.io.set(LCDC, lcdc | 0x02);
+    // and everything the osd draws every frame
+    system.osd.show_fps = true;
+    system.osd.underruns = Some(3);
+    system.osd.message("State saved");
+    let mut out = vec![0; system.framebuffer().len()];
+    let before = ALLOCATIONS.with(Cell::get);
+    for _ in 0..10 {
+        system.step_frame();
+        system.osd.frame();
+        system.composite_frame(&mut out);
+    }
+    let allocations = ALLOCATIONS.with(Cell::get) - before;
+    assert_eq!(allocations, 0, "{allocations} allocations in 10 frames");
+}
@@ -0,0 +1,39 @@
+//! Every SingleStepTests vector in `tests/sm83`, run against a flat 64KiB bus so the vectors can
+//! put code and data anywhere, ROM included.
+//! Read more: https://github.com/SingleStepTests/sm83
+use gbr::sm83::{self, TestCase};
+
+fn opcodes() -> Vec<String> {
+    let dir = format!("{}/tests/sm83", env!("CARGO_MANIFEST_DIR"));
+    let mut opcodes: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Couldn't read {dir}: {e}"))
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_suffix(".json").map(str::to_string)
+        })
+        .collect();
+    opcodes.sort();
+    opcodes
+}
+
+#[test]
+fn vectors() {
+    let opcodes = opcodes();
+    assert!(!opcodes.is_empty(), "tests/sm83 has no vectors");
+    let mut failures = vec![];
+    for opcode in &opcodes {
+        let cases: Vec<TestCase> = sm83::load(opcode);
+        assert!(!cases.is_empty(), "{opcode}.json has no cases");
+        for case in &cases {
+            if let Err(e) = sm83::run(case) {
+                failures.push(format!("{opcode}.json {e}"));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} failing cases:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}